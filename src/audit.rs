@@ -0,0 +1,348 @@
+use crate::sha256;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Append-only record of every command kyanite executes
+pub struct AuditLog {
+    file: Mutex<File>,
+    run: String,
+    uid: String,
+    cwd: String,
+    env_digest: String,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Ok(AuditLog {
+            file: Mutex::new(file),
+            run: format!("{}-{}", started.as_millis(), std::process::id()),
+            uid: current_uid(),
+            cwd: env::current_dir()?.display().to_string(),
+            env_digest: environment_digest(),
+        })
+    }
+
+    /// Records the settings of this run so it can be replayed the same way
+    pub fn record_run(&self, jobs: usize, keep_order: bool) -> io::Result<()> {
+        self.append(&format!(
+            "ts={}\trun={}\tevent=run\tjobs={}\tkeep_order={}",
+            format_timestamp(SystemTime::now()),
+            self.run,
+            jobs,
+            keep_order
+        ))
+    }
+
+    /// Records the command of the job numbered `seq` before it is executed
+    pub fn record_start(&self, seq: usize, worker: usize, command: &str) -> io::Result<()> {
+        self.append(&format!(
+            "ts={}\trun={}\tevent=start\tjob={}\tworker={}\tuid={}\tcwd={}\tenv={}\tcmd={}",
+            format_timestamp(SystemTime::now()),
+            self.run,
+            seq,
+            worker,
+            self.uid,
+            escape(&self.cwd),
+            self.env_digest,
+            escape(command)
+        ))
+    }
+
+    /// Records whether a previously started command succeeded
+    pub fn record_finish(&self, seq: usize, success: bool) -> io::Result<()> {
+        self.append(&format!(
+            "ts={}\trun={}\tevent=finish\tjob={}\tstatus={}",
+            format_timestamp(SystemTime::now()),
+            self.run,
+            seq,
+            if success { "ok" } else { "failed" }
+        ))
+    }
+
+    fn append(&self, record: &str) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        file.write_all(format!("{}\n", record).as_bytes())?;
+        file.flush()
+    }
+}
+
+#[cfg(unix)]
+fn current_uid() -> String {
+    unsafe { libc::getuid() }.to_string()
+}
+
+#[cfg(not(unix))]
+fn current_uid() -> String {
+    env::var("USERNAME").unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Digest of the sorted environment, so records prove which environment a command saw
+fn environment_digest() -> String {
+    let mut vars: Vec<_> = env::vars_os().collect();
+    vars.sort();
+
+    let mut hasher = sha256::Sha256::default();
+    for (key, value) in vars {
+        hasher.update(key.to_string_lossy().as_bytes());
+        hasher.update(b"=");
+        hasher.update(value.to_string_lossy().as_bytes());
+        hasher.update(b"\0");
+    }
+    sha256::hex(&hasher.finalize())
+}
+
+/// Escapes tabs, newlines and backslashes so each record stays on one line
+pub fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Commands recorded for one run of an audit log, in their original job order
+#[derive(Debug, PartialEq)]
+pub struct ReplayPlan {
+    pub run: String,
+    pub jobs: usize,
+    pub keep_order: bool,
+    pub commands: Vec<String>,
+}
+
+#[derive(Default)]
+struct RecordedRun {
+    jobs: usize,
+    keep_order: bool,
+    started: BTreeMap<usize, String>,
+    succeeded: HashSet<usize>,
+}
+
+/// Reads the commands of `run` (or the most recent run) back from an audit log
+pub fn read_replay(path: &Path, run: Option<&str>, only_failed: bool) -> io::Result<ReplayPlan> {
+    parse_replay(&fs::read_to_string(path)?, run, only_failed)
+}
+
+fn parse_replay(contents: &str, run: Option<&str>, only_failed: bool) -> io::Result<ReplayPlan> {
+    let mut runs: HashMap<String, RecordedRun> = HashMap::new();
+    let mut last_run = None;
+
+    for line in contents.lines() {
+        let fields: HashMap<&str, &str> = line
+            .split('\t')
+            .filter_map(|field| field.split_once('='))
+            .collect();
+        let (Some(&id), Some(&event)) = (fields.get("run"), fields.get("event")) else {
+            continue;
+        };
+        let job = fields.get("job").and_then(|job| job.parse().ok());
+        let recorded = runs.entry(id.to_string()).or_default();
+
+        match (event, job) {
+            ("run", _) => {
+                recorded.jobs = fields.get("jobs").and_then(|j| j.parse().ok()).unwrap_or(1);
+                recorded.keep_order = fields.get("keep_order") == Some(&"true");
+                last_run = Some(id.to_string());
+            }
+            ("start", Some(job)) => {
+                if let Some(cmd) = fields.get("cmd") {
+                    recorded.started.insert(job, unescape(cmd));
+                }
+            }
+            ("finish", Some(job)) if fields.get("status") == Some(&"ok") => {
+                recorded.succeeded.insert(job);
+            }
+            _ => {}
+        }
+    }
+
+    let id = match run {
+        Some(id) => id.to_string(),
+        None => last_run.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "no runs recorded in audit log")
+        })?,
+    };
+    let recorded = runs.remove(&id).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("run {} not found in audit log", id),
+        )
+    })?;
+
+    let commands = recorded
+        .started
+        .into_iter()
+        .filter(|(job, _)| !only_failed || !recorded.succeeded.contains(job))
+        .map(|(_, cmd)| cmd)
+        .collect();
+
+    Ok(ReplayPlan {
+        run: id,
+        jobs: recorded.jobs.max(1),
+        keep_order: recorded.keep_order,
+        commands,
+    })
+}
+
+/// Formats a time as an RFC 3339 UTC timestamp with millisecond precision
+pub fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() as i64;
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let seconds_of_day = secs.rem_euclid(86400);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Converts days since the Unix epoch to a (year, month, day) civil date
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            format_timestamp(UNIX_EPOCH + Duration::from_millis(951_782_400_250)),
+            "2000-02-29T00:00:00.250Z"
+        );
+        assert_eq!(
+            format_timestamp(UNIX_EPOCH + Duration::from_secs(1_791_978_780)),
+            "2026-10-14T11:53:00.000Z"
+        );
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("plain"), "plain");
+        assert_eq!(escape("a\tb\nc\\d"), "a\\tb\\nc\\\\d");
+    }
+
+    #[test]
+    fn test_record_start_appends_one_line() {
+        let path = env::temp_dir().join(format!("kyanite-test-audit-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let audit = AuditLog::open(&path).unwrap();
+        audit.record_start(0, 1, "echo 'a\tb'").unwrap();
+        audit.record_start(1, 0, "echo c").unwrap();
+        drop(audit);
+        AuditLog::open(&path)
+            .unwrap()
+            .record_start(2, 0, "echo d")
+            .unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].contains("\tevent=start\tjob=0\tworker=1\t"));
+        assert!(lines[0].starts_with("ts="));
+        assert!(lines[0].ends_with("\tcmd=echo 'a\\tb'"));
+        assert!(lines[2].ends_with("\tcmd=echo d"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_unescape_round_trips() {
+        let value = "printf 'a\tb\\n'\necho \\done";
+        assert_eq!(unescape(&escape(value)), value);
+    }
+
+    const LOG: &str = "ts=t\trun=1-1\tevent=run\tjobs=4\tkeep_order=false
+ts=t\trun=1-1\tevent=start\tjob=0\tworker=0\tuid=0\tcwd=/\tenv=x\tcmd=echo old
+ts=t\trun=2-2\tevent=run\tjobs=2\tkeep_order=true
+ts=t\trun=2-2\tevent=start\tjob=1\tworker=1\tuid=0\tcwd=/\tenv=x\tcmd=echo b
+ts=t\trun=2-2\tevent=start\tjob=0\tworker=0\tuid=0\tcwd=/\tenv=x\tcmd=echo a\\nline
+ts=t\trun=2-2\tevent=finish\tjob=1\tstatus=ok
+ts=t\trun=2-2\tevent=start\tjob=2\tworker=1\tuid=0\tcwd=/\tenv=x\tcmd=false
+ts=t\trun=2-2\tevent=finish\tjob=2\tstatus=failed
+ts=t\trun=2-2\tevent=finish\tjob=0\tstatus=ok
+";
+
+    #[test]
+    fn test_parse_replay_latest_run_in_job_order() {
+        let plan = parse_replay(LOG, None, false).unwrap();
+        assert_eq!(
+            plan,
+            ReplayPlan {
+                run: "2-2".to_string(),
+                jobs: 2,
+                keep_order: true,
+                commands: vec![
+                    "echo a\nline".to_string(),
+                    "echo b".to_string(),
+                    "false".to_string()
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_replay_selected_run_and_failures() {
+        let plan = parse_replay(LOG, Some("1-1"), true).unwrap();
+        assert_eq!(plan.jobs, 4);
+        assert_eq!(plan.commands, vec!["echo old".to_string()]);
+
+        let plan = parse_replay(LOG, None, true).unwrap();
+        assert_eq!(plan.commands, vec!["false".to_string()]);
+
+        assert!(parse_replay(LOG, Some("3-3"), false).is_err());
+        assert!(parse_replay("", None, false).is_err());
+    }
+}
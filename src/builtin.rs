@@ -0,0 +1,210 @@
+use crate::{deflate, http, sha256};
+use clap::ValueEnum;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Operations `--builtin` runs inside the worker instead of spawning a shell
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Builtin {
+    Copy,
+    Move,
+    Hash,
+    Gzip,
+    HttpGet,
+}
+
+impl Builtin {
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Copy => "copy",
+            Builtin::Move => "move",
+            Builtin::Hash => "hash",
+            Builtin::Gzip => "gzip",
+            Builtin::HttpGet => "http-get",
+        }
+    }
+
+    /// Runs the operation on the words of an expanded command, returning what it prints
+    pub fn run(self, args: &[String]) -> Result<Vec<u8>, String> {
+        match (self, args) {
+            (Builtin::Copy, [source, dest]) => {
+                let dest = destination(source, dest)?;
+                fs::copy(source, &dest).map_err(|e| format!("{}: {}", source, e))?;
+                Ok(Vec::new())
+            }
+            (Builtin::Move, [source, dest]) => {
+                let dest = destination(source, dest)?;
+                if fs::rename(source, &dest).is_err() {
+                    // across file systems a rename fails, so copy and remove instead
+                    fs::copy(source, &dest)
+                        .and_then(|_| fs::remove_file(source))
+                        .map_err(|e| format!("{}: {}", source, e))?;
+                }
+                Ok(Vec::new())
+            }
+            (Builtin::Copy | Builtin::Move, _) => {
+                Err(format!("{} takes a source and a destination", self.name()))
+            }
+            (Builtin::Hash, [_, ..]) => {
+                let mut out = String::new();
+                for path in args {
+                    let digest = crate::hash_file(Path::new(path))
+                        .map_err(|e| format!("{}: {}", path, e))?;
+                    out.push_str(&format!("{}  {}\n", sha256::hex(&digest), path));
+                }
+                Ok(out.into_bytes())
+            }
+            (Builtin::Gzip, [_, ..]) => {
+                for path in args {
+                    compress(path)?;
+                }
+                Ok(Vec::new())
+            }
+            (Builtin::Hash | Builtin::Gzip, []) => {
+                Err(format!("{} takes at least one file", self.name()))
+            }
+            (Builtin::HttpGet, [url] | [url, _]) => {
+                let response = http::get(&http::parse_url(url)?).map_err(|e| e.to_string())?;
+                if !(200..300).contains(&response.status) {
+                    return Err(format!("{} answered {}", url, response.status));
+                }
+                match args.get(1) {
+                    Some(path) => {
+                        fs::write(path, &response.body).map_err(|e| format!("{}: {}", path, e))?;
+                        Ok(Vec::new())
+                    }
+                    None => Ok(response.body),
+                }
+            }
+            (Builtin::HttpGet, _) => Err("http-get takes a URL and an optional file".to_string()),
+        }
+    }
+}
+
+/// Where a copy or move puts `source`: into `dest` when it is a directory, creating the
+/// parent directories of a file destination
+fn destination(source: &str, dest: &str) -> Result<PathBuf, String> {
+    let dest = Path::new(dest);
+    if dest.is_dir() || dest.as_os_str().to_string_lossy().ends_with('/') {
+        let name = Path::new(source)
+            .file_name()
+            .ok_or_else(|| format!("{} has no file name", source))?;
+        fs::create_dir_all(dest).map_err(|e| format!("{}: {}", dest.display(), e))?;
+        return Ok(dest.join(name));
+    }
+    if let Some(parent) = dest
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent).map_err(|e| format!("{}: {}", parent.display(), e))?;
+    }
+    Ok(dest.to_path_buf())
+}
+
+/// Replaces a file with its `.gz`, as `gzip` does
+fn compress(path: &str) -> Result<(), String> {
+    let target = format!("{}.gz", path);
+    if Path::new(&target).exists() {
+        return Err(format!("{} already exists", target));
+    }
+    let data = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    fs::write(&target, deflate::gzip(&data)).map_err(|e| format!("{}: {}", target, e))?;
+    fs::remove_file(path).map_err(|e| format!("{}: {}", path, e))
+}
+
+/// Splits a command into words the way `sh` would for plain words and quoting: single quotes
+/// are literal, double quotes and backslashes escape; nothing is expanded
+pub fn split_words(command: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated single quote".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err("unterminated double quote".to_string()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated double quote".to_string()),
+                    }
+                }
+            }
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    word.get_or_insert_with(String::new).push(c);
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_words() {
+        assert_eq!(
+            split_words(r#"cp 'a b' "c \"d\"" e\ f '' x"#).unwrap(),
+            ["cp", "a b", "c \"d\"", "e f", "", "x"]
+        );
+        assert!(split_words("'open").is_err());
+    }
+
+    #[test]
+    fn test_file_operations() {
+        let dir = std::env::temp_dir().join(format!("kyanite-builtin-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).display().to_string();
+        let run = |builtin: Builtin, args: &[String]| builtin.run(args);
+        fs::write(path("a"), "abc").unwrap();
+
+        run(Builtin::Copy, &[path("a"), format!("{}/", path("out"))]).unwrap();
+        assert_eq!(fs::read_to_string(path("out/a")).unwrap(), "abc");
+        run(Builtin::Move, &[path("out/a"), path("moved/b")]).unwrap();
+        assert!(!dir.join("out/a").exists());
+        assert_eq!(
+            run(Builtin::Hash, &[path("moved/b")]).unwrap(),
+            format!(
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  {}\n",
+                path("moved/b")
+            )
+            .into_bytes()
+        );
+        run(Builtin::Gzip, &[path("a")]).unwrap();
+        assert!(!dir.join("a").exists());
+        assert_eq!(
+            crate::compress::Format::detect(&fs::read(path("a.gz")).unwrap()),
+            Some(crate::compress::Format::Gzip)
+        );
+        assert_eq!(
+            run(Builtin::Copy, &[path("a")]),
+            Err("copy takes a source and a destination".to_string())
+        );
+        assert!(run(Builtin::Hash, &[path("missing")]).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,105 @@
+use crate::sha256;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Directory of successful job outputs keyed by a hash of the expanded command
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn open(dir: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Cache { dir })
+    }
+
+    /// Hashes the command together with the size and mtime of any referenced files, so entries
+    /// go stale when those files change
+    pub fn key(command: &str, files: &[PathBuf]) -> String {
+        let mut hasher = sha256::Sha256::default();
+        hasher.update(command.as_bytes());
+        for file in files {
+            hasher.update(b"\0");
+            hasher.update(fingerprint(file).as_bytes());
+        }
+        sha256::hex(&hasher.finalize())
+    }
+
+    pub fn get(&self, key: &str) -> io::Result<Option<String>> {
+        match fs::read(self.dir.join(key)) {
+            Ok(output) => Ok(Some(String::from_utf8_lossy(&output).into_owned())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Stores an output, writing to a temporary file first so readers never see partial entries
+    pub fn put(&self, key: &str, output: &str) -> io::Result<()> {
+        let partial = self
+            .dir
+            .join(format!(".{}.{}.tmp", key, std::process::id()));
+        fs::write(&partial, output)?;
+        fs::rename(&partial, self.dir.join(key))
+    }
+}
+
+fn fingerprint(path: &Path) -> String {
+    match fs::metadata(path) {
+        Ok(metadata) => {
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|since| since.as_nanos())
+                .unwrap_or_default();
+            format!("{}\t{}\t{}", path.display(), metadata.len(), modified)
+        }
+        Err(_) => format!("{}\tmissing", path.display()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_is_stable_per_command() {
+        assert_eq!(Cache::key("echo a", &[]), Cache::key("echo a", &[]));
+        assert_ne!(Cache::key("echo a", &[]), Cache::key("echo b", &[]));
+        assert_eq!(Cache::key("echo a", &[]).len(), 64);
+    }
+
+    #[test]
+    fn test_put_then_get() {
+        let dir = std::env::temp_dir().join(format!("kyanite-test-cache-{}", std::process::id()));
+        let cache = Cache::open(dir.clone()).unwrap();
+        let key = Cache::key("echo hello", &[]);
+
+        assert_eq!(cache.get(&key).unwrap(), None);
+        cache.put(&key, "hello\nworld").unwrap();
+        assert_eq!(cache.get(&key).unwrap().as_deref(), Some("hello\nworld"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_key_changes_with_referenced_file() {
+        let dir =
+            std::env::temp_dir().join(format!("kyanite-test-cachekey-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("input.txt");
+        let files = [file.clone()];
+
+        let missing = Cache::key("gzip input.txt", &files);
+        fs::write(&file, "one").unwrap();
+        let first = Cache::key("gzip input.txt", &files);
+        assert_ne!(missing, first);
+        assert_eq!(first, Cache::key("gzip input.txt", &files));
+        assert_ne!(first, Cache::key("gzip input.txt", &[]));
+
+        fs::write(&file, "three").unwrap();
+        assert_ne!(first, Cache::key("gzip input.txt", &files));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
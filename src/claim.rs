@@ -0,0 +1,70 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The subdirectories of a `--claim-dir` inbox that claimed files move through
+pub const STATES: [&str; 3] = ["inprogress", "done", "failed"];
+
+/// The names of the files waiting in the inbox, in name order; hidden files are taken to be
+/// still being written and are left alone
+pub fn pending(dir: &Path) -> io::Result<Vec<String>> {
+    for state in STATES {
+        fs::create_dir_all(dir.join(state))?;
+    }
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !name.starts_with('.') && entry.file_type()?.is_file() {
+            names.push(name);
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Claims a file by moving it into `inprogress/`, returning its new path, or none when another
+/// process claimed it first
+///
+/// A rename within one directory tree is atomic, also over NFS, so exactly one of several
+/// processes racing for a file wins it.
+pub fn claim(dir: &Path, name: &str) -> io::Result<Option<PathBuf>> {
+    let claimed = dir.join("inprogress").join(name);
+    match fs::rename(dir.join(name), &claimed) {
+        Ok(()) => Ok(Some(claimed)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Moves a claimed file on to `done/` or `failed/` once its job has finished
+pub fn finish(dir: &Path, name: &str, failed: bool) -> io::Result<PathBuf> {
+    let target = dir.join(if failed { "failed" } else { "done" }).join(name);
+    fs::rename(dir.join("inprogress").join(name), &target)?;
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_files_move_through_the_inbox() {
+        let dir = std::env::temp_dir().join(format!("kyanite-claim-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["b.csv", "a.csv", ".partial"] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+        assert_eq!(pending(&dir).unwrap(), ["a.csv", "b.csv"]);
+
+        let claimed = claim(&dir, "a.csv").unwrap().unwrap();
+        assert_eq!(fs::read_to_string(&claimed).unwrap(), "a.csv");
+        assert_eq!(claim(&dir, "a.csv").unwrap(), None);
+        assert_eq!(
+            finish(&dir, "a.csv", true).unwrap(),
+            dir.join("failed").join("a.csv")
+        );
+        assert_eq!(pending(&dir).unwrap(), ["b.csv"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
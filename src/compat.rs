@@ -0,0 +1,557 @@
+use crate::shell_quote;
+use regex::Regex;
+use std::path::Path;
+
+/// A tool whose command line `kyanite xargs` and `kyanite parallel` accept
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tool {
+    Xargs,
+    Parallel,
+}
+
+impl Tool {
+    /// The tool kyanite stands in for when its binary is installed or linked under that name
+    pub fn from_argv0(argv0: &Path) -> Option<Tool> {
+        match argv0.file_stem()?.to_str()? {
+            "xargs" => Some(Tool::Xargs),
+            "parallel" => Some(Tool::Parallel),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Tool::Xargs => "xargs",
+            Tool::Parallel => "parallel",
+        }
+    }
+
+    fn options(self) -> &'static [Opt] {
+        match self {
+            Tool::Xargs => XARGS,
+            Tool::Parallel => PARALLEL,
+        }
+    }
+}
+
+/// Whether an option takes a value, and for `Attached` that it is optional and must be written
+/// as `-iR` or `--replace=R`, as xargs has it
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Takes {
+    Nothing,
+    Value,
+    Attached,
+}
+
+struct Opt {
+    short: Option<char>,
+    /// The long name, or `-X` for an option that has none
+    long: &'static str,
+    takes: Takes,
+}
+
+const fn opt(short: Option<char>, long: &'static str, takes: Takes) -> Opt {
+    Opt { short, long, takes }
+}
+
+const XARGS: &[Opt] = &[
+    opt(Some('0'), "null", Takes::Nothing),
+    opt(Some('a'), "arg-file", Takes::Value),
+    opt(Some('d'), "delimiter", Takes::Value),
+    opt(Some('I'), "-I", Takes::Value),
+    opt(Some('i'), "replace", Takes::Attached),
+    opt(Some('L'), "max-lines", Takes::Value),
+    opt(Some('n'), "max-args", Takes::Value),
+    opt(Some('P'), "max-procs", Takes::Value),
+    opt(Some('r'), "no-run-if-empty", Takes::Nothing),
+    opt(Some('s'), "max-chars", Takes::Value),
+    opt(Some('t'), "verbose", Takes::Nothing),
+    opt(Some('x'), "exit", Takes::Nothing),
+];
+
+const PARALLEL: &[Opt] = &[
+    opt(Some('0'), "null", Takes::Nothing),
+    opt(Some('a'), "arg-file", Takes::Value),
+    opt(Some('d'), "delimiter", Takes::Value),
+    opt(Some('I'), "-I", Takes::Value),
+    opt(Some('j'), "jobs", Takes::Value),
+    opt(Some('P'), "max-procs", Takes::Value),
+    opt(Some('k'), "keep-order", Takes::Nothing),
+    opt(Some('n'), "max-args", Takes::Value),
+    opt(Some('N'), "max-replace-args", Takes::Value),
+    opt(Some('u'), "ungroup", Takes::Nothing),
+    opt(Some('v'), "verbose", Takes::Nothing),
+    opt(None, "block", Takes::Value),
+    opt(None, "colsep", Takes::Value),
+    opt(None, "dry-run", Takes::Nothing),
+    opt(None, "dryrun", Takes::Nothing),
+    opt(None, "group", Takes::Nothing),
+    opt(None, "halt", Takes::Value),
+    opt(None, "joblog", Takes::Value),
+    opt(None, "line-buffer", Takes::Nothing),
+    opt(None, "no-notice", Takes::Nothing),
+    opt(None, "pipe", Takes::Nothing),
+    opt(None, "pipe-part", Takes::Nothing),
+    opt(None, "recend", Takes::Value),
+    opt(None, "recstart", Takes::Value),
+    opt(None, "results", Takes::Value),
+    opt(None, "resume", Takes::Nothing),
+    opt(None, "resume-failed", Takes::Nothing),
+    opt(None, "retries", Takes::Value),
+    opt(None, "tag", Takes::Nothing),
+    opt(None, "tagstring", Takes::Value),
+    opt(None, "timeout", Takes::Value),
+    opt(None, "will-cite", Takes::Nothing),
+];
+
+/// Translates a command line of `tool` into the kyanite arguments that run the same jobs
+pub fn translate(tool: Tool, args: &[String]) -> Result<Vec<String>, String> {
+    let (options, operands) = parse(tool, args)?;
+    match tool {
+        Tool::Xargs => xargs(options, operands),
+        Tool::Parallel => parallel(options, operands),
+    }
+}
+
+/// The options given, each by its long name with its value or an empty one
+type Options = Vec<(&'static str, String)>;
+
+/// Splits the options, as getopt would, from the command that follows them
+fn parse(tool: Tool, args: &[String]) -> Result<(Options, &[String]), String> {
+    let unsupported = |arg: &str| {
+        format!(
+            "{} option {} is not supported by kyanite {}",
+            tool.name(),
+            arg,
+            tool.name()
+        )
+    };
+    let mut options = Vec::new();
+    let mut i = 0;
+    while let Some(arg) = args.get(i) {
+        i += 1;
+        if arg == "--" {
+            break;
+        }
+        if let Some(long) = arg.strip_prefix("--") {
+            let (name, attached) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (long, None),
+            };
+            let Some(opt) = tool.options().iter().find(|opt| opt.long == name) else {
+                return Err(unsupported(arg));
+            };
+            let value = match (opt.takes, attached) {
+                (Takes::Nothing, Some(_)) => {
+                    return Err(format!("{} option --{} takes no value", tool.name(), name));
+                }
+                (Takes::Value, None) => {
+                    i += 1;
+                    value_of(tool, arg, args.get(i - 1))?
+                }
+                (_, value) => value.unwrap_or_default(),
+            };
+            options.push((opt.long, value));
+        } else if let Some(cluster) = arg.strip_prefix('-').filter(|cluster| !cluster.is_empty()) {
+            for (at, short) in cluster.char_indices() {
+                let Some(opt) = tool.options().iter().find(|opt| opt.short == Some(short)) else {
+                    return Err(unsupported(&format!("-{}", short)));
+                };
+                let rest = &cluster[at + short.len_utf8()..];
+                match opt.takes {
+                    Takes::Nothing => {
+                        options.push((opt.long, String::new()));
+                        continue;
+                    }
+                    Takes::Value if rest.is_empty() => {
+                        i += 1;
+                        options.push((opt.long, value_of(tool, arg, args.get(i - 1))?));
+                    }
+                    _ => options.push((opt.long, rest.to_string())),
+                }
+                break;
+            }
+        } else {
+            i -= 1;
+            break;
+        }
+    }
+    Ok((options, &args[i..]))
+}
+
+fn value_of(tool: Tool, arg: &str, value: Option<&String>) -> Result<String, String> {
+    value
+        .cloned()
+        .ok_or_else(|| format!("{} option {} needs a value", tool.name(), arg))
+}
+
+/// Fails for a per-command argument count kyanite cannot honor, as it runs one input per job
+fn one_per_job(tool: Tool, name: &str, value: &str) -> Result<(), String> {
+    if value == "1" {
+        return Ok(());
+    }
+    Err(format!(
+        "{} --{} {}: kyanite runs one input per command, only 1 is supported",
+        tool.name(),
+        name,
+        value
+    ))
+}
+
+/// Splits an input line into items as xargs does without `-0` or `-d`: at unquoted blanks, or
+/// with `whole_line` (as for `-I`) only past leading ones; quotes and backslashes are removed
+/// from what they protect
+pub fn split_quoted(line: &str, whole_line: bool) -> Result<Vec<String>, String> {
+    let mut items = Vec::new();
+    let mut item: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' if item.is_none() => {}
+            ' ' | '\t' if !whole_line => items.extend(item.take()),
+            '\'' | '"' => {
+                let item = item.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some(quoted) if quoted == c => break,
+                        Some(quoted) => item.push(quoted),
+                        None => {
+                            let quote = if c == '"' { "double" } else { "single" };
+                            return Err(format!(
+                                "xargs: unmatched {} quote in input: {}",
+                                quote, line
+                            ));
+                        }
+                    }
+                }
+            }
+            '\\' => item.get_or_insert_with(String::new).extend(chars.next()),
+            c => item.get_or_insert_with(String::new).push(c),
+        }
+    }
+    items.extend(item);
+    Ok(items)
+}
+
+/// The worker count for `-P`/`-j`, where 0 (as many as possible) becomes kyanite's default
+fn jobs(tool: Tool, value: &str, kyanite: &mut Vec<String>) -> Result<(), String> {
+    match value.parse::<usize>() {
+        Ok(0) => Ok(()),
+        Ok(jobs) => {
+            kyanite.extend(["-j".to_string(), jobs.to_string()]);
+            Ok(())
+        }
+        Err(_) => Err(format!(
+            "{} jobs {}: only a plain number of jobs is supported",
+            tool.name(),
+            value
+        )),
+    }
+}
+
+fn xargs(options: Options, command: &[String]) -> Result<Vec<String>, String> {
+    let mut kyanite = Vec::new();
+    let mut replace = None;
+    let mut max_args = None;
+    let mut procs = false;
+    let mut quoted = true;
+    for (name, value) in options {
+        match name {
+            "null" => {
+                kyanite.push("-0".to_string());
+                quoted = false;
+            }
+            "arg-file" => kyanite.extend(["-a".to_string(), value]),
+            "delimiter" => {
+                kyanite.extend(["--delimiter".to_string(), value]);
+                quoted = false;
+            }
+            "-I" => replace = Some(value),
+            "replace" if value.is_empty() => replace = Some("{}".to_string()),
+            "replace" => replace = Some(value),
+            "max-lines" => {
+                return Err(format!(
+                    "xargs --max-lines {}: not supported by kyanite xargs, use -n to bound the \
+                     arguments per command",
+                    value
+                ));
+            }
+            "max-args" => match value.parse::<usize>() {
+                Ok(count) if count > 0 => max_args = Some(value),
+                _ => {
+                    return Err(format!(
+                        "xargs --max-args {}: expected a positive number",
+                        value
+                    ));
+                }
+            },
+            "max-procs" => {
+                jobs(Tool::Xargs, &value, &mut kyanite)?;
+                procs = true;
+            }
+            "max-chars" => kyanite.extend(["--max-chars".to_string(), value]),
+            "verbose" => kyanite.push("-v".to_string()),
+            // kyanite never runs a command without input, and fails an oversized one anyway
+            _ => {}
+        }
+    }
+    if replace.is_some()
+        && let Some(count) = max_args.as_ref().filter(|count| count.as_str() != "1")
+    {
+        return Err(format!(
+            "xargs -I with --max-args {}: each command takes one input in place",
+            count
+        ));
+    }
+    // xargs runs one command at a time unless told otherwise
+    if !procs {
+        kyanite.extend(["-j".to_string(), "1".to_string()]);
+    }
+    if quoted {
+        kyanite.push(match replace {
+            Some(_) => "--split-quoted=lines".to_string(),
+            None => "--split-quoted".to_string(),
+        });
+    }
+    // like xargs, as many arguments as fit go to each command, unless each input is put in place
+    if replace.is_none() {
+        kyanite.push("--xargs".to_string());
+        if let Some(count) = max_args {
+            kyanite.extend(["--max-args".to_string(), count]);
+        }
+    }
+
+    // xargs runs the command itself rather than through a shell, so every word is quoted and
+    // the input reaches it through the environment or as parameters, where no shell syntax in
+    // it can act
+    let input = "\"$KYANITE_INPUT\"";
+    let echo = ["echo".to_string()];
+    let command = if command.is_empty() { &echo } else { command };
+    let mut words: Vec<String> = command
+        .iter()
+        .map(|word| match &replace {
+            Some(replace) if !replace.is_empty() => word
+                .split(replace.as_str())
+                .map(|piece| match piece {
+                    "" => String::new(),
+                    piece => shell_quote(piece).into_owned(),
+                })
+                .collect::<Vec<_>>()
+                .join(input),
+            _ => shell_quote(word).into_owned(),
+        })
+        .collect();
+    if replace.is_none() {
+        words.push("\"$@\"".to_string());
+    }
+    let template = words.join(" ");
+
+    // the template needs no placeholder, so one is picked that nothing in it is taken for
+    let Some(placeholder) = ["{}", "[]", "<>", "@@", "%%"]
+        .into_iter()
+        .find(|placeholder| !template.contains(placeholder.chars().next().unwrap()))
+    else {
+        return Err("xargs command uses every placeholder kyanite could pick".to_string());
+    };
+    kyanite.extend(["-I".to_string(), placeholder.to_string(), template]);
+    Ok(kyanite)
+}
+
+fn parallel(options: Options, operands: &[String]) -> Result<Vec<String>, String> {
+    let mut kyanite = Vec::new();
+    let mut placeholder = None;
+    for (name, value) in options {
+        match name {
+            "null" => kyanite.push("-0".to_string()),
+            "arg-file" => kyanite.extend(["-a".to_string(), value]),
+            "delimiter" => kyanite.extend(["--delimiter".to_string(), value]),
+            "-I" => {
+                kyanite.extend(["-I".to_string(), value.clone()]);
+                placeholder = Some(value);
+            }
+            "jobs" | "max-procs" => jobs(Tool::Parallel, &value, &mut kyanite)?,
+            "keep-order" => kyanite.push("-k".to_string()),
+            "max-args" | "max-replace-args" => one_per_job(Tool::Parallel, name, &value)?,
+            "ungroup" | "line-buffer" => kyanite.push("--line-buffer".to_string()),
+            "verbose" => kyanite.push("-v".to_string()),
+            "colsep" => kyanite.extend(["--field-separator".to_string(), value]),
+            "dry-run" | "dryrun" => kyanite.push("-n".to_string()),
+            "halt" => kyanite.extend(halt(&value)?),
+            "pipe" | "pipe-part" | "resume" | "resume-failed" | "tag" => {
+                kyanite.push(format!("--{}", name))
+            }
+            "block" | "joblog" | "recend" | "recstart" | "results" | "retries" | "tagstring"
+            | "timeout" => kyanite.extend([format!("--{}", name), value]),
+            _ => {}
+        }
+    }
+
+    let split = operands
+        .iter()
+        .position(|operand| operand.starts_with(":::"))
+        .unwrap_or(operands.len());
+    let (command, sources) = operands.split_at(split);
+    let mut command = command.join(" ");
+    match &placeholder {
+        Some(placeholder) if !command.contains(placeholder.as_str()) => {
+            command = format!("{} {}", command, placeholder)
+                .trim_start()
+                .to_string();
+        }
+        Some(_) => {}
+        None => command = replacement_strings(&command),
+    }
+
+    // a `::::` file in front of the `:::` groups is what `-a` is to them
+    let mut sources = sources;
+    if let [separator, file, rest @ ..] = sources
+        && separator == "::::"
+    {
+        kyanite.extend(["-a".to_string(), file.clone()]);
+        sources = rest;
+    }
+    if let Some(separator) = sources
+        .iter()
+        .find(|operand| operand.starts_with(":::") && operand.as_str() != ":::")
+    {
+        return Err(format!(
+            "parallel {}: only `:::` groups, after a single leading `:::: file`, are supported",
+            separator
+        ));
+    }
+    kyanite.push(command);
+    kyanite.extend(sources.iter().cloned());
+    Ok(kyanite)
+}
+
+/// Rewrites GNU parallel's replacement strings as kyanite placeholders, appending `{}` when the
+/// command has none, as parallel does; `{.}`, `{/}`, `{//}` and `{/.}` are kyanite's too
+fn replacement_strings(command: &str) -> String {
+    let rewritten = command.replace("{%}", "$KYANITE_SLOT");
+    let placeholder = Regex::new(r"\{(\d+|#|//|/\.|/|\.)?\}").unwrap();
+    if command.is_empty() {
+        "{}".to_string()
+    } else if rewritten != command || placeholder.is_match(command) {
+        rewritten
+    } else {
+        format!("{} {{}}", command)
+    }
+}
+
+/// The kyanite options for a `--halt` policy: `never`, or `soon` or `now` after `fail=N` jobs
+/// failed (running jobs always finish)
+fn halt(policy: &str) -> Result<Vec<String>, String> {
+    let failures = match policy {
+        "never" | "0" => return Ok(Vec::new()),
+        "1" | "2" => Some("1"),
+        _ => policy
+            .strip_prefix("soon,fail=")
+            .or_else(|| policy.strip_prefix("now,fail="))
+            .filter(|count| count.parse::<usize>().is_ok_and(|count| count > 0)),
+    };
+    match failures {
+        Some("1") => Ok(vec!["--halt-on-error".to_string()]),
+        Some(count) => Ok(vec!["--max-failures".to_string(), count.to_string()]),
+        None => Err(format!(
+            "parallel --halt {}: only never and soon/now,fail=N are supported",
+            policy
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translated(tool: Tool, args: &str) -> Result<Vec<String>, String> {
+        let args: Vec<String> = args.split(' ').map(str::to_string).collect();
+        translate(tool, &args)
+    }
+
+    #[test]
+    fn test_translate_common_command_lines() {
+        assert_eq!(
+            translated(Tool::Xargs, "-0rP4 -n1 gzip -9").unwrap(),
+            [
+                "-0",
+                "-j",
+                "4",
+                "--xargs",
+                "--max-args",
+                "1",
+                "-I",
+                "{}",
+                "gzip -9 \"$@\""
+            ]
+        );
+        assert_eq!(
+            translated(Tool::Xargs, "rm -f").unwrap(),
+            [
+                "-j",
+                "1",
+                "--split-quoted",
+                "--xargs",
+                "-I",
+                "{}",
+                "rm -f \"$@\""
+            ]
+        );
+        assert_eq!(
+            translated(Tool::Xargs, "-I % cp % %.bak").unwrap(),
+            [
+                "-j",
+                "1",
+                "--split-quoted=lines",
+                "-I",
+                "{}",
+                "cp \"$KYANITE_INPUT\" \"$KYANITE_INPUT\".bak"
+            ]
+        );
+        assert_eq!(
+            translated(Tool::Xargs, "--max-procs=0 sh -c {}").unwrap(),
+            ["--split-quoted", "--xargs", "-I", "[]", "sh -c '{}' \"$@\""]
+        );
+        assert!(translated(Tool::Xargs, "-L 1 rm").is_err());
+        assert!(translated(Tool::Xargs, "-I {} -n 2 rm {}").is_err());
+        assert!(translated(Tool::Xargs, "-p rm").is_err());
+
+        assert_eq!(
+            translated(Tool::Parallel, "-j8 -k --halt now,fail=1 gzip").unwrap(),
+            ["-j", "8", "-k", "--halt-on-error", "gzip {}"]
+        );
+        assert_eq!(
+            translated(
+                Tool::Parallel,
+                "--joblog j.log convert {} {.}.png ::: a.jpg b.jpg"
+            )
+            .unwrap(),
+            [
+                "--joblog",
+                "j.log",
+                "convert {} {.}.png",
+                ":::",
+                "a.jpg",
+                "b.jpg"
+            ]
+        );
+        assert_eq!(
+            translated(Tool::Parallel, "echo {1}-{2} :::: in.txt ::: x").unwrap(),
+            ["-a", "in.txt", "echo {1}-{2}", ":::", "x"]
+        );
+        assert!(translated(Tool::Parallel, "echo ::: a :::+ b").is_err());
+        assert!(translated(Tool::Parallel, "--sshlogin host echo").is_err());
+    }
+
+    #[test]
+    fn test_split_quoted_like_xargs() {
+        assert_eq!(
+            split_quoted("  a 'b c' \"d\"e\\ f\tg", false).unwrap(),
+            ["a", "b c", "de f", "g"]
+        );
+        assert_eq!(split_quoted("  a 'b  c'", true).unwrap(), ["a b  c"]);
+        assert_eq!(
+            split_quoted("it's", false).unwrap_err(),
+            "xargs: unmatched single quote in input: it's"
+        );
+    }
+}
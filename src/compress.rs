@@ -0,0 +1,212 @@
+use clap::ValueEnum;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::thread;
+
+/// Compression formats handled through the `gzip`, `zstd` and `xz` programs
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    #[value(name = "gz")]
+    Gzip,
+    #[value(name = "zst")]
+    Zstd,
+    #[value(name = "xz")]
+    Xz,
+}
+
+impl Format {
+    /// Recognizes a compressed stream by its magic bytes
+    pub fn detect(header: &[u8]) -> Option<Self> {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            Some(Format::Gzip)
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Format::Zstd)
+        } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0]) {
+            Some(Format::Xz)
+        } else {
+            None
+        }
+    }
+
+    pub fn program(self) -> &'static str {
+        match self {
+            Format::Gzip => "gzip",
+            Format::Zstd => "zstd",
+            Format::Xz => "xz",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Gzip => ".gz",
+            Format::Zstd => ".zst",
+            Format::Xz => ".xz",
+        }
+    }
+
+    /// Compresses `data` in memory with the format's program
+    pub fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut child = Command::new(self.program())
+            .args(["-c", "-q"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let mut stdin = child.stdin.take().unwrap();
+        let output = thread::scope(|scope| {
+            scope.spawn(move || stdin.write_all(data));
+            child.wait_with_output()
+        })?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "{} failed with {}: {}",
+                self.program(),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            )));
+        }
+        Ok(output.stdout)
+    }
+}
+
+/// The `-a` input files read one after another, each decompressed if its contents are
+/// compressed; `-` reads stdin
+pub struct ArgFiles {
+    paths: VecDeque<PathBuf>,
+    current: Option<(PathBuf, Box<dyn Read + Send>)>,
+}
+
+impl ArgFiles {
+    pub fn new(paths: &[PathBuf]) -> Self {
+        ArgFiles {
+            paths: paths.iter().cloned().collect(),
+            current: None,
+        }
+    }
+}
+
+impl Read for ArgFiles {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let Some((path, reader)) = &mut self.current else {
+                let Some(path) = self.paths.pop_front() else {
+                    return Ok(0);
+                };
+                let reader = open(&path).map_err(|e| annotate(&path, e))?;
+                self.current = Some((path, reader));
+                continue;
+            };
+            match reader.read(buf) {
+                Ok(0) if !buf.is_empty() => self.current = None,
+                Ok(read) => return Ok(read),
+                Err(e) => return Err(annotate(path, e)),
+            }
+        }
+    }
+}
+
+fn annotate(path: &Path, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+}
+
+/// Opens an input file, decompressing it through its format's program when it is compressed
+fn open(path: &Path) -> io::Result<Box<dyn Read + Send>> {
+    let mut reader: BufReader<Box<dyn Read + Send>> = if path.as_os_str() == "-" {
+        BufReader::new(Box::new(io::stdin()))
+    } else {
+        BufReader::new(Box::new(File::open(path)?))
+    };
+    let Some(format) = Format::detect(reader.fill_buf()?) else {
+        return Ok(Box::new(reader));
+    };
+    let mut child = Command::new(format.program())
+        .args(["-d", "-c"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot run {}: {}", format.program(), e)))?;
+    let mut stdin = child.stdin.take().unwrap();
+    thread::spawn(move || io::copy(&mut reader, &mut stdin));
+    let stdout = child.stdout.take().unwrap();
+    Ok(Box::new(Decompressed {
+        child,
+        stdout,
+        program: format.program(),
+    }))
+}
+
+/// Output of a decompressing child, which turns a failed exit into a read error at the end
+struct Decompressed {
+    child: Child,
+    stdout: ChildStdout,
+    program: &'static str,
+}
+
+impl Read for Decompressed {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.stdout.read(buf)?;
+        if read == 0 && !buf.is_empty() {
+            let status = self.child.wait()?;
+            if !status.success() {
+                return Err(io::Error::other(format!(
+                    "{} failed with {}",
+                    self.program, status
+                )));
+            }
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Format::detect(&[0x1f, 0x8b, 8]), Some(Format::Gzip));
+        assert_eq!(
+            Format::detect(&[0x28, 0xb5, 0x2f, 0xfd]),
+            Some(Format::Zstd)
+        );
+        assert_eq!(Format::detect(b"\xfd7zXZ\0\0"), Some(Format::Xz));
+        assert_eq!(Format::detect(b"plain\n"), None);
+        assert_eq!(Format::detect(b""), None);
+    }
+
+    #[test]
+    fn test_arg_files_decompress_transparently() {
+        let dir = std::env::temp_dir().join(format!("kyanite-compress-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "a1\na2\n").unwrap();
+        let compressed = Format::Gzip.compress(b"b1\nb2\n").unwrap();
+        assert_eq!(Format::detect(&compressed), Some(Format::Gzip));
+        fs::write(dir.join("b.gz"), compressed).unwrap();
+        fs::write(dir.join("bad.gz"), [0x1f, 0x8b, 0, 0]).unwrap();
+
+        let mut contents = String::new();
+        ArgFiles::new(&[dir.join("a.txt"), dir.join("b.gz")])
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "a1\na2\nb1\nb2\n");
+
+        let mut contents = String::new();
+        let error = ArgFiles::new(&[dir.join("bad.gz")])
+            .read_to_string(&mut contents)
+            .unwrap_err();
+        assert!(error.to_string().contains("gzip failed"), "{}", error);
+        let error = ArgFiles::new(&[dir.join("missing")])
+            .read_to_string(&mut contents)
+            .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with(&dir.join("missing").display().to_string())
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
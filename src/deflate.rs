@@ -0,0 +1,232 @@
+/// Largest distance a match may reach back
+const WINDOW: usize = 32 * 1024;
+
+/// Longest match DEFLATE can encode
+const MAX_MATCH: usize = 258;
+
+/// Earlier positions with the same three-byte prefix tried per match
+const CHAIN: usize = 32;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Compresses `data` into a gzip member, for the `gzip` builtin
+///
+/// Matches are found with hash chains and coded with DEFLATE's fixed Huffman tables in a
+/// single block, which compresses less than `gzip -6` but needs no process.
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+    let mut bits = Bits {
+        out,
+        bit: 0,
+        len: 0,
+    };
+    bits.write(1, 1); // final block
+    bits.write(1, 2); // fixed Huffman codes
+
+    let mut chains = Chains {
+        data,
+        head: vec![usize::MAX; 1 << 15],
+        prev: vec![usize::MAX; data.len()],
+    };
+    let mut i = 0;
+    while i < data.len() {
+        let (mut best_len, mut best_distance) = (0, 0);
+        if i + 2 < data.len() {
+            let mut candidate = chains.head[chains.hash(i)];
+            let limit = MAX_MATCH.min(data.len() - i);
+            for _ in 0..CHAIN {
+                if candidate == usize::MAX || i - candidate > WINDOW {
+                    break;
+                }
+                let len = data[candidate..]
+                    .iter()
+                    .zip(&data[i..i + limit])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    (best_len, best_distance) = (len, i - candidate);
+                    if len == limit {
+                        break;
+                    }
+                }
+                candidate = chains.prev[candidate];
+            }
+        }
+        if best_len >= 3 {
+            bits.length(best_len);
+            bits.distance(best_distance);
+            for j in i..i + best_len {
+                chains.insert(j);
+            }
+            i += best_len;
+        } else {
+            bits.literal(u16::from(data[i]));
+            chains.insert(i);
+            i += 1;
+        }
+    }
+    bits.literal(256);
+    out = bits.finish();
+    out.extend(crc32(data).to_le_bytes());
+    out.extend((data.len() as u32).to_le_bytes());
+    out
+}
+
+/// Earlier positions of each three-byte prefix, newest first
+struct Chains<'a> {
+    data: &'a [u8],
+    head: Vec<usize>,
+    prev: Vec<usize>,
+}
+
+impl Chains<'_> {
+    fn hash(&self, i: usize) -> usize {
+        let key = u32::from(self.data[i]) << 16
+            | u32::from(self.data[i + 1]) << 8
+            | u32::from(self.data[i + 2]);
+        (key.wrapping_mul(2654435761) >> 17) as usize
+    }
+
+    fn insert(&mut self, i: usize) {
+        if i + 2 < self.data.len() {
+            let h = self.hash(i);
+            self.prev[i] = self.head[h];
+            self.head[h] = i;
+        }
+    }
+}
+
+/// Bit writer filling each byte from its least significant bit, as DEFLATE packs its stream
+struct Bits {
+    out: Vec<u8>,
+    bit: u32,
+    len: u32,
+}
+
+impl Bits {
+    fn write(&mut self, value: u32, count: u32) {
+        self.bit |= value << self.len;
+        self.len += count;
+        while self.len >= 8 {
+            self.out.push(self.bit as u8);
+            self.bit >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, which DEFLATE stores most significant bit first
+    fn code(&mut self, code: u32, count: u32) {
+        self.write(code.reverse_bits() >> (32 - count), count);
+    }
+
+    /// Writes a literal byte, or 256 for end of block, or a length symbol, in the fixed codes
+    fn literal(&mut self, symbol: u16) {
+        let symbol = u32::from(symbol);
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn length(&mut self, len: usize) {
+        let index = LENGTH_BASE
+            .iter()
+            .rposition(|&base| usize::from(base) <= len)
+            .unwrap();
+        self.literal(257 + index as u16);
+        let extra = len - usize::from(LENGTH_BASE[index]);
+        self.write(extra as u32, u32::from(LENGTH_EXTRA[index]));
+    }
+
+    fn distance(&mut self, distance: usize) {
+        let index = DISTANCE_BASE
+            .iter()
+            .rposition(|&base| usize::from(base) <= distance)
+            .unwrap();
+        self.code(index as u32, 5);
+        let extra = distance - usize::from(DISTANCE_BASE[index]);
+        self.write(extra as u32, u32::from(DISTANCE_EXTRA[index]));
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.out.push(self.bit as u8);
+        }
+        self.out
+    }
+}
+
+/// The CRC-32 gzip stores in its trailer
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                crc >> 1 ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    fn gunzip(data: &[u8]) -> Vec<u8> {
+        let mut child = Command::new("gzip")
+            .args(["-d", "-c"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(data).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        output.stdout
+    }
+
+    #[test]
+    fn test_gzip_round_trips_through_gzip() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        let text: Vec<u8> = (0..2000)
+            .flat_map(|i| {
+                format!("line {} of the input, {}\n", i % 37, i * 7919 % 1000).into_bytes()
+            })
+            .collect();
+        let binary: Vec<u8> = (0u32..70_000)
+            .map(|i| (i.wrapping_mul(2654435761) >> 24) as u8)
+            .collect();
+        for data in [
+            &b""[..],
+            b"a",
+            b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+            &text,
+            &binary,
+        ] {
+            assert_eq!(gunzip(&gzip(data)), data);
+        }
+        assert!(gzip(&text).len() < text.len() / 3);
+    }
+}
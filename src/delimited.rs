@@ -0,0 +1,115 @@
+use std::io::{self, BufRead};
+
+/// Splits input into records ending at `delimiter`, e.g. a NUL for `-0` or a newline by default
+///
+/// With the newline, a `\r` before it is dropped as well, like [`BufRead::lines`] does. The last
+/// record needs no delimiter after it.
+pub struct Delimited<R> {
+    reader: R,
+    delimiter: Vec<u8>,
+}
+
+impl<R: BufRead> Delimited<R> {
+    pub fn new(reader: R, delimiter: &[u8]) -> Self {
+        Delimited {
+            reader,
+            delimiter: delimiter.to_vec(),
+        }
+    }
+
+    /// Reads up to and including the next delimiter, or to the end of the input
+    fn read_record(&mut self) -> io::Result<Vec<u8>> {
+        let last = *self.delimiter.last().unwrap();
+        let mut record = Vec::new();
+        // a longer delimiter is found by reading to each of its last byte in turn
+        while self.reader.read_until(last, &mut record)? > 0 {
+            if record.ends_with(&self.delimiter) {
+                break;
+            }
+        }
+        Ok(record)
+    }
+}
+
+impl<R: BufRead> Iterator for Delimited<R> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut record = match self.read_record() {
+            Ok(record) if record.is_empty() => return None,
+            Ok(record) => record,
+            Err(e) => return Some(Err(e)),
+        };
+        if record.ends_with(&self.delimiter) {
+            record.truncate(record.len() - self.delimiter.len());
+            if self.delimiter == b"\n" && record.ends_with(b"\r") {
+                record.pop();
+            }
+        }
+        Some(String::from_utf8(record).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+    }
+}
+
+/// Parses a `--delimiter`, which may be written with the escapes `\0`, `\n`, `\t`, `\r`, `\\`
+/// and `\xHH`
+pub fn parse(s: &str) -> Result<String, String> {
+    let mut delimiter = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            delimiter.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('0') => delimiter.push('\0'),
+            Some('n') => delimiter.push('\n'),
+            Some('t') => delimiter.push('\t'),
+            Some('r') => delimiter.push('\r'),
+            Some('\\') => delimiter.push('\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) if hex.len() == 2 && byte.is_ascii() => delimiter.push(byte as char),
+                    _ => return Err(format!("invalid escape \\x{} in delimiter", hex)),
+                }
+            }
+            Some(other) => return Err(format!("invalid escape \\{} in delimiter", other)),
+            None => return Err("delimiter ends in a lone backslash".to_string()),
+        }
+    }
+    if delimiter.is_empty() {
+        return Err("delimiter must not be empty".to_string());
+    }
+    Ok(delimiter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(input: &str, delimiter: &str) -> Vec<String> {
+        Delimited::new(input.as_bytes(), delimiter.as_bytes())
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn test_records_split_at_delimiter() {
+        assert_eq!(split("a b\0c\nd\0", "\0"), ["a b", "c\nd"]);
+        assert_eq!(split("a\r\nb\n\nc", "\n"), ["a", "b", "", "c"]);
+        assert_eq!(split("a--b-c--", "--"), ["a", "b-c"]);
+        assert_eq!(split("", "\0"), Vec::<String>::new());
+        assert!(
+            Delimited::new(&b"\xff\0"[..], b"\0")
+                .next()
+                .unwrap()
+                .is_err()
+        );
+
+        assert_eq!(parse(r"\0").unwrap(), "\0");
+        assert_eq!(parse(r",\t\x1e").unwrap(), ",\t\x1e");
+        assert!(parse("").is_err());
+        assert!(parse(r"\q").is_err());
+        assert!(parse(r"\xff").is_err());
+    }
+}
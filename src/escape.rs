@@ -0,0 +1,25 @@
+/// The escaping placeholders, `{sqlq}` and the like, each quoting the input for one language
+pub const NAMES: [&str; 3] = ["sqlq", "csvq", "jsonq"];
+
+/// Quotes `value` as the escaping placeholder `name` does
+pub fn apply(name: &str, value: &str) -> Option<String> {
+    match name {
+        "sqlq" => Some(format!("'{}'", value.replace('\'', "''"))),
+        "csvq" => Some(format!("\"{}\"", value.replace('"', "\"\""))),
+        "jsonq" => Some(crate::mux::json_string(value)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quoting() {
+        assert_eq!(apply("sqlq", "O'Brien").unwrap(), "'O''Brien'");
+        assert_eq!(apply("csvq", "a,\"b\"").unwrap(), "\"a,\"\"b\"\"\"");
+        assert_eq!(apply("jsonq", "say \"hi\"\n").unwrap(), r#""say \"hi\"\n""#);
+        assert_eq!(apply("shq", "x"), None);
+    }
+}
@@ -0,0 +1,49 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// An advisory lock on a job's input file for `--flock-input`, released when dropped
+pub struct InputLock {
+    _file: File,
+}
+
+/// Takes an exclusive `flock` on the file without waiting, or none when another process holds
+/// one
+#[cfg(unix)]
+pub fn try_lock(path: &Path) -> io::Result<Option<InputLock>> {
+    use std::os::fd::AsRawFd;
+    let file = File::open(path)?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(Some(InputLock { _file: file }));
+    }
+    let e = io::Error::last_os_error();
+    if e.raw_os_error() == Some(libc::EWOULDBLOCK) {
+        return Ok(None);
+    }
+    Err(e)
+}
+
+#[cfg(not(unix))]
+pub fn try_lock(_path: &Path) -> io::Result<Option<InputLock>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "file locking is only supported on unix",
+    ))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_is_exclusive_until_dropped() {
+        let path = std::env::temp_dir().join(format!("kyanite-flock-{}", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let held = try_lock(&path).unwrap().unwrap();
+        assert!(try_lock(&path).unwrap().is_none());
+        drop(held);
+        assert!(try_lock(&path).unwrap().is_some());
+        assert!(try_lock(&path.with_extension("missing")).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+const HEADER: &str = "kyanite-state 1";
+/// How a run that cannot be frozen answers a freeze request, before its reason
+#[cfg(unix)]
+const REFUSED: &str = "refused: ";
+
+/// The pending work of a run stopped by `kyanite ctl freeze`, which `kyanite thaw` resumes
+///
+/// The run's own arguments are kept so it resumes with the same settings, and each job keeps
+/// its sequence number. A run frozen before reading all its input also keeps how many input
+/// lines it had read and the id of the next job, so the same input given again can continue it.
+#[derive(Debug, PartialEq)]
+pub struct Frozen {
+    pub args: Vec<String>,
+    pub total: Option<usize>,
+    pub read: Option<(usize, usize)>,
+    pub jobs: Vec<(usize, String)>,
+}
+
+impl Frozen {
+    /// Renders the state as lines of `arg`, `total`, `read` and `job` records after a header
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n", HEADER);
+        for arg in &self.args {
+            text.push_str(&format!("arg {}\n", escape(arg)));
+        }
+        if let Some(total) = self.total {
+            text.push_str(&format!("total {}\n", total));
+        }
+        if let Some((lines, next)) = self.read {
+            text.push_str(&format!("read {} {}\n", lines, next));
+        }
+        for (id, line) in &self.jobs {
+            text.push_str(&format!("job {} {}\n", id, escape(line)));
+        }
+        text
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return Err("not a kyanite state file".to_string());
+        }
+        let mut frozen = Frozen {
+            args: Vec::new(),
+            total: None,
+            read: None,
+            jobs: Vec::new(),
+        };
+        for (n, line) in lines.enumerate() {
+            let invalid = || format!("line {}: malformed record", n + 2);
+            match line.split_once(' ').unwrap_or((line, "")) {
+                ("arg", arg) => frozen.args.push(unescape(arg)),
+                ("total", total) => frozen.total = Some(total.parse().map_err(|_| invalid())?),
+                ("read", read) => {
+                    let (lines, next) = read.split_once(' ').ok_or_else(invalid)?;
+                    let lines = lines.parse().map_err(|_| invalid())?;
+                    frozen.read = Some((lines, next.parse().map_err(|_| invalid())?));
+                }
+                ("job", job) => {
+                    let (id, line) = job.split_once(' ').unwrap_or((job, ""));
+                    let id = id.parse().map_err(|_| invalid())?;
+                    frozen.jobs.push((id, unescape(line)));
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(frozen)
+    }
+}
+
+/// Escapes backslashes and line breaks, which `--pipe` blocks and templates may contain
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Where `kyanite ctl freeze` tells the run with process id `pid` to write its state
+pub fn request_path(pid: u32) -> PathBuf {
+    std::env::temp_dir().join(format!("kyanite-{}.freeze", pid))
+}
+
+/// Asks the run with process id `pid` to freeze into `state`, then waits for it to exit, or
+/// fails with the reason the run gives for not freezing
+#[cfg(unix)]
+pub fn request(pid: u32, state: &Path) -> io::Result<()> {
+    let request = request_path(pid);
+    std::fs::write(&request, state.to_string_lossy().as_bytes())?;
+    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGUSR1) } != 0 {
+        let e = io::Error::last_os_error();
+        let _ = std::fs::remove_file(&request);
+        return Err(e);
+    }
+    while unsafe { libc::kill(pid as libc::pid_t, 0) } == 0 {
+        if let Ok(reply) = std::fs::read_to_string(&request)
+            && let Some(reason) = reply.strip_prefix(REFUSED)
+        {
+            let _ = std::fs::remove_file(&request);
+            return Err(io::Error::other(reason.to_string()));
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    Ok(())
+}
+
+/// Answers the freeze request of this process with why it cannot be frozen
+#[cfg(unix)]
+pub fn refuse(reason: &str) -> io::Result<()> {
+    let request = request_path(std::process::id());
+    std::fs::write(request, format!("{}{}", REFUSED, reason))
+}
+
+#[cfg(not(unix))]
+pub fn request(_pid: u32, _state: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "freezing a run is only supported on unix",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_round_trips() {
+        let frozen = Frozen {
+            args: vec!["-j".to_string(), "4".to_string(), "echo '{}'\n".to_string()],
+            total: Some(10),
+            read: Some((8, 8)),
+            jobs: vec![(3, "a b".to_string()), (7, "c\\d\r\ne".to_string())],
+        };
+        let text = frozen.to_text();
+        assert_eq!(text.lines().count(), 8);
+        assert_eq!(Frozen::parse(&text), Ok(frozen));
+        assert!(Frozen::parse("arg x\n").is_err());
+        assert_eq!(
+            Frozen::parse(&format!("{}\njob x y\n", HEADER)),
+            Err("line 2: malformed record".to_string())
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use std::env;
+use std::path::{Component, Path, PathBuf};
+
+/// Static check that expanded commands only reference paths under the allowed roots
+///
+/// Arguments that look like paths (they contain a `/`, or are an option value after `=` that
+/// does) are resolved against the working directory without touching the filesystem, then
+/// rejected if they fall under a `--deny-path` root or, when `--allow-path` is given, outside
+/// every allowed root. Program names in command position are not checked.
+#[derive(Debug, Default)]
+pub struct PathGuard {
+    deny: Vec<PathBuf>,
+    allow: Vec<PathBuf>,
+    cwd: PathBuf,
+}
+
+impl PathGuard {
+    pub fn new(deny: &[PathBuf], allow: &[PathBuf]) -> Self {
+        let cwd = env::current_dir().unwrap_or_default();
+        let resolve = |paths: &[PathBuf]| {
+            paths
+                .iter()
+                .map(|path| resolve(&cwd, &path.to_string_lossy()))
+                .collect()
+        };
+        PathGuard {
+            deny: resolve(deny),
+            allow: resolve(allow),
+            cwd,
+        }
+    }
+
+    /// Returns the reason to reject a command that references a protected path
+    pub fn check(&self, command: &str) -> Result<(), String> {
+        for word in path_words(command) {
+            let path = resolve(&self.cwd, &word);
+            if self.deny.iter().any(|root| path.starts_with(root)) {
+                return Err(format!("command references denied path {}", word));
+            }
+            let allowed = self.allow.iter().any(|root| path.starts_with(root));
+            if !self.allow.is_empty() && !allowed && !STANDARD_DEVICES.contains(&word.as_str()) {
+                return Err(format!(
+                    "command references {} outside the allowed paths",
+                    word
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Devices commands commonly redirect to, allowed even outside the `--allow-path` roots
+const STANDARD_DEVICES: [&str; 4] = ["/dev/null", "/dev/stdin", "/dev/stdout", "/dev/stderr"];
+
+/// Resolves `..`, `.` and a leading `~` lexically
+fn resolve(cwd: &Path, word: &str) -> PathBuf {
+    let path = match word.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            let home = env::var_os("HOME").map(PathBuf::from).unwrap_or_default();
+            home.join(rest.trim_start_matches('/'))
+        }
+        _ => cwd.join(word),
+    };
+    let mut resolved = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::CurDir => {}
+            component => resolved.push(component),
+        }
+    }
+    resolved
+}
+
+/// Splits a command into shell words and returns those that look like paths
+fn path_words(command: &str) -> Vec<String> {
+    let mut words = Words {
+        command_position: true,
+        ..Words::default()
+    };
+    let mut quote = None;
+    let mut prev = ' ';
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') => words.push(chars.next()),
+            (Some(_), c) => words.push(Some(c)),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                words.in_word = true;
+            }
+            (None, '\\') => words.push(chars.next()),
+            (None, '&') if prev == '>' || prev == '<' => {}
+            (None, '\n' | ';' | '&' | '|' | '(' | ')' | '`') => {
+                words.finish();
+                words.command_position = true;
+            }
+            (None, c) if c.is_whitespace() => words.finish(),
+            (None, '<' | '>') => {
+                // descriptors like the 2 in 2>/dev/null are not arguments
+                if words.word.chars().all(|c| c.is_ascii_digit()) {
+                    words.word.clear();
+                    words.in_word = false;
+                } else {
+                    words.finish();
+                }
+                words.redirect = true;
+            }
+            (None, c) => words.push(Some(c)),
+        }
+        prev = c;
+    }
+    words.finish();
+    words.paths
+}
+
+#[derive(Default)]
+struct Words {
+    paths: Vec<String>,
+    word: String,
+    in_word: bool,
+    command_position: bool,
+    redirect: bool,
+}
+
+impl Words {
+    fn push(&mut self, c: Option<char>) {
+        if let Some(c) = c {
+            self.word.push(c);
+            self.in_word = true;
+        }
+    }
+
+    /// Ends the current word, keeping it if it is an argument that looks like a path
+    fn finish(&mut self) {
+        if !self.in_word {
+            return;
+        }
+        let word = std::mem::take(&mut self.word);
+        self.in_word = false;
+
+        let redirect = std::mem::take(&mut self.redirect);
+        let assignment = word
+            .split_once('=')
+            .filter(|(name, _)| !name.is_empty() && !name.contains('/'));
+        if self.command_position && !redirect && assignment.is_none() {
+            self.command_position = false;
+            return;
+        }
+        let candidate = match assignment {
+            Some((_, value)) if !redirect => value,
+            _ => word.as_str(),
+        };
+        if candidate.contains('/') {
+            self.paths.push(candidate.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_words() {
+        assert_eq!(
+            path_words("cp '/data/in 1.txt' ./out/ && /usr/bin/env X=/tmp/x sort --out=/etc/x"),
+            ["/data/in 1.txt", "./out/", "/tmp/x", "/etc/x"]
+        );
+        assert_eq!(
+            path_words("OUT=a/b convert in.png >>logs/run.log 2>/dev/null; rm -f ../x"),
+            ["a/b", "logs/run.log", "/dev/null", "../x"]
+        );
+        assert_eq!(path_words("echo hello world"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_check() {
+        let guard = PathGuard {
+            deny: vec![PathBuf::from("/etc"), PathBuf::from("/work/data/secret")],
+            allow: vec![PathBuf::from("/work/data"), PathBuf::from("/tmp")],
+            cwd: PathBuf::from("/work"),
+        };
+        assert!(guard.check("cp data/a.txt /tmp/a.txt").is_ok());
+        assert!(guard.check("/usr/bin/convert ./data/x/../y.png").is_ok());
+        assert_eq!(
+            guard.check("cat /etc/passwd").unwrap_err(),
+            "command references denied path /etc/passwd"
+        );
+        assert!(guard.check("cat data/secret/key").is_err());
+        assert_eq!(
+            guard.check("rm -rf data/../src").unwrap_err(),
+            "command references data/../src outside the allowed paths"
+        );
+        assert!(guard.check("tee > /var/log/x").is_err());
+        assert!(guard.check("cp data/a /tmp/b 2>/dev/null").is_ok());
+    }
+}
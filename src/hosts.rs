@@ -0,0 +1,319 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::SystemTime;
+
+/// Remote hosts that jobs are spread over with `--hostfile`
+///
+/// Each line of the file names an ssh destination, optionally prefixed with the most jobs it
+/// may run at once (`4/user@build1`). Hosts removed on reload finish their running jobs but
+/// get no new ones, and so do hosts taken out of rotation as unhealthy until they recover.
+pub struct Hosts {
+    path: PathBuf,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    hosts: Vec<Host>,
+    modified: Option<SystemTime>,
+}
+
+#[derive(Debug)]
+struct Host {
+    name: String,
+    max: Option<usize>,
+    running: usize,
+    active: bool,
+    healthy: bool,
+    failures: usize,
+    last_failed: bool,
+}
+
+/// A running job's claim on a host, released when dropped
+pub struct Lease<'a> {
+    hosts: &'a Hosts,
+    name: String,
+}
+
+impl Lease<'_> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Records how the host's job went, returning true if this took the host out of rotation:
+    /// after `max_failures` failed jobs in a row, unless the latest job on every other healthy
+    /// host failed too
+    pub fn record(&self, failed: bool, max_failures: usize) -> bool {
+        let mut inner = self
+            .hosts
+            .inner
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let others_ok = inner
+            .hosts
+            .iter()
+            .any(|host| host.name != self.name && host.healthy && !host.last_failed);
+        let Some(host) = inner.hosts.iter_mut().find(|host| host.name == self.name) else {
+            return false;
+        };
+        host.last_failed = failed;
+        host.failures = if failed { host.failures + 1 } else { 0 };
+        if host.healthy && max_failures > 0 && host.failures >= max_failures && others_ok {
+            host.healthy = false;
+            return true;
+        }
+        false
+    }
+}
+
+impl Drop for Lease<'_> {
+    fn drop(&mut self) {
+        let mut inner = self
+            .hosts
+            .inner
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(host) = inner.hosts.iter_mut().find(|host| host.name == self.name) {
+            host.running -= 1;
+        }
+        inner.hosts.retain(|host| host.active || host.running > 0);
+    }
+}
+
+/// Hosts that were added and removed by a reload
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Changes {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl Hosts {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let hosts = Hosts {
+            path: path.to_path_buf(),
+            inner: Mutex::new(Inner {
+                hosts: Vec::new(),
+                modified: None,
+            }),
+        };
+        hosts.reload()?;
+        if hosts
+            .inner
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .hosts
+            .is_empty()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no hosts listed",
+            ));
+        }
+        Ok(hosts)
+    }
+
+    /// Re-reads the host file if it changed since it was last read
+    pub fn reload(&self) -> io::Result<Changes> {
+        let modified = fs::metadata(&self.path)?.modified().ok();
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        if modified.is_some() && modified == inner.modified {
+            return Ok(Changes::default());
+        }
+        let listed = parse(&fs::read_to_string(&self.path)?);
+        inner.modified = modified;
+        Ok(inner.update(listed))
+    }
+
+    /// Claims the listed host with spare capacity that is running the fewest jobs
+    pub fn try_acquire(&self) -> Option<Lease<'_>> {
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        let host = inner
+            .hosts
+            .iter_mut()
+            .filter(|host| {
+                host.active && host.healthy && host.max.is_none_or(|max| host.running < max)
+            })
+            .min_by_key(|host| host.running)?;
+        host.running += 1;
+        Some(Lease {
+            hosts: self,
+            name: host.name.clone(),
+        })
+    }
+
+    /// Names of the hosts still listed in the host file
+    pub fn names(&self) -> Vec<String> {
+        let inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        inner
+            .hosts
+            .iter()
+            .filter(|host| host.active)
+            .map(|host| host.name.clone())
+            .collect()
+    }
+
+    /// Applies a health probe result, returning the host's new health if it changed
+    pub fn set_health(&self, name: &str, healthy: bool) -> Option<bool> {
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        let host = inner.hosts.iter_mut().find(|host| host.name == name)?;
+        if host.healthy == healthy {
+            return None;
+        }
+        host.healthy = healthy;
+        host.failures = 0;
+        host.last_failed = false;
+        Some(healthy)
+    }
+}
+
+impl Inner {
+    fn update(&mut self, listed: Vec<(String, Option<usize>)>) -> Changes {
+        let mut changes = Changes::default();
+        for host in &mut self.hosts {
+            if host.active && !listed.iter().any(|(name, _)| *name == host.name) {
+                host.active = false;
+                changes.removed.push(host.name.clone());
+            }
+        }
+        for (name, max) in listed {
+            match self.hosts.iter_mut().find(|host| host.name == name) {
+                Some(host) => {
+                    if !host.active {
+                        changes.added.push(name);
+                    }
+                    host.active = true;
+                    host.max = max;
+                }
+                None => {
+                    changes.added.push(name.clone());
+                    self.hosts.push(Host {
+                        name,
+                        max,
+                        running: 0,
+                        active: true,
+                        healthy: true,
+                        failures: 0,
+                        last_failed: false,
+                    });
+                }
+            }
+        }
+        self.hosts.retain(|host| host.active || host.running > 0);
+        changes
+    }
+}
+
+/// Parses host lines like `build1`, `user@build2` or `4/build3`, skipping blanks and `#` comments
+fn parse(contents: &str) -> Vec<(String, Option<usize>)> {
+    let mut hosts: Vec<(String, Option<usize>)> = Vec::new();
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (max, name) = match line.split_once('/') {
+            Some((max, name)) if max.parse::<usize>().is_ok_and(|max| max > 0) => {
+                (max.parse().ok(), name.trim())
+            }
+            _ => (None, line),
+        };
+        if !hosts.iter().any(|(listed, _)| listed == name) {
+            hosts.push((name.to_string(), max));
+        }
+    }
+    hosts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hosts(listed: &str) -> Hosts {
+        let mut inner = Inner {
+            hosts: Vec::new(),
+            modified: None,
+        };
+        inner.update(parse(listed));
+        Hosts {
+            path: PathBuf::new(),
+            inner: Mutex::new(inner),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("build1\n# spares\n\n 2/user@build2 # small\nbuild1\n"),
+            vec![
+                ("build1".to_string(), None),
+                ("user@build2".to_string(), Some(2))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_leases_spread_and_respect_limits() {
+        let hosts = hosts("a\n1/b\n");
+        let first = hosts.try_acquire().unwrap();
+        let second = hosts.try_acquire().unwrap();
+        assert_eq!([first.name(), second.name()], ["a", "b"]);
+        assert_eq!(hosts.try_acquire().unwrap().name(), "a");
+
+        drop(second);
+        assert_eq!(hosts.try_acquire().unwrap().name(), "b");
+    }
+
+    #[test]
+    fn test_removed_hosts_drain() {
+        let hosts = hosts("a\nb\n");
+        let running = hosts.try_acquire().unwrap();
+        assert_eq!(running.name(), "a");
+
+        let changes = hosts.inner.lock().unwrap().update(parse("b\nc\n"));
+        assert_eq!(changes.added, ["c"]);
+        assert_eq!(changes.removed, ["a"]);
+        for _ in 0..4 {
+            assert_ne!(hosts.try_acquire().unwrap().name(), "a");
+        }
+        assert_eq!(hosts.inner.lock().unwrap().hosts.len(), 3);
+        drop(running);
+        assert_eq!(hosts.inner.lock().unwrap().hosts.len(), 2);
+    }
+
+    #[test]
+    fn test_failing_host_leaves_rotation() {
+        let hosts = hosts("a\nb\n");
+        // a stays busy so b gets the jobs
+        let busy = hosts.try_acquire().unwrap();
+        let sick = |hosts: &Hosts| {
+            let lease = hosts.try_acquire().unwrap();
+            assert_eq!(lease.name(), "b");
+            lease.record(true, 2)
+        };
+        assert!(!sick(&hosts));
+        assert!(sick(&hosts));
+        drop(busy);
+        for _ in 0..3 {
+            assert_eq!(hosts.try_acquire().unwrap().name(), "a");
+        }
+
+        assert_eq!(hosts.set_health("b", true), Some(true));
+        assert_eq!(hosts.set_health("b", true), None);
+        assert_eq!(hosts.set_health("a", false), Some(false));
+        assert_eq!(hosts.try_acquire().unwrap().name(), "b");
+    }
+
+    #[test]
+    fn test_hosts_stay_when_every_host_fails() {
+        let hosts = hosts("a\nb\n");
+        for _ in 0..4 {
+            let first = hosts.try_acquire().unwrap();
+            let second = hosts.try_acquire().unwrap();
+            assert!(!first.record(true, 2));
+            assert!(!second.record(true, 2));
+        }
+        assert_eq!(hosts.names(), ["a", "b"]);
+        assert!(hosts.try_acquire().is_some());
+    }
+}
@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// A plain `http://host[:port][/path]` address
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Url {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+/// Parses an `http://` URL; there is no TLS client to speak `https://` with
+pub fn parse_url(s: &str) -> Result<Url, String> {
+    let Some(rest) = s.strip_prefix("http://") else {
+        return Err(format!("unsupported URL {}: only http:// is supported", s));
+    };
+    let (authority, path) = match rest.find(['/', '?']) {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .map_err(|_| format!("invalid port in URL {}", s))?,
+        ),
+        None => (authority, 80),
+    };
+    if host.is_empty() {
+        return Err(format!("URL {} has no host", s));
+    }
+    let path = match path.strip_prefix('?') {
+        Some(query) => format!("/?{}", query),
+        None => path.to_string(),
+    };
+    Ok(Url {
+        host: host.to_string(),
+        port,
+        path,
+    })
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+/// Fetches a URL over a new connection
+pub fn get(url: &Url) -> io::Result<Response> {
+    let close = [("Connection".to_string(), "close".to_string())];
+    send(&mut connect(url)?, "GET", url, &close, &[]).map(|(response, _)| response)
+}
+
+/// Keep-alive connections shared by the workers of `--http`, kept per host and port
+#[derive(Default)]
+pub struct Pool {
+    idle: Mutex<HashMap<String, Vec<BufReader<TcpStream>>>>,
+}
+
+impl Pool {
+    /// Sends a request over an idle connection to the URL's server, or a new one, and keeps
+    /// the connection for the next request when the server allows it
+    pub fn request(
+        &self,
+        method: &str,
+        url: &Url,
+        headers: &[(String, String)],
+        body: &[u8],
+    ) -> io::Result<Response> {
+        let key = format!("{}:{}", url.host, url.port);
+        let idle = self
+            .idle
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_mut(&key)
+            .and_then(Vec::pop);
+        let (response, reusable, stream) = match idle {
+            Some(mut stream) => match send(&mut stream, method, url, headers, body) {
+                Ok((response, reusable)) => (response, reusable, stream),
+                // the server may have closed the idle connection, which fails before any of
+                // the response arrives, so that request is sent again on a new one
+                Err(e) if closed(&e) => {
+                    let mut stream = connect(url)?;
+                    let (response, reusable) = send(&mut stream, method, url, headers, body)?;
+                    (response, reusable, stream)
+                }
+                Err(e) => return Err(e),
+            },
+            None => {
+                let mut stream = connect(url)?;
+                let (response, reusable) = send(&mut stream, method, url, headers, body)?;
+                (response, reusable, stream)
+            }
+        };
+        if reusable {
+            self.idle
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .entry(key)
+                .or_default()
+                .push(stream);
+        }
+        Ok(response)
+    }
+}
+
+fn closed(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+    )
+}
+
+fn connect(url: &Url) -> io::Result<BufReader<TcpStream>> {
+    let stream = TcpStream::connect((url.host.as_str(), url.port))?;
+    stream.set_read_timeout(Some(Duration::from_secs(60)))?;
+    Ok(BufReader::new(stream))
+}
+
+/// Writes a request and reads its response, which also says whether the connection can carry
+/// another request
+fn send(
+    stream: &mut BufReader<TcpStream>,
+    method: &str,
+    url: &Url,
+    headers: &[(String, String)],
+    body: &[u8],
+) -> io::Result<(Response, bool)> {
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}:{}\r\n",
+        method,
+        encode_target(&url.path),
+        url.host,
+        url.port
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if !body.is_empty() || !matches!(method, "GET" | "HEAD" | "DELETE" | "OPTIONS") {
+        request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    request.push_str("\r\n");
+    let mut request = request.into_bytes();
+    request.extend_from_slice(body);
+    stream.get_mut().write_all(&request)?;
+    read_response(stream, method == "HEAD")
+}
+
+/// Percent-encodes the bytes a request line cannot carry, such as the spaces of an input line
+/// substituted into a URL
+fn encode_target(path: &str) -> String {
+    let mut encoded = String::new();
+    for &byte in path.as_bytes() {
+        match byte {
+            b'!'..=b'~' if !b"\"<>\\^`{|}".contains(&byte) => encoded.push(char::from(byte)),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Reads a response whose body is delimited by `Content-Length`, chunked encoding or the end
+/// of the connection, and whether the connection stays open after it
+fn read_response(stream: &mut impl BufRead, head: bool) -> io::Result<(Response, bool)> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+    let mut line = String::new();
+    if stream.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed before a response",
+        ));
+    }
+    let mut reusable = line.starts_with("HTTP/1.1 ");
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| invalid("malformed HTTP status line"))?;
+    let mut length = None;
+    let mut chunked = false;
+    loop {
+        line.clear();
+        if stream.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                length = Some(
+                    value
+                        .parse()
+                        .map_err(|_| invalid("malformed Content-Length"))?,
+                );
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.eq_ignore_ascii_case("chunked");
+            } else if name.eq_ignore_ascii_case("connection") {
+                reusable = !value.eq_ignore_ascii_case("close");
+            }
+        }
+    }
+
+    let mut body = Vec::new();
+    if head || status < 200 || status == 204 || status == 304 {
+        // these responses never have a body, whatever their headers say
+    } else if chunked {
+        loop {
+            line.clear();
+            stream.read_line(&mut line)?;
+            let size = line.split(';').next().unwrap_or_default().trim();
+            let size = usize::from_str_radix(size, 16).map_err(|_| invalid("malformed chunk"))?;
+            if size == 0 {
+                // trailers end with an empty line
+                loop {
+                    line.clear();
+                    if stream.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+                        break;
+                    }
+                }
+                break;
+            }
+            let start = body.len();
+            body.resize(start + size, 0);
+            stream.read_exact(&mut body[start..])?;
+            stream.read_line(&mut line)?;
+        }
+    } else if let Some(length) = length {
+        body.resize(length, 0);
+        stream.read_exact(&mut body)?;
+    } else {
+        stream.read_to_end(&mut body)?;
+        reusable = false;
+    }
+    Ok((Response { status, body }, reusable))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_parse_url() {
+        let url = |host: &str, port, path: &str| Url {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        };
+        assert_eq!(parse_url("http://api"), Ok(url("api", 80, "/")));
+        assert_eq!(
+            parse_url("http://api:8080/v1/items?id=3"),
+            Ok(url("api", 8080, "/v1/items?id=3"))
+        );
+        assert_eq!(parse_url("http://api?q=1"), Ok(url("api", 80, "/?q=1")));
+        assert!(parse_url("https://api").is_err());
+        assert!(parse_url("http://:80/").is_err());
+        assert!(parse_url("http://api:x/").is_err());
+        assert_eq!(encode_target("/a b/ü?q=\"x\""), "/a%20b/%C3%BC?q=%22x%22");
+    }
+
+    #[test]
+    fn test_read_response_bodies() {
+        let mut sized = &b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhelloextra"[..];
+        assert_eq!(
+            read_response(&mut sized, false).unwrap(),
+            (
+                Response {
+                    status: 200,
+                    body: b"hello".to_vec()
+                },
+                true
+            )
+        );
+        assert_eq!(sized, b"extra");
+
+        let mut chunked = &b"HTTP/1.1 404 Not Found\r\nTransfer-Encoding: chunked\r\n\r\n\
+                             4\r\nmiss\r\n3;x=1\r\ning\r\n0\r\n\r\nnext"[..];
+        assert_eq!(
+            read_response(&mut chunked, false).unwrap().0,
+            Response {
+                status: 404,
+                body: b"missing".to_vec()
+            }
+        );
+        assert_eq!(chunked, b"next");
+
+        let mut until_close = &b"HTTP/1.0 200 OK\r\n\r\nall of it"[..];
+        let (response, reusable) = read_response(&mut until_close, false).unwrap();
+        assert_eq!((response.body, reusable), (b"all of it".to_vec(), false));
+        let mut closing = &b"HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 0\r\n\r\n"[..];
+        assert!(!read_response(&mut closing, false).unwrap().1);
+        let mut head = &b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\nnext"[..];
+        assert!(read_response(&mut head, true).unwrap().0.body.is_empty());
+        assert!(read_response(&mut &b"garbage\r\n\r\n"[..], false).is_err());
+        assert_eq!(
+            read_response(&mut &b""[..], false).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn test_pool_reuses_connections() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // one connection answers every request it is sent
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut stream = stream;
+            let mut requests = Vec::new();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim_end().is_empty() {
+                        break;
+                    }
+                    if let Some(value) = header.strip_prefix("Content-Length: ") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                let reply = format!("{} {}", line.trim_end(), String::from_utf8(body).unwrap());
+                write!(
+                    stream,
+                    "HTTP/1.1 201 Created\r\nContent-Length: {}\r\n\r\n{}",
+                    reply.len(),
+                    reply
+                )
+                .unwrap();
+                requests.push(line);
+            }
+            requests.len()
+        });
+
+        let pool = Pool::default();
+        let url = parse_url(&format!("http://127.0.0.1:{}/items", port)).unwrap();
+        let auth = [("Authorization".to_string(), "token".to_string())];
+        let first = pool.request("POST", &url, &auth, b"a").unwrap();
+        assert_eq!(first.status, 201);
+        assert_eq!(first.body, b"POST /items HTTP/1.1 a");
+        let second = pool.request("GET", &url, &[], b"").unwrap();
+        assert_eq!(second.body, b"GET /items HTTP/1.1 ");
+        drop(pool);
+        assert_eq!(server.join().unwrap(), 2);
+    }
+}
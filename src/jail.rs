@@ -0,0 +1,140 @@
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+
+/// Where and as whom each job runs, for `--chroot` and `--user`
+///
+/// Both are applied in the child between fork and exec, in the order chroot, group, user, so
+/// the job cannot leave the jail or regain root. `Command::uid` cannot be used because it
+/// drops root before `pre_exec` callbacks run.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Jail {
+    pub root: Option<CString>,
+    pub ids: Option<(u32, u32)>,
+}
+
+impl Jail {
+    pub fn new(root: Option<&Path>, ids: Option<(u32, u32)>) -> Self {
+        Jail {
+            root: root.map(|root| CString::new(root.as_os_str().as_bytes()).unwrap_or_default()),
+            ids,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none() && self.ids.is_none()
+    }
+
+    pub fn apply(self, command: &mut Command) {
+        if self.is_empty() {
+            return;
+        }
+        unsafe {
+            command.pre_exec(move || {
+                if let Some(root) = &self.root {
+                    check(libc::chroot(root.as_ptr()))?;
+                    check(libc::chdir(c"/".as_ptr()))?;
+                }
+                if let Some((uid, gid)) = self.ids {
+                    let groups = [gid as libc::gid_t];
+                    check(libc::setgroups(1, groups.as_ptr()))?;
+                    check(libc::setgid(gid as libc::gid_t))?;
+                    check(libc::setuid(uid as libc::uid_t))?;
+                }
+                Ok(())
+            });
+        }
+    }
+}
+
+/// Whether kyanite runs as root, which `--chroot` and `--user` require
+pub fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Resolves `USER[:GROUP]`, by name or numeric id, to a uid and gid; without a group the
+/// user's primary group is used
+pub fn parse_user(spec: &str) -> Result<(u32, u32), String> {
+    let (user, group) = match spec.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (spec, None),
+    };
+    let (uid, primary) = lookup_user(user)?;
+    let gid = match group {
+        Some(group) => lookup_group(group)?,
+        None => primary.ok_or_else(|| format!("user {} has no primary group, name one", user))?,
+    };
+    Ok((uid, gid))
+}
+
+fn lookup_user(user: &str) -> Result<(u32, Option<u32>), String> {
+    let name = CString::new(user).map_err(|_| format!("invalid user {:?}", user))?;
+    // getpwnam runs before any job is spawned, while nothing else looks up users
+    let entry = unsafe { libc::getpwnam(name.as_ptr()) };
+    if !entry.is_null() {
+        let entry = unsafe { &*entry };
+        return Ok((entry.pw_uid, Some(entry.pw_gid)));
+    }
+    match user.parse() {
+        Ok(uid) => Ok((uid, None)),
+        Err(_) => Err(format!("unknown user {}", user)),
+    }
+}
+
+fn lookup_group(group: &str) -> Result<u32, String> {
+    let name = CString::new(group).map_err(|_| format!("invalid group {:?}", group))?;
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    if !entry.is_null() {
+        return Ok(unsafe { (*entry).gr_gid });
+    }
+    group
+        .parse()
+        .map_err(|_| format!("unknown group {}", group))
+}
+
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_user() {
+        assert_eq!(parse_user("root"), Ok((0, 0)));
+        assert_eq!(parse_user("root:0"), Ok((0, 0)));
+        assert_eq!(parse_user("1234:5678"), Ok((1234, 5678)));
+        assert_eq!(
+            parse_user("1234"),
+            Err("user 1234 has no primary group, name one".to_string())
+        );
+        assert_eq!(
+            parse_user("no-such-user-kyanite"),
+            Err("unknown user no-such-user-kyanite".to_string())
+        );
+        assert_eq!(
+            parse_user("root:no-such-group-kyanite"),
+            Err("unknown group no-such-group-kyanite".to_string())
+        );
+    }
+
+    #[test]
+    fn test_chroot_and_user_reach_the_job() {
+        if !is_root() {
+            return;
+        }
+        let mut command = Command::new("/bin/sh");
+        command.arg("-c").arg("id -u; id -g; pwd");
+        Jail::new(Some(Path::new("/")), Some((65534, 65534))).apply(&mut command);
+        let output = command.output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "65534\n65534\n/\n");
+    }
+}
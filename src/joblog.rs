@@ -0,0 +1,400 @@
+use crate::audit::{escape, unescape};
+use crate::cache::Cache;
+use crate::usage::Usage;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const HEADER: &str =
+    "Seq\tStarttime\tJobRuntime\tExitval\tFingerprint\tInput\tCommand\tCpuTime\tMaxRss\tSignal";
+
+/// Tab-separated record of every finished job, appended to with `--joblog`
+///
+/// Each record carries a fingerprint of the command template, the input line and, when the
+/// line names a file, that file's size and modification time, so `kyanite diff` can tell which
+/// inputs changed since they last ran.
+///
+/// Records are committed together with the `--outfile` or `--results` files each job staged:
+/// the outputs are synced and renamed into place first, then the record is appended and synced, so a crash leaves at
+/// worst an output without its record, which the next `kyanite diff` runs again. Records wait
+/// in memory until `--commit-interval` has passed since the oldest one, to batch the syncs.
+pub struct JobLog {
+    batch: Mutex<Batch>,
+    interval: Duration,
+}
+
+struct Batch {
+    file: File,
+    records: Vec<(String, Vec<(PathBuf, PathBuf)>)>,
+    since: Instant,
+}
+
+/// A finished job as written to the job log
+pub struct Entry<'a> {
+    pub seq: usize,
+    pub start: SystemTime,
+    pub runtime: Duration,
+    pub exit: i32,
+    pub fingerprint: &'a str,
+    pub input: &'a str,
+    pub command: &'a str,
+    pub usage: Option<Usage>,
+    /// The signal that killed the command, if one did
+    pub signal: Option<i32>,
+}
+
+/// The latest record of an input in a job log
+#[derive(Debug, PartialEq, Eq)]
+pub struct Record {
+    pub fingerprint: String,
+    pub exit: i32,
+}
+
+impl JobLog {
+    /// Opens a job log for appending, dropping a record left half-written by a crash
+    pub fn open(path: &Path, interval: Duration) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        let complete = contents
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1);
+        if complete < contents.len() {
+            file.set_len(complete as u64)?;
+        }
+        if complete == 0 {
+            writeln!(file, "{}", HEADER)?;
+        }
+        Ok(JobLog {
+            batch: Mutex::new(Batch {
+                file,
+                records: Vec::new(),
+                since: Instant::now(),
+            }),
+            interval,
+        })
+    }
+
+    /// Queues a finished job with the `(temporary, target)` outputs it staged, committing the
+    /// batch once it is older than the commit interval
+    pub fn record(&self, entry: &Entry, outputs: Vec<(PathBuf, PathBuf)>) -> io::Result<()> {
+        let start = entry
+            .start
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        // the usage and signal come last, so logs written before they were recorded still read the
+        // same
+        let (cpu, max_rss) = entry
+            .usage
+            .map_or(("-".to_string(), "-".to_string()), |usage| {
+                (
+                    format!("{:.3}", usage.cpu.as_secs_f64()),
+                    usage.max_rss.to_string(),
+                )
+            });
+        let record = format!(
+            "{}\t{:.3}\t{:.3}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            entry.seq,
+            start,
+            entry.runtime.as_secs_f64(),
+            entry.exit,
+            entry.fingerprint,
+            escape(entry.input),
+            escape(entry.command),
+            cpu,
+            max_rss,
+            entry.signal.unwrap_or(0)
+        );
+        let mut batch = self.batch.lock().unwrap_or_else(PoisonError::into_inner);
+        if batch.records.is_empty() {
+            batch.since = Instant::now();
+        }
+        batch.records.push((record, outputs));
+        if batch.since.elapsed() >= self.interval {
+            batch.commit()?;
+        }
+        Ok(())
+    }
+
+    /// Commits the records still waiting for the commit interval
+    pub fn commit(&self) -> io::Result<()> {
+        self.batch
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .commit()
+    }
+}
+
+impl Batch {
+    /// Moves the staged outputs into place, then appends the records of the jobs whose outputs
+    /// all made it; a job with an output that could not be moved is left unrecorded
+    fn commit(&mut self) -> io::Result<()> {
+        let mut committed = String::new();
+        let mut lost = Vec::new();
+        let mut dirs = HashSet::new();
+        for (record, outputs) in self.records.drain(..) {
+            let mut failed = None;
+            for (temp, target) in outputs {
+                if failed.is_some() {
+                    let _ = fs::remove_file(&temp);
+                    continue;
+                }
+                let moved = File::open(&temp)
+                    .and_then(|file| file.sync_all())
+                    .and_then(|()| fs::rename(&temp, &target));
+                match moved {
+                    Ok(()) => {
+                        dirs.insert(target.parent().map(Path::to_path_buf).unwrap_or_default());
+                    }
+                    Err(e) => {
+                        let _ = fs::remove_file(&temp);
+                        failed = Some(format!("{}: {}", target.display(), e));
+                    }
+                }
+            }
+            match failed {
+                Some(output) => lost.push(output),
+                None => committed.push_str(&record),
+            }
+        }
+        // the renames must reach the disk before the records that vouch for them
+        #[cfg(unix)]
+        for dir in dirs {
+            let dir = if dir.as_os_str().is_empty() {
+                PathBuf::from(".")
+            } else {
+                dir
+            };
+            File::open(dir)?.sync_all()?;
+        }
+        #[cfg(not(unix))]
+        drop(dirs);
+        self.file.write_all(committed.as_bytes())?;
+        self.file.sync_data()?;
+        if !lost.is_empty() {
+            return Err(io::Error::other(format!(
+                "outputs not committed, their jobs are left unrecorded: {}",
+                lost.join(", ")
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Identifies what a job's result depends on: the template, the input line and the file it
+/// names, which for a `--pipe-part` range is the file the range is cut from
+pub fn fingerprint(template: &str, line: &str) -> String {
+    let file = match crate::part::parse(line) {
+        Some((path, _, _)) if Path::new(path).is_file() => path,
+        _ => line,
+    };
+    Cache::key(&format!("{}\0{}", template, line), &[PathBuf::from(file)])
+}
+
+/// Reads the latest record of each input from a job log
+pub fn read(path: &Path) -> io::Result<HashMap<String, Record>> {
+    let mut records = HashMap::new();
+    let contents = fs::read_to_string(path)?;
+    // a last line without its newline is a record a crash cut short
+    let complete = &contents[..contents.rfind('\n').map_or(0, |i| i + 1)];
+    for (number, line) in complete.lines().enumerate() {
+        if line.is_empty() || line.starts_with("Seq\t") {
+            continue;
+        }
+        let fields: Vec<&str> = line.splitn(7, '\t').collect();
+        let (Some(exit), Some(fingerprint), Some(input)) = (
+            fields.get(3).and_then(|exit| exit.parse().ok()),
+            fields.get(4),
+            fields.get(5),
+        ) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("malformed record on line {}", number + 1),
+            ));
+        };
+        records.insert(
+            unescape(input),
+            Record {
+                fingerprint: fingerprint.to_string(),
+                exit,
+            },
+        );
+    }
+    Ok(records)
+}
+
+/// How the current inputs compare with the runs recorded in a job log
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Diff {
+    pub scheduled: Vec<String>,
+    pub new: usize,
+    pub changed: usize,
+    pub failed: usize,
+    pub unchanged: usize,
+    pub removed: Vec<String>,
+}
+
+/// Schedules the inputs that never ran, changed since they ran, or failed when they last ran;
+/// `key` maps an input to the form it was recorded in, which differs with `--redact`
+pub fn diff(
+    records: &HashMap<String, Record>,
+    template: &str,
+    lines: Vec<String>,
+    key: impl Fn(&str) -> String,
+) -> Diff {
+    let mut diff = Diff::default();
+    let keys: Vec<String> = lines.iter().map(|line| key(line)).collect();
+    let current: HashSet<&String> = keys.iter().collect();
+    for (line, key) in lines.iter().zip(&keys) {
+        match records.get(key) {
+            None => diff.new += 1,
+            Some(record) if record.fingerprint != fingerprint(template, line) => diff.changed += 1,
+            Some(record) if record.exit != 0 => diff.failed += 1,
+            Some(_) => {
+                diff.unchanged += 1;
+                continue;
+            }
+        }
+        diff.scheduled.push(line.clone());
+    }
+    let mut removed: Vec<String> = records
+        .keys()
+        .filter(|input| !current.contains(input))
+        .cloned()
+        .collect();
+    removed.sort();
+    diff.removed = removed;
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_against_recorded_runs() {
+        let dir = std::env::temp_dir().join(format!("kyanite-joblog-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = |name: &str| dir.join(name).display().to_string();
+        for name in ["a", "b", "c", "d"] {
+            fs::write(file(name), name).unwrap();
+        }
+
+        let path = dir.join("jobs.log");
+        let log = JobLog::open(&path, Duration::ZERO).unwrap();
+        for (seq, (name, exit)) in [("a", 0), ("b", 0), ("c", 1), ("d", 0)].iter().enumerate() {
+            let input = file(name);
+            log.record(
+                &Entry {
+                    seq: seq + 1,
+                    start: SystemTime::now(),
+                    runtime: Duration::from_millis(20),
+                    exit: *exit,
+                    fingerprint: &fingerprint("gzip {}", &input),
+                    input: &input,
+                    command: &format!("gzip {}", input),
+                    usage: None,
+                    signal: None,
+                },
+                Vec::new(),
+            )
+            .unwrap();
+        }
+        drop(log);
+        fs::write(file("b"), "grown").unwrap();
+
+        let records = read(&path).unwrap();
+        let lines = vec![file("a"), file("b"), file("c"), file("e")];
+        let report = diff(&records, "gzip {}", lines, str::to_string);
+        assert_eq!(report.scheduled, [file("b"), file("c"), file("e")]);
+        assert_eq!(
+            (report.new, report.changed, report.failed, report.unchanged),
+            (1, 1, 1, 1)
+        );
+        assert_eq!(report.removed, [file("d")]);
+
+        let lines = vec![file("a")];
+        assert_eq!(
+            diff(&records, "gzip -9 {}", lines, str::to_string).changed,
+            1
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_rejects_malformed_records() {
+        let path = std::env::temp_dir().join(format!("kyanite-joblog-bad-{}", std::process::id()));
+        fs::write(&path, format!("{}\n1\t0.000\tx\n", HEADER)).unwrap();
+        assert_eq!(
+            read(&path).unwrap_err().to_string(),
+            "malformed record on line 2"
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_records_commit_with_their_output() {
+        let dir =
+            std::env::temp_dir().join(format!("kyanite-joblog-commit-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("jobs.log");
+        let log = JobLog::open(&path, Duration::from_secs(3600)).unwrap();
+        let entry = |seq| Entry {
+            seq,
+            start: SystemTime::now(),
+            runtime: Duration::ZERO,
+            exit: 0,
+            fingerprint: "f",
+            input: "a",
+            command: "echo a",
+            usage: Some(Usage {
+                cpu: Duration::from_millis(1500),
+                max_rss: 4096,
+            }),
+            signal: None,
+        };
+        fs::write(dir.join(".out.tmp"), "output").unwrap();
+        let staged = (dir.join(".out.tmp"), dir.join("out"));
+        log.record(&entry(1), vec![staged]).unwrap();
+        assert!(!dir.join("out").exists());
+        assert_eq!(read(&path).unwrap().len(), 0);
+
+        log.commit().unwrap();
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "output");
+        assert_eq!(read(&path).unwrap().len(), 1);
+        assert!(
+            fs::read_to_string(&path)
+                .unwrap()
+                .ends_with("\techo a\t1.500\t4096\t0\n")
+        );
+
+        let missing = (dir.join(".gone.tmp"), dir.join("gone"));
+        log.record(&entry(2), vec![missing]).unwrap();
+        assert!(log.commit().is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_torn_record_is_dropped() {
+        let path = std::env::temp_dir().join(format!("kyanite-joblog-torn-{}", std::process::id()));
+        fs::write(
+            &path,
+            format!("{}\n1\t0.000\t0.000\t0\tf\ta\techo a\n2\t0.0", HEADER),
+        )
+        .unwrap();
+        assert_eq!(read(&path).unwrap().len(), 1);
+        drop(JobLog::open(&path, Duration::ZERO).unwrap());
+        assert!(fs::read_to_string(&path).unwrap().ends_with("echo a\n"));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,226 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+/// Environment variable that hands the token pipe down to nested kyanite processes
+pub const ENV_VAR: &str = "KYANITE_JOBSERVER";
+
+/// Token pipe shared by nested kyanite processes to cap their combined concurrency
+///
+/// Like make's jobserver, every process owns one implicit token and reads a byte from the
+/// pipe for each additional job it runs at the same time, writing it back when the job ends.
+pub struct JobServer {
+    read: Mutex<File>,
+    write: File,
+    auth: String,
+    implicit: AtomicBool,
+}
+
+/// A slot in the shared job limit, returned to the jobserver when dropped
+pub struct Token<'a> {
+    server: &'a JobServer,
+    byte: Option<u8>,
+}
+
+impl Drop for Token<'_> {
+    fn drop(&mut self) {
+        match self.byte {
+            Some(byte) => {
+                let _ = (&self.server.write).write_all(&[byte]);
+            }
+            None => self.server.implicit.store(true, Ordering::SeqCst),
+        }
+    }
+}
+
+impl JobServer {
+    /// Creates a new jobserver holding `tokens` tokens besides the implicit one
+    #[cfg(unix)]
+    pub fn create(tokens: usize) -> io::Result<Self> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let server = unsafe { Self::from_fds(fds[0], fds[1]) };
+        (&server.write).write_all(&vec![b'+'; tokens])?;
+        Ok(server)
+    }
+
+    #[cfg(not(unix))]
+    pub fn create(_tokens: usize) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "jobserver is only supported on unix",
+        ))
+    }
+
+    /// Joins the jobserver inherited from a parent kyanite process, if any
+    pub fn from_env() -> Option<Self> {
+        Self::from_auth(&std::env::var(ENV_VAR).ok()?)
+    }
+
+    /// Joins the jobserver of a parent GNU make, as advertised in `MAKEFLAGS`
+    pub fn from_makeflags() -> Option<Self> {
+        Self::from_auth(makeflags_auth(&std::env::var("MAKEFLAGS").ok()?)?)
+    }
+
+    #[cfg(unix)]
+    fn from_auth(auth: &str) -> Option<Self> {
+        if let Some(path) = auth.strip_prefix("fifo:") {
+            let fifo = || OpenOptions::new().read(true).write(true).open(path).ok();
+            return Some(JobServer {
+                read: Mutex::new(fifo()?),
+                write: fifo()?,
+                auth: auth.to_string(),
+                implicit: AtomicBool::new(true),
+            });
+        }
+
+        let (read, write) = auth.split_once(',')?;
+        let (read, write) = (read.parse().ok()?, write.parse().ok()?);
+        let is_pipe = |fd| {
+            let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+            let found = unsafe { libc::fstat(fd, &mut stat) } == 0;
+            found && stat.st_mode & libc::S_IFMT == libc::S_IFIFO
+        };
+        if !is_pipe(read) || !is_pipe(write) {
+            return None;
+        }
+        Some(unsafe { Self::from_fds(read, write) })
+    }
+
+    #[cfg(not(unix))]
+    fn from_auth(_auth: &str) -> Option<Self> {
+        None
+    }
+
+    /// Safety: both descriptors must be open and owned by the returned jobserver
+    #[cfg(unix)]
+    unsafe fn from_fds(read: i32, write: i32) -> Self {
+        use std::os::unix::io::FromRawFd;
+        JobServer {
+            read: Mutex::new(unsafe { File::from_raw_fd(read) }),
+            write: unsafe { File::from_raw_fd(write) },
+            auth: format!("{},{}", read, write),
+            implicit: AtomicBool::new(true),
+        }
+    }
+
+    /// Takes a token, waiting briefly for one to become available
+    pub fn try_acquire(&self) -> io::Result<Option<Token<'_>>> {
+        if self.take_implicit() {
+            return Ok(Some(Token {
+                server: self,
+                byte: None,
+            }));
+        }
+
+        let mut read = self.read.lock().unwrap_or_else(PoisonError::into_inner);
+        if !readable(&read) {
+            return Ok(None);
+        }
+        let mut byte = [0];
+        if read.read(&mut byte)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "jobserver pipe was closed",
+            ));
+        }
+        Ok(Some(Token {
+            server: self,
+            byte: Some(byte[0]),
+        }))
+    }
+
+    fn take_implicit(&self) -> bool {
+        self.implicit
+            .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    }
+
+    /// Passes the jobserver on to a child process
+    pub fn export(&self, command: &mut Command) {
+        command.env(ENV_VAR, &self.auth);
+    }
+}
+
+/// Extracts the last `--jobserver-auth` (or older `--jobserver-fds`) value from `MAKEFLAGS`
+fn makeflags_auth(makeflags: &str) -> Option<&str> {
+    makeflags
+        .split_whitespace()
+        .filter_map(|flag| {
+            flag.strip_prefix("--jobserver-auth=")
+                .or_else(|| flag.strip_prefix("--jobserver-fds="))
+        })
+        .next_back()
+}
+
+/// Waits up to 50ms for the pipe to hold a token
+#[cfg(unix)]
+fn readable(file: &File) -> bool {
+    use std::os::unix::io::AsRawFd;
+    let mut fd = libc::pollfd {
+        fd: file.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut fd, 1, 50) > 0 }
+}
+
+#[cfg(not(unix))]
+fn readable(_file: &File) -> bool {
+    false
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_are_limited_and_returned() {
+        let server = JobServer::create(1).unwrap();
+        let implicit = server.try_acquire().unwrap().unwrap();
+        let piped = server.try_acquire().unwrap().unwrap();
+        assert_eq!(implicit.byte, None);
+        assert_eq!(piped.byte, Some(b'+'));
+        assert!(server.try_acquire().unwrap().is_none());
+
+        drop(piped);
+        let again = server.try_acquire().unwrap().unwrap();
+        assert_eq!(again.byte, Some(b'+'));
+        drop(implicit);
+        assert_eq!(server.try_acquire().unwrap().unwrap().byte, None);
+    }
+
+    #[test]
+    fn test_makeflags_auth() {
+        assert_eq!(makeflags_auth("-j4 --jobserver-auth=3,4"), Some("3,4"));
+        assert_eq!(
+            makeflags_auth(" -j --jobserver-auth=fifo:/tmp/GMfifo1 -- X=1"),
+            Some("fifo:/tmp/GMfifo1")
+        );
+        assert_eq!(
+            makeflags_auth("--jobserver-fds=5,6 -j --jobserver-fds=7,8"),
+            Some("7,8")
+        );
+        assert_eq!(makeflags_auth("-k -j4"), None);
+    }
+
+    #[test]
+    fn test_from_auth_rejects_invalid_descriptors() {
+        assert!(JobServer::from_auth("").is_none());
+        assert!(JobServer::from_auth("a,b").is_none());
+        assert!(JobServer::from_auth("100000,100001").is_none());
+        assert!(JobServer::from_auth("fifo:/nonexistent/kyanite-fifo").is_none());
+    }
+
+    #[test]
+    fn test_from_auth_requires_pipes() {
+        let file = File::open("/dev/null").unwrap();
+        use std::os::unix::io::AsRawFd;
+        let fd = file.as_raw_fd();
+        assert!(JobServer::from_auth(&format!("{},{}", fd, fd)).is_none());
+    }
+}
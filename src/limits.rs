@@ -0,0 +1,77 @@
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::time::Duration;
+
+/// Resource limits applied to each job with `setrlimit` before it executes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub cpu: Option<Duration>,
+    pub memory: Option<u64>,
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        self.cpu.is_none() && self.memory.is_none()
+    }
+
+    /// Sets the limits in the child between fork and exec, failing the spawn if the
+    /// platform refuses one of them
+    pub fn apply(self, command: &mut Command) {
+        if self.is_empty() {
+            return;
+        }
+        unsafe {
+            command.pre_exec(move || {
+                if let Some(cpu) = self.cpu {
+                    let mut limit = std::mem::zeroed();
+                    check(libc::getrlimit(libc::RLIMIT_CPU, &mut limit))?;
+                    lower(&mut limit, cpu.as_secs().max(1));
+                    check(libc::setrlimit(libc::RLIMIT_CPU, &limit))?;
+                }
+                if let Some(memory) = self.memory {
+                    let mut limit = std::mem::zeroed();
+                    check(libc::getrlimit(libc::RLIMIT_AS, &mut limit))?;
+                    lower(&mut limit, memory);
+                    check(libc::setrlimit(libc::RLIMIT_AS, &limit))?;
+                }
+                Ok(())
+            });
+        }
+    }
+}
+
+/// Lowers both the soft and hard limit, so the job cannot raise it again
+fn lower(limit: &mut libc::rlimit, value: u64) {
+    let value = value as libc::rlim_t;
+    if value < limit.rlim_max {
+        limit.rlim_max = value;
+    }
+    limit.rlim_cur = limit.rlim_max;
+}
+
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits_reach_the_job() {
+        let limits = Limits {
+            cpu: Some(Duration::from_secs(7)),
+            memory: Some(1 << 30),
+        };
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("ulimit -t; ulimit -v");
+        limits.apply(&mut command);
+        let output = command.output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "7\n1048576\n");
+    }
+}
@@ -0,0 +1,206 @@
+use regex::Regex;
+
+/// Named placeholders every template may use
+const NAMED: [&str; 3] = ["#", "total", "slotdir"];
+
+/// Named placeholders that only the `--on-success` and `--on-failure` hooks expand
+const HOOK_NAMED: [&str; 2] = ["exit", "output"];
+
+/// Returns a problem for each token of `template` that looks like a placeholder but never
+/// expands, and for field references past the `columns` the input is declared to have
+///
+/// Tokens preceded by `$` are shell parameter expansions and are skipped, as are contents that
+/// do not start like a placeholder (`{print $1}` in an awk program, `{a,b}` brace expansion).
+/// Placeholders longer than two characters have no delimiters to scan for and are not checked.
+pub fn check(template: &str, placeholder: &str, columns: Option<usize>, hook: bool) -> Vec<String> {
+    if placeholder.chars().count() > 2 {
+        return Vec::new();
+    }
+    let (open, close) = crate::placeholder_delimiters(placeholder);
+    // a shared delimiter like `@` pairs up ambiguously, so only whitespace-free tokens count
+    let contents = if open == close {
+        format!(r"[^{}\s]+", crate::regex_escape(close))
+    } else {
+        format!(
+            r"[^{}{}\n]*",
+            crate::regex_escape(open),
+            crate::regex_escape(close)
+        )
+    };
+    let token_re = Regex::new(&format!(
+        "{}({}){}",
+        crate::regex_escape(open),
+        contents,
+        crate::regex_escape(close)
+    ))
+    .unwrap();
+
+    let mut problems = Vec::new();
+    for caps in token_re.captures_iter(template) {
+        let token = caps.get(0).unwrap();
+        if template[..token.start()].ends_with('$') {
+            continue;
+        }
+        if let Some(reason) = problem(caps[1].trim(), columns, hook) {
+            problems.push(format!("{} never expands: {}", token.as_str(), reason));
+        }
+    }
+    problems
+}
+
+/// Why the contents of a placeholder token never expand, if they do not
+fn problem(contents: &str, columns: Option<usize>, hook: bool) -> Option<String> {
+    let first = contents.chars().next()?;
+    if crate::PATH_PLACEHOLDERS.contains(&contents) {
+        return None;
+    }
+    if let Some(rest) = contents.strip_prefix("s/") {
+        return substitution_problem(rest);
+    }
+    if let Some(rest) = contents.strip_prefix('/') {
+        return capture_problem(rest);
+    }
+    if first.is_ascii_digit() {
+        return field_problem(contents, columns);
+    }
+    if let Some(number) = contents.strip_prefix("line:") {
+        return match number.trim().parse::<usize>() {
+            Ok(0) => Some("record lines are numbered from 1".to_string()),
+            Ok(_) => None,
+            Err(_) => Some("a record line reference needs the form line:N".to_string()),
+        };
+    }
+    if let Some((name, field)) = contents.split_once(':')
+        && crate::escape::NAMES.contains(&name)
+    {
+        return match field.trim().parse::<usize>() {
+            Ok(_) => field_problem(field.trim(), columns),
+            Err(_) => Some(format!("{{{}:...}} needs a field number", name)),
+        };
+    }
+    if let Some(field) = contents.strip_prefix("meta:") {
+        return field
+            .is_empty()
+            .then(|| "{meta:...} needs a field name".to_string());
+    }
+    let identifier = (first.is_ascii_alphabetic() || first == '_')
+        && contents
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if NAMED.contains(&contents)
+        || crate::escape::NAMES.contains(&contents)
+        || (hook && HOOK_NAMED.contains(&contents))
+    {
+        None
+    } else if HOOK_NAMED.contains(&contents) {
+        Some("only the --on-success and --on-failure hooks expand it".to_string())
+    } else if identifier {
+        Some("unknown placeholder".to_string())
+    } else {
+        None
+    }
+}
+
+fn substitution_problem(rest: &str) -> Option<String> {
+    let parts: Vec<&str> = rest.splitn(3, '/').collect();
+    let [pattern, _, flags] = parts[..] else {
+        return Some("a substitution needs the form s/pattern/replacement/flags".to_string());
+    };
+    if pattern.is_empty() {
+        return Some("the substitution pattern is empty".to_string());
+    }
+    if let Some(flag) = flags.chars().find(|c| !"gi".contains(*c)) {
+        return Some(format!("unknown substitution flag {:?}, use g or i", flag));
+    }
+    Regex::new(pattern)
+        .err()
+        .map(|e| format!("invalid regex: {}", e))
+}
+
+fn capture_problem(rest: &str) -> Option<String> {
+    let Some((pattern, group)) = rest.rsplit_once('/') else {
+        return Some("a regex capture needs the form /regex/group".to_string());
+    };
+    let Ok(group) = group.parse::<usize>() else {
+        return Some("a regex capture needs the form /regex/group".to_string());
+    };
+    match Regex::new(pattern) {
+        Ok(re) if group >= re.captures_len() => Some(format!(
+            "the regex has {} capture groups",
+            re.captures_len() - 1
+        )),
+        Ok(_) => None,
+        Err(e) => Some(format!("invalid regex: {}", e)),
+    }
+}
+
+fn field_problem(contents: &str, columns: Option<usize>) -> Option<String> {
+    let contents = contents.strip_suffix(['!', '?']).unwrap_or(contents);
+    let number = contents.trim_end_matches(['+', '-']).trim_end();
+    let Ok(field) = number.parse::<usize>() else {
+        // `{1,2}` and `{1..5}` are shell brace expansions
+        if contents.contains(',') || contents.contains("..") {
+            return None;
+        }
+        return Some("a field reference needs the form N, N+ or N-".to_string());
+    };
+    if contents.len() - number.len() > 1 {
+        return Some("a field reference needs the form N, N+ or N-".to_string());
+    }
+    if field == 0 {
+        return Some("fields are numbered from 1".to_string());
+    }
+    match columns {
+        Some(columns) if field > columns => Some(format!(
+            "there is no field {} in input declared with --columns {}",
+            field, columns
+        )),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_placeholders_pass() {
+        let template = "convert {} {1} {2+} {3-} {2!} {3+?} {s/a/b/gi} {/(.+)\\.(.+)/2} {#} {total} \
+                        {slotdir} {meta:owner} {line:2} {sqlq} {jsonq:2} ${HOME} {a,b} {1..3} && awk '{print $1}'";
+        assert_eq!(check(template, "{}", Some(3), false), Vec::<String>::new());
+        assert_eq!(
+            check("cp {output} x-{exit}", "{}", None, true),
+            Vec::<String>::new()
+        );
+        assert!(check("cp XXX1 XXX9", "XXX", Some(1), false).is_empty());
+    }
+
+    #[test]
+    fn test_reports_typos() {
+        assert_eq!(
+            check(
+                "mv {} {s/a/b} {/x/1} {0} {4} {totl} {s/a/b/x} {exit} {line:x}",
+                "{}",
+                Some(3),
+                false
+            ),
+            [
+                "{s/a/b} never expands: a substitution needs the form s/pattern/replacement/flags",
+                "{/x/1} never expands: the regex has 0 capture groups",
+                "{0} never expands: fields are numbered from 1",
+                "{4} never expands: there is no field 4 in input declared with --columns 3",
+                "{totl} never expands: unknown placeholder",
+                "{s/a/b/x} never expands: unknown substitution flag 'x', use g or i",
+                "{exit} never expands: only the --on-success and --on-failure hooks expand it",
+                "{line:x} never expands: a record line reference needs the form line:N",
+            ]
+        );
+        assert_eq!(
+            check("cp @ @slotdir@/@1@ @2@ @s/a@", "@@", Some(1), false),
+            [
+                "@2@ never expands: there is no field 2 in input declared with --columns 1",
+                "@s/a@ never expands: a substitution needs the form s/pattern/replacement/flags",
+            ]
+        );
+    }
+}
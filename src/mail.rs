@@ -0,0 +1,183 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// The report `--mail-to` sends when a run completes
+pub struct Report {
+    pub subject: String,
+    pub body: String,
+    /// The inputs of the failed jobs, one per line, attached as `failed-inputs.txt`
+    pub failed_inputs: Option<String>,
+}
+
+/// Formats the report as a mail message, as plain text or with the failed inputs attached
+pub fn message(from: &str, to: &[String], report: &Report, boundary: &str) -> String {
+    let mut message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nMIME-Version: 1.0\r\n",
+        from,
+        to.join(", "),
+        report.subject
+    );
+    let text = "Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n";
+    match &report.failed_inputs {
+        None => {
+            message.push_str(text);
+            message.push_str("\r\n");
+            message.push_str(&crlf(&report.body));
+        }
+        Some(inputs) => {
+            message.push_str(&format!(
+                "Content-Type: multipart/mixed; boundary=\"{}\"\r\n\r\n",
+                boundary
+            ));
+            message.push_str(&format!("--{}\r\n{}\r\n", boundary, text));
+            message.push_str(&crlf(&report.body));
+            message.push_str(&format!(
+                "--{}\r\n{}Content-Disposition: attachment; filename=\"failed-inputs.txt\"\r\n\r\n",
+                boundary, text
+            ));
+            message.push_str(&crlf(inputs));
+            message.push_str(&format!("--{}--\r\n", boundary));
+        }
+    }
+    message
+}
+
+/// Text with every line ended by CRLF, as mail requires
+fn crlf(text: &str) -> String {
+    text.lines().map(|line| format!("{}\r\n", line)).collect()
+}
+
+/// Hands the message to the local `sendmail`, which takes the recipients from its headers
+pub fn sendmail(message: &str) -> io::Result<()> {
+    let mut child = Command::new("sendmail")
+        .args(["-t", "-oi"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to run sendmail: {}", e)))?;
+    let written = child.stdin.take().unwrap().write_all(message.as_bytes());
+    let status = child.wait()?;
+    written?;
+    if !status.success() {
+        return Err(io::Error::other(format!("sendmail failed: {}", status)));
+    }
+    Ok(())
+}
+
+/// Delivers the message to an SMTP relay at `HOST[:PORT]` (port 25 by default), without
+/// authentication or TLS
+pub fn smtp(server: &str, from: &str, to: &[String], message: &str) -> io::Result<()> {
+    let address = if server.contains(':') {
+        server.to_string()
+    } else {
+        format!("{}:25", server)
+    };
+    let stream = TcpStream::connect(&address)?;
+    stream.set_read_timeout(Some(Duration::from_secs(60)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    let mut exchange = |command: Option<String>, expected: u32| -> io::Result<()> {
+        if let Some(command) = &command {
+            writer.write_all(command.as_bytes())?;
+            writer.write_all(b"\r\n")?;
+        }
+        let reply = read_reply(&mut reader)?;
+        if !reply.starts_with(&expected.to_string()) {
+            return Err(io::Error::other(format!(
+                "smtp server {} replied: {}",
+                address,
+                reply.trim_end()
+            )));
+        }
+        Ok(())
+    };
+    exchange(None, 220)?;
+    exchange(Some(format!("HELO {}", hostname())), 250)?;
+    exchange(Some(format!("MAIL FROM:<{}>", from)), 250)?;
+    for recipient in to {
+        exchange(Some(format!("RCPT TO:<{}>", recipient)), 250)?;
+    }
+    exchange(Some("DATA".to_string()), 354)?;
+    exchange(Some(format!("{}.", dot_stuff(message))), 250)?;
+    exchange(Some("QUIT".to_string()), 221)
+}
+
+/// Reads a possibly multi-line SMTP reply, whose last line has a space after the code
+fn read_reply(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut reply = String::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "smtp server closed the connection",
+            ));
+        }
+        reply.push_str(&line);
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(reply);
+        }
+    }
+}
+
+/// Doubles the dot starting any line, so no line of the message ends the DATA early
+fn dot_stuff(message: &str) -> String {
+    let mut stuffed = String::new();
+    for line in message.split_inclusive("\r\n") {
+        if line.starts_with('.') {
+            stuffed.push('.');
+        }
+        stuffed.push_str(line);
+    }
+    stuffed
+}
+
+/// The sender used when no `--mail-from` is given
+pub fn default_from() -> String {
+    format!("kyanite@{}", hostname())
+}
+
+#[cfg(unix)]
+pub fn hostname() -> String {
+    let mut name = [0u8; 256];
+    if unsafe { libc::gethostname(name.as_mut_ptr().cast(), name.len()) } != 0 {
+        return "localhost".to_string();
+    }
+    let len = name
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(name.len());
+    String::from_utf8_lossy(&name[..len]).into_owned()
+}
+
+#[cfg(not(unix))]
+pub fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "localhost".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_attaches_failed_inputs() {
+        let mut report = Report {
+            subject: "kyanite: 1 of 2 jobs failed".to_string(),
+            body: "failed: 1\n.hidden\n".to_string(),
+            failed_inputs: None,
+        };
+        let to = ["ops@example.com".to_string(), "me@example.com".to_string()];
+        let plain = message("kyanite@host", &to, &report, "b");
+        assert!(plain.starts_with("From: kyanite@host\r\nTo: ops@example.com, me@example.com\r\n"));
+        assert!(plain.ends_with(
+            "charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\nfailed: 1\r\n.hidden\r\n"
+        ));
+
+        report.failed_inputs = Some("b.txt\n".to_string());
+        let attached = message("kyanite@host", &to, &report, "b");
+        assert!(attached.contains("Content-Type: multipart/mixed; boundary=\"b\"\r\n\r\n--b\r\n"));
+        assert!(attached.ends_with("filename=\"failed-inputs.txt\"\r\n\r\nb.txt\r\n--b--\r\n"));
+        assert!(dot_stuff(&attached).contains("\r\n..hidden\r\n"));
+    }
+}
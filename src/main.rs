@@ -1,910 +1,9860 @@
-use clap::Parser;
+mod audit;
+mod builtin;
+mod cache;
+mod claim;
+mod compat;
+mod compress;
+mod deflate;
+mod delimited;
+mod escape;
+mod flock;
+mod freeze;
+mod guard;
+mod hosts;
+mod http;
+#[cfg(unix)]
+mod jail;
+mod joblog;
+mod jobserver;
+#[cfg(unix)]
+mod limits;
+mod lint;
+mod mail;
+mod manifest;
+mod memory;
+mod meta;
+mod mux;
+mod otel;
+mod part;
+#[cfg(target_os = "linux")]
+mod power;
+#[cfg(unix)]
+mod pty;
+#[cfg(target_os = "linux")]
+mod reserve;
+mod review;
+mod sha256;
+mod split;
+mod status;
+mod syslog;
+mod timing;
+mod transaction;
+mod transfer;
+mod tune;
+mod usage;
+mod ws;
+
+use audit::AuditLog;
+use builtin::Builtin;
+use cache::Cache;
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use freeze::Frozen;
+use guard::PathGuard;
+use hosts::Hosts;
+use joblog::JobLog;
+use jobserver::JobServer;
+use manifest::Manifest;
+use meta::Meta;
+use otel::Tracer;
 use regex::Regex;
-use std::collections::BTreeMap;
-use std::io::{self, BufRead, BufReader};
-use std::process::Command;
-use std::sync::Arc;
-use std::sync::mpsc;
+use review::FailedJob;
+use split::Splitter;
+use status::StatusFifo;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
 use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use syslog::Syslog;
 use tokio::signal;
+use transaction::Transactions;
+use transfer::Transfers;
+use usage::Usage;
+use ws::EventStream;
 
 #[derive(Parser)]
 #[command(name = "kyanite")]
 #[command(about = "execute commands in parallel for each input line")]
+#[command(group(ArgGroup::new("auto_target").args(["target_latency", "target_load"]).multiple(true)))]
+#[command(group(ArgGroup::new("template").args(["command", "command_file", "http"]).required(true)))]
+#[command(group(ArgGroup::new("saved_outputs").args(["outfile", "results"])))]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Config {
+    #[command(subcommand)]
+    action: Option<Action>,
+
     #[arg(short = 'j', long = "jobs", default_value_t = num_cpus::get())]
     workers: usize,
 
     #[arg(short = 'k', long = "keep-order")]
     keep_order: bool,
 
+    #[arg(long = "order-by", value_enum, conflicts_with = "keep_order")]
+    order_by: Option<OrderBy>,
+
     #[arg(short = 'n', long = "dry-run")]
     dry_run: bool,
 
     #[arg(short = 'v', long = "verbose")]
     verbose: bool,
 
+    #[arg(long = "estimate", value_parser = parse_estimate, requires = "dry_run")]
+    estimate: Option<Estimate>,
+
     #[arg(long = "max-jobs", default_value_t = 0)]
     max_jobs: usize,
 
+    #[arg(long = "jitter", value_parser = parse_jitter)]
+    jitter: Option<Jitter>,
+
+    #[arg(long = "sample")]
+    sample: Option<usize>,
+
+    #[arg(long = "sample-random", requires = "sample")]
+    sample_random: bool,
+
+    #[arg(long = "seed")]
+    seed: Option<u64>,
+
+    #[arg(long = "start-seq", default_value_t = 1)]
+    start_seq: usize,
+
+    #[arg(long = "mux")]
+    mux: bool,
+
+    #[arg(long = "status-fifo")]
+    status_fifo: Option<PathBuf>,
+
+    #[arg(long = "self-mem-limit", value_parser = parse_size)]
+    self_mem_limit: Option<u64>,
+
+    #[arg(long = "time-report")]
+    time_report: bool,
+
+    #[arg(long = "bar")]
+    bar: bool,
+
+    #[arg(long = "eta")]
+    eta: bool,
+
+    #[arg(long = "status-interval", value_parser = parse_duration, default_value = "1s", requires = "status_fifo")]
+    status_interval: Duration,
+
+    #[arg(long = "outfile", conflicts_with = "mux")]
+    outfile: Option<String>,
+
+    #[arg(
+        long = "results",
+        conflicts_with_all = ["outfile", "mux", "only_errors", "split_output", "line_buffer", "until"]
+    )]
+    results: Option<PathBuf>,
+
+    #[arg(long = "split-output", value_parser = parse_count, requires = "outfile_prefix", conflicts_with_all = ["mux", "only_errors", "outfile"])]
+    split_output: Option<usize>,
+
+    #[arg(long = "outfile-prefix", requires = "split_output")]
+    outfile_prefix: Option<String>,
+
+    #[arg(long = "split-key", requires = "split_output")]
+    split_key: Option<String>,
+
+    #[arg(long = "compress-results", value_enum, requires = "outfile")]
+    compress_results: Option<compress::Format>,
+
+    #[arg(long = "partial-suffix", requires = "outfile")]
+    partial_suffix: Option<String>,
+
+    #[arg(long = "manifest", requires = "saved_outputs")]
+    manifest: Option<PathBuf>,
+
+    #[arg(long = "outfile-collision", value_enum, default_value_t = OutfileCollision::Fail, requires = "outfile")]
+    outfile_collision: OutfileCollision,
+
+    #[arg(long = "only-errors", conflicts_with_all = ["mux", "keep_order", "order_by", "dry_run"])]
+    only_errors: bool,
+
+    #[arg(
+        long = "line-buffer",
+        conflicts_with_all = ["keep_order", "order_by", "mux", "only_errors", "pty", "split_output", "race"]
+    )]
+    line_buffer: bool,
+
+    #[arg(long = "tag", conflicts_with_all = ["mux", "only_errors"])]
+    tag: bool,
+
+    #[arg(long = "tagstring", conflicts_with_all = ["mux", "only_errors", "tag"])]
+    tagstring: Option<String>,
+
+    #[arg(long = "pty")]
+    pty: bool,
+
+    #[arg(long = "review")]
+    review: bool,
+
     #[arg(short = 'I', long = "input", default_value = "{}")]
     placeholder: String,
 
     #[arg(long = "field-separator", default_value = " ")]
     field_separator: String,
 
-    command: String,
-}
+    #[arg(long = "max-runtime", value_parser = parse_duration)]
+    max_runtime: Option<Duration>,
 
-#[derive(Debug)]
-struct Job {
-    id: usize,
-    line: String,
-}
+    #[arg(long = "timeout", value_parser = parse_duration)]
+    timeout: Option<Duration>,
 
-#[derive(Debug)]
-struct JobResult {
-    id: usize,
-    output: String,
-    error: Option<String>,
-}
+    #[arg(long = "ramp-up", value_parser = parse_duration)]
+    ramp_up: Option<Duration>,
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let config = Config::parse();
+    #[arg(long = "timeout-grace", value_parser = parse_duration, default_value = "5s", requires = "timeout")]
+    timeout_grace: Duration,
 
-    let config_with_placeholder = Config {
-        placeholder: config.placeholder.clone(),
-        ..config
-    };
-    let config = Arc::new(config_with_placeholder);
-    let (job_tx, job_rx) = mpsc::channel::<Job>();
-    let (result_tx, result_rx) = mpsc::channel::<JobResult>();
+    #[arg(long = "halt-on-budget", value_enum, default_value_t = BudgetHalt::Wait)]
+    halt_on_budget: BudgetHalt,
 
-    let job_rx = Arc::new(std::sync::Mutex::new(job_rx));
-    let mut handles = Vec::new();
+    #[arg(long = "remaining-input")]
+    remaining_input: Option<String>,
 
-    for worker_id in 0..config.workers {
-        let job_rx = Arc::clone(&job_rx);
-        let result_tx = result_tx.clone();
-        let config = Arc::clone(&config);
+    #[arg(long = "until", value_parser = Regex::new)]
+    until: Option<Regex>,
 
-        let handle = thread::spawn(move || {
-            worker(worker_id, job_rx, result_tx, config);
-        });
-        handles.push(handle);
-    }
+    #[arg(long = "until-success")]
+    until_success: bool,
 
-    let config_clone = Arc::clone(&config);
-    let collector_handle = thread::spawn(move || {
-        result_collector(result_rx, config_clone);
-    });
+    #[arg(long = "race")]
+    race: bool,
 
-    let stdin = io::stdin();
-    let reader = BufReader::new(stdin);
-    let mut job_id = 0;
-    let mut job_count = 0;
+    #[arg(short = 'a', long = "arg-file")]
+    arg_files: Vec<PathBuf>,
 
-    let ctrl_c = signal::ctrl_c();
-    let input_task = async {
-        for line in reader.lines() {
-            if config.max_jobs > 0 && job_count >= config.max_jobs {
-                break;
-            }
+    #[arg(long = "claim-dir", conflicts_with_all = ["arg_files", "pipe", "sources", "preprocess"])]
+    claim_dir: Option<PathBuf>,
 
-            match line {
-                Ok(line) if !line.trim().is_empty() => {
-                    let job = Job { id: job_id, line };
+    #[arg(short = '0', long = "null", conflicts_with_all = ["delimiter", "pipe"])]
+    null: bool,
 
-                    if config.verbose {
-                        eprintln!("queued job {}: {}", job.id, job.line);
-                    }
+    #[arg(long = "delimiter", value_parser = delimited::parse, conflicts_with = "pipe")]
+    delimiter: Option<String>,
 
-                    if job_tx.send(job).is_err() {
-                        break;
-                    }
+    #[arg(
+        long = "split-quoted",
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "blanks",
+        conflicts_with_all = ["null", "delimiter", "pipe"]
+    )]
+    split_quoted: Option<QuotedSplit>,
 
-                    job_id += 1;
-                    job_count += 1;
-                }
-                Ok(_) => continue,
-                Err(e) => {
-                    eprintln!("error reading input: {}", e);
-                    std::process::exit(1);
-                }
-            }
-        }
-    };
+    #[arg(long = "pipe", conflicts_with_all = ["pty", "script", "record_regex", "preprocess"])]
+    pipe: bool,
 
-    tokio::select! {
-        _ = input_task => {
-            if config.verbose {
-                eprintln!("input finished, processed {} jobs", job_count);
-            }
-        }
-        _ = ctrl_c => {
-            if config.verbose {
-                eprintln!("\nreceived interrupt signal, shutting down gracefully...");
-            }
-        }
-    }
+    #[arg(long = "builtin", value_enum, conflicts_with_all = ["pty", "pipe", "script", "hostfile", "sandbox_profile", "chroot", "user"])]
+    builtin: Option<Builtin>,
 
-    drop(job_tx);
+    #[arg(long = "http", conflicts_with_all = ["builtin", "pty", "pipe", "script", "hostfile", "sandbox_profile", "chroot", "user"])]
+    http: Option<String>,
 
-    for handle in handles {
-        let _ = handle.join();
-    }
+    #[arg(long = "http-header", requires = "http")]
+    http_headers: Vec<String>,
 
-    drop(result_tx);
-    let _ = collector_handle.join();
+    #[arg(long = "http-body", requires = "http")]
+    http_body: Option<String>,
 
-    Ok(())
-}
+    #[arg(long = "block", value_parser = parse_size, default_value = "1M", requires = "pipe")]
+    block: u64,
 
-fn worker(
-    worker_id: usize,
-    job_rx: Arc<std::sync::Mutex<mpsc::Receiver<Job>>>,
-    result_tx: mpsc::Sender<JobResult>,
-    config: Arc<Config>,
-) {
-    loop {
-        let job = {
-            let rx = job_rx.lock().unwrap();
-            match rx.recv() {
-                Ok(job) => job,
-                Err(_) => break,
-            }
-        };
+    #[arg(long = "recend", value_parser = regex::bytes::Regex::new, default_value = r"\n", requires = "pipe")]
+    recend: regex::bytes::Regex,
 
-        if config.verbose {
-            eprintln!("worker {} processing job {}", worker_id, job.id);
-        }
+    #[arg(long = "recstart", value_parser = regex::bytes::Regex::new, requires = "pipe")]
+    recstart: Option<regex::bytes::Regex>,
 
-        let cmd_str = expand_template(
-            &config.command,
-            &job.line,
-            &config.field_separator,
-            &config.placeholder,
-        );
+    #[arg(long = "group-by", value_name = "TEMPLATE", requires = "pipe", conflicts_with_all = ["block", "recend", "recstart"])]
+    group_by: Option<String>,
 
-        let result = if config.dry_run {
-            JobResult {
-                id: job.id,
-                output: format!("[+] {}", cmd_str),
-                error: None,
-            }
-        } else {
-            match Command::new("sh").arg("-c").arg(&cmd_str).output() {
-                Ok(output) => {
-                    let stdout = String::from_utf8_lossy(&output.stdout);
-                    let stderr = String::from_utf8_lossy(&output.stderr);
-                    let combined = if stderr.is_empty() {
-                        stdout.trim_end().to_string()
-                    } else if stdout.is_empty() {
-                        stderr.trim_end().to_string()
-                    } else {
-                        format!("{}{}", stdout.trim_end(), stderr.trim_end())
-                    };
+    #[arg(long = "pipe-part", requires = "pipe", conflicts_with_all = ["recstart", "group_by"])]
+    pipe_part: bool,
 
-                    JobResult {
-                        id: job.id,
-                        output: combined,
-                        error: if output.status.success() {
-                            None
-                        } else {
-                            Some(format!("command failed with exit code: {}", output.status))
-                        },
-                    }
-                }
-                Err(e) => JobResult {
-                    id: job.id,
-                    output: String::new(),
-                    error: Some(format!("failed to execute command: {}", e)),
-                },
-            }
-        };
+    #[arg(long = "max-lines-per-sec", value_parser = parse_rate, requires = "pipe")]
+    max_lines_per_sec: Option<f64>,
 
-        if result_tx.send(result).is_err() {
-            break;
-        }
-    }
+    #[arg(long = "record-regex", value_parser = Regex::new)]
+    record_regex: Option<Regex>,
 
-    if config.verbose {
-        eprintln!("worker {} finished", worker_id);
-    }
-}
+    #[arg(long = "backoff-from-regex", value_parser = Regex::new)]
+    backoff_from_regex: Option<Regex>,
 
-fn result_collector(result_rx: mpsc::Receiver<JobResult>, config: Arc<Config>) {
-    if config.keep_order {
-        let mut results = BTreeMap::new();
-        let mut next_id = 0;
+    #[arg(long = "backoff-retries", default_value_t = 5)]
+    backoff_retries: usize,
 
-        for result in result_rx {
-            results.insert(result.id, result);
+    #[arg(long = "retries", default_value_t = 0)]
+    retries: usize,
 
-            while let Some(result) = results.remove(&next_id) {
-                print_result(&result, &config);
-                next_id += 1;
-            }
-        }
+    #[arg(long = "retry-delay", value_parser = parse_duration, default_value = "0s", requires = "retries")]
+    retry_delay: Duration,
 
-        for (_, result) in results {
-            print_result(&result, &config);
-        }
-    } else {
-        for result in result_rx {
-            print_result(&result, &config);
-        }
-    }
-}
+    #[arg(long = "retry-backoff", requires = "retries")]
+    retry_backoff: bool,
 
-fn print_result(result: &JobResult, config: &Config) {
-    if let Some(error) = &result.error {
-        eprintln!("error in job {}: {}", result.id, error);
-        if !result.output.is_empty() {
-            eprintln!("output: {}", result.output);
-        }
-    } else if !result.output.is_empty() {
-        if config.verbose {
-            println!("[job {}] {}", result.id, result.output);
-        } else {
-            println!("{}", result.output);
-        }
-    }
-}
+    #[arg(long = "max-failures", default_value_t = 0)]
+    max_failures: usize,
 
-/// Expands a command template with an input line using custom placeholder
-///
-/// Supports:
-/// - PLACEHOLDER: Full input line
-/// - PLACEHOLDERn: nth field (space-delimited)
-/// - PLACEHOLDERn+: Fields n through end
-/// - PLACEHOLDERn-: Fields 1 through n
-/// - PLACEHOLDERs/pat/repl/g: Sed substitution (g=global, i=case-insensitive)
-/// - PLACEHOLDER/pat/n: Regex capture group n
-fn expand_template(template: &str, line: &str, field_separator: &str, placeholder: &str) -> String {
-    let (open_delim, close_delim) = if placeholder.len() >= 2 {
-        (
-            placeholder.chars().next().unwrap(),
-            placeholder.chars().nth(placeholder.len() - 1).unwrap(),
-        )
-    } else {
-        let c = placeholder.chars().next().unwrap_or('{');
-        (c, c)
-    };
+    #[arg(long = "halt-on-error")]
+    halt_on_error: bool,
 
-    let open_escaped = regex_escape(open_delim);
-    let close_escaped = regex_escape(close_delim);
+    #[arg(long = "max-consecutive-failures", default_value_t = 0)]
+    max_consecutive_failures: usize,
 
-    let mut result = template.to_string();
+    #[arg(long = "circuit-breaker", value_parser = parse_breaker)]
+    circuit_breaker: Option<BreakerSettings>,
 
-    let sed_pattern = format!(
-        r"{}\s*s/([^/]+)/([^/]*)/(.*?){}",
-        open_escaped, close_escaped
-    );
-    let sed_re = Regex::new(&sed_pattern).unwrap();
-    result = sed_re
-        .replace_all(&result, |caps: &regex::Captures| {
-            let pattern = &caps[1];
-            let replacement = &caps[2];
-            let flags = &caps[3];
+    #[arg(long = "auto-jobs", requires = "auto_target")]
+    auto_jobs: bool,
 
-            let mut regex_pattern = pattern.to_string();
-            if flags.contains('i') {
-                regex_pattern = format!("(?i){}", regex_pattern);
-            }
+    #[arg(long = "target-latency", value_parser = parse_duration, requires = "auto_jobs")]
+    target_latency: Option<Duration>,
 
-            match Regex::new(&regex_pattern) {
-                Ok(re) => {
-                    if flags.contains('g') {
-                        re.replace_all(line, replacement).to_string()
-                    } else {
-                        re.replace(line, replacement).to_string()
-                    }
-                }
-                Err(_) => caps.get(0).unwrap().as_str().to_string(),
-            }
-        })
-        .to_string();
+    #[arg(long = "target-load", requires = "auto_jobs")]
+    target_load: Option<f64>,
 
-    let field_pattern = format!(r"{}\s*(\d+)([\+\-]?)\s*{}", open_escaped, close_escaped);
-    let field_re = Regex::new(&field_pattern).unwrap();
-    result = field_re
-        .replace_all(&result, |caps: &regex::Captures| {
-            let field_num: usize = caps[1].parse().unwrap_or(0);
-            let modifier = &caps[2];
+    #[arg(long = "worker-tmpdir")]
+    worker_tmpdir: bool,
 
-            let fields: Vec<&str> = line.split(field_separator).collect();
+    #[arg(long = "keep-tmpdir-on-failure", requires = "worker_tmpdir")]
+    keep_tmpdir_on_failure: bool,
 
-            if field_num == 0 || field_num > fields.len() {
-                return String::new();
-            }
+    #[arg(long = "command-file")]
+    command_file: Option<PathBuf>,
 
-            match modifier {
-                "+" => fields[(field_num - 1)..].join(field_separator),
-                "-" => fields[0..field_num].join(field_separator),
-                _ => fields[field_num - 1].to_string(),
-            }
-        })
-        .to_string();
+    #[arg(long = "script")]
+    script: bool,
 
-    let capture_pattern = format!(r"{}\s*/([^/]+)/(\d+)\s*{}", open_escaped, close_escaped);
-    let capture_re = Regex::new(&capture_pattern).unwrap();
-    result = capture_re
-        .replace_all(&result, |caps: &regex::Captures| {
-            let pattern = &caps[1];
-            let group_num: usize = caps[2].parse().unwrap_or(0);
+    #[arg(skip)]
+    script_path: Option<PathBuf>,
 
-            match Regex::new(pattern) {
-                Ok(re) => {
-                    if let Some(captures) = re.captures(line)
-                        && let Some(group) = captures.get(group_num)
-                    {
-                        return group.as_str().to_string();
-                    }
-                    String::new()
-                }
-                Err(_) => String::new(),
-            }
-        })
-        .to_string();
+    #[arg(long = "deny-path", value_delimiter = ',')]
+    deny_path: Vec<PathBuf>,
 
-    result = result.replace(placeholder, line);
+    #[arg(long = "allow-path", value_delimiter = ',')]
+    allow_path: Vec<PathBuf>,
 
-    result
-}
+    #[arg(skip)]
+    path_guard: Option<PathGuard>,
 
-fn regex_escape(c: char) -> String {
-    match c {
-        '\\' | '.' | '+' | '*' | '?' | '(' | ')' | '[' | ']' | '{' | '}' | '|' | '^' | '$' => {
-            format!("\\{}", c)
-        }
-        _ => c.to_string(),
-    }
-}
+    #[arg(long = "confirm-threshold")]
+    confirm_threshold: Option<usize>,
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[arg(
+        long = "verify-sha256-field",
+        value_name = "N",
+        conflicts_with = "verify"
+    )]
+    verify_sha256_field: Option<usize>,
 
-    #[test]
-    fn test_expand_template_basic() {
-        let result = expand_template("echo {}", "hello world", " ", "{}");
-        assert_eq!(result, "echo hello world");
-    }
+    #[arg(long = "verify", value_name = "FILE=HASH", value_parser = parse_verify)]
+    verify: Option<(String, String)>,
 
-    #[test]
-    fn test_expand_template_field_access() {
-        let result = expand_template("echo {1} {2}", "first second third", " ", "{}");
-        assert_eq!(result, "echo first second");
-    }
+    #[arg(long = "explain", value_name = "LINE")]
+    explain: Option<String>,
 
-    #[test]
-    fn test_expand_template_field_range() {
-        let result = expand_template("echo {2+}", "first second third fourth", " ", "{}");
-        assert_eq!(result, "echo second third fourth");
-    }
+    #[arg(long = "strict-template")]
+    strict_template: bool,
 
-    #[test]
-    fn test_expand_template_sed_substitution() {
-        let result = expand_template("echo {s/old/new/g}", "old old new", " ", "{}");
-        assert_eq!(result, "echo new new new");
+    #[arg(long = "columns")]
+    columns: Option<usize>,
+
+    #[arg(long = "meta")]
+    meta: Option<PathBuf>,
+
+    #[arg(skip)]
+    meta_records: Option<Meta>,
+
+    #[arg(
+        long = "safe",
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "job"
+    )]
+    safe: Option<SafeMode>,
+
+    #[arg(
+        long = "tune",
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "report",
+        conflicts_with_all = ["workers", "block"]
+    )]
+    tune: Option<TuneMode>,
+
+    #[arg(
+        long = "syslog",
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "user"
+    )]
+    syslog: Option<syslog::Facility>,
+
+    #[arg(long = "audit")]
+    audit: Option<PathBuf>,
+
+    #[arg(long = "joblog")]
+    joblog: Option<PathBuf>,
+
+    #[arg(long = "ws-listen", value_name = "ADDR")]
+    ws_listen: Option<String>,
+
+    #[arg(long = "otel-endpoint", value_name = "URL", value_parser = otel::parse_endpoint)]
+    otel_endpoint: Option<otel::Endpoint>,
+
+    #[arg(long = "resume", requires = "joblog", conflicts_with = "resume_failed")]
+    resume: bool,
+
+    #[arg(long = "resume-failed", requires = "joblog")]
+    resume_failed: bool,
+
+    #[arg(long = "commit-interval", value_parser = parse_duration, requires = "joblog")]
+    commit_interval: Option<Duration>,
+
+    #[arg(long = "order-within-key", value_name = "TEMPLATE")]
+    order_within_key: Option<String>,
+
+    #[arg(long = "redact", value_name = "REGEX[:REPLACEMENT]", value_parser = parse_redact)]
+    redact: Vec<(Regex, String)>,
+
+    #[arg(long = "cache")]
+    cache: Option<PathBuf>,
+
+    #[arg(long = "cache-key-files", requires = "cache")]
+    cache_key_files: Vec<String>,
+
+    #[arg(long = "on-success")]
+    on_success: Option<String>,
+
+    #[arg(long = "on-failure")]
+    on_failure: Option<String>,
+
+    #[arg(long = "mail-to")]
+    mail_to: Vec<String>,
+
+    #[arg(long = "mail-on", value_enum, default_value_t = MailOn::Fail, requires = "mail_to")]
+    mail_on: MailOn,
+
+    #[arg(long = "mail-from", requires = "mail_to")]
+    mail_from: Option<String>,
+
+    #[arg(long = "mail-failed-inputs", requires = "mail_to")]
+    mail_failed_inputs: bool,
+
+    #[arg(long = "smtp", requires = "mail_to")]
+    smtp: Option<String>,
+
+    #[arg(long = "on-complete")]
+    on_complete: Option<String>,
+
+    #[arg(long = "transaction-size", value_parser = parse_count, requires = "rollback")]
+    transaction_size: Option<usize>,
+
+    #[arg(long = "rollback", requires = "transaction_size")]
+    rollback: Option<String>,
+
+    #[arg(long = "preprocess", value_parser = parse_preprocess)]
+    preprocess: Option<Preprocess>,
+
+    #[arg(long = "preprocess-failure", value_enum, default_value_t = PreprocessFailure::Fail)]
+    preprocess_failure: PreprocessFailure,
+
+    #[arg(
+        long = "flock-input",
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "wait"
+    )]
+    flock_input: Option<FlockInput>,
+
+    #[arg(long = "max-chars", value_parser = parse_count)]
+    max_chars: Option<usize>,
+
+    #[arg(long = "xargs", conflicts_with_all = ["pipe", "record_regex", "script", "builtin", "http"])]
+    xargs: bool,
+
+    #[arg(long = "max-args", value_parser = parse_count, conflicts_with_all = ["pipe", "record_regex", "script", "builtin", "http"])]
+    max_args: Option<usize>,
+
+    #[arg(long = "max-line-length", value_parser = parse_count, conflicts_with = "pipe")]
+    max_line_length: Option<usize>,
+
+    #[arg(long = "long-line", value_enum, default_value_t = LongLine::Skip, requires = "max_line_length")]
+    long_line: LongLine,
+
+    #[arg(
+        long = "hostfile",
+        conflicts_with_all = ["script", "pty", "sandbox_profile", "limit_cpu", "limit_mem", "chroot", "user"]
+    )]
+    hostfile: Option<PathBuf>,
+
+    #[arg(long = "hostfile-watch", requires = "hostfile")]
+    hostfile_watch: bool,
+
+    #[arg(long = "transfer", requires = "hostfile")]
+    transfer: bool,
+
+    #[arg(long = "transfer-concurrency", requires = "transfer")]
+    transfer_concurrency: Option<usize>,
+
+    #[arg(long = "bwlimit", value_parser = parse_size, requires = "transfer")]
+    bwlimit: Option<u64>,
+
+    #[arg(long = "host-check", default_value = "true", requires = "hostfile")]
+    host_check: String,
+
+    #[arg(long = "host-check-interval", value_parser = parse_duration, default_value = "30s", requires = "hostfile")]
+    host_check_interval: Duration,
+
+    #[arg(long = "host-max-failures", default_value_t = 3, requires = "hostfile")]
+    host_max_failures: usize,
+
+    #[arg(long = "limit-cpu", value_parser = parse_duration)]
+    limit_cpu: Option<Duration>,
+
+    #[arg(long = "limit-mem", value_parser = parse_size)]
+    limit_mem: Option<u64>,
+
+    #[arg(long = "reserve-cpus")]
+    reserve_cpus: Option<usize>,
+
+    #[arg(long = "reserve-mem", value_parser = parse_size)]
+    reserve_mem: Option<u64>,
+
+    #[arg(long = "max-temp", value_parser = parse_max_temp, conflicts_with = "auto_jobs")]
+    max_temp: Option<f64>,
+
+    #[arg(long = "on-battery-jobs", conflicts_with = "auto_jobs")]
+    on_battery_jobs: Option<usize>,
+
+    #[arg(long = "sandbox-profile")]
+    sandbox_profile: Option<PathBuf>,
+
+    #[arg(long = "chroot", conflicts_with_all = ["script", "sandbox_profile"])]
+    chroot: Option<PathBuf>,
+
+    #[arg(long = "user", value_name = "USER:GROUP")]
+    user: Option<String>,
+
+    #[arg(skip)]
+    user_ids: Option<(u32, u32)>,
+
+    #[arg(skip)]
+    argv: Vec<String>,
+
+    #[arg(skip)]
+    thawed: Option<(PathBuf, Frozen)>,
+
+    #[arg(skip)]
+    pipe_blocks: Arc<PendingBlocks>,
+
+    #[arg(long = "shell", value_parser = parse_shell, default_value = "sh")]
+    shell: Shell,
+
+    #[arg(long = "path-style", value_enum, default_value_t = PathStyle::Auto)]
+    path_style: PathStyle,
+
+    #[arg(
+        long = "jobserver",
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "on"
+    )]
+    jobserver: Option<JobserverMode>,
+
+    command: Option<String>,
+
+    #[arg(
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        requires = "command",
+        conflicts_with = "pipe"
+    )]
+    sources: Vec<String>,
+}
+
+impl Config {
+    fn template(&self) -> &str {
+        self.command.as_deref().unwrap_or_default()
+    }
+
+    /// Whether several input lines go to each command, with `--xargs` or `--max-args`
+    fn batching(&self) -> bool {
+        self.xargs || self.max_args.is_some()
+    }
+
+    /// What separates the inputs of a batch in its job: a newline, or a NUL when `-0` or
+    /// `--delimiter` inputs may hold newlines
+    fn batch_separator(&self) -> char {
+        if self.null || self.delimiter.is_some() {
+            '\0'
+        } else {
+            '\n'
+        }
+    }
+
+    /// What input records end with: a NUL with `-0`, the `--delimiter`, or a newline
+    fn delimiter(&self) -> &[u8] {
+        match &self.delimiter {
+            _ if self.null => b"\0",
+            Some(delimiter) => delimiter.as_bytes(),
+            None => b"\n",
+        }
+    }
+
+    /// Applies the `--redact` rules to text kyanite writes to its logs and records
+    fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        redact(&self.redact, text)
+    }
+
+    fn order_by(&self) -> OrderBy {
+        match self.order_by {
+            Some(order) => order,
+            None if self.keep_order => OrderBy::Input,
+            None => OrderBy::Finish,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Action {
+    /// Re-execute the commands recorded in an audit log
+    Replay(ReplayArgs),
+    /// Check how a template expands for sample input lines
+    TestTemplate(TestTemplateArgs),
+    /// Print the template expanded for each input line, without running anything
+    Map(MapArgs),
+    /// Run only the inputs that are new, changed or failed since the runs in a job log
+    Diff(DiffArgs),
+    /// Control a running kyanite
+    Ctl(CtlArgs),
+    /// Resume a run saved by `kyanite ctl freeze`
+    Thaw(ThawArgs),
+    /// Run an xargs command line, translated to kyanite options
+    Xargs(CompatArgs),
+    /// Run a GNU parallel command line, translated to kyanite options
+    Parallel(CompatArgs),
+}
+
+#[derive(Args)]
+struct CompatArgs {
+    #[arg(
+        value_name = "ARGS",
+        trailing_var_arg = true,
+        allow_hyphen_values = true
+    )]
+    args: Vec<String>,
+}
+
+#[derive(Args)]
+struct CtlArgs {
+    #[command(subcommand)]
+    command: CtlCommand,
+}
+
+#[derive(Subcommand)]
+enum CtlCommand {
+    /// Let a run's running jobs finish, save its pending jobs to a state file and stop it
+    Freeze(FreezeArgs),
+}
+
+#[derive(Args)]
+struct FreezeArgs {
+    pid: u32,
+
+    #[arg(long = "state")]
+    state: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct ThawArgs {
+    state: PathBuf,
+}
+
+#[derive(Args)]
+struct DiffArgs {
+    #[arg(long = "joblog")]
+    joblog: PathBuf,
+
+    #[arg(
+        value_name = "RUN",
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        required = true
+    )]
+    run: Vec<String>,
+}
+
+#[derive(Args)]
+struct TestTemplateArgs {
+    template: String,
+
+    #[arg(long = "case", value_parser = parse_template_case, required = true)]
+    cases: Vec<(String, String)>,
+
+    #[arg(short = 'I', long = "input", default_value = "{}")]
+    placeholder: String,
+
+    #[arg(long = "field-separator", default_value = " ")]
+    field_separator: String,
+
+    #[arg(long = "start-seq", default_value_t = 1)]
+    start_seq: usize,
+}
+
+#[derive(Args)]
+struct MapArgs {
+    template: String,
+
+    #[arg(short = 'I', long = "input", default_value = "{}")]
+    placeholder: String,
+
+    #[arg(long = "field-separator", default_value = " ")]
+    field_separator: String,
+
+    #[arg(long = "start-seq", default_value_t = 1)]
+    start_seq: usize,
+
+    #[arg(short = 'j', long = "jobs", default_value_t = num_cpus::get())]
+    workers: usize,
+}
+
+#[derive(Args)]
+struct ReplayArgs {
+    #[arg(long = "audit")]
+    audit: PathBuf,
+
+    #[arg(long = "only-failed")]
+    only_failed: bool,
+
+    #[arg(long = "run")]
+    run: Option<String>,
+
+    #[arg(short = 'j', long = "jobs")]
+    workers: Option<usize>,
+
+    #[arg(short = 'v', long = "verbose")]
+    verbose: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Estimate {
+    PerJob(Duration),
+    Sample(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OrderBy {
+    Start,
+    Finish,
+    Input,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum BudgetHalt {
+    Wait,
+    Kill,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Preprocess {
+    Builtin(Vec<Transform>),
+    Command(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Transform {
+    Trim,
+    Lower,
+    Upper,
+    Basename,
+    Dirname,
+    Noext,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Shell {
+    Sh,
+    Wsl(Option<String>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OutfileCollision {
+    Fail,
+    Uniquify,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum PathStyle {
+    Auto,
+    Unix,
+    Windows,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum PreprocessFailure {
+    Skip,
+    Fail,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum MailOn {
+    Fail,
+    Always,
+}
+
+/// Where `--split-quoted` ends an input item: at every unquoted blank, or only at line ends
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum QuotedSplit {
+    Blanks,
+    Lines,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum FlockInput {
+    Wait,
+    Skip,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum LongLine {
+    Skip,
+    Fail,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum JobserverMode {
+    On,
+    Off,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum SafeMode {
+    Job,
+    Run,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum TuneMode {
+    Report,
+    Apply,
+}
+
+#[derive(Clone, Debug)]
+struct Job {
+    id: usize,
+    line: String,
+    /// The data of a `--pipe` block, for the command's stdin; `line` then names its byte range
+    block: Option<Vec<u8>>,
+}
+
+impl Job {
+    fn new(id: usize, line: String) -> Self {
+        Job {
+            id,
+            line,
+            block: None,
+        }
+    }
+
+    /// About how many bytes the job takes while it is queued, for `--self-mem-limit`
+    fn size(&self) -> u64 {
+        (size_of::<Job>() + self.line.len() + self.block.as_ref().map_or(0, Vec::len)) as u64
+    }
+}
+
+#[derive(Debug)]
+struct JobResult {
+    id: usize,
+    output: String,
+    stderr: String,
+    echoed: bool,
+    error: Option<String>,
+    exit_code: Option<i32>,
+    streams: Option<(Vec<u8>, Vec<u8>)>,
+    start: usize,
+    input: String,
+    usage: Option<Usage>,
+    attempts: usize,
+    /// The signal that killed the command, if one did
+    signal: Option<i32>,
+    timings: Option<timing::Timings>,
+    /// Whether the job was cut short because `--until` or `--until-success` ended the run
+    cancelled: bool,
+}
+
+impl JobResult {
+    /// A result of job `id` with no output, run once and successful, to fill in the rest with
+    /// struct update syntax
+    fn new(id: usize) -> Self {
+        JobResult {
+            id,
+            output: String::new(),
+            stderr: String::new(),
+            echoed: false,
+            error: None,
+            exit_code: None,
+            streams: None,
+            start: 0,
+            input: String::new(),
+            usage: None,
+            attempts: 1,
+            signal: None,
+            timings: None,
+            cancelled: false,
+        }
+    }
+
+    /// A result of job `id` that failed without output
+    fn failed(id: usize, error: String) -> Self {
+        JobResult {
+            error: Some(error),
+            ..JobResult::new(id)
+        }
+    }
+
+    /// About how many bytes the result takes while it is held, for `--self-mem-limit`
+    fn size(&self) -> u64 {
+        let streams = self
+            .streams
+            .as_ref()
+            .map_or(0, |(stdout, stderr)| stdout.len() + stderr.len());
+        (size_of::<JobResult>()
+            + self.output.len()
+            + self.stderr.len()
+            + self.input.len()
+            + streams) as u64
+    }
+
+    /// The job's stdout as text; a `--pipe` job keeps it only as the bytes it wrote
+    fn stdout_text(&self) -> Cow<'_, str> {
+        match &self.streams {
+            Some((stdout, _)) if self.output.is_empty() => trimmed_text(stdout),
+            _ => Cow::Borrowed(&self.output),
+        }
+    }
+
+    /// The job's stderr as text, like `stdout_text`
+    fn stderr_text(&self) -> Cow<'_, str> {
+        match &self.streams {
+            Some((_, stderr)) if self.stderr.is_empty() => trimmed_text(stderr),
+            _ => Cow::Borrowed(&self.stderr),
+        }
+    }
+
+    /// The job's stdout and stderr together, for reports that show both
+    fn combined_output(&self) -> Cow<'_, str> {
+        let (output, stderr) = (self.stdout_text(), self.stderr_text());
+        match (output.is_empty(), stderr.is_empty()) {
+            (_, true) => output,
+            (true, false) => stderr,
+            (false, false) => Cow::Owned(format!("{}\n{}", output, stderr)),
+        }
+    }
+}
+
+/// Output bytes as text without trailing whitespace, copied only if they are not UTF-8
+fn trimmed_text(bytes: &[u8]) -> Cow<'_, str> {
+    match String::from_utf8_lossy(bytes) {
+        Cow::Borrowed(text) => Cow::Borrowed(text.trim_end()),
+        Cow::Owned(text) => Cow::Owned(text.trim_end().to_string()),
+    }
+}
+
+/// Shared run state used to stop scheduling and terminate running children
+struct RunState {
+    stopped: AtomicBool,
+    killing: AtomicBool,
+    reason: OnceLock<String>,
+    running: Vec<Mutex<Option<u32>>>,
+    since: Vec<Mutex<Option<Instant>>>,
+    timed_out: Vec<AtomicBool>,
+    current: Vec<Mutex<Option<(String, Instant)>>>,
+    unstarted: Mutex<Vec<Job>>,
+    breaker: Option<Mutex<CircuitBreaker>>,
+    limit: AtomicUsize,
+    auto_jobs: Option<Mutex<AutoJobs>>,
+    audit: Option<AuditLog>,
+    joblog: Option<JobLog>,
+    clock: Option<timing::Clock>,
+    budget: Option<Arc<memory::Budget>>,
+    events: Option<EventStream>,
+    syslog: Option<Syslog>,
+    tracer: Option<Tracer>,
+    manifest: Option<Manifest>,
+    http: Option<http::Pool>,
+    frozen: OnceLock<PathBuf>,
+    cache: Option<Cache>,
+    total: OnceLock<usize>,
+    input_total: OnceLock<usize>,
+    /// The input lines read and the next job id, as of the last job queued
+    read: Mutex<(usize, usize)>,
+    queued: AtomicUsize,
+    started: AtomicUsize,
+    done: AtomicUsize,
+    failures: AtomicUsize,
+    jobserver: Option<JobServer>,
+    hosts: Option<Hosts>,
+    transfers: Option<Transfers>,
+    lanes: Option<Lanes>,
+    transactions: Option<Transactions>,
+    pacer: Option<Pacer>,
+    ramp: Option<(Instant, Duration)>,
+    outfiles: Mutex<HashMap<String, String>>,
+    usage: Mutex<usage::Totals>,
+    failed: Mutex<Vec<FailedJob>>,
+    failed_inputs: Mutex<Vec<(usize, String)>>,
+    paused_until: Mutex<Option<Instant>>,
+    holding: AtomicBool,
+    suspended: Mutex<Vec<u32>>,
+}
+
+impl RunState {
+    fn new(workers: usize) -> Self {
+        RunState {
+            stopped: AtomicBool::new(false),
+            killing: AtomicBool::new(false),
+            reason: OnceLock::new(),
+            running: (0..workers).map(|_| Mutex::new(None)).collect(),
+            since: (0..workers).map(|_| Mutex::new(None)).collect(),
+            timed_out: (0..workers).map(|_| AtomicBool::new(false)).collect(),
+            current: (0..workers).map(|_| Mutex::new(None)).collect(),
+            unstarted: Mutex::new(Vec::new()),
+            breaker: None,
+            limit: AtomicUsize::new(workers),
+            auto_jobs: None,
+            audit: None,
+            joblog: None,
+            clock: None,
+            budget: None,
+            events: None,
+            syslog: None,
+            tracer: None,
+            manifest: None,
+            http: None,
+            frozen: OnceLock::new(),
+            cache: None,
+            total: OnceLock::new(),
+            input_total: OnceLock::new(),
+            read: Mutex::new((0, 0)),
+            queued: AtomicUsize::new(0),
+            started: AtomicUsize::new(0),
+            done: AtomicUsize::new(0),
+            failures: AtomicUsize::new(0),
+            jobserver: None,
+            hosts: None,
+            transfers: None,
+            lanes: None,
+            transactions: None,
+            pacer: None,
+            ramp: None,
+            outfiles: Mutex::new(HashMap::new()),
+            usage: Mutex::new(usage::Totals::default()),
+            failed: Mutex::new(Vec::new()),
+            failed_inputs: Mutex::new(Vec::new()),
+            paused_until: Mutex::new(None),
+            holding: AtomicBool::new(false),
+            suspended: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn with_jobserver(mut self, jobserver: Option<JobServer>) -> Self {
+        self.jobserver = jobserver;
+        self
+    }
+
+    /// Reserves an `--outfile` path for a job's input, so two different inputs never share one
+    fn claim_outfile(
+        &self,
+        path: String,
+        line: &str,
+        seq: usize,
+        collision: OutfileCollision,
+    ) -> Result<String, String> {
+        let mut claimed = self.outfiles.lock().unwrap_or_else(PoisonError::into_inner);
+        let path = match claimed.get(&path) {
+            Some(input) if input == line => return Ok(path),
+            Some(input) if collision == OutfileCollision::Fail => {
+                return Err(format!(
+                    "output path {} is already used by input {:?}",
+                    path, input
+                ));
+            }
+            Some(_) => uniquify(&path, seq),
+            None => path,
+        };
+        claimed.insert(path.clone(), line.to_string());
+        Ok(path)
+    }
+
+    fn with_hosts(mut self, hosts: Option<Hosts>) -> Self {
+        self.hosts = hosts;
+        self
+    }
+
+    fn with_transfers(mut self, transfers: Option<Transfers>) -> Self {
+        self.transfers = transfers;
+        self
+    }
+
+    fn with_lanes(mut self, lanes: Option<Lanes>) -> Self {
+        self.lanes = lanes;
+        self
+    }
+
+    fn with_transactions(mut self, transactions: Option<Transactions>) -> Self {
+        self.transactions = transactions;
+        self
+    }
+
+    fn with_pacer(mut self, pacer: Option<Pacer>) -> Self {
+        self.pacer = pacer;
+        self
+    }
+
+    /// Starts the `--ramp-up` period, over which worker slots open one after another
+    fn with_ramp_up(mut self, period: Option<Duration>) -> Self {
+        self.ramp = period.map(|period| (Instant::now(), period));
+        self
+    }
+
+    fn with_events(mut self, events: Option<EventStream>) -> Self {
+        self.events = events;
+        self
+    }
+
+    fn with_syslog(mut self, syslog: Option<Syslog>) -> Self {
+        self.syslog = syslog;
+        self
+    }
+
+    fn with_tracer(mut self, tracer: Option<Tracer>) -> Self {
+        self.tracer = tracer;
+        self
+    }
+
+    fn with_manifest(mut self, manifest: Option<Manifest>) -> Self {
+        self.manifest = manifest;
+        self
+    }
+
+    fn with_http(mut self, http: Option<http::Pool>) -> Self {
+        self.http = http;
+        self
+    }
+
+    fn with_cache(mut self, cache: Option<Cache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    fn with_audit(mut self, audit: Option<AuditLog>) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    fn with_joblog(mut self, joblog: Option<JobLog>) -> Self {
+        self.joblog = joblog;
+        self
+    }
+
+    fn with_clock(mut self, clock: Option<timing::Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn with_budget(mut self, budget: Option<memory::Budget>) -> Self {
+        self.budget = budget.map(Arc::new);
+        self
+    }
+
+    /// Queues a job for the workers, returning false once they are all gone
+    fn send_job(&self, job_tx: &mpsc::Sender<Job>, job: Job) -> bool {
+        if let Some(budget) = &self.budget {
+            budget.queue(job.size(), || self.is_stopped());
+        }
+        if let Some(clock) = &self.clock {
+            clock.queued(job.id);
+        }
+        if job_tx.send(job).is_err() {
+            return false;
+        }
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        true
+    }
+
+    /// Hands a finished job to the collector, returning false once it is gone
+    fn send_result(&self, result_tx: &mpsc::Sender<JobResult>, result: JobResult) -> bool {
+        if let Some(budget) = &self.budget {
+            budget.hold(result.size(), || self.is_stopped());
+        }
+        result_tx.send(result).is_ok()
+    }
+
+    fn with_breaker(mut self, settings: Option<BreakerSettings>) -> Self {
+        self.breaker = settings.map(|settings| Mutex::new(CircuitBreaker::new(settings)));
+        self
+    }
+
+    fn with_auto_jobs(mut self, controller: Option<AutoJobs>) -> Self {
+        if let Some(controller) = &controller {
+            self.limit.store(controller.limit, Ordering::SeqCst);
+        }
+        self.auto_jobs = controller.map(Mutex::new);
+        self
+    }
+
+    /// Whether a worker slot is within the current concurrency limit, has opened in the
+    /// `--ramp-up` period, and new jobs are not held back for the `--reserve-cpus` and
+    /// `--reserve-mem` headroom
+    fn admits(&self, worker_id: usize) -> bool {
+        worker_id < self.limit.load(Ordering::SeqCst)
+            && !self.holding.load(Ordering::SeqCst)
+            && self.ramp.is_none_or(|(started, period)| {
+                started.elapsed() >= period * worker_id as u32 / self.running.len() as u32
+            })
+    }
+
+    /// Stops scheduling new jobs, returning true for the first caller
+    fn stop(&self, reason: impl Into<String>) -> bool {
+        let first = self.reason.set(reason.into()).is_ok();
+        self.stopped.store(true, Ordering::SeqCst);
+        first
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    fn reason(&self) -> &str {
+        self.reason.get().map(String::as_str).unwrap_or("stopped")
+    }
+
+    fn kill_running(&self) {
+        self.killing.store(true, Ordering::SeqCst);
+        for slot in &self.running {
+            if let Some(pid) = *slot.lock().unwrap_or_else(PoisonError::into_inner) {
+                terminate(pid);
+            }
+        }
+    }
+
+    /// Forwards an interrupt to every running job and its processes: SIGTERM, or SIGKILL with
+    /// `kill`
+    fn signal_running(&self, kill: bool) {
+        self.killing.store(true, Ordering::SeqCst);
+        for slot in &self.running {
+            if let Some(pid) = *slot.lock().unwrap_or_else(PoisonError::into_inner) {
+                expire(pid, kill);
+            }
+        }
+    }
+
+    fn register(&self, worker_id: usize, pid: u32) {
+        *self.since[worker_id]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(Instant::now());
+        self.timed_out[worker_id].store(false, Ordering::SeqCst);
+        *self.running[worker_id]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(pid);
+        if self.killing.load(Ordering::SeqCst) {
+            terminate(pid);
+        }
+    }
+
+    fn unregister(&self, worker_id: usize) {
+        self.since[worker_id]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(pid) = self.running[worker_id]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+        {
+            self.suspended
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .retain(|&paused| paused != pid);
+        }
+    }
+
+    /// Holds back new job starts for at least `delay`
+    fn pause_for(&self, delay: Duration) {
+        let until = Instant::now() + delay;
+        let mut paused_until = self
+            .paused_until
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if paused_until.is_none_or(|current| current < until) {
+            *paused_until = Some(until);
+        }
+    }
+
+    /// Sleeps until a pause requested with `pause_for` is over or the run is stopped
+    fn wait_until_resumed(&self) {
+        loop {
+            let Some(until) = *self
+                .paused_until
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+            else {
+                return;
+            };
+            let now = Instant::now();
+            if now >= until || self.is_stopped() {
+                return;
+            }
+            thread::sleep((until - now).min(Duration::from_millis(100)));
+        }
+    }
+}
+
+/// Spaces out the lines written to jobs' stdin for `--max-lines-per-sec`, shared by all jobs so
+/// the rate holds across the fan-out
+struct Pacer {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl Pacer {
+    fn new(lines_per_sec: f64) -> Self {
+        Pacer {
+            interval: Duration::from_secs_f64(1.0 / lines_per_sec),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Blocks until the next line may be written
+    fn wait(&self) {
+        let slot = {
+            let mut next = self.next.lock().unwrap_or_else(PoisonError::into_inner);
+            let slot = (*next).max(Instant::now());
+            *next = slot + self.interval;
+            slot
+        };
+        if let Some(delay) = slot.checked_duration_since(Instant::now()) {
+            thread::sleep(delay);
+        }
+    }
+}
+
+/// Random delay range applied before each job starts
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Jitter {
+    min: Duration,
+    max: Duration,
+}
+
+impl Jitter {
+    fn sample(&self, job_id: usize) -> Duration {
+        let spread = (self.max - self.min).as_nanos() as u64;
+        if spread == 0 {
+            return self.min;
+        }
+        let random = random_u64(RandomStream::Jitter, job_id as u64);
+        self.min + Duration::from_nanos(random % (spread + 1))
+    }
+}
+
+/// Independent sequences of random numbers, so each randomized feature is reproducible on its own
+#[derive(Clone, Copy)]
+enum RandomStream {
+    Jitter = 1,
+    Sample = 2,
+}
+
+static SEED: OnceLock<u64> = OnceLock::new();
+
+static SHELL: OnceLock<Shell> = OnceLock::new();
+
+static PATH_STYLE: OnceLock<PathStyle> = OnceLock::new();
+
+/// Fixes the seed of all randomized behavior, before anything random is drawn
+fn seed_random(seed: u64) {
+    let _ = SEED.set(seed);
+}
+
+/// Returns the `index`th pseudo-random number of a stream, derived from the run's seed
+fn random_u64(stream: RandomStream, index: u64) -> u64 {
+    let seed = *SEED.get_or_init(|| {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        now.as_nanos() as u64 ^ u64::from(std::process::id()) << 32
+    });
+
+    // splitmix64
+    let mut z = (seed ^ (stream as u64).wrapping_mul(0xd6e8_feb8_6659_fd93))
+        .wrapping_add(index.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct BreakerSettings {
+    fails: usize,
+    window: Duration,
+    cooldown: Duration,
+}
+
+#[derive(Debug, PartialEq)]
+enum Admission {
+    Run,
+    Probe,
+    Wait(Duration),
+}
+
+#[derive(Debug, PartialEq)]
+enum BreakerState {
+    Closed,
+    Open(Instant),
+    Probing,
+}
+
+/// Pauses scheduling when failures spike, then probes with a single job before resuming
+struct CircuitBreaker {
+    settings: BreakerSettings,
+    failures: VecDeque<Instant>,
+    state: BreakerState,
+}
+
+impl CircuitBreaker {
+    fn new(settings: BreakerSettings) -> Self {
+        CircuitBreaker {
+            settings,
+            failures: VecDeque::new(),
+            state: BreakerState::Closed,
+        }
+    }
+
+    fn admit(&mut self, now: Instant) -> Admission {
+        match self.state {
+            BreakerState::Closed => Admission::Run,
+            BreakerState::Open(until) if now < until => Admission::Wait(until - now),
+            BreakerState::Open(_) => {
+                self.state = BreakerState::Probing;
+                Admission::Probe
+            }
+            BreakerState::Probing => Admission::Wait(Duration::from_millis(100)),
+        }
+    }
+
+    /// Records a finished job, returning a message when the breaker changes state
+    fn record(&mut self, failed: bool, probe: bool, now: Instant) -> Option<String> {
+        let cooldown = self.settings.cooldown;
+
+        if probe {
+            if failed {
+                self.state = BreakerState::Open(now + cooldown);
+                return Some(format!(
+                    "circuit breaker probe failed, pausing for {:?}",
+                    cooldown
+                ));
+            }
+            self.state = BreakerState::Closed;
+            return Some("circuit breaker probe succeeded, resuming".to_string());
+        }
+
+        if !failed || self.state != BreakerState::Closed {
+            return None;
+        }
+
+        self.failures.push_back(now);
+        while let Some(&first) = self.failures.front() {
+            if now.duration_since(first) > self.settings.window {
+                self.failures.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        if self.failures.len() < self.settings.fails {
+            return None;
+        }
+
+        self.failures.clear();
+        self.state = BreakerState::Open(now + cooldown);
+        Some(format!(
+            "circuit breaker open after {} failures within {:?}, pausing for {:?}",
+            self.settings.fails, self.settings.window, cooldown
+        ))
+    }
+}
+
+/// Adjusts the concurrency limit with additive increase, multiplicative decrease
+struct AutoJobs {
+    limit: usize,
+    max: usize,
+    target_latency: Option<Duration>,
+    target_load: Option<f64>,
+    under_target: usize,
+    settling: usize,
+}
+
+impl AutoJobs {
+    fn new(max: usize, target_latency: Option<Duration>, target_load: Option<f64>) -> Self {
+        AutoJobs {
+            limit: 1,
+            max: max.max(1),
+            target_latency,
+            target_load,
+            under_target: 0,
+            settling: 0,
+        }
+    }
+
+    /// Feeds one finished job into the controller, returning the new limit when it changes
+    fn observe(&mut self, latency: Duration, load: Option<f64>) -> Option<usize> {
+        let over_latency = self.target_latency.is_some_and(|target| latency > target);
+        let over_load = self
+            .target_load
+            .zip(load)
+            .is_some_and(|(target, load)| load > target);
+
+        // jobs started before the last decrease still report at the old concurrency
+        if self.settling > 0 {
+            self.settling -= 1;
+            return None;
+        }
+
+        if over_latency || over_load {
+            self.under_target = 0;
+            if self.limit == 1 {
+                return None;
+            }
+            self.limit = (self.limit / 2).max(1);
+            self.settling = self.limit;
+            return Some(self.limit);
+        }
+
+        self.under_target += 1;
+        if self.under_target >= self.limit && self.limit < self.max {
+            self.under_target = 0;
+            self.limit += 1;
+            return Some(self.limit);
+        }
+        None
+    }
+}
+
+#[cfg(unix)]
+fn load_average() -> Option<f64> {
+    let mut load = [0.0f64; 1];
+    let count = unsafe { libc::getloadavg(load.as_mut_ptr(), 1) };
+    (count == 1).then_some(load[0])
+}
+
+#[cfg(not(unix))]
+fn load_average() -> Option<f64> {
+    None
+}
+
+/// Blocks until the circuit breaker admits a job, returning whether it is the probe
+fn wait_for_breaker(breaker: &Mutex<CircuitBreaker>, state: &RunState) -> bool {
+    loop {
+        let admission = breaker
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .admit(Instant::now());
+        match admission {
+            Admission::Run => return false,
+            Admission::Probe => return true,
+            Admission::Wait(delay) => {
+                if state.is_stopped() {
+                    return false;
+                }
+                thread::sleep(delay.min(Duration::from_millis(100)));
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn terminate(pid: u32) {
+    signal_group(pid, libc::SIGTERM);
+}
+
+/// Signals the process group a job leads (see `run_command`), or the job alone if it has none
+#[cfg(unix)]
+fn signal_group(pid: u32, signal: libc::c_int) {
+    unsafe {
+        if libc::kill(-(pid as libc::pid_t), signal) != 0 {
+            libc::kill(pid as libc::pid_t, signal);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn terminate(pid: u32) {
+    let _ = Command::new("taskkill")
+        .args(["/PID", &pid.to_string(), "/T", "/F"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+}
+
+/// Signals a job with SIGTERM, or SIGKILL with `kill`, as when it ran past `--timeout` or
+/// kyanite was interrupted: its process group, and on Linux also its whole process tree, so no
+/// descendant keeps its output open
+#[cfg(unix)]
+fn expire(pid: u32, kill: bool) {
+    let signal = if kill { libc::SIGKILL } else { libc::SIGTERM };
+    #[cfg(target_os = "linux")]
+    reserve::signal_tree(pid, signal);
+    signal_group(pid, signal);
+}
+
+#[cfg(not(unix))]
+fn expire(pid: u32, _kill: bool) {
+    terminate(pid);
+}
+
+type Input = Box<dyn Iterator<Item = io::Result<String>> + Send>;
+
+/// Splits input into blocks of about `size` bytes for `--pipe`, cutting only where a `--recend`
+/// match is directly followed by a `--recstart` match, so records are never split across jobs
+struct Blocks<R> {
+    reader: R,
+    size: usize,
+    recend: regex::bytes::Regex,
+    recstart: Option<regex::bytes::Regex>,
+    buffer: Vec<u8>,
+    eof: bool,
+}
+
+/// Runs `--pipe --group-by`: one long-lived job per worker, fed every input line whose key
+/// hashes to it, so all lines with the same key reach the same process; their output is
+/// written to `out` a whole line at a time. Returns whether every job succeeded.
+fn run_partitioned<W: Write + Send>(
+    config: &Config,
+    key: &str,
+    source: impl Read,
+    out: &Mutex<W>,
+) -> io::Result<bool> {
+    let pacer = config.max_lines_per_sec.map(Pacer::new);
+    let mut children = Vec::new();
+    for slot in 0..config.workers.max(1) {
+        let mut command = shell_command(config.template());
+        command
+            .env("KYANITE_SLOT", (slot + 1).to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped());
+        children.push(command.spawn()?);
+    }
+    let mut stdins: Vec<_> = children
+        .iter_mut()
+        .map(|child| child.stdin.take().unwrap())
+        .collect();
+    let outputs: Vec<_> = children
+        .iter_mut()
+        .map(|child| child.stdout.take().unwrap())
+        .collect();
+
+    let fed = thread::scope(|scope| {
+        for output in outputs {
+            scope.spawn(move || {
+                let mut reader = BufReader::new(output);
+                let mut line = Vec::new();
+                while reader
+                    .read_until(b'\n', &mut line)
+                    .is_ok_and(|read| read > 0)
+                {
+                    let _ = out
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .write_all(&line);
+                    line.clear();
+                }
+            });
+        }
+        let mut reader = BufReader::new(source);
+        let mut line = Vec::new();
+        let fed = loop {
+            line.clear();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) => break Ok(()),
+                Ok(_) => {}
+                Err(e) => break Err(e),
+            }
+            let text = String::from_utf8_lossy(&line);
+            let key = expand_template(
+                key,
+                text.trim_end_matches(['\n', '\r']),
+                &config.field_separator,
+                &config.placeholder,
+            );
+            let slot = split::slot(&key, stdins.len());
+            if let Some(pacer) = &pacer {
+                pacer.wait();
+            }
+            if let Err(e) = stdins[slot].write_all(&line) {
+                let e =
+                    io::Error::new(e.kind(), format!("job {} stopped reading: {}", slot + 1, e));
+                break Err(e);
+            }
+        };
+        // closing the pipes lets the jobs finish, and their output threads with them
+        drop(stdins);
+        fed
+    });
+
+    let mut succeeded = true;
+    for (slot, mut child) in children.into_iter().enumerate() {
+        let status = child.wait()?;
+        if !status.success() {
+            eprintln!("job {} failed: {}", slot + 1, status);
+            succeeded = false;
+        }
+    }
+    fed.map(|()| succeeded)
+}
+
+impl<R: Read> Blocks<R> {
+    fn new(reader: R, config: &Config) -> Self {
+        Blocks {
+            reader,
+            size: usize::try_from(config.block).unwrap_or(usize::MAX).max(1),
+            recend: config.recend.clone(),
+            recstart: config.recstart.clone(),
+            buffer: Vec::new(),
+            eof: false,
+        }
+    }
+
+    /// The last record boundary within the block size, or the first one after it when a
+    /// record is longer than a block
+    fn boundary(&self) -> Option<usize> {
+        let mut last = None;
+        for found in self.recend.find_iter(&self.buffer) {
+            let end = found.end();
+            // a match touching the end of the buffer may continue in data not read yet
+            if end == 0 || end >= self.buffer.len() {
+                continue;
+            }
+            let starts = self.recstart.as_ref().is_none_or(|recstart| {
+                recstart
+                    .find_at(&self.buffer, end)
+                    .is_some_and(|start| start.start() == end)
+            });
+            if !starts {
+                continue;
+            }
+            if end > self.size {
+                return last.or(Some(end));
+            }
+            last = Some(end);
+        }
+        last
+    }
+
+    fn take(&mut self, len: usize) -> Vec<u8> {
+        let rest = self.buffer.split_off(len);
+        std::mem::replace(&mut self.buffer, rest)
+    }
+}
+
+/// The `--pipe` blocks read but not yet queued as jobs. Blocks go through the input by the
+/// name of their byte range, as `--pipe-part` ranges do, so the job log, `--resume` and the
+/// job's environment see the name, while `read_input` takes the data back for the job's stdin
+#[derive(Default)]
+struct PendingBlocks(Mutex<VecDeque<(String, Vec<u8>)>>);
+
+impl PendingBlocks {
+    fn push(&self, name: String, data: Vec<u8>) {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push_back((name, data));
+    }
+
+    /// The data of the block named `name`, dropping the blocks before it that the input
+    /// filters left out
+    fn take(&self, name: &str) -> Option<Vec<u8>> {
+        let mut pending = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let at = pending.iter().position(|(pending, _)| pending == name)?;
+        pending.drain(..at);
+        pending.pop_front().map(|(_, data)| data)
+    }
+}
+
+/// The blocks of `--pipe` as input, each named by its byte range
+fn block_input(blocks: Blocks<Box<dyn Read + Send>>, pending: Arc<PendingBlocks>) -> Input {
+    let mut offset = 0;
+    Box::new(blocks.map(move |block| {
+        let block = block?;
+        let name = format!("bytes {}-{}", offset, offset + block.len());
+        offset += block.len();
+        pending.push(name.clone(), block);
+        Ok(name)
+    }))
+}
+
+impl<R: Read> Iterator for Blocks<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.eof && self.buffer.len() <= self.size {
+                return (!self.buffer.is_empty()).then(|| Ok(self.take(self.buffer.len())));
+            }
+            if self.buffer.len() >= self.size || self.eof {
+                match self.boundary() {
+                    Some(cut) => return Some(Ok(self.take(cut))),
+                    None if self.eof => return Some(Ok(self.take(self.buffer.len()))),
+                    None => {}
+                }
+            }
+            let filled = self.buffer.len();
+            self.buffer.resize(filled + 64 * 1024, 0);
+            match self.reader.read(&mut self.buffer[filled..]) {
+                Ok(read) => {
+                    self.buffer.truncate(filled + read);
+                    self.eof = read == 0;
+                }
+                Err(e) => {
+                    self.buffer.truncate(filled);
+                    if e.kind() != io::ErrorKind::Interrupted {
+                        return Some(Err(e));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Splits the arguments after the template into the value lists of its `:::` groups
+fn input_sources(args: &[String]) -> Result<Vec<Vec<String>>, String> {
+    let mut sources: Vec<Vec<String>> = Vec::new();
+    for arg in args {
+        match sources.last_mut() {
+            _ if arg == ":::" => sources.push(Vec::new()),
+            Some(values) => values.push(arg.clone()),
+            None => {
+                return Err(format!(
+                    "unexpected argument {}: expected ::: after the command",
+                    arg
+                ));
+            }
+        }
+    }
+    Ok(sources)
+}
+
+/// Every combination of `first` with one value from each of `rest`, the last varying
+/// fastest, with `SOURCE_BREAK` between the values so each is one of `{1}`, `{2}` and on
+fn product(first: &str, rest: &[Vec<String>]) -> Vec<String> {
+    let mut lines = vec![first.to_string()];
+    for values in rest {
+        lines = lines
+            .iter()
+            .flat_map(|line| {
+                values
+                    .iter()
+                    .map(move |value| format!("{}{}{}", line, SOURCE_BREAK, value))
+            })
+            .collect();
+    }
+    lines
+}
+
+/// Separates the values of the input sources in a line of their cross product, so a value
+/// holding the field separator is still a single field
+const SOURCE_BREAK: char = '\u{E002}';
+
+/// The fields of an input line: the values of its sources, or the line split at the separator
+fn split_fields<'a>(line: &'a str, field_separator: &str) -> Vec<&'a str> {
+    if line.contains(SOURCE_BREAK) {
+        line.split(SOURCE_BREAK).collect()
+    } else {
+        line.split(field_separator).collect()
+    }
+}
+
+/// An input line as `{}` and `KYANITE_INPUT` show it, the values of its sources joined with
+/// the field separator
+fn shown_input<'a>(line: &'a str, field_separator: &str) -> Cow<'a, str> {
+    if line.contains(SOURCE_BREAK) {
+        Cow::Owned(line.replace(SOURCE_BREAK, field_separator))
+    } else {
+        Cow::Borrowed(line)
+    }
+}
+
+/// The lines of each `-a` file after the first, which are sources of their own
+fn arg_file_sources(config: &Config) -> Vec<Vec<String>> {
+    config.arg_files[1..]
+        .iter()
+        .map(|path| {
+            let reader = BufReader::new(compress::ArgFiles::new(std::slice::from_ref(path)));
+            delimited::Delimited::new(reader, config.delimiter())
+                .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+                .collect::<io::Result<Vec<String>>>()
+                .unwrap_or_else(|e| {
+                    eprintln!("error reading input: {}", e);
+                    std::process::exit(EXIT_INTERNAL_ERROR);
+                })
+        })
+        .collect()
+}
+
+/// Joins input lines into multi-line records for `--record-regex`, starting a new record at
+/// each line the pattern matches; lines before the first match form a record of their own
+struct Records {
+    lines: Input,
+    start: Regex,
+    pending: Option<String>,
+}
+
+impl Iterator for Records {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next() {
+                Some(Ok(line)) => line,
+                Some(Err(e)) => return Some(Err(e)),
+                None => return self.pending.take().map(Ok),
+            };
+            match &mut self.pending {
+                Some(record) if !self.start.is_match(&line) => {
+                    record.push('\n');
+                    record.push_str(&line);
+                }
+                pending => {
+                    if let Some(record) = pending.replace(line) {
+                        return Some(Ok(record));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Packs input lines into the argument lists of commands for `--xargs` and `--max-args`, joined
+/// by the batch separator: at most `--max-args` lines each, and with `--xargs` as many as keep
+/// the command within the length limit, where a line too long to share a command runs alone
+struct Batches {
+    lines: Input,
+    separator: char,
+    max_args: usize,
+    limit: Option<usize>,
+    /// The length of the command with no input, and how many times the input appears in it
+    base: usize,
+    uses: usize,
+    carried: Option<String>,
+}
+
+impl Batches {
+    fn new(lines: Input, config: &Config) -> Self {
+        let expanded = |line: &str| {
+            expand_template(
+                config.template(),
+                line,
+                &config.field_separator,
+                &config.placeholder,
+            )
+            .len()
+        };
+        let base = expanded("");
+        Batches {
+            lines,
+            separator: config.batch_separator(),
+            max_args: config.max_args.unwrap_or(usize::MAX),
+            limit: config.xargs.then(|| command_limit(config.max_chars)),
+            base: base + POSITIONAL_ZERO.len() + 1,
+            uses: expanded("x") - base,
+            carried: None,
+        }
+    }
+}
+
+impl Iterator for Batches {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut batch: Vec<String> = Vec::new();
+        let mut length = self.base;
+        while batch.len() < self.max_args {
+            let line = match self.carried.take().map(Ok).or_else(|| self.lines.next()) {
+                Some(Ok(line)) if line.trim().is_empty() => continue,
+                Some(Ok(line)) if line.contains(self.separator) => {
+                    return Some(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("input {:?} holds the separator of batches", line),
+                    )));
+                }
+                Some(Ok(line)) => line,
+                Some(Err(e)) => return Some(Err(e)),
+                None => break,
+            };
+            // the line goes into the command once per use of the input and once as a parameter
+            let grows = (self.uses + 1) * (line.len() + 1);
+            if let Some(limit) = self.limit
+                && !batch.is_empty()
+                && length + grows > limit
+            {
+                self.carried = Some(line);
+                break;
+            }
+            length += grows;
+            batch.push(line);
+        }
+        let separator = self.separator.to_string();
+        (!batch.is_empty()).then(|| Ok(batch.join(&separator)))
+    }
+}
+
+/// Exit status when some jobs failed
+const EXIT_SOME_FAILED: i32 = 1;
+/// Exit status when every job failed
+const EXIT_ALL_FAILED: i32 = 2;
+/// Exit status for a command line kyanite cannot run, the same clap gives a malformed one
+const EXIT_USAGE: i32 = 2;
+/// Exit status after a second interrupt, as a shell reports a process ended by SIGINT
+const EXIT_INTERRUPTED: i32 = 130;
+/// Exit status when kyanite itself could not go on, e.g. with an unreadable input or log
+const EXIT_INTERNAL_ERROR: i32 = 255;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args_os().map(|arg| arg.to_string_lossy().into_owned());
+    let argv0 = args.next().unwrap_or_default();
+    let args: Vec<String> = args.collect();
+    // installed or linked as `xargs` or `parallel`, kyanite takes that tool's command line
+    let mut config = match compat::Tool::from_argv0(Path::new(&argv0)) {
+        Some(tool) => compat_config(tool, &args),
+        None => {
+            let mut config = Config::parse();
+            config.argv = args;
+            config
+        }
+    };
+    let mut diff_joblog = None;
+
+    match config.action.take() {
+        Some(Action::Replay(args)) => return replay(args).await,
+        Some(Action::TestTemplate(args)) => {
+            let failed = test_template(&args, &mut io::stdout());
+            std::process::exit(if failed == 0 { 0 } else { 1 });
+        }
+        Some(Action::Map(args)) => {
+            let mut out = io::BufWriter::new(io::stdout().lock());
+            if let Err(e) = map_lines(&args, io::stdin().lock(), &mut out) {
+                eprintln!("error mapping input: {}", e);
+                std::process::exit(EXIT_INTERNAL_ERROR);
+            }
+            return Ok(());
+        }
+        Some(Action::Diff(args)) => {
+            let argv = std::iter::once("kyanite".to_string()).chain(args.run.clone());
+            config = Config::try_parse_from(argv).unwrap_or_else(|e| e.exit());
+            if config.action.is_some() {
+                eprintln!("diff takes the options and command of a run, not a subcommand");
+                std::process::exit(EXIT_USAGE);
+            }
+            config.argv = args.run;
+            // the run is recorded too, so the next diff starts from it
+            if config.joblog.is_none() {
+                config.joblog = Some(args.joblog.clone());
+                let joblog = args.joblog.to_string_lossy().into_owned();
+                config.argv.splice(0..0, ["--joblog".to_string(), joblog]);
+            }
+            diff_joblog = Some(args.joblog);
+        }
+        Some(Action::Xargs(args)) => config = compat_config(compat::Tool::Xargs, &args.args),
+        Some(Action::Parallel(args)) => config = compat_config(compat::Tool::Parallel, &args.args),
+        Some(Action::Ctl(args)) => match args.command {
+            CtlCommand::Freeze(args) => {
+                ctl_freeze(&args);
+                return Ok(());
+            }
+        },
+        Some(Action::Thaw(args)) => {
+            let frozen = fs::read_to_string(&args.state)
+                .map_err(|e| e.to_string())
+                .and_then(|text| Frozen::parse(&text))
+                .unwrap_or_else(|e| {
+                    eprintln!("error reading state {}: {}", args.state.display(), e);
+                    std::process::exit(EXIT_INTERNAL_ERROR);
+                });
+            let argv = std::iter::once("kyanite".to_string()).chain(frozen.args.clone());
+            config = Config::try_parse_from(argv).unwrap_or_else(|e| e.exit());
+            config.argv = frozen.args.clone();
+            config.thawed = Some((args.state, frozen));
+        }
+        None => {}
+    }
+
+    if let Some(path) = &config.command_file {
+        match fs::read_to_string(path) {
+            Ok(contents) => config.command = Some(strip_template_comments(&contents)),
+            Err(e) => {
+                eprintln!("error reading command file {}: {}", path.display(), e);
+                std::process::exit(EXIT_INTERNAL_ERROR);
+            }
+        }
+    }
+
+    if let Some(request) = &config.http {
+        if let Err(e) = check_http_request(request) {
+            eprintln!("error: --http {}: {}", request, e);
+            std::process::exit(EXIT_USAGE);
+        }
+        config.command = Some(request.clone());
+    }
+
+    if !config.deny_path.is_empty() || !config.allow_path.is_empty() {
+        config.path_guard = Some(PathGuard::new(&config.deny_path, &config.allow_path));
+    }
+
+    if let Some(path) = &config.meta {
+        match Meta::load(path) {
+            Ok(meta) => config.meta_records = Some(meta),
+            Err(e) => {
+                eprintln!("error reading metadata {}: {}", path.display(), e);
+                std::process::exit(EXIT_INTERNAL_ERROR);
+            }
+        }
+    }
+
+    let problems = template_problems(&config);
+    for problem in &problems {
+        let level = if config.strict_template {
+            "error"
+        } else {
+            "warning"
+        };
+        eprintln!("{}: {}", level, problem);
+    }
+    if config.strict_template && !problems.is_empty() {
+        std::process::exit(EXIT_USAGE);
+    }
+    if let Some(problem) = usage_problem(&config) {
+        eprintln!("{}", problem);
+        std::process::exit(EXIT_USAGE);
+    }
+
+    if let Some(line) = &config.explain {
+        explain(&config, line, &mut io::stdout());
+        return Ok(());
+    }
+
+    if config.script {
+        match write_script(config.template()) {
+            Ok(path) => config.script_path = Some(path),
+            Err(e) => {
+                eprintln!("error writing script: {}", e);
+                std::process::exit(EXIT_INTERNAL_ERROR);
+            }
+        }
+    }
+
+    if let Some(seed) = config.seed {
+        seed_random(seed);
+    }
+    let _ = SHELL.set(config.shell.clone());
+    let _ = PATH_STYLE.set(config.path_style);
+
+    if let Some(mode) = config.tune {
+        tune(&mut config, mode);
+        if mode == TuneMode::Report {
+            return Ok(());
+        }
+    }
+
+    let sources = match input_sources(&config.sources) {
+        Ok(sources) => sources,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(EXIT_USAGE);
+        }
+    };
+
+    if let Some(reserved) = config.reserve_cpus {
+        let available = num_cpus::get().saturating_sub(reserved).max(1);
+        config.workers = config.workers.min(available);
+    }
+
+    if config.chroot.is_some() || config.user.is_some() {
+        jail(&mut config);
+    }
+
+    if let Some(path) = &config.sandbox_profile
+        && let Err(e) = fs::metadata(path)
+    {
+        eprintln!("error reading sandbox profile {}: {}", path.display(), e);
+        std::process::exit(EXIT_INTERNAL_ERROR);
+    }
+
+    // blocks are cut from the -a files read one after another; lines are crossed with each
+    // file after the first as a source of its own
+    let blocks = config.pipe || config.group_by.is_some();
+    let source: Box<dyn Read + Send> = if config.arg_files.is_empty() {
+        Box::new(io::stdin())
+    } else if blocks {
+        Box::new(compress::ArgFiles::new(&config.arg_files))
+    } else {
+        Box::new(compress::ArgFiles::new(&config.arg_files[..1]))
+    };
+    if let Some(key) = &config.group_by {
+        #[cfg(unix)]
+        refuse_freezing(&config);
+        let out = Mutex::new(io::stdout());
+        match run_partitioned(&config, key, source, &out) {
+            Ok(true) => return Ok(()),
+            Ok(false) => std::process::exit(EXIT_SOME_FAILED),
+            Err(e) => {
+                eprintln!("error feeding workers: {}", e);
+                std::process::exit(EXIT_INTERNAL_ERROR);
+            }
+        }
+    }
+    // a thawed run that had read all its input needs none, see read_input
+    let read_all = |(_, frozen): &(PathBuf, Frozen)| frozen.read.is_none();
+    let mut input: Input = if config.thawed.as_ref().is_some_and(read_all) {
+        // the frozen jobs are queued as they were, see read_input
+        Box::new(std::iter::empty())
+    } else if let Some(dir) = &config.claim_dir {
+        match claim::pending(dir) {
+            Ok(names) => Box::new(names.into_iter().map(Ok)),
+            Err(e) => {
+                eprintln!("error reading claim directory {}: {}", dir.display(), e);
+                std::process::exit(EXIT_INTERNAL_ERROR);
+            }
+        }
+    } else if config.pipe_part {
+        let path = config.arg_files[0].to_string_lossy();
+        match part::Parts::open(&path, config.block, config.recend.clone()) {
+            Ok(parts) => Box::new(parts),
+            Err(e) => {
+                eprintln!("error opening {}: {}", path, e);
+                std::process::exit(EXIT_INTERNAL_ERROR);
+            }
+        }
+    } else if config.pipe {
+        block_input(
+            Blocks::new(source, &config),
+            Arc::clone(&config.pipe_blocks),
+        )
+    } else {
+        Box::new(delimited::Delimited::new(
+            BufReader::new(source),
+            config.delimiter(),
+        ))
+    };
+    if let Some(split) = config.split_quoted {
+        let whole_line = split == QuotedSplit::Lines;
+        input = Box::new(input.flat_map(move |line| {
+            match line.map(|line| compat::split_quoted(&line, whole_line)) {
+                Ok(Ok(items)) => items.into_iter().map(Ok).collect(),
+                Ok(Err(e)) => vec![Err(io::Error::new(io::ErrorKind::InvalidData, e))],
+                Err(e) => vec![Err(e)],
+            }
+        }));
+    }
+    let mut sources = sources;
+    if !blocks && config.arg_files.len() > 1 {
+        sources.splice(0..0, arg_file_sources(&config));
+    }
+    if !sources.is_empty() && !config.thawed.as_ref().is_some_and(read_all) {
+        let mut rest = sources;
+        if config.arg_files.is_empty() {
+            // without -a the first ::: group takes the place of stdin
+            input = Box::new(rest.remove(0).into_iter().map(Ok));
+        }
+        input = Box::new(input.flat_map(move |line| match line {
+            Ok(line) => product(&line, &rest).into_iter().map(Ok).collect(),
+            Err(e) => vec![Err(e)],
+        }));
+    }
+    if let Some(start) = &config.record_regex {
+        input = Box::new(Records {
+            lines: input,
+            start: start.clone(),
+            pending: None,
+        });
+    }
+    if config.batching() {
+        input = Box::new(Batches::new(input, &config));
+    }
+    if let Some(path) = &diff_joblog {
+        input = diff_input(&config, path, input);
+    }
+    if (config.resume || config.resume_failed)
+        && let Some(path) = &config.joblog
+    {
+        input = resume_input(&config, path, input);
+    }
+    if let Some(estimate) = config.estimate {
+        print_estimate(&config, estimate, input);
+        return Ok(());
+    }
+    if let Err(e) = run(config, input).await {
+        eprintln!("error: {}", e);
+        std::process::exit(EXIT_INTERNAL_ERROR);
+    }
+    Ok(())
+}
+
+/// Parses the kyanite options an xargs or GNU parallel command line translates to
+fn compat_config(tool: compat::Tool, args: &[String]) -> Config {
+    let argv = compat::translate(tool, args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(EXIT_USAGE);
+    });
+    let mut config =
+        Config::try_parse_from(std::iter::once("kyanite".to_string()).chain(argv.clone()))
+            .unwrap_or_else(|e| e.exit());
+    if config.action.is_some() {
+        eprintln!("the translated command line names a kyanite subcommand");
+        std::process::exit(EXIT_USAGE);
+    }
+    config.argv = argv;
+    config
+}
+
+/// What makes options that parsed fine unusable together or on this platform, checked before
+/// anything runs
+fn usage_problem(config: &Config) -> Option<&'static str> {
+    let problem = if cfg!(not(unix)) && (config.limit_cpu.is_some() || config.limit_mem.is_some()) {
+        "--limit-cpu and --limit-mem are only supported on unix"
+    } else if cfg!(not(target_os = "linux"))
+        && (config.max_temp.is_some() || config.on_battery_jobs.is_some())
+    {
+        "--max-temp and --on-battery-jobs are only supported on Linux"
+    } else if config.pipe_part && config.arg_files.len() != 1 {
+        "--pipe-part reads its blocks from exactly one -a file"
+    } else if cfg!(not(target_os = "linux"))
+        && (config.reserve_cpus.is_some() || config.reserve_mem.is_some())
+    {
+        "--reserve-cpus and --reserve-mem are only supported on Linux"
+    } else if cfg!(not(unix)) && (config.chroot.is_some() || config.user.is_some()) {
+        "--chroot and --user are only supported on unix"
+    } else if cfg!(not(unix)) && config.flock_input.is_some() {
+        "--flock-input is only available on unix"
+    } else if cfg!(not(unix)) && config.syslog.is_some() {
+        "--syslog is only available on unix"
+    } else if cfg!(not(target_os = "macos")) && config.sandbox_profile.is_some() {
+        "--sandbox-profile requires sandbox-exec, which is only available on macOS"
+    } else {
+        return None;
+    };
+    Some(problem)
+}
+
+/// Applies each `--redact` pattern to `text` in turn
+fn redact<'a>(patterns: &[(Regex, String)], text: &'a str) -> Cow<'a, str> {
+    let mut text = Cow::Borrowed(text);
+    for (pattern, replacement) in patterns {
+        if let Cow::Owned(redacted) = pattern.replace_all(&text, replacement.as_str()) {
+            text = Cow::Owned(redacted);
+        }
+    }
+    text
+}
+
+/// Reads the latest record of each input from a job log, which may not exist yet
+fn recorded_runs(path: &Path) -> HashMap<String, joblog::Record> {
+    match joblog::read(path) {
+        Ok(records) => records,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+        Err(e) => {
+            eprintln!("error reading job log {}: {}", path.display(), e);
+            std::process::exit(EXIT_INTERNAL_ERROR);
+        }
+    }
+}
+
+/// Drops the inputs the job log already records as finished for `--resume`, or as succeeded for
+/// `--resume-failed`, so a rerun over the same input picks up where the last one stopped
+fn resume_input(config: &Config, path: &Path, input: Input) -> Input {
+    let records = recorded_runs(path);
+    let rerun_failed = config.resume_failed;
+    let patterns = config.redact.clone();
+    let verbose = config.verbose;
+    Box::new(input.filter(move |line| {
+        let Ok(line) = line else {
+            return true;
+        };
+        let key = redact(&patterns, line);
+        let done = records
+            .get(key.as_ref())
+            .is_some_and(|record| record.exit == 0 || !rerun_failed);
+        if done && verbose {
+            eprintln!("skipping {}: already in the job log", key);
+        }
+        !done
+    }))
+}
+
+/// Reads all input and keeps the lines that are new, changed or failed since the runs recorded
+/// in the job log at `path`, reporting what was skipped and removed on stderr
+fn diff_input(config: &Config, path: &Path, input: Input) -> Input {
+    let records = recorded_runs(path);
+    let mut lines = Vec::new();
+    for line in input {
+        match line {
+            Ok(line) if !line.trim().is_empty() => lines.push(line),
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("error reading input: {}", e);
+                std::process::exit(EXIT_INTERNAL_ERROR);
+            }
+        }
+    }
+
+    let diff = joblog::diff(&records, config.template(), lines, |line| {
+        config.redact(line).into_owned()
+    });
+    for input in &diff.removed {
+        eprintln!("removed: {}", input);
+    }
+    eprintln!(
+        "{} new, {} changed, {} failed, {} unchanged, {} removed since {}",
+        diff.new,
+        diff.changed,
+        diff.failed,
+        diff.unchanged,
+        diff.removed.len(),
+        path.display()
+    );
+    Box::new(diff.scheduled.into_iter().map(Ok))
+}
+
+/// Checks that `--chroot` and `--user` can be honored and resolves the user, exiting if not
+#[cfg(unix)]
+fn jail(config: &mut Config) {
+    if !jail::is_root() {
+        eprintln!("--chroot and --user require running as root");
+        std::process::exit(EXIT_INTERNAL_ERROR);
+    }
+    if let Some(root) = &config.chroot
+        && !root.is_dir()
+    {
+        eprintln!("chroot directory {} does not exist", root.display());
+        std::process::exit(EXIT_INTERNAL_ERROR);
+    }
+    if let Some(user) = &config.user {
+        match jail::parse_user(user) {
+            Ok(ids) => config.user_ids = Some(ids),
+            Err(e) => {
+                eprintln!("error resolving --user {}: {}", user, e);
+                std::process::exit(EXIT_INTERNAL_ERROR);
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn jail(_config: &mut Config) {
+    unreachable!("--chroot and --user are rejected off unix before a run starts");
+}
+
+/// Prints the projected duration of a run for `--dry-run --estimate` without running it
+fn print_estimate(config: &Config, estimate: Estimate, input: Input) {
+    let mut jobs = Vec::new();
+    for line in input {
+        match line {
+            Ok(line) if !line.trim().is_empty() => {
+                if config.max_jobs > 0 && jobs.len() >= config.max_jobs {
+                    break;
+                }
+                jobs.push(Job::new(jobs.len(), line));
+            }
+            Ok(_) => continue,
+            Err(e) => {
+                eprintln!("error reading input: {}", e);
+                std::process::exit(EXIT_INTERNAL_ERROR);
+            }
+        }
+    }
+
+    let (per_job, basis) = match estimate {
+        Estimate::PerJob(duration) => (duration, "given".to_string()),
+        Estimate::Sample(count) => {
+            let sampled: Vec<&Job> = jobs.iter().take(count).collect();
+            let started = Instant::now();
+            for job in &sampled {
+                let Ok((_, mut command)) =
+                    prepare_command(config, None, job, Some(jobs.len()), None)
+                else {
+                    continue;
+                };
+                if config.verbose {
+                    eprintln!("sampling job {}", config.start_seq + job.id);
+                }
+                let _ = command.stdin(Stdio::null()).output();
+            }
+            let mean = started.elapsed() / sampled.len().max(1) as u32;
+            (mean, format!("mean of {} sampled jobs", sampled.len()))
+        }
+    };
+
+    let (wall_time, per_worker) = project_run(jobs.len(), config.workers, per_job);
+    let finish = std::time::SystemTime::now() + wall_time;
+    let counts: Vec<String> = per_worker.iter().map(usize::to_string).collect();
+    println!("jobs: {}", jobs.len());
+    println!("workers: {}", config.workers);
+    println!("per job: {:?} ({})", per_job, basis);
+    println!("projected wall time: {:?}", wall_time);
+    println!("projected finish: {}", audit::format_timestamp(finish));
+    println!("jobs per worker: {}", counts.join(" "));
+}
+
+/// Projects the wall time and per-worker job counts of `jobs` equally long jobs
+fn project_run(jobs: usize, workers: usize, per_job: Duration) -> (Duration, Vec<usize>) {
+    let workers = workers.clamp(1, jobs.max(1));
+    let per_worker: Vec<usize> = (0..workers)
+        .map(|worker| jobs / workers + usize::from(worker < jobs % workers))
+        .collect();
+    let rounds = per_worker.first().copied().unwrap_or(0);
+    (per_job * rounds as u32, per_worker)
+}
+
+/// Re-runs the commands of a recorded run verbatim, with its concurrency and ordering
+/// Expands the template for every `--case` input and compares it with the expected command,
+/// returning the number of mismatches
+fn test_template(args: &TestTemplateArgs, out: &mut impl Write) -> usize {
+    let config = template_config(
+        &args.template,
+        &args.placeholder,
+        &args.field_separator,
+        args.start_seq,
+    );
+    let total = Some(args.cases.len());
+    let mut failed = 0;
+    for (id, (line, expected)) in args.cases.iter().enumerate() {
+        let job = Job::new(id, line.clone());
+        let actual = match expand_command(config.template(), &config, None, &job, total) {
+            Ok(command) => command,
+            Err(e) => e,
+        };
+        if actual == *expected {
+            let _ = writeln!(out, "ok: {}", line);
+        } else {
+            failed += 1;
+            let _ = writeln!(out, "FAIL: {}", line);
+            let _ = writeln!(out, "  expected: {}", expected);
+            let _ = writeln!(out, "  actual:   {}", actual);
+        }
+    }
+    let _ = writeln!(
+        out,
+        "{} passed, {} failed",
+        args.cases.len() - failed,
+        failed
+    );
+    failed
+}
+
+/// The settings of a run that expands `template` with these placeholder options
+fn template_config(
+    template: &str,
+    placeholder: &str,
+    field_separator: &str,
+    start_seq: usize,
+) -> Config {
+    let start_seq = start_seq.to_string();
+    Config::parse_from([
+        "kyanite",
+        "-I",
+        placeholder,
+        "--field-separator",
+        field_separator,
+        "--start-seq",
+        &start_seq,
+        "--",
+        template,
+    ])
+}
+
+/// Lines `kyanite map` reads before expanding them across its threads
+const MAP_CHUNK: usize = 4096;
+
+/// Expands the template for each non-blank input line with `kyanite map`, writing the results
+/// in input order; chunks of lines are split across `-j` threads
+fn map_lines(args: &MapArgs, input: impl BufRead, out: &mut impl Write) -> io::Result<()> {
+    let config = template_config(
+        &args.template,
+        &args.placeholder,
+        &args.field_separator,
+        args.start_seq,
+    );
+    let mut lines = input
+        .lines()
+        .filter(|line| line.as_ref().map_or(true, |line| !line.trim().is_empty()));
+    // `{total}` needs every line read first
+    if needs_total(&config) {
+        let lines: Vec<String> = lines.collect::<io::Result<_>>()?;
+        let total = Some(lines.len());
+        write_mapped(&config, args.workers, 0, lines, total, out)?;
+        return out.flush();
+    }
+    let mut first_id = 0;
+    loop {
+        let chunk: Vec<String> = lines.by_ref().take(MAP_CHUNK).collect::<io::Result<_>>()?;
+        if chunk.is_empty() {
+            return out.flush();
+        }
+        let len = chunk.len();
+        write_mapped(&config, args.workers, first_id, chunk, None, out)?;
+        first_id += len;
+    }
+}
+
+fn write_mapped(
+    config: &Config,
+    workers: usize,
+    first_id: usize,
+    lines: Vec<String>,
+    total: Option<usize>,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let per_thread = lines.len().div_ceil(workers.max(1)).max(1);
+    let expanded: Vec<Vec<String>> = thread::scope(|scope| {
+        let handles: Vec<_> = lines
+            .chunks(per_thread)
+            .enumerate()
+            .map(|(n, part)| {
+                scope.spawn(move || {
+                    part.iter()
+                        .enumerate()
+                        .map(|(i, line)| {
+                            let job = Job::new(first_id + n * per_thread + i, line.clone());
+                            expand_command(config.template(), config, None, &job, total)
+                                .unwrap_or_else(|e| e)
+                        })
+                        .collect()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    });
+    for line in expanded.iter().flatten() {
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+async fn replay(args: ReplayArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let plan = match audit::read_replay(&args.audit, args.run.as_deref(), args.only_failed) {
+        Ok(plan) => plan,
+        Err(e) => {
+            eprintln!("error reading audit log {}: {}", args.audit.display(), e);
+            std::process::exit(EXIT_INTERNAL_ERROR);
+        }
+    };
+
+    let mut argv = vec![
+        "kyanite".to_string(),
+        "--jobs".to_string(),
+        args.workers.unwrap_or(plan.jobs).to_string(),
+    ];
+    if plan.keep_order {
+        argv.push("--keep-order".to_string());
+    }
+    if args.verbose {
+        argv.push("--verbose".to_string());
+        eprintln!(
+            "replaying {} commands from run {}",
+            plan.commands.len(),
+            plan.run
+        );
+    }
+    argv.extend(["--".to_string(), "{}".to_string()]);
+
+    let input: Input = Box::new(plan.commands.into_iter().map(Ok));
+    run(Config::parse_from(argv), input).await
+}
+
+async fn run(config: Config, input: Input) -> Result<(), Box<dyn std::error::Error>> {
+    let config_with_placeholder = Config {
+        placeholder: config.placeholder.clone(),
+        ..config
+    };
+    let config = Arc::new(config_with_placeholder);
+    let started = Instant::now();
+    let jobserver = open_jobserver(&config);
+    let hosts = config
+        .hostfile
+        .as_ref()
+        .map(|path| match Hosts::load(path) {
+            Ok(hosts) => hosts,
+            Err(e) => {
+                eprintln!("error reading hostfile {}: {}", path.display(), e);
+                std::process::exit(EXIT_INTERNAL_ERROR);
+            }
+        });
+    let auto_jobs = config
+        .auto_jobs
+        .then(|| AutoJobs::new(config.workers, config.target_latency, config.target_load));
+    let audit = config.audit.as_ref().map(|path| {
+        match AuditLog::open(path).and_then(|audit| {
+            audit.record_run(config.workers, config.keep_order)?;
+            Ok(audit)
+        }) {
+            Ok(audit) => audit,
+            Err(e) => {
+                eprintln!("error opening audit log {}: {}", path.display(), e);
+                std::process::exit(EXIT_INTERNAL_ERROR);
+            }
+        }
+    });
+    let joblog = config
+        .joblog
+        .as_ref()
+        .filter(|_| !config.dry_run)
+        .map(
+            |path| match JobLog::open(path, config.commit_interval.unwrap_or_default()) {
+                Ok(joblog) => joblog,
+                Err(e) => {
+                    eprintln!("error opening job log {}: {}", path.display(), e);
+                    std::process::exit(EXIT_INTERNAL_ERROR);
+                }
+            },
+        );
+    let events = config
+        .ws_listen
+        .as_ref()
+        .map(|addr| match EventStream::listen(addr) {
+            Ok(events) => {
+                events.run(config.workers);
+                events
+            }
+            Err(e) => {
+                eprintln!("error listening on {}: {}", addr, e);
+                std::process::exit(EXIT_INTERNAL_ERROR);
+            }
+        });
+    let splitter = config.split_output.map(|count| {
+        let prefix = config.outfile_prefix.as_deref().unwrap_or_default();
+        Splitter::create(prefix, count).unwrap_or_else(|e| {
+            eprintln!("error creating split output: {}", e);
+            std::process::exit(EXIT_INTERNAL_ERROR);
+        })
+    });
+    let cache = config
+        .cache
+        .as_ref()
+        .map(|dir| match Cache::open(dir.clone()) {
+            Ok(cache) => cache,
+            Err(e) => {
+                eprintln!("error opening cache {}: {}", dir.display(), e);
+                std::process::exit(EXIT_INTERNAL_ERROR);
+            }
+        });
+    let state = Arc::new(
+        RunState::new(config.workers)
+            .with_breaker(config.circuit_breaker)
+            .with_auto_jobs(auto_jobs)
+            .with_audit(audit)
+            .with_joblog(joblog)
+            .with_clock(
+                config
+                    .time_report
+                    .then(|| timing::Clock::new(config.workers)),
+            )
+            .with_budget(config.self_mem_limit.map(memory::Budget::new))
+            .with_events(events)
+            .with_syslog(config.syslog.map(Syslog::open))
+            .with_tracer(config.otel_endpoint.clone().map(Tracer::new))
+            .with_manifest(config.manifest.as_ref().map(|_| Manifest::default()))
+            .with_http(config.http.as_ref().map(|_| http::Pool::default()))
+            .with_cache(cache)
+            .with_jobserver(jobserver)
+            .with_hosts(hosts)
+            .with_lanes(config.order_within_key.as_ref().map(|_| Lanes::default()))
+            .with_transactions(config.transaction_size.map(Transactions::new))
+            .with_pacer(config.max_lines_per_sec.map(Pacer::new))
+            .with_ramp_up(config.ramp_up)
+            .with_transfers(config.transfer.then(|| {
+                let limit = config.transfer_concurrency.unwrap_or(config.workers);
+                Transfers::new(limit, config.bwlimit.map(|rate| rate.div_ceil(1024)))
+            })),
+    );
+    let (job_tx, job_rx) = mpsc::channel::<Job>();
+    let (result_tx, result_rx) = mpsc::channel::<JobResult>();
+
+    let slot_dirs = if config.worker_tmpdir {
+        match create_slot_dirs(config.workers) {
+            Ok(dirs) => dirs.into_iter().map(Some).collect(),
+            Err(e) => {
+                eprintln!("error creating worker scratch directories: {}", e);
+                std::process::exit(EXIT_INTERNAL_ERROR);
+            }
+        }
+    } else {
+        vec![None; config.workers]
+    };
+
+    let job_rx = Arc::new(Mutex::new(job_rx));
+    let mut handles = Vec::new();
+
+    for (worker_id, slot_dir) in slot_dirs.into_iter().enumerate() {
+        let job_rx = Arc::clone(&job_rx);
+        let result_tx = result_tx.clone();
+        let config = Arc::clone(&config);
+        let state = Arc::clone(&state);
+
+        let handle = thread::spawn(move || {
+            supervise(
+                worker_id,
+                config.start_seq,
+                &job_rx,
+                &result_tx,
+                |handover| {
+                    worker(
+                        worker_id,
+                        slot_dir.clone(),
+                        Arc::clone(&job_rx),
+                        result_tx.clone(),
+                        Arc::clone(&config),
+                        Arc::clone(&state),
+                        handover,
+                    )
+                },
+            );
+        });
+        handles.push(handle);
+    }
+
+    let config_clone = Arc::clone(&config);
+    let collector_state = Arc::clone(&state);
+    let collector_handle =
+        thread::spawn(move || result_collector(result_rx, config_clone, collector_state, splitter));
+
+    if let Some(budget) = config.max_runtime {
+        let state = Arc::clone(&state);
+        let kill = config.halt_on_budget == BudgetHalt::Kill;
+        thread::spawn(move || {
+            thread::sleep(budget);
+            if state.stop(format!("max runtime of {:?} reached", budget)) {
+                eprintln!("{}, no new jobs will be started", state.reason());
+                if kill {
+                    state.kill_running();
+                }
+            }
+        });
+    }
+
+    if config.hostfile_watch {
+        watch_hostfile(&state, config.verbose);
+    }
+    if let Some(timeout) = config.timeout {
+        enforce_timeout(&state, timeout, config.timeout_grace);
+    }
+    #[cfg(target_os = "linux")]
+    if config.reserve_cpus.is_some() || config.reserve_mem.is_some() {
+        let reservation = reserve::Reservation {
+            cpus: config.reserve_cpus.unwrap_or(0),
+            memory: config.reserve_mem.unwrap_or(0),
+        };
+        keep_reservation(&state, reservation, config.verbose);
+    }
+    #[cfg(target_os = "linux")]
+    if config.max_temp.is_some() || config.on_battery_jobs.is_some() {
+        throttle_for_power(&state, &config);
+    }
+    if state.hosts.is_some() && !config.dry_run {
+        check_hosts(&state, &config);
+    }
+
+    let status = config
+        .status_fifo
+        .as_ref()
+        .map(|path| report_status(path.clone(), config.status_interval, &state, started));
+
+    let progress = (config.bar || config.eta).then(|| show_progress(&state, started, config.bar));
+
+    let input_config = Arc::clone(&config);
+    let input_state = Arc::clone(&state);
+    thread::spawn(move || {
+        read_input(input, job_tx, &input_config, &input_state);
+    });
+
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "dragonfly"
+    ))]
+    tokio::spawn(report_on_info(Arc::clone(&state), started));
+
+    let mut workers_done = tokio::task::spawn_blocking(move || {
+        for handle in handles {
+            let _ = handle.join();
+        }
+    });
+
+    tokio::select! {
+        _ = &mut workers_done => {}
+        _ = signal::ctrl_c() => {
+            if config.verbose {
+                eprintln!("\nreceived interrupt signal, terminating running jobs...");
+            }
+            state.stop("received interrupt signal");
+            state.signal_running(false);
+            tokio::select! {
+                _ = &mut workers_done => {}
+                _ = signal::ctrl_c() => {
+                    eprintln!("\nreceived second interrupt signal, killing running jobs");
+                    state.signal_running(true);
+                    let _ = io::stdout().flush();
+                    std::process::exit(EXIT_INTERRUPTED);
+                }
+            }
+        }
+        path = freeze_requested(&config, &state, started) => {
+            eprintln!("freezing: waiting for running jobs to finish");
+            let _ = state.frozen.set(path);
+            state.stop("frozen");
+            let _ = workers_done.await;
+        }
+    }
+
+    if let Some(path) = state.frozen.get() {
+        match freeze_run(path, &config, &job_rx, &state) {
+            Ok(pending) => eprintln!("froze {} pending jobs to {}", pending, path.display()),
+            Err(e) => eprintln!("error writing state {}: {}", path.display(), e),
+        }
+    } else if state.is_stopped()
+        && let Some(path) = &config.remaining_input
+    {
+        match write_remaining(path, &job_rx, &state, REMAINING_INPUT_WAIT) {
+            Ok(true) if config.verbose => eprintln!("remaining input written to {}", path),
+            Ok(true) => {}
+            Ok(false) => eprintln!(
+                "input still open after {}s, {} holds the remaining input read until then",
+                REMAINING_INPUT_WAIT.as_secs(),
+                path
+            ),
+            Err(e) => eprintln!("error writing remaining input to {}: {}", path, e),
+        }
+    }
+
+    if let Some(joblog) = &state.joblog
+        && let Err(e) = joblog.commit()
+    {
+        eprintln!("error writing job log: {}", e);
+    }
+
+    // a thawed run that finished has used up its state, so it cannot be resumed twice
+    if let Some((path, _)) = &config.thawed
+        && !state.is_stopped()
+        && let Err(e) = fs::remove_file(path)
+    {
+        eprintln!("error removing state {}: {}", path.display(), e);
+    }
+
+    drop(result_tx);
+    let mut counts = collector_handle.join().unwrap_or_default();
+
+    if let Some((finished, handle)) = progress {
+        finished.store(true, Ordering::SeqCst);
+        let _ = handle.join();
+    }
+
+    if config.review {
+        let fixed = review_failures(&state);
+        counts.total -= fixed;
+        counts.succeeded += fixed;
+    }
+
+    if let Some(status) = &status {
+        status.finish(&status_line(&state, started));
+    }
+
+    if let Some(events) = &state.events {
+        let stopped = state.is_stopped().then(|| state.reason());
+        events.close(
+            counts.succeeded,
+            counts.total,
+            stopped,
+            &state.usage.lock().unwrap_or_else(PoisonError::into_inner),
+        );
+    }
+
+    if let (Some(manifest), Some(path)) = (&state.manifest, &config.manifest) {
+        match write_atomically(path, manifest.to_json().as_bytes(), true, None) {
+            Ok(_) if config.verbose => eprintln!("manifest written to {}", path.display()),
+            Ok(_) => {}
+            Err(e) => eprintln!("error writing manifest {}: {}", path.display(), e),
+        }
+    }
+
+    if let Some(tracer) = &state.tracer {
+        tracer.finish(
+            &config.redact(config.template()),
+            counts.succeeded,
+            counts.total,
+        );
+    }
+
+    if config.sample.is_some() {
+        print_sample_summary(&config, &state, &counts, started.elapsed());
+    }
+
+    if (config.verbose || config.sample.is_some())
+        && let Some(summary) = state
+            .usage
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .summary()
+    {
+        eprintln!("{}", summary);
+    }
+
+    if let Some(clock) = &state.clock {
+        eprintln!("{}", clock.report(started.elapsed(), config.workers));
+    }
+
+    if let (Some(transactions), Some(template)) = (&state.transactions, &config.rollback) {
+        for (group, inputs) in transactions.unfinished() {
+            run_rollback(template, group, &inputs, &config);
+        }
+    }
+
+    if let Some(template) = &config.on_complete {
+        run_on_complete(template, &config, &state, &counts);
+    }
+
+    if !config.mail_to.is_empty()
+        && (config.mail_on == MailOn::Always || counts.total > 0 || state.is_stopped())
+    {
+        mail_report(&config, &state, &counts, started.elapsed());
+    }
+
+    if let Some(path) = &config.script_path {
+        let _ = fs::remove_file(path);
+    }
+    let _ = fs::remove_dir(run_dir());
+
+    if config.race && counts.succeeded == 0 {
+        eprintln!("no job succeeded");
+    }
+    let status = exit_status(&counts, config.race);
+    if status != 0 {
+        let _ = io::stdout().flush();
+        std::process::exit(status);
+    }
+
+    Ok(())
+}
+
+/// Whether some or all of a run's jobs failed; in a race only whether one won, and once the
+/// `--until` condition was reached not at all, since the other jobs are cancelled
+fn exit_status(counts: &FailureCounts, race: bool) -> i32 {
+    if race {
+        return if counts.succeeded > 0 {
+            0
+        } else {
+            EXIT_ALL_FAILED
+        };
+    }
+    if counts.reached {
+        return 0;
+    }
+    match (counts.total, counts.succeeded) {
+        (0, _) => 0,
+        (_, 0) => EXIT_ALL_FAILED,
+        _ => EXIT_SOME_FAILED,
+    }
+}
+
+/// Ends jobs that run longer than `--timeout`, escalating from SIGTERM to SIGKILL when one is
+/// still running `grace` later; jobs still finishing after the run was stopped are held to it too
+fn enforce_timeout(state: &Arc<RunState>, timeout: Duration, grace: Duration) {
+    let state = Arc::clone(state);
+    thread::spawn(move || {
+        loop {
+            thread::sleep(Duration::from_millis(50));
+            for (slot, since) in state.since.iter().enumerate() {
+                let Some(elapsed) = since
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .map(|since| since.elapsed())
+                else {
+                    continue;
+                };
+                let Some(pid) = *state.running[slot]
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                else {
+                    continue;
+                };
+                if elapsed >= timeout + grace {
+                    expire(pid, true);
+                } else if elapsed >= timeout && !state.timed_out[slot].swap(true, Ordering::SeqCst)
+                {
+                    expire(pid, false);
+                }
+            }
+        }
+    });
+}
+
+/// Re-reads the `--hostfile` every second so hosts can be added or retired during the run
+fn watch_hostfile(state: &Arc<RunState>, verbose: bool) {
+    let state = Arc::clone(state);
+    thread::spawn(move || {
+        let Some(hosts) = &state.hosts else {
+            return;
+        };
+        while !state.is_stopped() {
+            thread::sleep(Duration::from_secs(1));
+            match hosts.reload() {
+                Ok(changes) => {
+                    for host in changes.added {
+                        eprintln!("host {} added", host);
+                    }
+                    for host in changes.removed {
+                        eprintln!("host {} removed, draining its running jobs", host);
+                    }
+                }
+                Err(e) if verbose => eprintln!("error reloading hostfile: {}", e),
+                Err(_) => {}
+            }
+        }
+    });
+}
+
+/// Keeps the `--reserve-cpus` and `--reserve-mem` headroom free: while the machine has less, no
+/// new jobs start and running ones are paused one a second, and once there is room again they
+/// resume one a second before new jobs start
+#[cfg(target_os = "linux")]
+fn keep_reservation(state: &Arc<RunState>, reservation: reserve::Reservation, verbose: bool) {
+    let state = Arc::clone(state);
+    thread::spawn(move || {
+        let cpus = num_cpus::get();
+        let mut meter = reserve::Meter::default();
+        while !state.is_stopped() && !state.killing.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_secs(1));
+            let Some(headroom) = meter.measure(cpus) else {
+                continue;
+            };
+            if reservation.violated(headroom) {
+                state.holding.store(true, Ordering::SeqCst);
+                // one job always keeps running, so paused jobs holding memory cannot stall the run
+                if active_jobs(&state).is_empty()
+                    && let Some(pid) = resume_job(&state)
+                    && verbose
+                {
+                    eprintln!("resumed job process {} to keep the run going", pid);
+                } else if let Some(pid) = suspend_job(&state)
+                    && verbose
+                {
+                    eprintln!(
+                        "paused job process {}: {:.1} CPUs idle, {} available",
+                        pid,
+                        headroom.idle_cpus,
+                        tune::size(headroom.available)
+                    );
+                }
+            } else if state
+                .suspended
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .is_empty()
+            {
+                state.holding.store(false, Ordering::SeqCst);
+            } else if reservation.restored(headroom)
+                && let Some(pid) = resume_job(&state)
+                && verbose
+            {
+                eprintln!("resumed job process {}", pid);
+            }
+        }
+        // stopped jobs could never finish or be terminated
+        while resume_job(&state).is_some() {}
+        state.holding.store(false, Ordering::SeqCst);
+    });
+}
+
+/// The pids of running jobs that are not paused, by worker slot
+#[cfg(target_os = "linux")]
+fn active_jobs(state: &RunState) -> Vec<u32> {
+    let suspended = state
+        .suspended
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    state
+        .running
+        .iter()
+        .filter_map(|slot| *slot.lock().unwrap_or_else(PoisonError::into_inner))
+        .filter(|pid| !suspended.contains(pid))
+        .collect()
+}
+
+/// Checks the temperature and power supply every few seconds: above `--max-temp` no new jobs
+/// start until the sensors cool down, and on battery at most `--on-battery-jobs` run
+#[cfg(target_os = "linux")]
+fn throttle_for_power(state: &Arc<RunState>, config: &Config) {
+    let state = Arc::clone(state);
+    let (workers, max_temp, battery_jobs) =
+        (config.workers, config.max_temp, config.on_battery_jobs);
+    let verbose = config.verbose;
+    thread::spawn(move || {
+        let mut hot = false;
+        let mut limit = workers;
+        while !state.is_stopped() {
+            if let Some(max) = max_temp
+                && let Some(temperature) = power::max_temperature()
+            {
+                if !hot && temperature >= max {
+                    hot = true;
+                    eprintln!(
+                        "{:.0}C reached, holding new jobs until it cools",
+                        temperature
+                    );
+                } else if hot && temperature <= max - power::COOLDOWN {
+                    hot = false;
+                    if verbose {
+                        eprintln!("cooled to {:.0}C, resuming", temperature);
+                    }
+                }
+            }
+            let wanted = match battery_jobs {
+                _ if hot => 0,
+                Some(jobs) if power::on_battery() => jobs.min(workers),
+                _ => workers,
+            };
+            if wanted != limit {
+                if verbose && !hot {
+                    eprintln!("running up to {} jobs", wanted);
+                }
+                limit = wanted;
+                state.limit.store(limit, Ordering::SeqCst);
+            }
+            thread::sleep(Duration::from_secs(2));
+        }
+    });
+}
+
+/// Pauses the active job in the highest worker slot unless it is the only one, returning its
+/// pid
+#[cfg(target_os = "linux")]
+fn suspend_job(state: &RunState) -> Option<u32> {
+    let active = active_jobs(state);
+    if active.len() < 2 {
+        return None;
+    }
+    let pid = *active.last()?;
+    reserve::signal_tree(pid, libc::SIGSTOP);
+    state
+        .suspended
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(pid);
+    Some(pid)
+}
+
+/// Resumes the most recently paused job, returning its pid
+#[cfg(target_os = "linux")]
+fn resume_job(state: &RunState) -> Option<u32> {
+    let pid = state
+        .suspended
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .pop()?;
+    reserve::signal_tree(pid, libc::SIGCONT);
+    Some(pid)
+}
+
+/// Probes every host with `--host-check` each interval, taking failing hosts out of rotation
+/// and returning them once they pass again
+fn check_hosts(state: &Arc<RunState>, config: &Arc<Config>) {
+    let state = Arc::clone(state);
+    let config = Arc::clone(config);
+    thread::spawn(move || {
+        let Some(hosts) = &state.hosts else {
+            return;
+        };
+        while !state.is_stopped() {
+            thread::scope(|scope| {
+                for host in hosts.names() {
+                    let config = &config;
+                    scope.spawn(move || {
+                        let healthy = remote_command(&host, &shell_command(&config.host_check))
+                            .stdin(Stdio::null())
+                            .stdout(Stdio::null())
+                            .stderr(Stdio::null())
+                            .status()
+                            .is_ok_and(|status| status.success());
+                        match hosts.set_health(&host, healthy) {
+                            Some(false) => eprintln!(
+                                "host {} failed its health check, no longer dispatching jobs to it",
+                                host
+                            ),
+                            Some(true) => eprintln!("host {} is healthy again", host),
+                            None => {}
+                        }
+                    });
+                }
+            });
+            thread::sleep(config.host_check_interval);
+        }
+    });
+}
+
+/// Writes a status line to the `--status-fifo` pipe every `interval` once a reader opens it
+fn report_status(
+    path: PathBuf,
+    interval: Duration,
+    state: &Arc<RunState>,
+    started: Instant,
+) -> Arc<StatusFifo> {
+    let status = Arc::new(StatusFifo::new());
+    let fifo = Arc::clone(&status);
+    let state = Arc::clone(state);
+    thread::spawn(move || {
+        if let Err(e) = fifo.open(&path) {
+            eprintln!("error opening status fifo {}: {}", path.display(), e);
+            return;
+        }
+        while fifo.write(&status_line(&state, started)) {
+            thread::sleep(interval);
+        }
+    });
+    status
+}
+
+/// Redraws the `--bar` or `--eta` progress line on stderr a few times a second, until the flag
+/// returned is set; the last line drawn then stays on the terminal
+fn show_progress(
+    state: &Arc<RunState>,
+    started: Instant,
+    bar: bool,
+) -> (Arc<AtomicBool>, thread::JoinHandle<()>) {
+    let finished = Arc::new(AtomicBool::new(false));
+    let done = Arc::clone(&finished);
+    let state = Arc::clone(state);
+    let handle = thread::spawn(move || {
+        loop {
+            let last = done.load(Ordering::SeqCst);
+            let running = state
+                .current
+                .iter()
+                .filter(|slot| {
+                    slot.lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .is_some()
+                })
+                .count();
+            let line = status::progress(
+                state.done.load(Ordering::SeqCst),
+                state.total.get().copied(),
+                running,
+                state.failures.load(Ordering::SeqCst),
+                started.elapsed(),
+                bar,
+            );
+            eprint!("\r{}\x1b[K", line);
+            if last {
+                eprintln!();
+                return;
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+    });
+    (finished, handle)
+}
+
+/// What the run is doing right now, for `SIGUSR1` and `SIGINFO`
+fn status_snapshot(state: &RunState, started: Instant) -> String {
+    let workers: Vec<_> = state
+        .current
+        .iter()
+        .map(|slot| {
+            let current = slot.lock().unwrap_or_else(PoisonError::into_inner);
+            current
+                .as_ref()
+                .map(|(command, since)| (command.clone(), since.elapsed()))
+        })
+        .collect();
+    let queued = state.queued.load(Ordering::SeqCst);
+    status::snapshot(
+        state.done.load(Ordering::SeqCst),
+        state.failures.load(Ordering::SeqCst),
+        queued.saturating_sub(state.started.load(Ordering::SeqCst)),
+        started.elapsed(),
+        &workers,
+    )
+}
+
+fn status_line(state: &RunState, started: Instant) -> String {
+    status::line(
+        state.done.load(Ordering::SeqCst),
+        state.total.get().copied(),
+        state.failures.load(Ordering::SeqCst),
+        started.elapsed(),
+    )
+}
+
+/// Offers the failed jobs of the run for interactive triage on the terminal
+fn review_failures(state: &RunState) -> usize {
+    let mut failed =
+        std::mem::take(&mut *state.failed.lock().unwrap_or_else(PoisonError::into_inner));
+    if failed.is_empty() {
+        return 0;
+    }
+    failed.sort_by_key(|job| job.seq);
+
+    let tty = if cfg!(windows) { "CONIN$" } else { "/dev/tty" };
+    let mut input = match File::open(tty) {
+        Ok(file) => BufReader::new(file),
+        Err(e) => {
+            eprintln!("cannot review failed jobs without a terminal: {}", e);
+            return 0;
+        }
+    };
+    let run = |cmd: &str| shell_command(cmd).stdin(Stdio::null()).output();
+    match review::review(failed, &mut input, &mut io::stderr(), run) {
+        Ok(fixed) => fixed,
+        Err(e) => {
+            eprintln!("error reviewing failed jobs: {}", e);
+            0
+        }
+    }
+}
+
+/// Joins a jobserver inherited from kyanite or make, or creates one with `--jobserver`
+fn open_jobserver(config: &Config) -> Option<JobServer> {
+    if config.jobserver == Some(JobserverMode::Off) {
+        return None;
+    }
+    if let Some(jobserver) = JobServer::from_env() {
+        if config.verbose {
+            eprintln!("using jobserver inherited from parent process");
+        }
+        return Some(jobserver);
+    }
+    if let Some(jobserver) = JobServer::from_makeflags() {
+        if config.verbose {
+            eprintln!("using jobserver of parent make");
+        }
+        return Some(jobserver);
+    }
+    if config.jobserver != Some(JobserverMode::On) {
+        return None;
+    }
+    match JobServer::create(config.workers.saturating_sub(1)) {
+        Ok(jobserver) => Some(jobserver),
+        Err(e) => {
+            eprintln!("error creating jobserver: {}", e);
+            std::process::exit(EXIT_INTERNAL_ERROR);
+        }
+    }
+}
+
+fn run_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("kyanite-{}", std::process::id()))
+}
+
+fn write_script(script: &str) -> io::Result<PathBuf> {
+    let dir = run_dir();
+    fs::create_dir_all(&dir)?;
+    let path = dir.join("script.sh");
+    fs::write(&path, format!("{}\n", script))?;
+    Ok(path)
+}
+
+fn create_slot_dirs(workers: usize) -> io::Result<Vec<PathBuf>> {
+    let root = run_dir();
+    (0..workers)
+        .map(|worker_id| {
+            let dir = root.join(format!("slot-{}", worker_id));
+            fs::create_dir_all(&dir)?;
+            Ok(dir)
+        })
+        .collect()
+}
+
+fn read_input(input: Input, job_tx: mpsc::Sender<Job>, config: &Config, state: &RunState) {
+    let mut job_id = 0;
+    let mut seen = 0;
+    let confirm = config.confirm_threshold.is_some() && !config.dry_run;
+    let buffer = needs_total(config) || confirm;
+    let mut buffered = Vec::new();
+    let mut reservoir = Vec::new();
+
+    // lines a thawed run had read before it was frozen
+    let mut skip = 0;
+    if let Some((_, frozen)) = &config.thawed {
+        if let Some(total) = frozen.total {
+            let _ = state.total.set(total);
+        }
+        for (id, line) in &frozen.jobs {
+            let job = Job::new(*id, line.clone());
+            if !state.send_job(&job_tx, job) {
+                break;
+            }
+        }
+        let Some((lines, next)) = frozen.read else {
+            return;
+        };
+        (skip, seen, job_id) = (lines, lines, next);
+    }
+
+    for line in input {
+        // a frozen run stops reading, leaving the rest of the input to its thaw
+        if state.is_stopped() && (config.remaining_input.is_none() || state.frozen.get().is_some())
+        {
+            return;
+        }
+
+        if config.max_jobs > 0 && seen >= config.max_jobs {
+            break;
+        }
+
+        match line {
+            Ok(line) if !line.trim().is_empty() && skip > 0 => skip -= 1,
+            Ok(line) if !line.trim().is_empty() => {
+                seen += 1;
+                if let Some(size) = config.sample {
+                    if config.sample_random {
+                        sample_line(&mut reservoir, size, seen, line);
+                        continue;
+                    }
+                    if job_id >= size {
+                        continue;
+                    }
+                }
+
+                let job = new_job(job_id, line, config);
+
+                if config.verbose && !state.is_stopped() {
+                    eprintln!(
+                        "queued job {}: {}",
+                        config.start_seq + job.id,
+                        config.redact(&job.line)
+                    );
+                }
+
+                if buffer {
+                    buffered.push(job);
+                } else if !state.send_job(&job_tx, job) {
+                    break;
+                }
+
+                job_id += 1;
+                if !buffer {
+                    *state.read.lock().unwrap_or_else(PoisonError::into_inner) = (seen, job_id);
+                }
+            }
+            Ok(_) => continue,
+            Err(e) => {
+                eprintln!("error reading input: {}", e);
+                std::process::exit(EXIT_INTERNAL_ERROR);
+            }
+        }
+    }
+
+    for (_, line) in reservoir {
+        buffered.push(new_job(job_id, line, config));
+        job_id += 1;
+    }
+
+    let _ = state.input_total.set(seen);
+    let _ = state.total.set(job_id);
+
+    if confirm && !confirm_run(&buffered, config) {
+        if state.stop("batch was not confirmed") {
+            eprintln!("batch was not confirmed, no jobs will be started");
+        }
+        return;
+    }
+
+    for job in buffered {
+        if !state.send_job(&job_tx, job) {
+            break;
+        }
+    }
+    *state.read.lock().unwrap_or_else(PoisonError::into_inner) = (seen, job_id);
+
+    if config.verbose && !state.is_stopped() {
+        eprintln!("input finished, processed {} jobs", job_id);
+    }
+}
+
+/// A job for an input line, or for the name of a `--pipe` block with the block's data
+fn new_job(id: usize, line: String, config: &Config) -> Job {
+    let block = config
+        .pipe
+        .then(|| config.pipe_blocks.take(&line))
+        .flatten();
+    Job {
+        block,
+        ..Job::new(id, line)
+    }
+}
+
+/// Keeps a uniform random sample of `size` lines in input order (reservoir sampling)
+fn sample_line(reservoir: &mut Vec<(usize, String)>, size: usize, seen: usize, line: String) {
+    if reservoir.len() < size {
+        reservoir.push((seen, line));
+        return;
+    }
+    let slot = (random_u64(RandomStream::Sample, seen as u64) % seen as u64) as usize;
+    if slot < size {
+        reservoir.remove(slot);
+        let position = reservoir.partition_point(|(index, _)| *index < seen);
+        reservoir.insert(position, (seen, line));
+    }
+}
+
+/// Asks on the terminal before running a batch larger than `--confirm-threshold` or one that
+/// looks destructive
+fn confirm_run(jobs: &[Job], config: &Config) -> bool {
+    let tty = if cfg!(windows) { "CONIN$" } else { "/dev/tty" };
+    let mut input = match File::open(tty) {
+        Ok(file) => BufReader::new(file),
+        Err(e) => {
+            eprintln!("cannot confirm the batch without a terminal: {}", e);
+            return false;
+        }
+    };
+    confirm_batch(jobs, config, &mut input, &mut io::stderr()).unwrap_or_else(|e| {
+        eprintln!("error confirming the batch: {}", e);
+        false
+    })
+}
+
+/// Shows the size of the batch and a sample of its commands, and requires the job count to be
+/// typed back when confirmation is needed
+fn confirm_batch(
+    jobs: &[Job],
+    config: &Config,
+    input: &mut impl BufRead,
+    out: &mut impl Write,
+) -> io::Result<bool> {
+    let total = Some(jobs.len());
+    let commands: Vec<String> = jobs
+        .iter()
+        .filter_map(|job| prepare_command(config, None, job, total, None).ok())
+        .map(|(cmd_str, _)| cmd_str)
+        .collect();
+    let destructive: Vec<&String> = commands
+        .iter()
+        .filter(|command| looks_destructive(command))
+        .collect();
+    let threshold = config.confirm_threshold.unwrap_or(usize::MAX);
+    if jobs.len() <= threshold && destructive.is_empty() {
+        return Ok(true);
+    }
+
+    writeln!(out, "about to run {} jobs, for example:", jobs.len())?;
+    for command in commands.iter().take(5) {
+        writeln!(out, "  {}", command)?;
+    }
+    if !destructive.is_empty() {
+        writeln!(
+            out,
+            "{} commands look destructive, for example:",
+            destructive.len()
+        )?;
+        for command in destructive.iter().take(3) {
+            writeln!(out, "  {}", command)?;
+        }
+    }
+    write!(out, "type {} to run them: ", jobs.len())?;
+    out.flush()?;
+
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(answer.trim() == jobs.len().to_string())
+}
+
+/// Whether a command deletes files or data, by the commands and SQL statements it contains
+fn looks_destructive(command: &str) -> bool {
+    static DESTRUCTIVE: OnceLock<Regex> = OnceLock::new();
+    DESTRUCTIVE
+        .get_or_init(|| {
+            Regex::new(r"(?i)(^|[\s;&|(`])(rm|rmdir|shred|unlink|mkfs(\.\w+)?|dd)\s|\b(drop|truncate)\s+(table|database|schema)\b|\bdelete\s+from\b|--delete\b").unwrap()
+        })
+        .is_match(command)
+}
+
+/// Placeholders in the command, `--outfile` and hook templates that can never expand
+fn template_problems(config: &Config) -> Vec<String> {
+    let columns = config.columns;
+    let mut problems = lint::check(config.template(), &config.placeholder, columns, false);
+    if let Some(outfile) = &config.outfile {
+        for problem in lint::check(outfile, &config.placeholder, columns, false) {
+            problems.push(format!("--outfile {}", problem));
+        }
+    }
+    for (name, hook) in [
+        ("--on-success", &config.on_success),
+        ("--on-failure", &config.on_failure),
+    ] {
+        if let Some(hook) = hook {
+            for problem in lint::check(hook, &config.placeholder, columns, true) {
+                problems.push(format!("{} {}", name, problem));
+            }
+        }
+    }
+    problems
+}
+
+/// Whether any job template uses `{total}`, which requires reading all input before starting
+fn needs_total(config: &Config) -> bool {
+    [
+        Some(config.template()),
+        config.on_success.as_deref(),
+        config.on_failure.as_deref(),
+    ]
+    .into_iter()
+    .flatten()
+    .any(|template| expand_named(template, &config.placeholder, "total", "") != template)
+}
+
+/// How long `--remaining-input` waits for the rest of the input to be read
+const REMAINING_INPUT_WAIT: Duration = Duration::from_secs(2);
+
+/// Writes jobs that were never started to `path`, in input order, followed by the input read
+/// within `wait`; returns false if the input was still open by then
+fn write_remaining(
+    path: &str,
+    job_rx: &Mutex<mpsc::Receiver<Job>>,
+    state: &RunState,
+    wait: Duration,
+) -> io::Result<bool> {
+    let mut file = io::BufWriter::new(File::create(path)?);
+    for job in pending_jobs(job_rx, state) {
+        writeln!(file, "{}", job.line)?;
+    }
+    let job_rx = job_rx.lock().unwrap_or_else(PoisonError::into_inner);
+    let deadline = Instant::now() + wait;
+    let read_all = loop {
+        match job_rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(job) => writeln!(file, "{}", job.line)?,
+            Err(RecvTimeoutError::Disconnected) => break true,
+            Err(RecvTimeoutError::Timeout) => break false,
+        }
+    };
+    file.flush()?;
+    Ok(read_all)
+}
+
+/// Takes the jobs that were never started, in input order, without waiting for more input
+fn pending_jobs(job_rx: &Mutex<mpsc::Receiver<Job>>, state: &RunState) -> Vec<Job> {
+    let mut pending = std::mem::take(
+        &mut *state
+            .unstarted
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner),
+    );
+    if let Some(lanes) = &state.lanes {
+        pending.extend(lanes.drain());
+    }
+    pending.sort_by_key(|job| job.id);
+    pending.extend(
+        job_rx
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .try_iter(),
+    );
+    pending
+}
+
+/// Saves the pending jobs and arguments of a frozen run to its state file, returning how many
+/// jobs it holds
+fn freeze_run(
+    path: &Path,
+    config: &Config,
+    job_rx: &Mutex<mpsc::Receiver<Job>>,
+    state: &RunState,
+) -> io::Result<usize> {
+    // where reading stopped, unless the run had read all its input
+    let read = *state.read.lock().unwrap_or_else(PoisonError::into_inner);
+    let frozen = Frozen {
+        args: config.argv.clone(),
+        total: state.total.get().copied(),
+        read: state.input_total.get().is_none().then_some(read),
+        jobs: pending_jobs(job_rx, state)
+            .into_iter()
+            .map(|job| (job.id, job.line))
+            .collect(),
+    };
+    write_atomically(path, frozen.to_text().as_bytes(), true, None)?;
+    Ok(frozen.jobs.len())
+}
+
+/// Waits for `kyanite ctl freeze` to signal the run, returning where it wants the state saved,
+/// or turning every request down if `config` cannot be frozen
+///
+/// A `SIGUSR1` without a freeze request prints the run's status to stderr instead.
+#[cfg(unix)]
+async fn freeze_requested(config: &Config, state: &RunState, started: Instant) -> PathBuf {
+    use signal::unix::{SignalKind, signal};
+    let Ok(mut signals) = signal(SignalKind::user_defined1()) else {
+        return std::future::pending().await;
+    };
+    loop {
+        signals.recv().await;
+        let request = freeze::request_path(std::process::id());
+        match (fs::read_to_string(&request), freeze_refusal(config)) {
+            (Ok(_), Some(reason)) => {
+                let _ = freeze::refuse(reason);
+            }
+            (Ok(path), None) => {
+                let _ = fs::remove_file(&request);
+                return PathBuf::from(path);
+            }
+            (Err(_), _) => eprintln!("{}", status_snapshot(state, started)),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn freeze_requested(_config: &Config, _state: &RunState, _started: Instant) -> PathBuf {
+    std::future::pending().await
+}
+
+/// Why a run with `config` cannot save its pending jobs to a state file, if it cannot
+fn freeze_refusal(config: &Config) -> Option<&'static str> {
+    if config.group_by.is_some() {
+        Some("a --group-by run streams its input into long-running jobs and cannot be frozen")
+    } else if config.pipe {
+        Some("the blocks of a --pipe run are not kept in a state file, so it cannot be frozen")
+    } else {
+        None
+    }
+}
+
+/// Turns down every `kyanite ctl freeze` of a run that does not go through `run`, which would
+/// otherwise be killed by the signal
+#[cfg(unix)]
+fn refuse_freezing(config: &Config) {
+    use signal::unix::{SignalKind, signal};
+    let (Some(reason), Ok(mut signals)) =
+        (freeze_refusal(config), signal(SignalKind::user_defined1()))
+    else {
+        return;
+    };
+    tokio::spawn(async move {
+        while signals.recv().await.is_some() {
+            if fs::metadata(freeze::request_path(std::process::id())).is_ok() {
+                let _ = freeze::refuse(reason);
+            }
+        }
+    });
+}
+
+/// Prints the run's status to stderr on every `SIGINFO` (Ctrl+T)
+#[cfg(any(
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+async fn report_on_info(state: Arc<RunState>, started: Instant) {
+    use signal::unix::{SignalKind, signal};
+    let Ok(mut signals) = signal(SignalKind::info()) else {
+        return;
+    };
+    while signals.recv().await.is_some() {
+        eprintln!("{}", status_snapshot(&state, started));
+    }
+}
+
+/// Freezes a running kyanite with `kyanite ctl freeze`, waiting until it has saved its state
+fn ctl_freeze(args: &FreezeArgs) {
+    let state = args
+        .state
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(format!("kyanite-{}.state", args.pid)));
+    let state = std::path::absolute(&state).unwrap_or(state);
+    match freeze::request(args.pid, &state) {
+        Ok(()) if state.exists() => {
+            eprintln!(
+                "frozen to {}, resume with: kyanite thaw {}",
+                state.display(),
+                state.display()
+            );
+        }
+        Ok(()) => {
+            eprintln!(
+                "process {} exited without saving {}",
+                args.pid,
+                state.display()
+            );
+            std::process::exit(EXIT_INTERNAL_ERROR);
+        }
+        Err(e) => {
+            eprintln!("error freezing process {}: {}", args.pid, e);
+            std::process::exit(EXIT_INTERNAL_ERROR);
+        }
+    }
+}
+
+/// How many times a worker slot is restarted after panics before it is left empty
+const MAX_WORKER_PANICS: usize = 3;
+
+/// What a panicking worker leaves its replacement: the job it was running, run once more
+#[derive(Default)]
+struct Handover {
+    retry: Option<Job>,
+    running: Option<Job>,
+}
+
+/// Runs a worker, restarting it when it panics so its slot is not lost; the job it was running
+/// is run again by the new worker, and reported failed if it panics a second time
+fn supervise(
+    worker_id: usize,
+    start_seq: usize,
+    job_rx: &Mutex<mpsc::Receiver<Job>>,
+    result_tx: &mpsc::Sender<JobResult>,
+    mut run: impl FnMut(&mut Handover),
+) {
+    let mut handover = Handover::default();
+    let mut retried = None;
+    for panics in 1..=MAX_WORKER_PANICS {
+        let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| run(&mut handover))) else {
+            return;
+        };
+        // the queue is shared with the other workers, which must not panic in turn
+        job_rx.clear_poison();
+        let message = panic_message(payload.as_ref());
+        let failed = match handover.running.take() {
+            Some(job) if retried != Some(job.id) && panics < MAX_WORKER_PANICS => {
+                eprintln!(
+                    "error in worker {}: panicked running job {}: {}; restarting it to run the job again",
+                    worker_id,
+                    start_seq + job.id,
+                    message
+                );
+                retried = Some(job.id);
+                handover.retry = Some(job);
+                None
+            }
+            Some(job) => {
+                eprintln!(
+                    "error in worker {}: panicked running job {} again: {}",
+                    worker_id,
+                    start_seq + job.id,
+                    message
+                );
+                Some(job)
+            }
+            None => {
+                eprintln!(
+                    "error in worker {}: panicked: {}; restarting it",
+                    worker_id, message
+                );
+                None
+            }
+        };
+        if let Some(job) = failed {
+            let result = JobResult {
+                error: Some(format!("worker panicked: {}", message)),
+                input: job.line,
+                ..JobResult::new(job.id)
+            };
+            if result_tx.send(result).is_err() {
+                return;
+            }
+        }
+    }
+    eprintln!(
+        "error in worker {}: panicked {} times, leaving its slot empty",
+        worker_id, MAX_WORKER_PANICS
+    );
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => payload
+            .downcast_ref::<String>()
+            .map_or("unknown cause", String::as_str),
+    }
+}
+
+fn worker(
+    worker_id: usize,
+    slot_dir: Option<PathBuf>,
+    job_rx: Arc<Mutex<mpsc::Receiver<Job>>>,
+    result_tx: mpsc::Sender<JobResult>,
+    config: Arc<Config>,
+    state: Arc<RunState>,
+    handover: &mut Handover,
+) {
+    let mut slot_failed = false;
+
+    loop {
+        handover.running = None;
+        if state.is_stopped() {
+            state
+                .unstarted
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .extend(handover.retry.take());
+            break;
+        }
+
+        if !state.admits(worker_id) {
+            thread::sleep(Duration::from_millis(50));
+            continue;
+        }
+
+        // the job a panicked worker left is run before any other
+        let retry = handover.retry.take();
+        let ready = match retry {
+            Some(_) => None,
+            None => state.lanes.as_ref().and_then(Lanes::take_ready),
+        };
+        let (job, _lane) = match (retry, ready) {
+            (Some(job), _) => (job, None),
+            (None, Some((key, job))) => (job, state.lanes.as_ref().map(|lanes| lanes.hold(key))),
+            (None, None) => {
+                let rx = job_rx.lock().unwrap_or_else(PoisonError::into_inner);
+                let job = match rx.recv_timeout(Duration::from_millis(100)) {
+                    Ok(job) => {
+                        if let Some(budget) = &state.budget {
+                            budget.dequeue(job.size());
+                        }
+                        job
+                    }
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+                // admitted while the receiver is held, so jobs enter their lanes in input order
+                match (&state.lanes, &config.order_within_key) {
+                    (Some(lanes), Some(template)) => {
+                        let key = expand_template(
+                            template,
+                            &job.line,
+                            &config.field_separator,
+                            &config.placeholder,
+                        );
+                        // a job whose key is busy waits in its lane and is run by whichever
+                        // worker finishes the job ahead of it
+                        let Some(job) = lanes.admit(&key, job) else {
+                            continue;
+                        };
+                        (job, Some(lanes.hold(key)))
+                    }
+                    _ => (job, None),
+                }
+            }
+        };
+
+        handover.running = Some(job.clone());
+
+        let probe = state
+            .breaker
+            .as_ref()
+            .is_some_and(|breaker| wait_for_breaker(breaker, &state));
+        state.wait_until_resumed();
+
+        if let Some(jitter) = config.jitter
+            && !config.dry_run
+        {
+            thread::sleep(jitter.sample(job.id));
+        }
+
+        let token = match &state.jobserver {
+            Some(jobserver) if !config.dry_run => wait_for_token(jobserver, &state),
+            _ => None,
+        };
+
+        let host = match &state.hosts {
+            Some(hosts) if !config.dry_run => wait_for_host(hosts, &state),
+            _ => None,
+        };
+        let host_name = host.as_ref().map(|host| host.name());
+
+        if state.is_stopped() {
+            state
+                .unstarted
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(job);
+            break;
+        }
+        let start = state.started.fetch_add(1, Ordering::SeqCst);
+        let queue = state
+            .clock
+            .as_ref()
+            .map_or(Duration::ZERO, |clock| clock.taken(job.id));
+
+        if config.verbose {
+            match host_name {
+                Some(host) => {
+                    eprintln!(
+                        "worker {} processing job {} on {}",
+                        worker_id,
+                        config.start_seq + job.id,
+                        host
+                    )
+                }
+                None => eprintln!(
+                    "worker {} processing job {}",
+                    worker_id,
+                    config.start_seq + job.id
+                ),
+            }
+        }
+
+        let original = state.joblog.as_ref().map(|_| job.line.clone());
+        let mut job = match config
+            .preprocess
+            .as_ref()
+            .map(|p| preprocess(&job.line, p, config.path_style))
+        {
+            None => job,
+            Some(Ok(line)) if !line.is_empty() => Job { line, ..job },
+            Some(outcome) => {
+                let error = match outcome {
+                    Err(e) if config.preprocess_failure == PreprocessFailure::Fail => {
+                        Some(format!("preprocess failed: {}", e))
+                    }
+                    _ => {
+                        if config.verbose {
+                            eprintln!(
+                                "skipping job {} after preprocessing",
+                                config.start_seq + job.id
+                            );
+                        }
+                        None
+                    }
+                };
+                slot_failed |= error.is_some();
+                let result = JobResult {
+                    error,
+                    start,
+                    input: job.line.clone(),
+                    ..JobResult::new(job.id)
+                };
+                if !state.send_result(&result_tx, result) {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        let mut lock = None;
+        let unusable = claim_input(&mut job, &config)
+            .or_else(|| {
+                line_too_long(&job.line, config.max_line_length)
+                    .map(|problem| (problem, config.long_line == LongLine::Fail))
+            })
+            .or_else(|| {
+                missing_field(
+                    config.template(),
+                    &job.line,
+                    &config.field_separator,
+                    &config.placeholder,
+                )
+            })
+            .or_else(|| {
+                let mode = config.flock_input?;
+                lock_input(&job, mode, &config, &state)
+                    .map(|held| lock = Some(held))
+                    .err()
+            });
+        // held until the job's result is sent
+        let _lock = lock;
+        if let Some((problem, fail)) = unusable {
+            let error = if fail {
+                Some(problem)
+            } else {
+                eprintln!("skipping job {}: {}", config.start_seq + job.id, problem);
+                None
+            };
+            slot_failed |= error.is_some();
+            release_claim(&mut job, &config, error.is_some());
+            let result = JobResult {
+                error,
+                start,
+                input: job.line.clone(),
+                ..JobResult::new(job.id)
+            };
+            if !state.send_result(&result_tx, result) {
+                break;
+            }
+            continue;
+        }
+
+        if let (Some(transfers), Some(host)) = (&state.transfers, host_name)
+            && let Err(e) = transfers.send(host, &job.line)
+        {
+            slot_failed = true;
+            let result = JobResult {
+                error: Some(format!(
+                    "transfer of {} to {} failed: {}",
+                    job.line, host, e
+                )),
+                start,
+                input: job.line.clone(),
+                ..JobResult::new(job.id)
+            };
+            if !state.send_result(&result_tx, result) {
+                break;
+            }
+            continue;
+        }
+
+        let total = state.total.get().copied();
+        let mut result = match prepare_command(&config, slot_dir.as_deref(), &job, total, host_name)
+        {
+            Err(reason) => {
+                if config.safe == Some(SafeMode::Run) && state.stop(reason.clone()) {
+                    eprintln!("{}, no new jobs will be started", state.reason());
+                }
+                JobResult::failed(job.id, reason)
+            }
+            Ok((cmd_str, _)) if config.dry_run => JobResult {
+                output: format!("[+] {}", cmd_str),
+                ..JobResult::new(job.id)
+            },
+            Ok((cmd_str, mut command)) => {
+                if let Some(events) = &state.events {
+                    let seq = config.start_seq + job.id;
+                    events.start(seq, worker_id, host_name, &config.redact(&job.line));
+                }
+                let started_at = SystemTime::now();
+                let timer = Instant::now();
+                let mut retries = 0;
+                let mut attempts = 0;
+                *state.current[worker_id]
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner) =
+                    Some((config.redact(&cmd_str).into_owned(), Instant::now()));
+                let mut result = loop {
+                    job_environment(&mut command, &job, worker_id, &config, &state);
+                    let result = run_job(&job, &cmd_str, command, worker_id, &config, &state);
+                    attempts += 1;
+                    let backoff = config
+                        .backoff_from_regex
+                        .as_ref()
+                        .and_then(|pattern| {
+                            backoff_delay(pattern, &result.stdout_text())
+                                .or_else(|| backoff_delay(pattern, &result.stderr_text()))
+                        })
+                        .filter(|_| retries < config.backoff_retries && !state.is_stopped());
+                    let retry =
+                        result.error.is_some() && attempts <= config.retries && !state.is_stopped();
+                    if backoff.is_none() && !retry {
+                        break result;
+                    }
+                    let Ok((_, next)) =
+                        prepare_command(&config, slot_dir.as_deref(), &job, total, host_name)
+                    else {
+                        break result;
+                    };
+
+                    match backoff {
+                        Some(delay) => {
+                            retries += 1;
+                            eprintln!(
+                                "job {} asked to back off for {}s, pausing before retry {}",
+                                config.start_seq + job.id,
+                                delay.as_secs_f64(),
+                                retries
+                            );
+                            state.pause_for(delay);
+                        }
+                        None => {
+                            let delay = retry_delay(&config, attempts);
+                            if config.verbose {
+                                let seq = config.start_seq + job.id;
+                                eprintln!("{}", retry_notice(seq, delay, attempts, config.retries));
+                            }
+                            thread::sleep(delay);
+                        }
+                    }
+                    state.wait_until_resumed();
+                    command = next;
+                };
+                state.current[worker_id]
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .take();
+                result.attempts = attempts;
+                let ran = timer.elapsed();
+                let result = verify_checksum(result, &config, slot_dir.as_deref(), &job, total);
+                let (result, staged) = match &config.results {
+                    Some(dir) => {
+                        let runtime = timer.elapsed();
+                        save_results(result, dir, &config, &job, &cmd_str, runtime, &state)
+                    }
+                    None => (result, Vec::new()),
+                };
+                let (result, staged) = match &config.outfile {
+                    Some(template) => {
+                        let slot_dir = slot_dir.as_deref();
+                        let (result, output) =
+                            save_output(result, template, &config, slot_dir, &job, total, &state);
+                        (result, output.into_iter().collect())
+                    }
+                    None => (result, staged),
+                };
+                if let Some(joblog) = &state.joblog {
+                    let input = original.as_deref().unwrap_or(&job.line);
+                    let exit = match (&result.error, result.exit_code) {
+                        (None, _) => 0,
+                        (Some(_), Some(code)) if code != 0 => code,
+                        (Some(_), _) => -1,
+                    };
+                    let entry = joblog::Entry {
+                        seq: config.start_seq + job.id,
+                        start: started_at,
+                        runtime: timer.elapsed(),
+                        exit,
+                        fingerprint: &joblog::fingerprint(config.template(), input),
+                        input: &config.redact(input),
+                        command: &config.redact(&cmd_str),
+                        usage: result.usage,
+                        signal: result.signal,
+                    };
+                    if let Err(e) = joblog.record(&entry, staged) {
+                        eprintln!("error writing job log: {}", e);
+                    }
+                }
+                if let Some(tracer) = &state.tracer {
+                    let error = result.error.as_deref().map(|error| config.redact(error));
+                    tracer.job(&otel::JobSpan {
+                        seq: config.start_seq + job.id,
+                        start: started_at,
+                        runtime: timer.elapsed(),
+                        exit: result.exit_code,
+                        error: error.as_deref(),
+                        worker: worker_id,
+                        host: host_name,
+                        input: &config.redact(&job.line),
+                    });
+                }
+                run_hook(&job, &result, slot_dir.as_deref(), total, &config);
+                let mut result = result;
+                if let Some(clock) = &state.clock {
+                    let spawn = clock.take_spawn(worker_id);
+                    result.timings = Some(timing::Timings {
+                        class: timing::class(&cmd_str),
+                        queue,
+                        spawn,
+                        run: ran.saturating_sub(spawn),
+                        output: timer.elapsed() - ran,
+                        reorder: Duration::ZERO,
+                    });
+                }
+                if config.review
+                    && let Some(error) = &result.error
+                {
+                    state
+                        .failed
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .push(FailedJob {
+                            seq: config.start_seq + job.id,
+                            command: cmd_str,
+                            output: result.combined_output().into_owned(),
+                            error: error.clone(),
+                        });
+                }
+                result
+            }
+        };
+        if let (Some(transactions), Some(template)) = (&state.transactions, &config.rollback)
+            && let Some((group, inputs)) =
+                transactions.finish(job.id, &job.line, result.error.is_some(), total)
+        {
+            run_rollback(template, group, &inputs, &config);
+        }
+        if let Some(host) = &host
+            && host.record(result.error.is_some(), config.host_max_failures)
+        {
+            eprintln!(
+                "host {} failed {} jobs in a row, no longer dispatching jobs to it",
+                host.name(),
+                config.host_max_failures
+            );
+        }
+        drop(host);
+        drop(token);
+
+        if let Some(breaker) = &state.breaker
+            && let Some(message) = breaker
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .record(result.error.is_some(), probe, Instant::now())
+        {
+            eprintln!("{}", message);
+        }
+
+        release_claim(&mut job, &config, result.error.is_some());
+        slot_failed |= result.error.is_some();
+        result.start = start;
+        result.input = job.line;
+
+        if !state.send_result(&result_tx, result) {
+            break;
+        }
+    }
+
+    if let Some(dir) = &slot_dir {
+        if slot_failed && config.keep_tmpdir_on_failure {
+            eprintln!(
+                "keeping scratch directory {} after failed jobs",
+                dir.display()
+            );
+        } else if let Err(e) = fs::remove_dir_all(dir) {
+            eprintln!("error removing scratch directory {}: {}", dir.display(), e);
+        }
+    }
+
+    if config.verbose {
+        eprintln!("worker {} finished", worker_id);
+    }
+}
+
+/// Reports an input line longer than `--max-line-length`, which would build a command too long
+/// for the system to run
+fn line_too_long(line: &str, max: Option<usize>) -> Option<String> {
+    let max = max?;
+    (line.len() > max).then(|| {
+        format!(
+            "input line of {} bytes is longer than --max-line-length {}",
+            line.len(),
+            max
+        )
+    })
+}
+
+/// Claims the `--claim-dir` file a job's input names, making the input the claimed file's path;
+/// a file another process claimed first skips the job
+fn claim_input(job: &mut Job, config: &Config) -> Option<(String, bool)> {
+    let dir = config.claim_dir.as_ref()?;
+    if config.dry_run {
+        job.line = dir.join(&job.line).display().to_string();
+        return None;
+    }
+    match claim::claim(dir, &job.line) {
+        Ok(Some(path)) => {
+            job.line = path.display().to_string();
+            None
+        }
+        Ok(None) => Some((
+            format!("{} was claimed by another process", job.line),
+            false,
+        )),
+        Err(e) => Some((format!("cannot claim {}: {}", job.line, e), true)),
+    }
+}
+
+/// Moves a job's claimed file on to `done/` or `failed/`, making the input its final path
+fn release_claim(job: &mut Job, config: &Config, failed: bool) {
+    let Some(dir) = &config.claim_dir else {
+        return;
+    };
+    let path = Path::new(&job.line);
+    if path.parent() != Some(&dir.join("inprogress")) {
+        return;
+    }
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    match claim::finish(dir, &name, failed) {
+        Ok(path) => job.line = path.display().to_string(),
+        Err(e) => eprintln!("error moving {} out of inprogress: {}", job.line, e),
+    }
+}
+
+/// Locks the file a job's input names for `--flock-input`, waiting for another holder to
+/// release it or, with `skip`, giving up on the job; the error says whether the job fails
+fn lock_input(
+    job: &Job,
+    mode: FlockInput,
+    config: &Config,
+    state: &RunState,
+) -> Result<flock::InputLock, (String, bool)> {
+    let path = Path::new(&job.line);
+    let mut waiting = false;
+    loop {
+        match flock::try_lock(path) {
+            Ok(Some(lock)) => return Ok(lock),
+            Ok(None) if mode == FlockInput::Skip || state.is_stopped() => {
+                return Err((format!("{} is locked by another process", job.line), false));
+            }
+            Ok(None) => {
+                if config.verbose && !waiting {
+                    eprintln!(
+                        "job {} waiting for the lock on {}",
+                        config.start_seq + job.id,
+                        job.line
+                    );
+                }
+                waiting = true;
+                thread::sleep(Duration::from_millis(100));
+            }
+            Err(e) => return Err((format!("cannot lock {}: {}", job.line, e), true)),
+        }
+    }
+}
+
+/// The first field a `{N!}` or `{N?}` placeholder requires that the line lacks or has empty,
+/// and whether the job fails (`!`) rather than being skipped (`?`); a failing one wins
+fn missing_field(
+    template: &str,
+    line: &str,
+    field_separator: &str,
+    placeholder: &str,
+) -> Option<(String, bool)> {
+    if !template.contains(['!', '?']) {
+        return None;
+    }
+    let (open_delim, close_delim) = placeholder_delimiters(placeholder);
+    let pattern = format!(
+        r"{}\s*(\d+)[\+\-]?([!?])\s*{}",
+        regex_escape(open_delim),
+        regex_escape(close_delim)
+    );
+    let fields = split_fields(line, field_separator);
+    let mut missing = None;
+    for caps in Regex::new(&pattern).unwrap().captures_iter(template) {
+        let number: usize = caps[1].parse().unwrap_or(0);
+        let present = number
+            .checked_sub(1)
+            .and_then(|i| fields.get(i))
+            .is_some_and(|field| !field.is_empty());
+        if present {
+            continue;
+        }
+        let fail = &caps[2] == "!";
+        if missing.as_ref().is_none_or(|(_, failing)| fail && !failing) {
+            missing = Some((format!("field {} is missing ({})", number, &caps[0]), fail));
+        }
+    }
+    missing
+}
+
+/// Builds the command for a job, returning its display form alongside it
+fn prepare_command(
+    config: &Config,
+    slot_dir: Option<&Path>,
+    job: &Job,
+    total: Option<usize>,
+    host: Option<&str>,
+) -> Result<(String, Command), String> {
+    let (cmd_str, command) = match &config.script_path {
+        Some(path) => (
+            format!("sh {} {}", path.display(), shell_quote(&job.line)),
+            script_command(path, &shown_input(&job.line, &config.field_separator)),
+        ),
+        None => {
+            let cmd_str = expand_command(config.template(), config, slot_dir, job, total)?;
+            if let Some(guard) = &config.path_guard {
+                guard.check(&cmd_str)?;
+            }
+            let mut command = shell_command(&cmd_str);
+            let mut length = cmd_str.len();
+            if config.batching() {
+                // a batch's lines are also the shell's positional parameters, for "$@"
+                command
+                    .arg(POSITIONAL_ZERO)
+                    .args(job.line.split(config.batch_separator()));
+                length += POSITIONAL_ZERO.len() + job.line.len() + 2;
+            }
+            check_command_length(length, config.max_chars)?;
+            (cmd_str, command)
+        }
+    };
+    if let Some(host) = host {
+        return Ok((cmd_str, remote_command(host, &command)));
+    }
+    let command = match &config.sandbox_profile {
+        Some(profile) => sandboxed(profile, &command),
+        None => command,
+    };
+    #[cfg(unix)]
+    let command = {
+        let mut command = command;
+        jail::Jail::new(config.chroot.as_deref(), config.user_ids).apply(&mut command);
+        limits::Limits {
+            cpu: config.limit_cpu,
+            memory: config.limit_mem,
+        }
+        .apply(&mut command);
+        command
+    };
+    Ok((cmd_str, command))
+}
+
+/// What `$0` is in a batched command, before the batch's lines as its positional parameters
+const POSITIONAL_ZERO: &str = "kyanite";
+
+static COMMAND_LIMIT: OnceLock<usize> = OnceLock::new();
+
+/// The longest command, with its arguments, that runs: `--max-chars` or what the system allows
+fn command_limit(max_chars: Option<usize>) -> usize {
+    max_chars.unwrap_or_else(|| *COMMAND_LIMIT.get_or_init(system_command_limit))
+}
+
+/// Fails a command of `length` bytes longer than `--max-chars`, or without it than the system
+/// will run, rather than leaving the shell to fail with a confusing `E2BIG`
+fn check_command_length(length: usize, max_chars: Option<usize>) -> Result<(), String> {
+    let limit = command_limit(max_chars);
+    if length <= limit {
+        return Ok(());
+    }
+    let source = match max_chars {
+        Some(_) => "--max-chars",
+        None => "the system limit",
+    };
+    Err(format!(
+        "command of {} bytes is longer than {} of {} bytes",
+        length, source, limit
+    ))
+}
+
+/// The longest command the system runs, worked out as xargs does: the argument space beside
+/// the environment, less room for the variables kyanite adds; on Linux at most the size of a
+/// single argument, which the whole command is to `sh -c`
+#[cfg(unix)]
+fn system_command_limit() -> usize {
+    let arg_max = match unsafe { libc::sysconf(libc::_SC_ARG_MAX) } {
+        max if max > 0 => max as usize,
+        _ => 4096,
+    };
+    let environment: usize = std::env::vars_os()
+        .map(|(name, value)| name.len() + value.len() + 2 + size_of::<usize>())
+        .sum();
+    let limit = arg_max.saturating_sub(environment + 2048);
+    if cfg!(target_os = "linux") {
+        // MAX_ARG_STRLEN
+        limit.min(128 * 1024 - 1)
+    } else {
+        limit
+    }
+}
+
+/// The longest command line `CreateProcess` accepts, less room for the shell around it
+#[cfg(not(unix))]
+fn system_command_limit() -> usize {
+    32767 - 2048
+}
+
+/// Runs a job's command on a `--hostfile` host over ssh
+fn remote_command(host: &str, command: &Command) -> Command {
+    let remote: Vec<_> = std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| shell_quote(&arg.to_string_lossy()).into_owned())
+        .collect();
+    let mut ssh = Command::new("ssh");
+    ssh.args(["-o", "BatchMode=yes", "-o", "ConnectTimeout=10", "--", host])
+        .arg(remote.join(" "));
+    ssh
+}
+
+/// Wraps a job's command in macOS `sandbox-exec` with the `--sandbox-profile` file
+fn sandboxed(profile: &Path, command: &Command) -> Command {
+    let mut wrapped = Command::new("sandbox-exec");
+    wrapped
+        .arg("-f")
+        .arg(profile)
+        .arg(command.get_program())
+        .args(command.get_args());
+    for (name, value) in command.get_envs() {
+        match value {
+            Some(value) => wrapped.env(name, value),
+            None => wrapped.env_remove(name),
+        };
+    }
+    wrapped
+}
+
+/// Prints how the template expands for one input line as the first job, step by step
+fn explain(config: &Config, line: &str, out: &mut impl Write) {
+    let job = Job::new(0, line.to_string());
+    let template = config.template();
+    let _ = writeln!(out, "template: {}", template);
+    let _ = writeln!(out, "input:    {:?}", line);
+
+    let expanded = expand_job_placeholders(template, config, None, &job, None);
+    if expanded != template {
+        let _ = writeln!(out, "after job placeholders: {}", expanded);
+    }
+    let input = match config.shell {
+        Shell::Wsl(_) => wsl_path(line).unwrap_or_else(|| line.to_string()),
+        Shell::Sh => line.to_string(),
+    };
+    if input != line {
+        let _ = writeln!(out, "input as a WSL path: {:?}", input);
+    }
+
+    let mut trace = Vec::new();
+    let command = expand_template_traced(
+        &expanded,
+        &input,
+        &config.field_separator,
+        &config.placeholder,
+        false,
+        Some(&mut trace),
+    );
+    for step in trace {
+        let _ = writeln!(out, "{}", step);
+    }
+    let _ = writeln!(out, "command: {}", command);
+
+    if config.safe.is_some()
+        && let Err(e) = expand_command(template, config, None, &job, None)
+    {
+        let _ = writeln!(out, "rejected by --safe: {}", e);
+    }
+    if let Some(guard) = &config.path_guard
+        && let Err(e) = guard.check(&command)
+    {
+        let _ = writeln!(out, "rejected by the path checks: {}", e);
+    }
+}
+
+/// Expands the job placeholders and input of a command template, checking it in `--safe` mode
+fn expand_command(
+    template: &str,
+    config: &Config,
+    slot_dir: Option<&Path>,
+    job: &Job,
+    total: Option<usize>,
+) -> Result<String, String> {
+    let template = expand_job_placeholders(template, config, slot_dir, job, total);
+    let line = match config.shell {
+        // a `--pipe` block goes to the job's stdin, never into its command
+        _ if config.pipe => Cow::Borrowed(""),
+        // a batch's lines are separate arguments
+        _ if config.batching() => Cow::Owned(job.line.replace(config.batch_separator(), " ")),
+        Shell::Wsl(_) => wsl_path(&job.line).map_or(Cow::Borrowed(job.line.as_str()), Cow::Owned),
+        Shell::Sh => Cow::Borrowed(job.line.as_str()),
+    };
+    if config.safe.is_some() {
+        check_safe(&expand_template_marked(
+            &template,
+            &line,
+            &config.field_separator,
+            &config.placeholder,
+            true,
+        ))
+    } else {
+        Ok(expand_template(
+            &template,
+            &line,
+            &config.field_separator,
+            &config.placeholder,
+        ))
+    }
+}
+
+/// Expands `{#}`, `{total}`, `{slotdir}` and `{meta:field}`, leaving the input placeholders
+fn expand_job_placeholders(
+    template: &str,
+    config: &Config,
+    slot_dir: Option<&Path>,
+    job: &Job,
+    total: Option<usize>,
+) -> String {
+    let mut template = expand_named(
+        template,
+        &config.placeholder,
+        "#",
+        &(config.start_seq + job.id).to_string(),
+    );
+    if let Some(total) = total {
+        template = expand_named(&template, &config.placeholder, "total", &total.to_string());
+    }
+    if let Some(dir) = slot_dir {
+        template = expand_named(
+            &template,
+            &config.placeholder,
+            "slotdir",
+            &dir.to_string_lossy(),
+        );
+    }
+    if let Some(meta) = &config.meta_records {
+        template = expand_meta(&template, &config.placeholder, meta, job);
+    }
+    template
+}
+
+/// Replaces `{meta:field}` with the field of the job's `--meta` record, or nothing if it has none
+fn expand_meta(template: &str, placeholder: &str, meta: &Meta, job: &Job) -> String {
+    let (open_delim, close_delim) = placeholder_delimiters(placeholder);
+    let pattern = format!(
+        r"{}meta:([^{}]+){}",
+        regex_escape(open_delim),
+        regex_escape(close_delim),
+        regex_escape(close_delim)
+    );
+    Regex::new(&pattern)
+        .unwrap()
+        .replace_all(template, |caps: &regex::Captures| {
+            meta.get(&job.line, job.id + 1, &caps[1])
+                .unwrap_or_default()
+                .to_string()
+        })
+        .into_owned()
+}
+
+/// Moves a job's stdout into its `--outfile`, leaving only stderr to be printed; with a
+/// `--joblog` the output is only staged, returned as `(temporary, target)` for the job log to
+/// commit together with the job's record
+fn save_output(
+    mut result: JobResult,
+    template: &str,
+    config: &Config,
+    slot_dir: Option<&Path>,
+    job: &Job,
+    total: Option<usize>,
+    state: &RunState,
+) -> (JobResult, Option<(PathBuf, PathBuf)>) {
+    let path = expand_template(
+        &expand_job_placeholders(template, config, slot_dir, job, total),
+        &job.line,
+        &config.field_separator,
+        &config.placeholder,
+    );
+    let Some((mut stdout, stderr)) = result.streams.take() else {
+        return (result, None);
+    };
+    // stdout goes to the file, leaving only stderr to show
+    result.output.clear();
+    result.stderr = String::from_utf8_lossy(&stderr).trim_end().to_string();
+    let path = match config.compress_results {
+        Some(format) => match format.compress(&stdout) {
+            Ok(compressed) => {
+                stdout = compressed;
+                format!("{}{}", path, format.extension())
+            }
+            Err(e) => {
+                result.error = Some(format!("error compressing output: {}", e));
+                return (result, None);
+            }
+        },
+        None => path,
+    };
+    let seq = config.start_seq + job.id;
+    let path = match state.claim_outfile(path, &job.line, seq, config.outfile_collision) {
+        Ok(path) => path,
+        Err(reason) => {
+            result.error = Some(reason);
+            return (result, None);
+        }
+    };
+    let succeeded = result.error.is_none();
+    let partial_suffix = config.partial_suffix.as_deref();
+    let (written, staged) = if state.joblog.is_some() {
+        match stage_output(Path::new(&path), &stdout, succeeded, partial_suffix) {
+            Ok(staged) => (
+                Ok(staged.as_ref().map(|(_, target)| target.clone())),
+                staged,
+            ),
+            Err(e) => (Err(e), None),
+        }
+    } else {
+        let written = write_atomically(Path::new(&path), &stdout, succeeded, partial_suffix);
+        (written, None)
+    };
+    match written {
+        Ok(Some(saved)) => {
+            if config.verbose {
+                eprintln!(
+                    "job {} output written to {}",
+                    config.start_seq + job.id,
+                    saved.display()
+                );
+            }
+            if let Some(manifest) = &state.manifest {
+                manifest.record(seq, &job.line, &saved, &stdout, !succeeded);
+            }
+        }
+        Ok(None) => {}
+        Err(e) if succeeded => {
+            result.error = Some(format!("error writing output to {}: {}", path, e));
+        }
+        Err(e) => eprintln!(
+            "error writing output of job {} to {}: {}",
+            config.start_seq + job.id,
+            path,
+            e
+        ),
+    }
+    (result, staged)
+}
+
+/// Writes a job's stdout, stderr and `meta.json` to its `--results` directory `DIR/SEQ`, leaving
+/// a one-line summary as the output to print; with a job log the files are only staged, for the
+/// log to move into place with the job's record
+fn save_results(
+    mut result: JobResult,
+    dir: &Path,
+    config: &Config,
+    job: &Job,
+    cmd_str: &str,
+    runtime: Duration,
+    state: &RunState,
+) -> (JobResult, Vec<(PathBuf, PathBuf)>) {
+    let seq = config.start_seq + job.id;
+    let (stdout, stderr) = result.streams.take().unwrap_or_else(|| {
+        let stdout = result.output.clone().into_bytes();
+        (stdout, result.stderr.clone().into_bytes())
+    });
+    let exit = match result.exit_code {
+        Some(code) => code.to_string(),
+        None => "null".to_string(),
+    };
+    let error = match &result.error {
+        Some(error) => mux::json_string(&config.redact(error)),
+        None => "null".to_string(),
+    };
+    let meta = format!(
+        "{{\"seq\":{},\"command\":{},\"input\":{},\"exit\":{},\"error\":{},\"runtime\":{:.3}}}\n",
+        seq,
+        mux::json_string(&config.redact(cmd_str)),
+        mux::json_string(&config.redact(&job.line)),
+        exit,
+        error,
+        runtime.as_secs_f64()
+    );
+    let job_dir = dir.join(seq.to_string());
+    let files = [
+        ("stdout", stdout.as_slice()),
+        ("stderr", stderr.as_slice()),
+        ("meta.json", meta.as_bytes()),
+    ];
+    let mut staged = Vec::new();
+    let written = files.iter().try_for_each(|(name, data)| {
+        let path = job_dir.join(name);
+        if state.joblog.is_some() {
+            staged.extend(stage_output(&path, data, true, None)?);
+        } else {
+            write_atomically(&path, data, true, None)?;
+        }
+        Ok::<_, io::Error>(())
+    });
+    match written {
+        Ok(()) => {
+            if let Some(manifest) = &state.manifest {
+                for (name, data) in files {
+                    manifest.record(seq, &job.line, &job_dir.join(name), data, false);
+                }
+            }
+        }
+        Err(e) => {
+            for (temp, _) in staged.drain(..) {
+                let _ = fs::remove_file(temp);
+            }
+            let error = format!("error writing results to {}: {}", job_dir.display(), e);
+            result.error.get_or_insert(error);
+        }
+    }
+    result.output = format!(
+        "exited {} in {:.2}s: {}B stdout, {}B stderr in {}",
+        exit,
+        runtime.as_secs_f64(),
+        tune::size(stdout.len() as u64),
+        tune::size(stderr.len() as u64),
+        job_dir.display()
+    );
+    result.stderr.clear();
+    (result, staged)
+}
+
+/// Fails a successful job whose stdout (`--verify-sha256-field`) or produced file (`--verify`)
+/// does not have the SHA-256 given by its input
+fn verify_checksum(
+    mut result: JobResult,
+    config: &Config,
+    slot_dir: Option<&Path>,
+    job: &Job,
+    total: Option<usize>,
+) -> JobResult {
+    if result.error.is_some() {
+        return result;
+    }
+    let expand = |template: &str| {
+        expand_template(
+            &expand_job_placeholders(template, config, slot_dir, job, total),
+            &job.line,
+            &config.field_separator,
+            &config.placeholder,
+        )
+    };
+    let (what, expected, actual) = if let Some(field) = config.verify_sha256_field {
+        // cached results carry no stdout and were checked when they ran
+        let Some((stdout, _)) = &result.streams else {
+            return result;
+        };
+        let Some(expected) = field.checked_sub(1).and_then(|i| {
+            split_fields(&job.line, &config.field_separator)
+                .get(i)
+                .copied()
+        }) else {
+            result.error = Some(format!("input has no field {} to verify against", field));
+            return result;
+        };
+        let mut hasher = sha256::Sha256::default();
+        hasher.update(stdout);
+        (
+            "stdout".to_string(),
+            expected.to_string(),
+            hasher.finalize(),
+        )
+    } else if let Some((file, hash)) = &config.verify {
+        let path = expand(file);
+        match hash_file(Path::new(&path)) {
+            Ok(digest) => (path, expand(hash), digest),
+            Err(e) => {
+                result.error = Some(format!("error reading {} to verify: {}", path, e));
+                return result;
+            }
+        }
+    } else {
+        return result;
+    };
+    let actual = sha256::hex(&actual);
+    if !expected.trim().eq_ignore_ascii_case(&actual) {
+        result.error = Some(format!(
+            "sha256 of {} is {}, expected {}",
+            what,
+            actual,
+            expected.trim()
+        ));
+    }
+    result
+}
+
+fn hash_file(path: &Path) -> io::Result<[u8; 32]> {
+    let mut file = File::open(path)?;
+    let mut hasher = sha256::Sha256::default();
+    let mut buf = [0; 64 * 1024];
+    loop {
+        match file.read(&mut buf)? {
+            0 => return Ok(hasher.finalize()),
+            n => hasher.update(&buf[..n]),
+        }
+    }
+}
+
+/// Adds a job's sequence number before the extension of a colliding path: `out/a.txt` becomes
+/// `out/a.3.txt`
+fn uniquify(path: &str, seq: usize) -> String {
+    let name_start = path.rfind('/').map_or(0, |i| i + 1);
+    match path[name_start..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let (stem, ext) = path.split_at(name_start + dot);
+            format!("{}.{}{}", stem, seq, ext)
+        }
+        _ => format!("{}.{}", path, seq),
+    }
+}
+
+/// Writes to a temporary file next to `path` and renames it into place, so readers never see
+/// a partial file; output of a failed job is kept as `path` plus `partial_suffix`, if given
+fn write_atomically(
+    path: &Path,
+    data: &[u8],
+    succeeded: bool,
+    partial_suffix: Option<&str>,
+) -> io::Result<Option<PathBuf>> {
+    let Some((temp, target)) = stage_output(path, data, succeeded, partial_suffix)? else {
+        return Ok(None);
+    };
+    let renamed = fs::rename(&temp, &target);
+    if renamed.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    renamed.map(|()| Some(target))
+}
+
+/// Writes the data `write_atomically` would save to a temporary file beside its target,
+/// returning both without moving it into place
+fn stage_output(
+    path: &Path,
+    data: &[u8],
+    succeeded: bool,
+    partial_suffix: Option<&str>,
+) -> io::Result<Option<(PathBuf, PathBuf)>> {
+    let target = match (succeeded, partial_suffix) {
+        (true, _) => path.to_path_buf(),
+        (false, Some(suffix)) => {
+            let mut name = path.as_os_str().to_owned();
+            name.push(suffix);
+            PathBuf::from(name)
+        }
+        (false, None) => return Ok(None),
+    };
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file name"))?;
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+    if let Some(dir) = dir {
+        fs::create_dir_all(dir)?;
+    }
+    static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+    let temp = dir.unwrap_or(Path::new(".")).join(format!(
+        ".{}.kyanite-{}-{}",
+        name.to_string_lossy(),
+        std::process::id(),
+        TEMP_FILES.fetch_add(1, Ordering::SeqCst)
+    ));
+    if let Err(e) = fs::write(&temp, data) {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    Ok(Some((temp, target)))
+}
+
+/// Blocks until the jobserver hands out a token, returning none once the run is stopped
+fn wait_for_token<'a>(jobserver: &'a JobServer, state: &RunState) -> Option<jobserver::Token<'a>> {
+    while !state.is_stopped() {
+        match jobserver.try_acquire() {
+            Ok(Some(token)) => return Some(token),
+            Ok(None) => {}
+            Err(e) => {
+                if state.stop(format!("jobserver failed: {}", e)) {
+                    eprintln!("{}, no new jobs will be started", state.reason());
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Waits for a `--hostfile` host with spare capacity, giving up if the run is stopped
+fn wait_for_host<'a>(hosts: &'a Hosts, state: &RunState) -> Option<hosts::Lease<'a>> {
+    while !state.is_stopped() {
+        if let Some(lease) = hosts.try_acquire() {
+            return Some(lease);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    None
+}
+
+/// Summarizes a `--sample` run and projects how long the full input would take
+fn print_sample_summary(
+    config: &Config,
+    state: &RunState,
+    counts: &FailureCounts,
+    elapsed: Duration,
+) {
+    let ran = counts.succeeded + counts.total;
+    let input_total = state.input_total.get().copied().unwrap_or(ran);
+    eprintln!(
+        "sampled {} of {} jobs ({}): {} succeeded, {} failed in {:?}",
+        ran,
+        input_total,
+        if config.sample_random {
+            "random"
+        } else {
+            "first"
+        },
+        counts.succeeded,
+        counts.total,
+        elapsed
+    );
+    if ran > 0 && input_total > ran {
+        let per_job = elapsed * config.workers.min(ran) as u32 / ran as u32;
+        let (projected, _) = project_run(input_total, config.workers, per_job);
+        eprintln!(
+            "projected full run at -j {}: {:?}",
+            config.workers, projected
+        );
+    }
+}
+
+/// Mails the summary of the run to the `--mail-to` addresses
+fn mail_report(config: &Config, state: &RunState, counts: &FailureCounts, elapsed: Duration) {
+    let finished = counts.succeeded + counts.total;
+    let total = state.total.get().copied().unwrap_or(finished);
+    let subject = if counts.total > 0 {
+        format!("kyanite: {} of {} jobs failed", counts.total, total)
+    } else if state.is_stopped() {
+        format!("kyanite: run halted after {} of {} jobs", finished, total)
+    } else {
+        format!("kyanite: all {} jobs succeeded", total)
+    };
+    let host = mail::hostname();
+    let mut body = format!(
+        "command: {}\nhost: {}\nelapsed: {:.1}s\nsucceeded: {}\nfailed: {}\nunfinished: {}\n",
+        config.redact(config.template()),
+        host,
+        elapsed.as_secs_f64(),
+        counts.succeeded,
+        counts.total,
+        total.saturating_sub(finished)
+    );
+    if state.is_stopped() {
+        body.push_str(&format!("halted: {}\n", state.reason()));
+    }
+    if let Some(summary) = state
+        .usage
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .summary()
+    {
+        body.push_str(&format!("{}\n", summary));
+    }
+    let failed_inputs = config.mail_failed_inputs.then(|| {
+        let mut inputs = std::mem::take(
+            &mut *state
+                .failed_inputs
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        inputs.sort_by_key(|(seq, _)| *seq);
+        inputs
+            .into_iter()
+            .map(|(_, input)| format!("{}\n", input))
+            .collect()
+    });
+    let report = mail::Report {
+        subject,
+        body,
+        failed_inputs,
+    };
+
+    let from = config.mail_from.clone().unwrap_or_else(mail::default_from);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let boundary = format!("kyanite-{}-{}", std::process::id(), nanos);
+    let message = mail::message(&from, &config.mail_to, &report, &boundary);
+    let sent = match &config.smtp {
+        Some(server) => mail::smtp(server, &from, &config.mail_to, &message),
+        None => mail::sendmail(&message),
+    };
+    match sent {
+        Ok(()) if config.verbose => eprintln!("report mailed to {}", config.mail_to.join(", ")),
+        Ok(()) => {}
+        Err(e) => eprintln!("error mailing report: {}", e),
+    }
+}
+
+/// Runs the `--on-complete` command once after the final job, with a summary of the run
+fn run_on_complete(template: &str, config: &Config, state: &RunState, counts: &FailureCounts) {
+    let finished = counts.succeeded + counts.total;
+    let total = state.total.get().copied().unwrap_or(finished);
+    let cmd_str = expand_named(template, &config.placeholder, "total", &total.to_string());
+    if config.verbose {
+        eprintln!("running on-complete hook: {}", config.redact(&cmd_str));
+    }
+
+    let mut command = shell_command(&cmd_str);
+    command
+        .stdin(Stdio::null())
+        .env("KYANITE_TOTAL", total.to_string())
+        .env("KYANITE_SUCCEEDED", counts.succeeded.to_string())
+        .env("KYANITE_FAILED", counts.total.to_string())
+        .env(
+            "KYANITE_UNFINISHED",
+            total.saturating_sub(finished).to_string(),
+        );
+    if state.is_stopped() {
+        command.env("KYANITE_HALT_REASON", state.reason());
+    }
+
+    match command.status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => eprintln!(
+            "error in on-complete hook: command failed with exit code: {}",
+            status
+        ),
+        Err(e) => eprintln!(
+            "error in on-complete hook: failed to execute command: {}",
+            e
+        ),
+    }
+}
+
+/// Runs `--rollback` for a transaction with a failed job, with the group's inputs shell-quoted
+/// in place of `{}` and one per line on stdin
+fn run_rollback(template: &str, group: usize, inputs: &[String], config: &Config) {
+    let quoted: Vec<_> = inputs.iter().map(|input| shell_quote(input)).collect();
+    let cmd_str = expand_template(
+        template,
+        &quoted.join(" "),
+        &config.field_separator,
+        &config.placeholder,
+    );
+    let number = group + 1;
+    eprintln!(
+        "transaction {} had a failed job, rolling back its {} jobs",
+        number,
+        inputs.len()
+    );
+    if config.verbose {
+        eprintln!("running rollback: {}", config.redact(&cmd_str));
+    }
+
+    let result = shell_command(&cmd_str)
+        .stdin(Stdio::piped())
+        .env("KYANITE_TRANSACTION", number.to_string())
+        .spawn()
+        .and_then(|mut child| {
+            if let Some(mut stdin) = child.stdin.take() {
+                // a rollback that ignores its stdin may exit before reading it
+                let _ = stdin.write_all(format!("{}\n", inputs.join("\n")).as_bytes());
+            }
+            child.wait()
+        });
+    match result {
+        Ok(status) if status.success() => {}
+        Ok(status) => eprintln!(
+            "error in rollback for transaction {}: command failed with exit code: {}",
+            number, status
+        ),
+        Err(e) => eprintln!(
+            "error in rollback for transaction {}: failed to execute command: {}",
+            number, e
+        ),
+    }
+}
+
+/// Exports the job's position in the run to its environment
+fn job_environment(
+    command: &mut Command,
+    job: &Job,
+    worker_id: usize,
+    config: &Config,
+    state: &RunState,
+) {
+    command
+        .env("KYANITE_SEQ", (config.start_seq + job.id).to_string())
+        .env("KYANITE_SLOT", (worker_id + 1).to_string())
+        // NULs between the inputs of a batch cannot go into the environment
+        .env(
+            "KYANITE_INPUT",
+            shown_input(&job.line, &config.field_separator).replace('\0', "\n"),
+        )
+        .env("KYANITE_JOBS", config.workers.to_string());
+    if let Some(total) = state.total.get() {
+        command.env("KYANITE_TOTAL", total.to_string());
+    }
+    if let Some(jobserver) = &state.jobserver {
+        jobserver.export(command);
+    } else {
+        command.env_remove(jobserver::ENV_VAR);
+    }
+}
+
+/// Runs the `--on-success` or `--on-failure` hook for a finished job
+fn run_hook(
+    job: &Job,
+    result: &JobResult,
+    slot_dir: Option<&Path>,
+    total: Option<usize>,
+    config: &Config,
+) {
+    let (name, template) = match (&result.error, &config.on_success, &config.on_failure) {
+        (None, Some(template), _) => ("on-success", template),
+        (Some(_), _, Some(template)) => ("on-failure", template),
+        _ => return,
+    };
+
+    let exit_code = result.exit_code.unwrap_or(-1).to_string();
+    let template = expand_named(template, &config.placeholder, "exit", &exit_code);
+    let output_path = run_dir().join(format!("output-{}", job.id));
+    let with_output = expand_named(
+        &template,
+        &config.placeholder,
+        "output",
+        &output_path.to_string_lossy(),
+    );
+    let writes_output = with_output != template;
+
+    let hook_result =
+        expand_command(&with_output, config, slot_dir, job, total).and_then(|cmd_str| {
+            if writes_output {
+                fs::create_dir_all(run_dir())
+                    .and_then(|_| fs::write(&output_path, &*result.combined_output()))
+                    .map_err(|e| format!("failed to write job output: {}", e))?;
+            }
+            if config.verbose {
+                eprintln!(
+                    "running {} hook for job {}: {}",
+                    name,
+                    config.start_seq + job.id,
+                    config.redact(&cmd_str)
+                );
+            }
+            shell_command(&cmd_str)
+                .stdin(Stdio::null())
+                .output()
+                .map_err(|e| format!("failed to execute command: {}", e))
+        });
+
+    if writes_output {
+        let _ = fs::remove_file(&output_path);
+    }
+
+    match hook_result {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => {
+            eprintln!(
+                "error in {} hook for job {}: command failed with exit code: {}",
+                name,
+                config.start_seq + job.id,
+                output.status
+            );
+            let combined = format!(
+                "{}{}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            );
+            if !combined.trim_end().is_empty() {
+                eprintln!("output: {}", combined.trim_end());
+            }
+        }
+        Err(e) => eprintln!(
+            "error in {} hook for job {}: {}",
+            name,
+            config.start_seq + job.id,
+            e
+        ),
+    }
+}
+
+/// Runs a prepared job, serving it from the cache and recording it in the audit log as needed
+fn run_job(
+    job: &Job,
+    cmd_str: &str,
+    command: Command,
+    worker_id: usize,
+    config: &Config,
+    state: &RunState,
+) -> JobResult {
+    let job_id = job.id;
+    let cache_key = state.cache.as_ref().map(|_| {
+        let files: Vec<PathBuf> = config
+            .cache_key_files
+            .iter()
+            .map(|template| {
+                PathBuf::from(expand_template(
+                    template,
+                    &job.line,
+                    &config.field_separator,
+                    &config.placeholder,
+                ))
+            })
+            .collect();
+        Cache::key(cmd_str, &files)
+    });
+    if let (Some(cache), Some(key)) = (&state.cache, &cache_key) {
+        match cache.get(key) {
+            Ok(Some(output)) => {
+                if config.verbose {
+                    eprintln!("job {} served from cache", config.start_seq + job_id);
+                }
+                return JobResult {
+                    output,
+                    exit_code: Some(0),
+                    ..JobResult::new(job_id)
+                };
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("error reading cache entry {}: {}", key, e),
+        }
+    }
+
+    let seq = config.start_seq + job_id;
+    if let Some(audit) = &state.audit
+        && let Err(e) = audit.record_start(seq, worker_id, &config.redact(cmd_str))
+    {
+        return JobResult::failed(job_id, format!("failed to write audit record: {}", e));
+    }
+
+    let tag = config
+        .line_buffer
+        .then(|| output_tag(config, &job.line).unwrap_or_default());
+    let echo = |stderr: bool, line: &[u8]| echo_line(stderr, tag.as_deref().unwrap_or(""), line);
+    let echo = tag.is_some().then_some(&echo as usage::Echo);
+    let result = match (config.builtin, &state.http) {
+        (Some(builtin), _) => run_builtin(job_id, builtin, cmd_str, config),
+        (None, Some(pool)) => {
+            let total = state.total.get().copied();
+            let expand = |template: &str| {
+                let template = expand_job_placeholders(template, config, None, job, total);
+                expand_template(
+                    &template,
+                    &job.line,
+                    &config.field_separator,
+                    &config.placeholder,
+                )
+            };
+            let headers: Vec<String> = config.http_headers.iter().map(|h| expand(h)).collect();
+            let body = config.http_body.as_deref().map(expand).unwrap_or_default();
+            run_http(job_id, cmd_str, &headers, &body, pool, config)
+        }
+        // a `--pipe-part` job names its byte range, read only once the job starts
+        (None, None) if config.pipe_part => match part::read(&job.line) {
+            Ok(data) => execute(job_id, command, Some(&data), echo, worker_id, config, state),
+            Err(e) => JobResult::failed(job_id, format!("error reading {}: {}", job.line, e)),
+        },
+        // a block frozen with `kyanite ctl freeze` was not kept, only its name
+        (None, None) if config.pipe && job.block.is_none() => JobResult::failed(
+            job_id,
+            format!("the data of --pipe block {} was not kept", job.line),
+        ),
+        (None, None) => execute(
+            job_id,
+            command,
+            job.block.as_deref(),
+            echo,
+            worker_id,
+            config,
+            state,
+        ),
+    };
+
+    if let Some(audit) = &state.audit
+        && let Err(e) = audit.record_finish(seq, result.error.is_none())
+    {
+        eprintln!("error writing audit record: {}", e);
+    }
+
+    if let (Some(cache), Some(key)) = (&state.cache, &cache_key)
+        && result.error.is_none()
+        && let Err(e) = cache.put(key, &result.stdout_text())
+    {
+        eprintln!("error writing cache entry {}: {}", key, e);
+    }
+
+    result
+}
+
+fn execute(
+    job_id: usize,
+    command: Command,
+    stdin: Option<&[u8]>,
+    echo: Option<usage::Echo>,
+    worker_id: usize,
+    config: &Config,
+    state: &RunState,
+) -> JobResult {
+    let started = Instant::now();
+    let output = if config.pty {
+        run_pty_command(command, worker_id, state).map(|output| (output, None))
+    } else {
+        run_command(command, stdin, echo, worker_id, state)
+    };
+    if let Some(auto_jobs) = &state.auto_jobs {
+        let mut auto_jobs = auto_jobs.lock().unwrap_or_else(PoisonError::into_inner);
+        let load = auto_jobs.target_load.and_then(|_| load_average());
+        if let Some(limit) = auto_jobs.observe(started.elapsed(), load) {
+            state.limit.store(limit, Ordering::SeqCst);
+            if config.verbose {
+                eprintln!("adjusting concurrency to {} workers", limit);
+            }
+        }
+    }
+
+    match output {
+        Ok((output, usage)) => {
+            // the output of a `--pipe` job is only kept as the bytes it wrote, read as text
+            // where needed
+            let text = |bytes: &[u8]| {
+                if config.pipe {
+                    String::new()
+                } else {
+                    trimmed_text(bytes).into_owned()
+                }
+            };
+            JobResult {
+                output: text(&output.stdout),
+                stderr: text(&output.stderr),
+                echoed: echo.is_some(),
+                signal: exit_signal(&output.status),
+                error: if state.timed_out[worker_id].load(Ordering::SeqCst) {
+                    let timeout = config.timeout.unwrap_or_default();
+                    Some(format!("timed out after {:?}", timeout))
+                } else if output.status.success() {
+                    None
+                } else if state.killing.load(Ordering::SeqCst) {
+                    Some(format!("terminated: {}", state.reason()))
+                } else {
+                    Some(format!("command failed with exit code: {}", output.status))
+                },
+                exit_code: output.status.code(),
+                streams: keeps_streams(config).then_some((output.stdout, output.stderr)),
+                usage,
+                ..JobResult::new(job_id)
+            }
+        }
+        Err(e) => JobResult::failed(job_id, format!("failed to execute command: {}", e)),
+    }
+}
+
+#[cfg(unix)]
+fn exit_signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+/// Whether a job's stdout and stderr are kept apart in its result, for what reads them later
+fn keeps_streams(config: &Config) -> bool {
+    config.mux
+        || config.pipe
+        || config.only_errors
+        || config.outfile.is_some()
+        || config.results.is_some()
+        || config.verify_sha256_field.is_some()
+}
+
+/// Runs a `--builtin` operation in the worker on the words of the expanded command
+fn run_builtin(job_id: usize, builtin: Builtin, cmd_str: &str, config: &Config) -> JobResult {
+    let (stdout, error) = match builtin::split_words(cmd_str).and_then(|args| builtin.run(&args)) {
+        Ok(stdout) => (stdout, None),
+        Err(e) => (
+            Vec::new(),
+            Some(format!("{} failed: {}", builtin.name(), e)),
+        ),
+    };
+    JobResult {
+        output: String::from_utf8_lossy(&stdout).trim_end().to_string(),
+        exit_code: Some(if error.is_none() { 0 } else { 1 }),
+        error,
+        streams: keeps_streams(config).then_some((stdout, Vec::new())),
+        ..JobResult::new(job_id)
+    }
+}
+
+/// Splits an `--http` request into its method, `GET` when it has none, and URL
+fn split_http_request(request: &str) -> (&str, &str) {
+    match request.trim().split_once(char::is_whitespace) {
+        Some((method, url)) if method.bytes().all(|b| b.is_ascii_uppercase()) => {
+            (method, url.trim_start())
+        }
+        _ => ("GET", request.trim()),
+    }
+}
+
+/// Rejects `--http` requests no job could send, before any runs
+fn check_http_request(request: &str) -> Result<(), String> {
+    let (_, url) = split_http_request(request);
+    if url.starts_with("https://") {
+        return Err("only http:// URLs are supported".to_string());
+    }
+    Ok(())
+}
+
+/// Sends an `--http` request from the expanded `METHOD URL`; the response status is recorded
+/// as the exit status, and anything but 2xx fails the job
+fn run_http(
+    job_id: usize,
+    request: &str,
+    headers: &[String],
+    body: &str,
+    pool: &http::Pool,
+    config: &Config,
+) -> JobResult {
+    let (method, url) = split_http_request(request);
+    let response = http::parse_url(url).and_then(|url| {
+        let headers = headers
+            .iter()
+            .map(|header| match header.split_once(':') {
+                Some((name, value)) => Ok((name.trim().to_string(), value.trim().to_string())),
+                None => Err(format!("header {:?} is not NAME: VALUE", header)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        pool.request(method, &url, &headers, body.as_bytes())
+            .map_err(|e| format!("{} {}: {}", method, url.path, e))
+    });
+    let (status, stdout, error) = match response {
+        Ok(response) if (200..300).contains(&response.status) => {
+            (Some(i32::from(response.status)), response.body, None)
+        }
+        Ok(response) => (
+            Some(i32::from(response.status)),
+            response.body,
+            Some(format!("{} {} answered {}", method, url, response.status)),
+        ),
+        Err(e) => (None, Vec::new(), Some(format!("request failed: {}", e))),
+    };
+    JobResult {
+        output: String::from_utf8_lossy(&stdout).trim_end().to_string(),
+        exit_code: status,
+        error,
+        streams: keeps_streams(config).then_some((stdout, Vec::new())),
+        ..JobResult::new(job_id)
+    }
+}
+
+/// Calibrates with `--tune`, then prints the recommended settings or applies them to the run
+fn tune(config: &mut Config, mode: TuneMode) {
+    let calibration = match tune::calibrate(num_cpus::get()) {
+        Ok(calibration) => calibration,
+        Err(e) => {
+            eprintln!("error calibrating: {}", e);
+            std::process::exit(EXIT_INTERNAL_ERROR);
+        }
+    };
+    if mode == TuneMode::Report {
+        let _ = calibration.report(&mut io::stdout());
+        return;
+    }
+    config.workers = calibration.workers();
+    let mut applied = format!("-j {}", config.workers);
+    if config.pipe {
+        config.block = calibration.block();
+        applied.push_str(&format!(" --block {}", tune::size(config.block)));
+    }
+    eprintln!("tuned: {}", applied);
+}
+
+fn shell_command(cmd_str: &str) -> Command {
+    let mut command = shell_program();
+    command.arg("-c").arg(cmd_str);
+    command
+}
+
+/// Runs the `--script` file with the input line as `$1` and `KYANITE_INPUT`
+fn script_command(path: &Path, line: &str) -> Command {
+    let mut command = shell_program();
+    match SHELL.get() {
+        Some(Shell::Wsl(_)) => {
+            let path = path.to_string_lossy();
+            command.arg(wsl_path(&path).unwrap_or_else(|| path.into_owned()));
+            command.arg(wsl_path(line).as_deref().unwrap_or(line));
+        }
+        _ => {
+            command.arg(path).arg(line);
+        }
+    }
+    command.env("KYANITE_INPUT", line);
+    command
+}
+
+/// Environment variables kyanite sets for jobs, forwarded into WSL through `WSLENV`
+const WSL_FORWARDED: [&str; 9] = [
+    "KYANITE_SEQ",
+    "KYANITE_SLOT",
+    "KYANITE_INPUT",
+    "KYANITE_JOBS",
+    "KYANITE_TOTAL",
+    "KYANITE_SUCCEEDED",
+    "KYANITE_FAILED",
+    "KYANITE_UNFINISHED",
+    "KYANITE_HALT_REASON",
+];
+
+/// Starts `sh`, or `sh` inside a WSL distribution with `--shell wsl[:distro]`
+fn shell_program() -> Command {
+    let Some(Shell::Wsl(distro)) = SHELL.get() else {
+        return Command::new("sh");
+    };
+    let mut command = Command::new("wsl.exe");
+    if let Some(distro) = distro {
+        command.arg("--distribution").arg(distro);
+    }
+    let mut forwarded = std::env::var("WSLENV").unwrap_or_default();
+    for name in WSL_FORWARDED {
+        if !forwarded.is_empty() {
+            forwarded.push(':');
+        }
+        forwarded.push_str(name);
+    }
+    command.env("WSLENV", forwarded).arg("--exec").arg("sh");
+    command
+}
+
+/// Translates an absolute Windows path like `C:\data\in.txt` to its WSL form `/mnt/c/data/in.txt`
+fn wsl_path(path: &str) -> Option<String> {
+    let (root, rest) = PathStyle::Windows.split_root(path);
+    let rest = rest.replace('\\', "/");
+    let bytes = root.as_bytes();
+    if root.len() == 3 && bytes[1] == b':' {
+        return Some(format!("/mnt/{}/{}", root[..1].to_ascii_lowercase(), rest));
+    }
+    // Files inside a distribution are reachable from Windows as \\wsl$\<distro>\...
+    let share = root.trim_matches(|c| c == '\\' || c == '/');
+    let (host, _) = share.split_once(['\\', '/'])?;
+    if host.eq_ignore_ascii_case("wsl$") || host.eq_ignore_ascii_case("wsl.localhost") {
+        return Some(format!("/{}", rest));
+    }
+    None
+}
+
+/// Rewrites an input line with the built-in transforms or by piping it through a command
+fn preprocess(line: &str, preprocess: &Preprocess, style: PathStyle) -> Result<String, String> {
+    let command = match preprocess {
+        Preprocess::Builtin(transforms) => {
+            return Ok(transforms.iter().fold(line.to_string(), |line, transform| {
+                transform.apply(&line, style)
+            }));
+        }
+        Preprocess::Command(command) => command,
+    };
+
+    let mut child = shell_command(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to execute command: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = writeln!(stdin, "{}", line);
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("failed to execute command: {}", e))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!(
+            "command failed with exit code: {}{}",
+            output.status,
+            if stderr.trim().is_empty() {
+                String::new()
+            } else {
+                format!(" ({})", stderr.trim())
+            }
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim_end_matches(['\n', '\r'])
+        .to_string())
+}
+
+impl Transform {
+    fn apply(self, line: &str, style: PathStyle) -> String {
+        let sep = |c| style.is_separator(c);
+        match self {
+            Transform::Trim => line.trim().to_string(),
+            Transform::Lower => line.to_lowercase(),
+            Transform::Upper => line.to_uppercase(),
+            Transform::Basename => {
+                let (_, path) = style.split_root(line);
+                path.trim_end_matches(sep)
+                    .rsplit(sep)
+                    .next()
+                    .unwrap_or_default()
+                    .to_string()
+            }
+            Transform::Dirname => {
+                let (root, path) = style.split_root(line);
+                match path.trim_end_matches(sep).rsplit_once(sep) {
+                    Some((dir, _)) => format!("{}{}", root, dir.trim_end_matches(sep)),
+                    None if root.is_empty() => ".".to_string(),
+                    None => root.to_string(),
+                }
+            }
+            Transform::Noext => {
+                let name_start = line.rfind(sep).map_or(0, |i| i + 1);
+                match line[name_start..].rfind('.') {
+                    Some(dot) if dot > 0 => line[..name_start + dot].to_string(),
+                    _ => line.to_string(),
+                }
+            }
+        }
+    }
+}
+
+/// The path placeholders, named by what is inside their delimiters
+const PATH_PLACEHOLDERS: [&str; 4] = ["/", "//", ".", "/."];
+
+/// Expands the path placeholder `name` for `line`: its basename (`/`), dirname (`//`), the line
+/// without its extension (`.`) or the basename without it (`/.`)
+fn path_placeholder(name: &str, line: &str, style: PathStyle) -> Option<String> {
+    let transforms: &[Transform] = match name {
+        "/" => &[Transform::Basename],
+        "//" => &[Transform::Dirname],
+        "." => &[Transform::Noext],
+        "/." => &[Transform::Basename, Transform::Noext],
+        _ => return None,
+    };
+    let path = transforms.iter().fold(line.to_string(), |path, transform| {
+        transform.apply(&path, style)
+    });
+    Some(path)
+}
+
+impl PathStyle {
+    /// Resolves `auto` to the path style of the platform kyanite runs on
+    fn resolve(self) -> PathStyle {
+        match self {
+            PathStyle::Auto if cfg!(windows) => PathStyle::Windows,
+            PathStyle::Auto => PathStyle::Unix,
+            style => style,
+        }
+    }
+
+    fn is_separator(self, c: char) -> bool {
+        c == '/' || (c == '\\' && self.resolve() == PathStyle::Windows)
+    }
+
+    /// Splits a path into its root (`/`, `C:`, `C:\`, `\\server\share\`) and the rest
+    fn split_root(self, path: &str) -> (&str, &str) {
+        let sep = |c| self.is_separator(c);
+        let len = match self.resolve() {
+            PathStyle::Windows => {
+                let bytes = path.as_bytes();
+                if path.starts_with(sep) && path[1..].starts_with(sep) {
+                    // UNC paths are rooted at \\server\share\
+                    let mut parts = path[2..].splitn(3, sep);
+                    let server = parts.next().unwrap_or_default();
+                    let share = parts.next();
+                    let len = 2 + server.len() + share.map_or(0, |share| 1 + share.len());
+                    len + usize::from(parts.next().is_some())
+                } else if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+                    2 + usize::from(path[2..].starts_with(sep))
+                } else {
+                    usize::from(path.starts_with(sep))
+                }
+            }
+            _ => usize::from(path.starts_with('/')),
+        };
+        path.split_at(len)
+    }
+}
+
+/// Runs a command with `stdin` as its input, tracking its pid so it can be terminated
+///
+/// On unix the job leads a process group of its own, so stopping it reaches what it started.
+fn run_command(
+    mut command: Command,
+    stdin: Option<&[u8]>,
+    echo: Option<usage::Echo>,
+    worker_id: usize,
+    state: &RunState,
+) -> io::Result<(Output, Option<Usage>)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    command
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let spawning = Instant::now();
+    let mut child = command.spawn()?;
+    if let Some(clock) = &state.clock {
+        clock.spawned(worker_id, spawning.elapsed());
+    }
+
+    state.register(worker_id, child.id());
+    let output = match (stdin, child.stdin.take()) {
+        (Some(data), Some(mut pipe)) => thread::scope(|scope| {
+            // fed from another thread so a job that fills its output before reading cannot
+            // deadlock; a job that exits without reading everything is not an error
+            scope.spawn(move || match &state.pacer {
+                Some(pacer) => {
+                    for line in data.split_inclusive(|&byte| byte == b'\n') {
+                        pacer.wait();
+                        if pipe.write_all(line).is_err() {
+                            break;
+                        }
+                    }
+                }
+                None => {
+                    let _ = pipe.write_all(data);
+                }
+            });
+            usage::wait_with_output(child, echo)
+        }),
+        _ => usage::wait_with_output(child, echo),
+    };
+    state.unregister(worker_id);
+    output
+}
+
+/// Runs a command with a pseudo-terminal as its stdout and stderr, which are captured together
+#[cfg(unix)]
+fn run_pty_command(command: Command, worker_id: usize, state: &RunState) -> io::Result<Output> {
+    let (mut child, mut master) = pty::spawn(command)?;
+    state.register(worker_id, child.id());
+    let stdout = pty::read_output(&mut master);
+    let status = child.wait();
+    state.unregister(worker_id);
+    Ok(Output {
+        status: status?,
+        stdout: stdout?,
+        stderr: Vec::new(),
+    })
+}
+
+#[cfg(not(unix))]
+fn run_pty_command(_command: Command, _worker_id: usize, _state: &RunState) -> io::Result<Output> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "pseudo-terminals are only supported on unix",
+    ))
+}
+
+#[derive(Debug, Default)]
+struct FailureCounts {
+    total: usize,
+    consecutive: usize,
+    succeeded: usize,
+    /// Whether a job met the `--until` or `--until-success` condition
+    reached: bool,
+}
+
+impl FailureCounts {
+    fn record(&mut self, failed: bool) {
+        if failed {
+            self.total += 1;
+            self.consecutive += 1;
+        } else {
+            self.succeeded += 1;
+            self.consecutive = 0;
+        }
+    }
+
+    /// Returns the reason to stop the run if a failure guard was tripped
+    fn exceeded(&self, config: &Config) -> Option<String> {
+        if config.halt_on_error && self.total > 0 {
+            Some("a job failed".to_string())
+        } else if config.max_failures > 0 && self.total >= config.max_failures {
+            Some(format!("{} jobs failed", self.total))
+        } else if config.max_consecutive_failures > 0
+            && self.consecutive >= config.max_consecutive_failures
+        {
+            Some(format!("{} consecutive jobs failed", self.consecutive))
+        } else {
+            None
+        }
+    }
+}
+
+/// How long to wait before retrying a job that failed its `attempts`th time, doubling with each
+/// attempt under `--retry-backoff`
+fn retry_delay(config: &Config, attempts: usize) -> Duration {
+    if config.retry_backoff {
+        let doublings = attempts.saturating_sub(1).min(30) as u32;
+        config.retry_delay.saturating_mul(1 << doublings)
+    } else {
+        config.retry_delay
+    }
+}
+
+/// Extracts the delay a job asked for with `--backoff-from-regex`, from the pattern's first group
+fn backoff_delay(pattern: &Regex, output: &str) -> Option<Duration> {
+    let captures = pattern.captures(output)?;
+    let value = captures.get(1).or_else(|| captures.get(0))?;
+    parse_duration(value.as_str()).ok()
+}
+
+/// Returns the reason to end the run if a job met the `--until` or `--until-success` condition
+fn until_reached(result: &JobResult, config: &Config) -> Option<String> {
+    if (config.until_success || config.race) && result.error.is_none() {
+        Some(format!("job {} succeeded", config.start_seq + result.id))
+    } else if let Some(pattern) = &config.until
+        && (pattern.is_match(&result.stdout_text()) || pattern.is_match(&result.stderr_text()))
+    {
+        Some(format!(
+            "output of job {} matched {}",
+            config.start_seq + result.id,
+            pattern
+        ))
+    } else {
+        None
+    }
+}
+
+fn result_collector(
+    result_rx: mpsc::Receiver<JobResult>,
+    config: Arc<Config>,
+    state: Arc<RunState>,
+    mut splitter: Option<Splitter>,
+) -> FailureCounts {
+    let mut failures = FailureCounts::default();
+    let mut check_failures = |result: &mut JobResult| {
+        result.cancelled = failures.reached && result.error.is_some();
+        if !result.cancelled {
+            failures.record(result.error.is_some());
+        }
+        if config.verbose && result.attempts > 1 {
+            eprintln!(
+                "job {} {} after {} attempts",
+                config.start_seq + result.id,
+                if result.error.is_none() {
+                    "succeeded"
+                } else {
+                    "failed"
+                },
+                result.attempts
+            );
+        }
+        let done = state.done.fetch_add(1, Ordering::SeqCst) + 1;
+        if let Some(usage) = result.usage {
+            state
+                .usage
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .add(config.start_seq + result.id, usage);
+        }
+        if let Some(error) = &result.error
+            && !result.cancelled
+        {
+            state.failures.fetch_add(1, Ordering::SeqCst);
+            if let Some(syslog) = &state.syslog {
+                let message = format!(
+                    "job {} failed: {}; input: {}",
+                    config.start_seq + result.id,
+                    error,
+                    result.input
+                );
+                let severity = syslog::severity(result.exit_code);
+                syslog.log(severity, &config.redact(&message));
+            }
+            if config.mail_failed_inputs {
+                let input = config.redact(&result.input).into_owned();
+                let seq = config.start_seq + result.id;
+                state
+                    .failed_inputs
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .push((seq, input));
+            }
+        }
+        if let Some(events) = &state.events {
+            let error = result.error.as_deref().map(|error| config.redact(error));
+            let seq = config.start_seq + result.id;
+            events.finish(seq, result.exit_code, error.as_deref(), result.usage);
+            let failed = state.failures.load(Ordering::SeqCst);
+            events.progress(done, state.total.get().copied(), failed);
+        }
+        if let Some(reason) = failures.exceeded(&config)
+            && state.stop(reason)
+        {
+            eprintln!("{}, no new jobs will be started", state.reason());
+        }
+        if !failures.reached
+            && let Some(reason) = until_reached(result, &config)
+        {
+            failures.reached = true;
+            if state.stop(reason) {
+                eprintln!("{}, cancelling remaining jobs", state.reason());
+                state.kill_running();
+            }
+        }
+    };
+    let mut stdout_closed = false;
+    let mut race_won = false;
+    let mut emit = |result: &JobResult| {
+        if stdout_closed {
+            return;
+        }
+        if config.race {
+            if race_won || result.error.is_some() {
+                if config.verbose
+                    && let Some(error) = &result.error
+                {
+                    eprintln!(
+                        "job {} lost the race: {}",
+                        config.start_seq + result.id,
+                        error
+                    );
+                }
+                return;
+            }
+            race_won = true;
+        }
+        if result.cancelled {
+            if config.verbose {
+                eprintln!("job {} was cancelled", config.start_seq + result.id);
+            }
+            return;
+        }
+        let printed = match &mut splitter {
+            Some(splitter) => split_result(splitter, result, &config),
+            None => print_result(result, &config),
+        };
+        match printed {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                stdout_closed = true;
+                if state.stop("output was closed") {
+                    if config.verbose {
+                        eprintln!("output was closed, terminating running jobs");
+                    }
+                    state.kill_running();
+                }
+            }
+            Err(e) => eprintln!(
+                "error writing output of job {}: {}",
+                config.start_seq + result.id,
+                e
+            ),
+        }
+    };
+
+    // with `--time-report`, when each result arrived, to tell how long ordering held it back
+    let mut received: HashMap<usize, Instant> = HashMap::new();
+    let mut emit = |result: JobResult, held: Duration| {
+        let writing = Instant::now();
+        emit(&result);
+        if let (Some(clock), Some(mut timings)) = (&state.clock, result.timings) {
+            timings.reorder = held;
+            timings.output += writing.elapsed();
+            clock.record(timings);
+        }
+    };
+
+    let mut reorder = Reorder::new(config.order_by()).with_budget(state.budget.clone());
+    for mut result in result_rx {
+        if let Some(budget) = &state.budget {
+            budget.release(result.size());
+        }
+        check_failures(&mut result);
+        if state.clock.is_some() {
+            received.insert(result.id, Instant::now());
+        }
+        for result in reorder.push(result) {
+            let held = received.remove(&result.id).map(|at| at.elapsed());
+            emit(result, held.unwrap_or_default());
+        }
+    }
+    for result in reorder.finish() {
+        let held = received.remove(&result.id).map(|at| at.elapsed());
+        emit(result, held.unwrap_or_default());
+    }
+
+    failures
+}
+
+/// Keyed FIFO lanes for `--order-within-key`: at most one job per key is running or ready at a
+/// time, and the others wait in input order until it finishes
+#[derive(Default)]
+struct Lanes {
+    waiting: Mutex<HashMap<String, VecDeque<Job>>>,
+    ready: Mutex<VecDeque<(String, Job)>>,
+}
+
+/// A worker's hold on a lane, handing the lane's next job over when dropped
+struct LaneHold<'a> {
+    lanes: &'a Lanes,
+    key: String,
+}
+
+impl Lanes {
+    /// Returns the job if its lane is free, otherwise queues it behind the lane's other jobs
+    fn admit(&self, key: &str, job: Job) -> Option<Job> {
+        let mut waiting = self.waiting.lock().unwrap_or_else(PoisonError::into_inner);
+        match waiting.get_mut(key) {
+            Some(lane) => {
+                lane.push_back(job);
+                None
+            }
+            None => {
+                waiting.insert(key.to_string(), VecDeque::new());
+                Some(job)
+            }
+        }
+    }
+
+    fn hold(&self, key: String) -> LaneHold<'_> {
+        LaneHold { lanes: self, key }
+    }
+
+    fn take_ready(&self) -> Option<(String, Job)> {
+        self.ready
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop_front()
+    }
+
+    /// Removes every job that has not started yet
+    fn drain(&self) -> Vec<Job> {
+        let mut waiting = self.waiting.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut ready = self.ready.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut jobs: Vec<Job> = ready.drain(..).map(|(_, job)| job).collect();
+        jobs.extend(waiting.drain().flat_map(|(_, lane)| lane));
+        jobs
+    }
+}
+
+impl Drop for LaneHold<'_> {
+    fn drop(&mut self) {
+        let mut waiting = self
+            .lanes
+            .waiting
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        match waiting.get_mut(&self.key).and_then(VecDeque::pop_front) {
+            Some(next) => {
+                let key = std::mem::take(&mut self.key);
+                self.lanes
+                    .ready
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .push_back((key, next));
+            }
+            None => {
+                waiting.remove(&self.key);
+            }
+        }
+    }
+}
+
+/// Holds back results until they can be printed in the order chosen with `--order-by`
+struct Reorder {
+    order: OrderBy,
+    pending: BTreeMap<usize, JobResult>,
+    next: usize,
+    budget: Option<Arc<memory::Budget>>,
+    spilled: HashMap<usize, PathBuf>,
+}
+
+impl Reorder {
+    fn new(order: OrderBy) -> Self {
+        Reorder {
+            order,
+            pending: BTreeMap::new(),
+            next: 0,
+            budget: None,
+            spilled: HashMap::new(),
+        }
+    }
+
+    /// With `--self-mem-limit`, moves the output of held back results to disk while the budget
+    /// is exceeded
+    fn with_budget(mut self, budget: Option<Arc<memory::Budget>>) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Adds a finished job, returning every result that is now ready to print
+    fn push(&mut self, result: JobResult) -> Vec<JobResult> {
+        let key = match self.order {
+            OrderBy::Finish => return vec![result],
+            OrderBy::Start => result.start,
+            OrderBy::Input => result.id,
+        };
+        if let Some(budget) = &self.budget {
+            budget.keep(Reorder::held(&result));
+        }
+        self.pending.insert(key, result);
+
+        let mut ready = Vec::new();
+        while let Some(result) = self.pending.remove(&self.next) {
+            ready.push(self.release(self.next, result));
+            self.next += 1;
+        }
+        self.spill();
+        ready
+    }
+
+    /// Returns the results still held back because earlier ones never finished
+    fn finish(mut self) -> impl Iterator<Item = JobResult> {
+        let pending = std::mem::take(&mut self.pending);
+        pending
+            .into_iter()
+            .map(move |(key, result)| self.release(key, result))
+    }
+
+    /// Moves output, and the raw streams kept with it, to disk from the results that will be
+    /// printed last, until what is held fits the budget again
+    fn spill(&mut self) {
+        let Some(budget) = &self.budget else {
+            return;
+        };
+        let dir = std::env::temp_dir();
+        for (key, result) in self.pending.iter_mut().rev() {
+            if !budget.exceeded() {
+                break;
+            }
+            let bytes = Reorder::held(result);
+            if bytes == 0 || self.spilled.contains_key(key) {
+                continue;
+            }
+            let mut parts = vec![result.output.as_bytes(), result.stderr.as_bytes()];
+            if let Some((stdout, stderr)) = &result.streams {
+                parts.extend([stdout.as_slice(), stderr.as_slice()]);
+            }
+            match memory::spill(&dir, result.id, &parts) {
+                Ok(path) => {
+                    result.output = String::new();
+                    result.stderr = String::new();
+                    if let Some(streams) = &mut result.streams {
+                        *streams = (Vec::new(), Vec::new());
+                    }
+                    budget.let_go(bytes);
+                    self.spilled.insert(*key, path);
+                }
+                Err(e) => {
+                    eprintln!("error moving held back output to disk: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Hands out a result no longer held back, reading back its output if it was moved to disk
+    fn release(&mut self, key: usize, mut result: JobResult) -> JobResult {
+        let Some(path) = self.spilled.remove(&key) else {
+            if let Some(budget) = &self.budget {
+                budget.let_go(Reorder::held(&result));
+            }
+            return result;
+        };
+        match memory::unspill(&path) {
+            Ok(parts) => {
+                let mut parts = parts.into_iter();
+                let mut text =
+                    || String::from_utf8_lossy(&parts.next().unwrap_or_default()).into_owned();
+                (result.output, result.stderr) = (text(), text());
+                if let Some(streams) = &mut result.streams {
+                    *streams = (
+                        parts.next().unwrap_or_default(),
+                        parts.next().unwrap_or_default(),
+                    );
+                }
+            }
+            Err(e) => eprintln!("error reading back spilled output: {}", e),
+        }
+        result
+    }
+
+    /// How many bytes of output a held back result takes
+    fn held(result: &JobResult) -> u64 {
+        let streams = result
+            .streams
+            .as_ref()
+            .map_or(0, |(stdout, stderr)| stdout.len() + stderr.len());
+        (result.output.len() + result.stderr.len() + streams) as u64
+    }
+}
+
+fn print_result(result: &JobResult, config: &Config) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    if config.mux {
+        if let Some(error) = &result.error {
+            eprintln!("error in job {}: {}", config.start_seq + result.id, error);
+        }
+        let (out, err) = match &result.streams {
+            Some((out, err)) => (out.as_slice(), err.as_slice()),
+            None => (result.output.as_bytes(), result.stderr.as_bytes()),
+        };
+        let seq = config.start_seq + result.id;
+        let frames = mux::frames(seq, out, err, result.exit_code, result.error.as_deref());
+        return writeln!(stdout, "{}", frames);
+    }
+
+    if config.only_errors {
+        return match failure_report(result, config.start_seq) {
+            Some(report) => writeln!(stdout, "{}", report),
+            None => Ok(()),
+        };
+    }
+
+    if let Some(error) = &result.error {
+        report_failure(result, error, config.start_seq);
+    } else if let Some((out, err)) = passed_through(result, config) {
+        stdout.write_all(out)?;
+        io::stderr().write_all(err)?;
+    } else if !result.echoed {
+        let (output, stderr) = (result.stdout_text(), result.stderr_text());
+        if !output.is_empty() {
+            let output = tagged(&output, result, config);
+            if config.verbose {
+                writeln!(stdout, "[job {}] {}", config.start_seq + result.id, output)?;
+            } else {
+                writeln!(stdout, "{}", output)?;
+            }
+        }
+        if !stderr.is_empty() {
+            eprintln!("{}", tagged(&stderr, result, config));
+        }
+    }
+    Ok(())
+}
+
+/// The stdout and stderr of a `--pipe` job, to print byte for byte rather than as trimmed text,
+/// as filters like `gzip` write binary output; not when lines are tagged or numbered
+fn passed_through<'a>(result: &'a JobResult, config: &Config) -> Option<(&'a [u8], &'a [u8])> {
+    let (out, err) = result.streams.as_ref()?;
+    let plain = config.pipe && !result.echoed && !config.verbose;
+    (plain && output_tag(config, &result.input).is_none()).then_some((out, err))
+}
+
+/// What `-v` prints before a job is retried, saying how long until then unless it is retried
+/// at once
+fn retry_notice(seq: usize, delay: Duration, retry: usize, retries: usize) -> String {
+    let wait = if delay.is_zero() {
+        String::new()
+    } else {
+        format!(" in {}s", delay.as_secs_f64())
+    };
+    format!(
+        "job {} failed, retrying{} (retry {} of {})",
+        seq, wait, retry, retries
+    )
+}
+
+/// Reports a failed job on stderr with its output, unless `--line-buffer` already showed it
+fn report_failure(result: &JobResult, error: &str, start_seq: usize) {
+    eprintln!("error in job {}: {}", start_seq + result.id, error);
+    let output = result.combined_output();
+    if !result.echoed && !output.is_empty() {
+        eprintln!("output: {}", output);
+    }
+}
+
+/// The `--tag` of a job's output lines (its input and a tab) or its expanded `--tagstring`
+fn output_tag(config: &Config, input: &str) -> Option<String> {
+    match &config.tagstring {
+        Some(template) => Some(expand_template(
+            template,
+            input,
+            &config.field_separator,
+            &config.placeholder,
+        )),
+        None => config.tag.then(|| format!("{}\t", input)),
+    }
+}
+
+/// Output of a job with each line prefixed by its tag, if it has one
+fn tagged<'a>(output: &'a str, result: &JobResult, config: &Config) -> Cow<'a, str> {
+    match output_tag(config, &result.input) {
+        Some(tag) => Cow::Owned(tag_lines(output, &tag)),
+        None => Cow::Borrowed(output),
+    }
+}
+
+/// Writes a line of a running job's output through for `--line-buffer`, to stdout or stderr
+/// as the job wrote it
+fn echo_line(stderr: bool, tag: &str, line: &[u8]) {
+    let newline = if line.ends_with(b"\n") {
+        &b""[..]
+    } else {
+        b"\n"
+    };
+    let written = if stderr {
+        let mut err = io::stderr().lock();
+        err.write_all(tag.as_bytes())
+            .and_then(|_| err.write_all(line))
+            .and_then(|_| err.write_all(newline))
+    } else {
+        let mut out = io::stdout().lock();
+        out.write_all(tag.as_bytes())
+            .and_then(|_| out.write_all(line))
+            .and_then(|_| out.write_all(newline))
+            .and_then(|_| out.flush())
+    };
+    // a closed stdout is noticed when the job's result is written
+    let _ = written;
+}
+
+fn tag_lines(output: &str, tag: &str) -> String {
+    output
+        .split('\n')
+        .map(|line| format!("{}{}", tag, line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Writes a job's output to one of the `--split-output` files, reporting failures as
+/// `print_result` does
+fn split_result(splitter: &mut Splitter, result: &JobResult, config: &Config) -> io::Result<()> {
+    if let Some(error) = &result.error {
+        report_failure(result, error, config.start_seq);
+        return Ok(());
+    }
+    let stderr = result.stderr_text();
+    if !stderr.is_empty() {
+        eprintln!("{}", tagged(&stderr, result, config));
+    }
+    let output = result.stdout_text();
+    if output.is_empty() {
+        return Ok(());
+    }
+    let key = config.split_key.as_ref().map(|template| {
+        expand_template(
+            template,
+            &result.input,
+            &config.field_separator,
+            &config.placeholder,
+        )
+    });
+    splitter.write(key.as_deref(), &tagged(&output, result, config))
+}
+
+/// Describes a failed job for `--only-errors`: its input, exit code and stderr
+fn failure_report(result: &JobResult, start_seq: usize) -> Option<String> {
+    let error = result.error.as_ref()?;
+    let status = match result.exit_code {
+        Some(code) => format!("exit code {}", code),
+        None => error.clone(),
+    };
+    let mut report = format!(
+        "job {} failed ({}): {}",
+        start_seq + result.id,
+        status,
+        result.input
+    );
+    let stderr = match &result.streams {
+        Some((_, stderr)) => String::from_utf8_lossy(stderr).into_owned(),
+        None => result.combined_output().into_owned(),
+    };
+    for line in stderr.trim_end().lines() {
+        report.push_str("\n  ");
+        report.push_str(line);
+    }
+    Some(report)
+}
+
+/// Expands a command template with an input line using custom placeholder
+///
+/// Supports:
+/// - PLACEHOLDER: Full input line
+/// - PLACEHOLDERn: nth field (space-delimited)
+/// - PLACEHOLDERn+: Fields n through end
+/// - PLACEHOLDERn-: Fields 1 through n
+/// - PLACEHOLDERs/pat/repl/g: Sed substitution (g=global, i=case-insensitive)
+/// - PLACEHOLDER/pat/n: Regex capture group n
+/// - {/}, {//}, {.}, {/.}: Basename, dirname, input without extension, basename without it
+fn expand_template(template: &str, line: &str, field_separator: &str, placeholder: &str) -> String {
+    expand_template_marked(template, line, field_separator, placeholder, false)
+}
+
+const INPUT_START: char = '\u{E000}';
+const INPUT_END: char = '\u{E001}';
+
+fn mark_input(value: String, mark: bool) -> String {
+    if mark {
+        format!("{}{}{}", INPUT_START, value, INPUT_END)
+    } else {
+        value
+    }
+}
+
+/// Expands a template, optionally wrapping every input-derived value in marker characters
+fn expand_template_marked(
+    template: &str,
+    line: &str,
+    field_separator: &str,
+    placeholder: &str,
+    mark: bool,
+) -> String {
+    expand_template_traced(template, line, field_separator, placeholder, mark, None)
+}
+
+/// Expands a template, describing each placeholder match and the string after each pass in
+/// `trace` for `--explain`
+fn expand_template_traced(
+    template: &str,
+    line: &str,
+    field_separator: &str,
+    placeholder: &str,
+    mark: bool,
+    mut trace: Option<&mut Vec<String>>,
+) -> String {
+    let mut note = |text: String| {
+        if let Some(trace) = trace.as_mut() {
+            trace.push(text);
+        }
+    };
+    let (open_delim, close_delim) = placeholder_delimiters(placeholder);
+
+    let open_escaped = regex_escape(open_delim);
+    let close_escaped = regex_escape(close_delim);
+
+    let fields = split_fields(line, field_separator);
+    let shown = shown_input(line, field_separator);
+    let line = shown.as_ref();
+
+    let mut result = template.to_string();
+
+    let sed_pattern = format!(
+        r"{}\s*s/([^/]+)/([^/]*)/(.*?){}",
+        open_escaped, close_escaped
+    );
+    let sed_re = Regex::new(&sed_pattern).unwrap();
+    result = sed_re
+        .replace_all(&result, |caps: &regex::Captures| {
+            let pattern = &caps[1];
+            let replacement = &caps[2];
+            let flags = &caps[3];
+
+            let mut regex_pattern = pattern.to_string();
+            if flags.contains('i') {
+                regex_pattern = format!("(?i){}", regex_pattern);
+            }
+
+            match Regex::new(&regex_pattern) {
+                Ok(re) => {
+                    let value = if flags.contains('g') {
+                        re.replace_all(line, replacement).to_string()
+                    } else {
+                        re.replace(line, replacement).to_string()
+                    };
+                    note(format!(
+                        "{}: substitute /{}/ with {:?} in the input{} -> {:?}",
+                        &caps[0],
+                        regex_pattern,
+                        replacement,
+                        if flags.contains('g') {
+                            " (every match)"
+                        } else {
+                            ""
+                        },
+                        value
+                    ));
+                    mark_input(value, mark)
+                }
+                Err(e) => {
+                    note(format!("{}: invalid regex, left as is ({})", &caps[0], e));
+                    caps.get(0).unwrap().as_str().to_string()
+                }
+            }
+        })
+        .to_string();
+    note_pass(&mut note, "sed", &result);
+
+    let record_pattern = format!(r"{}\s*line:(\d+)\s*{}", open_escaped, close_escaped);
+    let record_re = Regex::new(&record_pattern).unwrap();
+    if record_re.is_match(&result) {
+        result = record_re
+            .replace_all(&result, |caps: &regex::Captures| {
+                let number: usize = caps[1].parse().unwrap_or(0);
+                let Some(value) = number.checked_sub(1).and_then(|i| line.lines().nth(i)) else {
+                    note(format!(
+                        "{}: the record has {} lines -> empty",
+                        &caps[0],
+                        line.lines().count()
+                    ));
+                    return String::new();
+                };
+                note(format!(
+                    "{}: line {} of the record -> {:?}",
+                    &caps[0], number, value
+                ));
+                mark_input(value.to_string(), mark)
+            })
+            .to_string();
+        note_pass(&mut note, "record line", &result);
+    }
+
+    let field_pattern = format!(
+        r"{}\s*(\d+)([\+\-]?)[!?]?\s*{}",
+        open_escaped, close_escaped
+    );
+    let field_re = Regex::new(&field_pattern).unwrap();
+    result = field_re
+        .replace_all(&result, |caps: &regex::Captures| {
+            let field_num: usize = caps[1].parse().unwrap_or(0);
+            let modifier = &caps[2];
+
+            if field_num == 0 || field_num > fields.len() {
+                note(format!(
+                    "{}: split on {:?} into {} fields, no field {} -> empty",
+                    &caps[0],
+                    field_separator,
+                    fields.len(),
+                    field_num
+                ));
+                return String::new();
+            }
+
+            let (value, which) = match modifier {
+                "+" => (
+                    fields[(field_num - 1)..].join(field_separator),
+                    "fields from",
+                ),
+                "-" => (fields[0..field_num].join(field_separator), "fields up to"),
+                _ => (fields[field_num - 1].to_string(), "field"),
+            };
+            note(format!(
+                "{}: split on {:?} into {} fields, {} {} -> {:?}",
+                &caps[0],
+                field_separator,
+                fields.len(),
+                which,
+                field_num,
+                value
+            ));
+            mark_input(value, mark)
+        })
+        .to_string();
+    note_pass(&mut note, "field", &result);
+
+    let capture_pattern = format!(r"{}\s*/([^/]+)/(\d+)\s*{}", open_escaped, close_escaped);
+    let capture_re = Regex::new(&capture_pattern).unwrap();
+    result = capture_re
+        .replace_all(&result, |caps: &regex::Captures| {
+            let pattern = &caps[1];
+            let group_num: usize = caps[2].parse().unwrap_or(0);
+
+            match Regex::new(pattern) {
+                Ok(re) => {
+                    let Some(captures) = re.captures(line) else {
+                        note(format!(
+                            "{}: /{}/ does not match -> empty",
+                            &caps[0], pattern
+                        ));
+                        return String::new();
+                    };
+                    if let Some(group) = captures.get(group_num) {
+                        note(format!(
+                            "{}: /{}/ matched {:?}, group {} -> {:?}",
+                            &caps[0],
+                            pattern,
+                            &captures[0],
+                            group_num,
+                            group.as_str()
+                        ));
+                        return mark_input(group.as_str().to_string(), mark);
+                    }
+                    note(format!(
+                        "{}: /{}/ matched {:?} without group {} -> empty",
+                        &caps[0], pattern, &captures[0], group_num
+                    ));
+                    String::new()
+                }
+                Err(e) => {
+                    note(format!("{}: invalid regex ({}) -> empty", &caps[0], e));
+                    String::new()
+                }
+            }
+        })
+        .to_string();
+    note_pass(&mut note, "capture", &result);
+
+    let path_pattern = format!(r"{}\s*(//|/\.|/|\.)\s*{}", open_escaped, close_escaped);
+    let path_re = Regex::new(&path_pattern).unwrap();
+    if path_re.is_match(&result) {
+        let style = PATH_STYLE.get().copied().unwrap_or(PathStyle::Auto);
+        result = path_re
+            .replace_all(&result, |caps: &regex::Captures| {
+                let value = path_placeholder(&caps[1], line, style).unwrap_or_default();
+                note(format!("{}: {:?} as a path -> {:?}", &caps[0], line, value));
+                mark_input(value, mark)
+            })
+            .to_string();
+        note_pass(&mut note, "path", &result);
+    }
+
+    let escape_pattern = format!(
+        r"{}\s*({})(?::(\d+))?\s*{}",
+        open_escaped,
+        escape::NAMES.join("|"),
+        close_escaped
+    );
+    let escape_re = Regex::new(&escape_pattern).unwrap();
+    if escape_re.is_match(&result) {
+        result = escape_re
+            .replace_all(&result, |caps: &regex::Captures| {
+                let value = match caps.get(2) {
+                    Some(number) => {
+                        let number: usize = number.as_str().parse().unwrap_or(0);
+                        number
+                            .checked_sub(1)
+                            .and_then(|i| fields.get(i))
+                            .copied()
+                            .unwrap_or_default()
+                    }
+                    None => line,
+                };
+                let quoted = escape::apply(&caps[1], value).unwrap_or_default();
+                note(format!("{}: {:?} quoted -> {}", &caps[0], value, quoted));
+                mark_input(quoted, mark)
+            })
+            .to_string();
+        note_pass(&mut note, "escape", &result);
+    }
+
+    if result.contains(placeholder) {
+        note(format!("{}: the whole input -> {:?}", placeholder, line));
+    }
+    result = result.replace(placeholder, &mark_input(line.to_string(), mark));
+
+    result
+}
+
+/// Records the string left after a pass of `expand_template_traced`
+fn note_pass(note: &mut impl FnMut(String), pass: &str, result: &str) {
+    note(format!("  after {} placeholders: {}", pass, result));
+}
+
+/// Checks a marked expansion for input-derived shell metacharacters outside of safe quoting,
+/// returning the unmarked command when it is safe
+fn check_safe(marked: &str) -> Result<String, String> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Quote {
+        None,
+        Single,
+        Double,
+    }
+
+    let mut quote = Quote::None;
+    let mut depth = 0usize;
+    let mut escaped = false;
+    let mut command = String::with_capacity(marked.len());
+
+    for c in marked.chars() {
+        match c {
+            INPUT_START => {
+                depth += 1;
+                continue;
+            }
+            INPUT_END => {
+                depth = depth.saturating_sub(1);
+                continue;
+            }
+            _ => command.push(c),
+        }
+
+        if depth > 0 {
+            let unsafe_here = match quote {
+                Quote::None => c.is_whitespace() || "|&;<>()$`\\\"'*?[]{}~#!".contains(c),
+                Quote::Single => c == '\'',
+                Quote::Double => "\"$`\\".contains(c),
+            };
+            if unsafe_here {
+                let position = match quote {
+                    Quote::None => "outside quotes",
+                    Quote::Single => "inside single quotes",
+                    Quote::Double => "inside double quotes",
+                };
+                return Err(format!(
+                    "unsafe command: input contains {:?} {}",
+                    c, position
+                ));
+            }
+            continue;
+        }
+
+        if escaped {
+            escaped = false;
+            continue;
+        }
+
+        quote = match (quote, c) {
+            (Quote::None, '\'') => Quote::Single,
+            (Quote::None, '"') => Quote::Double,
+            (Quote::Single, '\'') | (Quote::Double, '"') => Quote::None,
+            (Quote::None | Quote::Double, '\\') => {
+                escaped = true;
+                quote
+            }
+            _ => quote,
+        };
+    }
+
+    Ok(command)
+}
+
+/// Drops full-line `#` comments from a command file, leaving heredoc bodies untouched
+fn strip_template_comments(contents: &str) -> String {
+    let heredoc_re = Regex::new(r#"(?:^|[^<])<<(-?)\s*['"]?(\w+)['"]?"#).unwrap();
+    let mut lines = Vec::new();
+    let mut terminators: VecDeque<(String, bool)> = VecDeque::new();
+
+    for line in contents.lines() {
+        if let Some((word, strip_tabs)) = terminators.front() {
+            let candidate = if *strip_tabs {
+                line.trim_start_matches('\t')
+            } else {
+                line
+            };
+            if candidate == word {
+                terminators.pop_front();
+            }
+            lines.push(line);
+            continue;
+        }
+
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
+
+        for caps in heredoc_re.captures_iter(line) {
+            terminators.push_back((caps[2].to_string(), !caps[1].is_empty()));
+        }
+        lines.push(line);
+    }
+
+    lines.join("\n").trim_end().to_string()
+}
+
+fn placeholder_delimiters(placeholder: &str) -> (char, char) {
+    if placeholder.len() >= 2 {
+        (
+            placeholder.chars().next().unwrap(),
+            placeholder.chars().nth(placeholder.len() - 1).unwrap(),
+        )
+    } else {
+        let c = placeholder.chars().next().unwrap_or('{');
+        (c, c)
+    }
+}
+
+/// Expands a named job placeholder such as `{slotdir}` using the placeholder's delimiters
+fn expand_named(template: &str, placeholder: &str, name: &str, value: &str) -> String {
+    let (open_delim, close_delim) = placeholder_delimiters(placeholder);
+    template.replace(&format!("{}{}{}", open_delim, name, close_delim), value)
+}
+
+/// Quotes a string for safe use as a single shell word
+fn shell_quote(s: &str) -> Cow<'_, str> {
+    let safe = !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_./:=@%+,-".contains(c));
+    if safe {
+        Cow::Borrowed(s)
+    } else {
+        Cow::Owned(format!("'{}'", s.replace('\'', "'\\''")))
+    }
+}
+
+fn regex_escape(c: char) -> String {
+    match c {
+        '\\' | '.' | '+' | '*' | '?' | '(' | ')' | '[' | ']' | '{' | '}' | '|' | '^' | '$' => {
+            format!("\\{}", c)
+        }
+        _ => c.to_string(),
+    }
+}
+
+/// Parses circuit breaker settings like `fails=20,window=60s,cooldown=5m`
+fn parse_breaker(s: &str) -> Result<BreakerSettings, String> {
+    let mut settings = BreakerSettings {
+        fails: 0,
+        window: Duration::from_secs(60),
+        cooldown: Duration::from_secs(60),
+    };
+
+    for part in s.split(',') {
+        let (key, value) = part
+            .split_once('=')
+            .ok_or_else(|| format!("expected key=value, got: {}", part))?;
+        match key.trim() {
+            "fails" => {
+                settings.fails = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("invalid fails value: {}", value))?
+            }
+            "window" => settings.window = parse_duration(value)?,
+            "cooldown" => settings.cooldown = parse_duration(value)?,
+            other => return Err(format!("unknown circuit breaker setting: {}", other)),
+        }
+    }
+
+    if settings.fails == 0 {
+        return Err("circuit breaker needs fails=N with N > 0".to_string());
+    }
+    Ok(settings)
+}
+
+/// Parses `builtin:trim,lower,...` transform chains; anything else is a filter command
+fn parse_preprocess(s: &str) -> Result<Preprocess, String> {
+    let Some(names) = s.strip_prefix("builtin:") else {
+        return Ok(Preprocess::Command(s.to_string()));
+    };
+    names
+        .split(',')
+        .map(|name| {
+            Transform::from_str(name.trim(), true)
+                .map_err(|_| format!("unknown transform: {}", name.trim()))
+        })
+        .collect::<Result<_, _>>()
+        .map(Preprocess::Builtin)
+}
+
+/// Parses `--estimate` as a per-job duration guess or `sample=K`
+fn parse_estimate(s: &str) -> Result<Estimate, String> {
+    match s.strip_prefix("sample=") {
+        Some(count) => match count.parse() {
+            Ok(count) if count > 0 => Ok(Estimate::Sample(count)),
+            _ => Err(format!("invalid sample size: {}", count)),
+        },
+        None => parse_duration(s).map(Estimate::PerJob),
+    }
+}
+
+/// Parses a byte size like `512M` or `2G` (binary units), or a plain number of bytes
+/// Parses a count that must be at least one
+fn parse_count(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(0) | Err(_) => Err(format!("invalid count {}: expected a positive number", s)),
+        Ok(count) => Ok(count),
+    }
+}
+
+/// Parses a `--max-lines-per-sec` rate, which may be fractional
+fn parse_rate(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if rate.is_finite() && rate > 0.0 => Ok(rate),
+        _ => Err(format!("invalid rate {}: expected a positive number", s)),
+    }
+}
+
+/// Parses a `--max-temp` in degrees Celsius, `85` or `85C`
+fn parse_max_temp(s: &str) -> Result<f64, String> {
+    let degrees = s.strip_suffix(['C', 'c']).unwrap_or(s);
+    match degrees.trim().parse::<f64>() {
+        Ok(degrees) if degrees.is_finite() && degrees > 0.0 => Ok(degrees),
+        _ => Err(format!(
+            "invalid temperature {}: expected degrees Celsius like 85C",
+            s
+        )),
+    }
+}
+
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (number, shift) = match s.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&s[..i], 10),
+        Some((i, 'M' | 'm')) => (&s[..i], 20),
+        Some((i, 'G' | 'g')) => (&s[..i], 30),
+        Some((i, 'T' | 't')) => (&s[..i], 40),
+        _ => (s, 0),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .filter(|&n| n > 0)
+        .ok_or_else(|| format!("invalid size: {}", s))
+}
+
+/// Parses a `--case` of `test-template` as `input line=expected command`, split at the first `=`
+fn parse_template_case(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(line, expected)| (line.to_string(), expected.to_string()))
+        .ok_or_else(|| format!("expected 'input line=expected command': {}", s))
+}
+
+/// Parses `--redact` as a regex and an optional replacement after the first unescaped `:`,
+/// `[REDACTED]` by default; a `:` in the regex itself is written `\:`
+fn parse_redact(s: &str) -> Result<(Regex, String), String> {
+    let split = s
+        .char_indices()
+        .find(|&(i, c)| c == ':' && !s[..i].ends_with('\\'))
+        .map(|(i, _)| i);
+    let (pattern, replacement) = match split {
+        Some(i) => (&s[..i], &s[i + 1..]),
+        None => (s, "[REDACTED]"),
+    };
+    let pattern = Regex::new(pattern).map_err(|e| e.to_string())?;
+    Ok((pattern, replacement.to_string()))
+}
+
+/// Parses `--verify` as a file template and a hash template, split at the last `=`
+fn parse_verify(s: &str) -> Result<(String, String), String> {
+    s.rsplit_once('=')
+        .filter(|(file, hash)| !file.is_empty() && !hash.is_empty())
+        .map(|(file, hash)| (file.to_string(), hash.to_string()))
+        .ok_or_else(|| format!("expected 'file template=hash template': {}", s))
+}
+
+/// Parses `--shell` as `sh`, `wsl` or `wsl:<distro>`
+fn parse_shell(s: &str) -> Result<Shell, String> {
+    match s.split_once(':') {
+        None if s == "sh" => Ok(Shell::Sh),
+        None if s == "wsl" => Ok(Shell::Wsl(None)),
+        Some(("wsl", distro)) if !distro.is_empty() => Ok(Shell::Wsl(Some(distro.to_string()))),
+        _ => Err(format!(
+            "unknown shell: {} (expected sh, wsl or wsl:<distro>)",
+            s
+        )),
+    }
+}
+
+/// Parses a jitter range like `100..500ms`, or a single maximum like `2s` meaning `0..2s`
+fn parse_jitter(s: &str) -> Result<Jitter, String> {
+    let Some((min, max)) = s.split_once("..") else {
+        return Ok(Jitter {
+            min: Duration::ZERO,
+            max: parse_duration(s)?,
+        });
+    };
+
+    let unit = max.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+    let min = if min.trim().parse::<f64>().is_ok() && !unit.is_empty() {
+        parse_duration(&format!("{}{}", min.trim(), unit))?
+    } else {
+        parse_duration(min)?
+    };
+    let max = parse_duration(max)?;
+    if min > max {
+        return Err(format!("jitter minimum exceeds maximum: {}", s));
+    }
+    Ok(Jitter { min, max })
+}
+
+/// Parses durations like `500ms`, `30s`, `5m`, `2h`, `1d` or `1h30m`; bare numbers are seconds
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err("empty duration".to_string());
+    }
+    if let Ok(secs) = s.parse::<f64>() {
+        return Duration::try_from_secs_f64(secs).map_err(|e| e.to_string());
+    }
+
+    let re = Regex::new(r"(\d+(?:\.\d+)?)(ms|s|m|h|d)").unwrap();
+    let mut total = 0.0;
+    let mut consumed = 0;
+    for caps in re.captures_iter(s) {
+        let whole = caps.get(0).unwrap();
+        if whole.start() != consumed {
+            return Err(format!("invalid duration: {}", s));
+        }
+        consumed = whole.end();
+
+        let value: f64 = caps[1]
+            .parse()
+            .map_err(|_| format!("invalid duration: {}", s))?;
+        total += value
+            * match &caps[2] {
+                "ms" => 0.001,
+                "s" => 1.0,
+                "m" => 60.0,
+                "h" => 3600.0,
+                _ => 86400.0,
+            };
+    }
+
+    if consumed != s.len() {
+        return Err(format!("invalid duration: {}", s));
+    }
+    Duration::try_from_secs_f64(total).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_template_basic() {
+        let result = expand_template("echo {}", "hello world", " ", "{}");
+        assert_eq!(result, "echo hello world");
+    }
+
+    #[test]
+    fn test_expand_template_field_access() {
+        let result = expand_template("echo {1} {2}", "first second third", " ", "{}");
+        assert_eq!(result, "echo first second");
+    }
+
+    #[test]
+    fn test_expand_template_field_range() {
+        let result = expand_template("echo {2+}", "first second third fourth", " ", "{}");
+        assert_eq!(result, "echo second third fourth");
+    }
+
+    #[test]
+    fn test_expand_template_sed_substitution() {
+        let result = expand_template("echo {s/old/new/g}", "old old new", " ", "{}");
+        assert_eq!(result, "echo new new new");
+    }
+
+    #[test]
+    fn test_expand_template_regex_capture() {
+        let result = expand_template("echo {/(.+)\\.(.+)/1}", "file.txt", " ", "{}");
+        assert_eq!(result, "echo file");
+    }
+
+    #[test]
+    fn test_expand_template_complex() {
+        let result = expand_template("cp {} {s/.mp4/.mp3/g}", "video.mp4", " ", "{}");
+        assert_eq!(result, "cp video.mp4 video.mp3");
+    }
+
+    #[test]
+    fn test_expand_template_empty_field() {
+        let result = expand_template("echo {5}", "one two three", " ", "{}");
+        assert_eq!(result, "echo ");
+    }
+
+    #[test]
+    fn test_config_default_values() {
+        use clap::Parser;
+        let config = Config::parse_from(["kyanite", "echo {}"]);
+        assert_eq!(config.workers, num_cpus::get());
+        assert!(!config.keep_order);
+        assert!(!config.dry_run);
+        assert!(!config.verbose);
+        assert_eq!(config.max_jobs, 0);
+        assert_eq!(config.placeholder, "{}");
+        assert_eq!(config.field_separator, " ");
+        assert_eq!(config.max_runtime, None);
+        assert_eq!(config.halt_on_budget, BudgetHalt::Wait);
+        assert_eq!(config.remaining_input, None);
+        assert_eq!(config.max_failures, 0);
+        assert_eq!(config.max_consecutive_failures, 0);
+        assert!(!config.auto_jobs);
+        assert_eq!(config.command.as_deref(), Some("echo {}"));
+        assert_eq!(config.command_file, None);
+    }
+
+    #[test]
+    fn test_config_parsing() {
+        use clap::Parser;
+        let config = Config::parse_from([
+            "kyanite",
+            "-j",
+            "4",
+            "-k",
+            "-n",
+            "-v",
+            "--max-jobs",
+            "10",
+            "-I",
+            "@",
+            "--field-separator",
+            ",",
+            "echo @",
+        ]);
+        assert_eq!(config.workers, 4);
+        assert!(config.keep_order);
+        assert!(config.dry_run);
+        assert!(config.verbose);
+        assert_eq!(config.max_jobs, 10);
+        assert_eq!(config.placeholder, "@");
+        assert_eq!(config.field_separator, ",");
+        assert_eq!(config.command.as_deref(), Some("echo @"));
+    }
+
+    #[test]
+    fn test_expand_template_edge_cases() {
+        let result = expand_template("echo {s/([/broken/g}", "test", " ", "{}");
+        assert!(result.contains("s/([/broken/g"));
+
+        let result = expand_template("echo {abc}", "test", " ", "{}");
+        assert_eq!(result, "echo {abc}");
+
+        let result = expand_template("echo {5}", "one two three", " ", "{}");
+        assert_eq!(result, "echo ");
+    }
+
+    #[test]
+    fn test_simple_replacement_with_bracket_placeholder() {
+        let result = "echo []".replace("[]", "hello");
+        assert_eq!(result, "echo hello");
+    }
+
+    #[test]
+    fn test_simple_replacement_with_at_placeholder() {
+        let result = "echo @@".replace("@@", "world");
+        assert_eq!(result, "echo world");
+    }
+
+    #[test]
+    fn test_simple_replacement_with_angle_bracket_placeholder() {
+        let result = "echo <>".replace("<>", "test");
+        assert_eq!(result, "echo test");
+    }
+
+    #[test]
+    fn test_simple_replacement_with_custom_string_placeholder() {
+        let result = "echo PLACEHOLDER".replace("PLACEHOLDER", "value");
+        assert_eq!(result, "echo value");
+    }
+
+    #[test]
+    fn test_simple_replacement_multiple_occurrences() {
+        let result = "echo @@ and @@".replace("@@", "test");
+        assert_eq!(result, "echo test and test");
+    }
+
+    #[test]
+    fn test_simple_replacement_with_special_characters() {
+        let result = "echo %%".replace("%%", "special");
+        assert_eq!(result, "echo special");
+    }
+
+    #[test]
+    fn test_simple_replacement_with_empty_string() {
+        let result = "echo TEST".replace("TEST", "");
+        assert_eq!(result, "echo ");
+    }
+
+    #[test]
+    fn test_simple_replacement_preserves_other_content() {
+        let result = "start [middle] end".replace("[middle]", "REPLACED");
+        assert_eq!(result, "start REPLACED end");
+    }
+
+    #[test]
+    fn test_placeholder_behavior_documentation() {
+        let result = expand_template("echo {1}", "first second", " ", "{}");
+        assert_eq!(result, "echo first");
+
+        let result = expand_template("echo [1]", "first second", " ", "[]");
+        assert_eq!(result, "echo first");
+
+        let result = expand_template("echo @1@", "first second", " ", "@@");
+        assert_eq!(result, "echo first");
+    }
+
+    #[test]
+    fn test_template_expansion_with_angle_brackets() {
+        let result = expand_template("echo <1> <2>", "alpha beta gamma", " ", "<>");
+        assert_eq!(result, "echo alpha beta");
+    }
+
+    #[test]
+    fn test_template_expansion_with_brackets_field_access() {
+        let result = expand_template("echo [1] [2]", "first second third", " ", "[]");
+        assert_eq!(result, "echo first second");
+    }
+
+    #[test]
+    fn test_template_expansion_with_brackets_field_range() {
+        let result = expand_template("echo [2+]", "first second third fourth", " ", "[]");
+        assert_eq!(result, "echo second third fourth");
+    }
+
+    #[test]
+    fn test_template_expansion_with_brackets_sed() {
+        let result = expand_template("echo [s/old/new/g]", "old old new", " ", "[]");
+        assert_eq!(result, "echo new new new");
+    }
+
+    #[test]
+    fn test_template_expansion_with_brackets_regex_capture() {
+        let result = expand_template("echo [/(.+)\\.(.+)/1]", "file.txt", " ", "[]");
+        assert_eq!(result, "echo file");
+    }
+
+    #[test]
+    fn test_template_expansion_with_brackets_complex() {
+        let result = expand_template("cp [] [s/.mp4/.mp3/g]", "video.mp4", " ", "[]");
+        assert_eq!(result, "cp video.mp4 video.mp3");
+    }
+
+    #[test]
+    fn test_template_expansion_with_at_placeholder_field_access() {
+        let result = expand_template("echo @1@ @2@", "first second third", " ", "@@");
+        assert_eq!(result, "echo first second");
+    }
+
+    #[test]
+    fn test_template_expansion_with_at_placeholder_field_range() {
+        let result = expand_template("echo @2+@", "first second third fourth", " ", "@@");
+        assert_eq!(result, "echo second third fourth");
+    }
+
+    #[test]
+    fn test_template_expansion_with_at_placeholder_sed() {
+        let result = expand_template("echo @s/old/new/g@", "old old new", " ", "@@");
+        assert_eq!(result, "echo new new new");
+    }
+
+    #[test]
+    fn test_template_expansion_with_at_placeholder_regex_capture() {
+        let result = expand_template("echo @/(.+)\\.(.+)/1@", "file.txt", " ", "@@");
+        assert_eq!(result, "echo file");
+    }
+
+    #[test]
+    fn test_template_expansion_with_percent_placeholder() {
+        let result = expand_template("echo %s/foo/bar/g%", "foo foo baz", " ", "%%");
+        assert_eq!(result, "echo bar bar baz");
+    }
+
+    #[test]
+    fn test_template_expansion_with_pipe_placeholder() {
+        let result = expand_template("echo |1| |2|", "one two three", " ", "||");
+        assert_eq!(result, "echo one two");
+    }
+
+    #[test]
+    fn test_template_expansion_with_custom_delimiter_field_access() {
+        let result = expand_template("echo ::1::", "a b c d", " ", "::");
+        assert!(result.contains("a"), "Result should contain 'a'");
+    }
+
+    #[test]
+    fn test_template_expansion_with_custom_delimiter_field_range() {
+        let result = expand_template("echo ::2+::", "a b c d", " ", "::");
+        assert!(result.contains("b"), "Result should contain 'b'");
+    }
+
+    #[test]
+    fn test_template_expansion_with_colons_sed() {
+        let result = expand_template("echo :s/a/x/g:", "a b c", " ", "::");
+        assert_eq!(result, "echo x b c");
+    }
+
+    #[test]
+    fn test_template_expansion_mixed_placeholders() {
+        let result = expand_template("echo [] [1]", "hello world", " ", "[]");
+        assert_eq!(result, "echo hello world hello");
+    }
+
+    #[test]
+    fn test_template_expansion_field_minus_with_custom_placeholder() {
+        let result = expand_template("echo [3-]", "first second third fourth", " ", "[]");
+        assert_eq!(result, "echo first second third");
+    }
+
+    #[test]
+    fn test_template_expansion_case_insensitive_with_custom_placeholder() {
+        let result = expand_template("echo [s/HELLO/world/i]", "HELLO hello", " ", "[]");
+        assert_eq!(result, "echo world hello");
+
+        let result = expand_template("echo [s/HELLO/world/gi]", "HELLO hello", " ", "[]");
+        assert_eq!(result, "echo world world");
+    }
+
+    #[test]
+    fn test_template_expansion_regex_capture_group2() {
+        let result = expand_template("echo [/(\\d+)-(\\w+)/2]", "123-abc", " ", "[]");
+        assert_eq!(result, "echo abc");
+    }
+
+    #[test]
+    fn test_template_expansion_empty_field_with_custom_placeholder() {
+        let result = expand_template("echo [5]", "one two three", " ", "[]");
+        assert_eq!(result, "echo ");
+    }
+
+    #[test]
+    fn test_placeholder_at_start() {
+        let result = "@@ suffix".replace("@@", "prefix");
+        assert_eq!(result, "prefix suffix");
+    }
+
+    #[test]
+    fn test_placeholder_at_end() {
+        let result = "prefix @".replace("@", "suffix");
+        assert_eq!(result, "prefix suffix");
+    }
+
+    #[test]
+    fn test_placeholder_with_spaces() {
+        let result = "echo { x }".replace("{ x }", "test");
+        assert_eq!(result, "echo test");
+    }
+
+    #[test]
+    fn test_placeholder_with_underscores() {
+        let result = "echo _placeholder_".replace("_placeholder_", "value");
+        assert_eq!(result, "echo value");
+    }
+
+    #[test]
+    fn test_placeholder_with_dashes() {
+        let result = "echo --value--".replace("--value--", "test");
+        assert_eq!(result, "echo test");
+    }
+
+    #[test]
+    fn test_placeholder_substring_replacement() {
+        let result = "test_test_test".replace("test", "X");
+        assert_eq!(result, "X_X_X");
+    }
+
+    #[test]
+    fn test_placeholder_case_sensitive() {
+        let result = "ABC abc".replace("ABC", "123");
+        assert_eq!(result, "123 abc");
+    }
+
+    #[test]
+    fn test_placeholder_with_numbers() {
+        let result = "echo #1#".replace("#1#", "first");
+        assert_eq!(result, "echo first");
+    }
+
+    #[test]
+    fn test_placeholder_with_colon() {
+        let result = "echo ::val::".replace("::val::", "content");
+        assert_eq!(result, "echo content");
+    }
+
+    #[test]
+    fn test_placeholder_with_pipe() {
+        let result = "echo |val|".replace("|val|", "data");
+        assert_eq!(result, "echo data");
+    }
+
+    #[test]
+    fn test_multiple_different_placeholders() {
+        let result = "start @ end %"
+            .replace("@", "middle1")
+            .replace("%", "middle2");
+        assert_eq!(result, "start middle1 end middle2");
+    }
+
+    #[test]
+    fn test_placeholder_with_special_regex_chars() {
+        let result = "echo $1$".replace("$1$", "group1");
+        assert_eq!(result, "echo group1");
+    }
+
+    #[test]
+    fn test_placeholder_long_string() {
+        let result = "echo __PLACEHOLDER_HERE__".replace("__PLACEHOLDER_HERE__", "value");
+        assert_eq!(result, "echo value");
+    }
+
+    #[test]
+    fn test_placeholder_empty_value_replacement() {
+        let result = "start [] end".replace("[]", "multi word value");
+        assert_eq!(result, "start multi word value end");
+    }
+
+    #[test]
+    fn test_real_world_file_renaming_with_brackets() {
+        let result = expand_template("mv [] [s/\\.jpg/.png/]", "photo.jpg", " ", "[]");
+        assert_eq!(result, "mv photo.jpg photo.png");
+    }
+
+    #[test]
+    fn test_real_world_csv_field_extraction_with_at() {
+        let result = expand_template(
+            "echo 'User: @1@, Email: @3@'",
+            "alice admin alice@example.com",
+            " ",
+            "@@",
+        );
+        assert_eq!(result, "echo 'User: alice, Email: alice@example.com'");
+    }
+
+    #[test]
+    fn test_field_separator_with_comma() {
+        let result = expand_template(
+            "echo \"Name: {1}, Email: {2}\"",
+            "jacobi,j@cobi.dev",
+            ",",
+            "{}",
+        );
+        assert_eq!(result, "echo \"Name: jacobi, Email: j@cobi.dev\"");
+    }
+
+    #[test]
+    fn test_field_separator_with_comma_multiple_lines() {
+        let result1 = expand_template(
+            "echo \"Name: {1}, Email: {2}\"",
+            "jacobi,j@cobi.dev",
+            ",",
+            "{}",
+        );
+        assert_eq!(result1, "echo \"Name: jacobi, Email: j@cobi.dev\"");
+
+        let result2 = expand_template("echo \"Name: {1}, Email: {2}\"", "jade,j@de", ",", "{}");
+        assert_eq!(result2, "echo \"Name: jade, Email: j@de\"");
+    }
+
+    #[test]
+    fn test_real_world_log_processing_with_angle_brackets() {
+        let result = expand_template(
+            "echo Timestamp: <1>, Level: <2>",
+            "2024-01-15 ERROR",
+            " ",
+            "<>",
+        );
+        assert_eq!(result, "echo Timestamp: 2024-01-15, Level: ERROR");
+    }
+
+    #[test]
+    fn test_real_world_batch_video_conversion() {
+        let result = expand_template("ffmpeg -i [] [s/\\.avi/.mp4/]", "movie.avi", " ", "[]");
+        assert_eq!(result, "ffmpeg -i movie.avi movie.mp4");
+    }
+
+    #[test]
+    fn test_real_world_backup_with_timestamp() {
+        let result = expand_template("cp [] backups/[]-2024", "document.txt", " ", "[]");
+        assert_eq!(result, "cp document.txt backups/document.txt-2024");
+    }
+
+    #[test]
+    fn test_real_world_url_rewriting_with_at() {
+        let result = expand_template("echo @s/http:/https:/@", "http://example.com", " ", "@@");
+        assert_eq!(result, "echo https://example.com");
+    }
+
+    #[test]
+    fn test_real_word_field_ranges_for_logs() {
+        let result = expand_template(
+            "echo 'Message: [3+]'",
+            "INFO 2024-01-15 This is a log message",
+            " ",
+            "[]",
+        );
+        assert_eq!(result, "echo 'Message: This is a log message'");
+    }
+
+    #[test]
+    fn test_real_world_extract_extension_with_regex() {
+        let result = expand_template("echo [/(.+)\\.(.+)/2]", "archive.tar.gz", " ", "[]");
+        assert_eq!(result, "echo gz");
+    }
+
+    #[test]
+    fn test_real_world_filename_sanitization() {
+        let result = expand_template("echo [s/ /_/g]", "my document file.txt", " ", "[]");
+        assert_eq!(result, "echo my_document_file.txt");
+    }
+
+    #[test]
+    fn test_real_word_case_conversion() {
+        let result = expand_template("mv [] [s/[A-Z]/\\L$0/g]", "FILE.TXT", " ", "[]");
+        assert!(result.contains("FILE.TXT"));
+    }
+
+    #[test]
+    fn test_real_word_process_list_of_paths() {
+        let result = expand_template("echo 'Processing: [1]'", "/home/user/file.txt", " ", "[]");
+        assert_eq!(result, "echo 'Processing: /home/user/file.txt'");
+    }
+
+    #[test]
+    fn test_real_word_create_symlinks() {
+        let result = expand_template("ln -s [1] [2]", "original.txt link-to-original", " ", "[]");
+        assert_eq!(result, "ln -s original.txt link-to-original");
+    }
+
+    #[test]
+    fn test_real_word_remove_file_extension() {
+        let result = expand_template("echo [s/.txt//]", "document.txt", " ", "[]");
+        assert_eq!(result, "echo document");
+    }
+
+    #[test]
+    fn test_real_word_extract_filename_from_path() {
+        let result = expand_template("echo [3]", "path to file.txt", " ", "[]");
+        assert_eq!(result, "echo file.txt");
+    }
+
+    #[test]
+    fn test_real_word_batch_resize_images() {
+        let result = expand_template(
+            "convert [] -resize 800x600 resized/[]",
+            "photo.jpg",
+            " ",
+            "[]",
+        );
+        assert_eq!(
+            result,
+            "convert photo.jpg -resize 800x600 resized/photo.jpg"
+        );
+    }
+
+    #[test]
+    fn test_real_word_extract_fields_from_ps_output() {
+        let result = expand_template(
+            "echo 'PID: @1@, USER: @2@, CMD: @3+@'",
+            "1234 root /usr/bin/myapp --daemon",
+            " ",
+            "@@",
+        );
+        assert_eq!(
+            result,
+            "echo 'PID: 1234, USER: root, CMD: /usr/bin/myapp --daemon'"
+        );
+    }
+
+    #[test]
+    fn test_real_word_normalize_whitespace() {
+        let result = expand_template("echo [s/\\s+/ /g]", "hello    world   test", " ", "[]");
+        assert_eq!(result, "echo hello world test");
+    }
+
+    #[test]
+    fn test_real_word_replace_underscores_with_dashes() {
+        let result = expand_template("echo [s/_/-/g]", "my_file_name.txt", " ", "[]");
+        assert_eq!(result, "echo my-file-name.txt");
+    }
+
+    #[test]
+    fn test_real_word_add_prefix_to_files() {
+        let result = expand_template("echo [s/^/backup_/]", "file.txt", " ", "[]");
+        assert_eq!(result, "echo backup_file.txt");
+    }
+
+    #[test]
+    fn test_real_word_add_suffix_to_files() {
+        let result = expand_template("echo [].bak", "file.txt", " ", "[]");
+        assert_eq!(result, "echo file.txt.bak");
+    }
+
+    #[test]
+    fn test_parse_duration_units() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(86400)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+    }
+
+    #[test]
+    fn test_parse_duration_invalid() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("2x").is_err());
+        assert!(parse_duration("h2").is_err());
+        assert!(parse_duration("1h 30m").is_err());
+    }
+
+    #[test]
+    fn test_config_budget_parsing() {
+        use clap::Parser;
+        let config = Config::parse_from([
+            "kyanite",
+            "--max-runtime",
+            "2h",
+            "--halt-on-budget",
+            "kill",
+            "--remaining-input",
+            "rest.txt",
+            "echo {}",
+        ]);
+        assert_eq!(config.max_runtime, Some(Duration::from_secs(7200)));
+        assert_eq!(config.halt_on_budget, BudgetHalt::Kill);
+        assert_eq!(config.remaining_input.as_deref(), Some("rest.txt"));
+    }
+
+    #[test]
+    fn test_run_state_stop_keeps_first_reason() {
+        let state = RunState::new(1);
+        assert!(!state.is_stopped());
+        assert!(state.stop("budget"));
+        assert!(!state.stop("interrupt"));
+        assert!(state.is_stopped());
+        assert_eq!(state.reason(), "budget");
+    }
+
+    #[test]
+    fn test_failure_counts_total_limit() {
+        use clap::Parser;
+        let config = Config::parse_from(["kyanite", "--max-failures", "2", "echo {}"]);
+        let mut counts = FailureCounts::default();
+        counts.record(true);
+        counts.record(false);
+        assert_eq!(counts.exceeded(&config), None);
+        counts.record(true);
+        assert_eq!(counts.exceeded(&config), Some("2 jobs failed".to_string()));
+    }
+
+    #[test]
+    fn test_failure_counts_consecutive_limit() {
+        use clap::Parser;
+        let config = Config::parse_from(["kyanite", "--max-consecutive-failures", "3", "echo {}"]);
+        let mut counts = FailureCounts::default();
+        counts.record(true);
+        counts.record(true);
+        counts.record(false);
+        counts.record(true);
+        counts.record(true);
+        assert_eq!(counts.exceeded(&config), None);
+        counts.record(true);
+        assert_eq!(
+            counts.exceeded(&config),
+            Some("3 consecutive jobs failed".to_string())
+        );
+    }
+
+    #[test]
+    fn test_failure_counts_disabled_by_default() {
+        use clap::Parser;
+        let config = Config::parse_from(["kyanite", "echo {}"]);
+        let mut counts = FailureCounts::default();
+        for _ in 0..100 {
+            counts.record(true);
+        }
+        assert_eq!(counts.exceeded(&config), None);
+    }
+
+    #[test]
+    fn test_parse_breaker() {
+        assert_eq!(
+            parse_breaker("fails=20,window=60s,cooldown=5m"),
+            Ok(BreakerSettings {
+                fails: 20,
+                window: Duration::from_secs(60),
+                cooldown: Duration::from_secs(300),
+            })
+        );
+        assert_eq!(parse_breaker("fails=3").map(|s| s.fails), Ok(3));
+        assert!(parse_breaker("window=60s").is_err());
+        assert!(parse_breaker("fails=3,speed=fast").is_err());
+        assert!(parse_breaker("fails").is_err());
+    }
+
+    #[test]
+    fn test_circuit_breaker_opens_and_probes() {
+        let mut breaker = CircuitBreaker::new(BreakerSettings {
+            fails: 2,
+            window: Duration::from_secs(10),
+            cooldown: Duration::from_secs(30),
+        });
+        let start = Instant::now();
+
+        assert_eq!(breaker.admit(start), Admission::Run);
+        assert_eq!(breaker.record(true, false, start), None);
+        assert!(breaker.record(true, false, start).is_some());
+        assert_eq!(
+            breaker.admit(start + Duration::from_secs(10)),
+            Admission::Wait(Duration::from_secs(20))
+        );
+
+        let after = start + Duration::from_secs(31);
+        assert_eq!(breaker.admit(after), Admission::Probe);
+        assert!(matches!(breaker.admit(after), Admission::Wait(_)));
+        assert!(breaker.record(true, true, after).is_some());
+        assert!(matches!(breaker.admit(after), Admission::Wait(_)));
+
+        let later = after + Duration::from_secs(31);
+        assert_eq!(breaker.admit(later), Admission::Probe);
+        assert!(breaker.record(false, true, later).is_some());
+        assert_eq!(breaker.admit(later), Admission::Run);
+    }
+
+    #[test]
+    fn test_circuit_breaker_window_expires_failures() {
+        let mut breaker = CircuitBreaker::new(BreakerSettings {
+            fails: 2,
+            window: Duration::from_secs(10),
+            cooldown: Duration::from_secs(30),
+        });
+        let start = Instant::now();
+
+        assert_eq!(breaker.record(true, false, start), None);
+        assert_eq!(
+            breaker.record(true, false, start + Duration::from_secs(11)),
+            None
+        );
+        assert_eq!(
+            breaker.admit(start + Duration::from_secs(11)),
+            Admission::Run
+        );
+    }
+
+    #[test]
+    fn test_auto_jobs_requires_target() {
+        use clap::Parser;
+        assert!(Config::try_parse_from(["kyanite", "--auto-jobs", "echo {}"]).is_err());
+        assert!(Config::try_parse_from(["kyanite", "--target-latency", "2s", "echo {}"]).is_err());
+
+        let config = Config::parse_from([
+            "kyanite",
+            "--auto-jobs",
+            "--target-latency",
+            "2s",
+            "echo {}",
+        ]);
+        assert!(config.auto_jobs);
+        assert_eq!(config.target_latency, Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn test_auto_jobs_additive_increase() {
+        let mut controller = AutoJobs::new(3, Some(Duration::from_secs(2)), None);
+        let fast = Duration::from_millis(100);
+        assert_eq!(controller.observe(fast, None), Some(2));
+        assert_eq!(controller.observe(fast, None), None);
+        assert_eq!(controller.observe(fast, None), Some(3));
+        for _ in 0..10 {
+            assert_eq!(controller.observe(fast, None), None);
+        }
+        assert_eq!(controller.limit, 3);
+    }
+
+    #[test]
+    fn test_auto_jobs_multiplicative_decrease() {
+        let mut controller = AutoJobs::new(16, Some(Duration::from_secs(2)), None);
+        controller.limit = 8;
+        let slow = Duration::from_secs(5);
+        assert_eq!(controller.observe(slow, None), Some(4));
+        for _ in 0..4 {
+            assert_eq!(controller.observe(slow, None), None);
+        }
+        assert_eq!(controller.observe(slow, None), Some(2));
+    }
+
+    #[test]
+    fn test_auto_jobs_load_target() {
+        let mut controller = AutoJobs::new(16, None, Some(4.0));
+        controller.limit = 4;
+        let latency = Duration::from_secs(60);
+        assert_eq!(controller.observe(latency, Some(2.0)), None);
+        assert_eq!(controller.observe(latency, Some(8.0)), Some(2));
+    }
+
+    #[test]
+    fn test_expand_named_slotdir() {
+        let result = expand_named("cd {slotdir} && run {}", "{}", "slotdir", "/tmp/slot-0");
+        assert_eq!(result, "cd /tmp/slot-0 && run {}");
+
+        let result = expand_named("cd [slotdir]", "[]", "slotdir", "/tmp/slot-1");
+        assert_eq!(result, "cd /tmp/slot-1");
+
+        let result = expand_named("cd @slotdir@ && echo @", "@", "slotdir", "/tmp/slot-2");
+        assert_eq!(result, "cd /tmp/slot-2 && echo @");
+    }
+
+    #[test]
+    fn test_slotdir_survives_template_expansion() {
+        let template = expand_named("cp @ @slotdir@/@1@", "@", "slotdir", "/tmp/slot-3");
+        let result = expand_template(&template, "a.txt", " ", "@");
+        assert_eq!(result, "cp a.txt /tmp/slot-3/a.txt");
+    }
+
+    #[test]
+    fn test_config_requires_command_or_file() {
+        use clap::Parser;
+        assert!(Config::try_parse_from(["kyanite"]).is_err());
+        assert!(
+            Config::try_parse_from(["kyanite", "--command-file", "cmd.sh", "echo {}"]).is_err()
+        );
+
+        let config = Config::parse_from(["kyanite", "--command-file", "cmd.sh"]);
+        assert_eq!(config.command_file, Some(PathBuf::from("cmd.sh")));
+        assert_eq!(config.command, None);
+    }
+
+    #[test]
+    fn test_strip_template_comments() {
+        let contents =
+            "#!/bin/sh\n# convert {} to mp3\nffmpeg -i {} \\\n  {s/.mp4/.mp3/g} # inline\n\n";
+        assert_eq!(
+            strip_template_comments(contents),
+            "ffmpeg -i {} \\\n  {s/.mp4/.mp3/g} # inline"
+        );
+    }
+
+    #[test]
+    fn test_strip_template_comments_keeps_heredocs() {
+        let contents = "cat <<'EOF' > {}.md\n# Title for {}\nEOF\n# comment\ncat <<-END\n\t# kept\n\tEND\necho done";
+        assert_eq!(
+            strip_template_comments(contents),
+            "cat <<'EOF' > {}.md\n# Title for {}\nEOF\ncat <<-END\n\t# kept\n\tEND\necho done"
+        );
+
+        let contents = "cat <<< {}\n# dropped";
+        assert_eq!(strip_template_comments(contents), "cat <<< {}");
+    }
+
+    #[test]
+    fn test_expand_template_multiline() {
+        let template = "echo {1} \\\n  {2}\nmv {} {s/txt/bak/}";
+        let result = expand_template(template, "a.txt b", " ", "{}");
+        assert_eq!(result, "echo a.txt \\\n  b\nmv a.txt b a.bak b");
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("file.txt"), "file.txt");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote("$(rm -rf /)"), "'$(rm -rf /)'");
+    }
+
+    #[test]
+    fn test_script_command_passes_input_as_argument() {
+        let dir = std::env::temp_dir().join(format!("kyanite-test-script-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("script.sh");
+        fs::write(
+            &path,
+            "awk '{ print $2 }' <<EOF\n$1\nEOF\necho \"${KYANITE_INPUT}\"\n",
+        )
+        .unwrap();
+
+        let output = script_command(&path, "one {two} three").output().unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "{two}\none {two} three\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn safe_expand(template: &str, line: &str) -> Result<String, String> {
+        check_safe(&expand_template_marked(template, line, " ", "{}", true))
+    }
+
+    #[test]
+    fn test_marked_expansion_matches_plain_expansion() {
+        let template = "cp {} {s/.mp4/.mp3/g} {1} {/(.+)\\.(.+)/1}";
+        let marked = expand_template_marked(template, "video.mp4", " ", "{}", true);
+        assert!(marked.contains(INPUT_START));
+        assert_eq!(
+            check_safe(&marked),
+            Ok(expand_template(template, "video.mp4", " ", "{}"))
+        );
+    }
+
+    #[test]
+    fn test_safe_allows_plain_and_quoted_input() {
+        assert_eq!(
+            safe_expand("echo {}", "file.txt"),
+            Ok("echo file.txt".to_string())
+        );
+        assert_eq!(
+            safe_expand("echo '{}'", "a; rm -rf $HOME"),
+            Ok("echo 'a; rm -rf $HOME'".to_string())
+        );
+        assert_eq!(
+            safe_expand("echo \"{}\"", "a b; c"),
+            Ok("echo \"a b; c\"".to_string())
+        );
+        assert_eq!(
+            safe_expand("echo \\'{}", "plain"),
+            Ok("echo \\'plain".to_string())
+        );
+    }
+
+    #[test]
+    fn test_safe_rejects_unquoted_metacharacters() {
+        assert!(safe_expand("echo {}", "a; rm -rf /").is_err());
+        assert!(safe_expand("echo {}", "$(id)").is_err());
+        assert!(safe_expand("echo {}", "two words").is_err());
+        assert!(safe_expand("echo {1}", "x|y z").is_err());
+        assert!(safe_expand("echo '{}' {}", "a&b").is_err());
+    }
+
+    #[test]
+    fn test_safe_rejects_quote_breakouts() {
+        let err = safe_expand("echo '{}'", "it's").unwrap_err();
+        assert!(err.contains("single quotes"), "{}", err);
+        assert!(safe_expand("echo \"{}\"", "$(id)").is_err());
+        assert!(safe_expand("echo \"{}\"", "a\"b").is_err());
+        assert!(safe_expand("echo \"{}\"", "`id`").is_err());
+    }
+
+    #[test]
+    fn test_config_safe_modes() {
+        use clap::Parser;
+        let config = Config::parse_from(["kyanite", "echo {}"]);
+        assert_eq!(config.safe, None);
+        let config = Config::parse_from(["kyanite", "--safe", "echo {}"]);
+        assert_eq!(config.safe, Some(SafeMode::Job));
+        let config = Config::parse_from(["kyanite", "--safe=run", "echo {}"]);
+        assert_eq!(config.safe, Some(SafeMode::Run));
+    }
+
+    #[test]
+    fn test_config_replay_subcommand() {
+        use clap::Parser;
+        let config = Config::parse_from([
+            "kyanite",
+            "replay",
+            "--audit",
+            "audit.log",
+            "--only-failed",
+            "-j",
+            "3",
+        ]);
+        let Some(Action::Replay(args)) = config.action else {
+            panic!("expected replay subcommand");
+        };
+        assert_eq!(args.audit, PathBuf::from("audit.log"));
+        assert!(args.only_failed);
+        assert_eq!(args.workers, Some(3));
+        assert_eq!(args.run, None);
+
+        let config = Config::parse_from(["kyanite", "echo {}"]);
+        assert!(config.action.is_none());
+    }
+
+    #[test]
+    fn test_replay_template_is_verbatim() {
+        let command = "printf '%s\\n' {1} [x] | sed 's/a/b/g'\necho {}";
+        assert_eq!(expand_template("{}", command, " ", "{}"), command);
+    }
+
+    #[test]
+    fn test_expand_command_sequence_number() {
+        use clap::Parser;
+        let config = Config::parse_from(["kyanite", "echo {#} {}"]);
+        let job = Job::new(4, "a.txt".to_string());
+        let result = expand_command("echo {#} {} {1}", &config, None, &job, None).unwrap();
+        assert_eq!(result, "echo 5 a.txt a.txt");
+
+        let config = Config::parse_from(["kyanite", "-I", "@", "echo @#@"]);
+        let result = expand_command(
+            "cp @ @slotdir@/@#@",
+            &config,
+            Some(Path::new("/s")),
+            &job,
+            None,
+        );
+        assert_eq!(result.unwrap(), "cp a.txt /s/5");
+    }
+
+    #[test]
+    fn test_run_hook_on_failure() {
+        use clap::Parser;
+        let dir = std::env::temp_dir().join(format!("kyanite-test-hook-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let hook = format!("cp {{output}} {}/{{}}-{{#}}-{{exit}}.log", dir.display());
+        let config = Config::parse_from(["kyanite", "--on-failure", &hook, "false"]);
+        let job = Job::new(0, "bad".to_string());
+
+        let mut result = JobResult {
+            output: "boom".to_string(),
+            exit_code: Some(0),
+            ..JobResult::new(0)
+        };
+        run_hook(&job, &result, None, None, &config);
+        assert!(fs::read_dir(&dir).unwrap().next().is_none());
+
+        result.error = Some("command failed with exit code: 3".to_string());
+        result.exit_code = Some(3);
+        run_hook(&job, &result, None, None, &config);
+        assert_eq!(fs::read_to_string(dir.join("bad-1-3.log")).unwrap(), "boom");
+        assert!(!run_dir().join("output-0").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_job_environment() {
+        use clap::Parser;
+        let config = Config::parse_from(["kyanite", "-j", "3", "true"]);
+        let state = RunState::new(3);
+        let job = Job::new(6, "a b".to_string());
+        let script = "echo $KYANITE_SEQ $KYANITE_SLOT $KYANITE_JOBS \"$KYANITE_INPUT\" ${KYANITE_TOTAL-unknown}";
+
+        let mut command = shell_command(script);
+        job_environment(&mut command, &job, 1, &config, &state);
+        let output = command.output().unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "7 2 3 a b unknown\n"
+        );
+
+        state.total.set(9).unwrap();
+        let mut command = shell_command(script);
+        job_environment(&mut command, &job, 1, &config, &state);
+        let output = command.output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "7 2 3 a b 9\n");
+    }
+
+    #[test]
+    fn test_config_jobserver_modes() {
+        use clap::Parser;
+        let config = Config::parse_from(["kyanite", "echo {}"]);
+        assert_eq!(config.jobserver, None);
+
+        let config = Config::parse_from(["kyanite", "--jobserver", "echo {}"]);
+        assert_eq!(config.jobserver, Some(JobserverMode::On));
+        assert_eq!(config.command.as_deref(), Some("echo {}"));
+
+        let config = Config::parse_from(["kyanite", "--jobserver=off", "echo {}"]);
+        assert_eq!(config.jobserver, Some(JobserverMode::Off));
+    }
+
+    #[test]
+    fn test_parse_preprocess() {
+        assert_eq!(
+            parse_preprocess("builtin:trim, lower"),
+            Ok(Preprocess::Builtin(vec![Transform::Trim, Transform::Lower]))
+        );
+        assert_eq!(
+            parse_preprocess("tr -d '\\r'"),
+            Ok(Preprocess::Command("tr -d '\\r'".to_string()))
+        );
+        assert!(parse_preprocess("builtin:trim,shout").is_err());
+    }
+
+    #[test]
+    fn test_preprocess_builtin_transforms() {
+        let chain = parse_preprocess("builtin:trim,basename,noext,upper").unwrap();
+        assert_eq!(
+            preprocess("  /data/In.Tar.gz \t", &chain, PathStyle::Unix).unwrap(),
+            "IN.TAR"
+        );
+
+        let unix = |transform: Transform, path| transform.apply(path, PathStyle::Unix);
+        assert_eq!(unix(Transform::Dirname, "/data/in.txt"), "/data");
+        assert_eq!(unix(Transform::Dirname, "/in.txt"), "/");
+        assert_eq!(unix(Transform::Dirname, "in.txt"), ".");
+        assert_eq!(unix(Transform::Noext, ".bashrc"), ".bashrc");
+        assert_eq!(unix(Transform::Noext, "dir.d/file"), "dir.d/file");
+        assert_eq!(
+            unix(Transform::Basename, "C:\\data\\in.txt"),
+            "C:\\data\\in.txt"
+        );
     }
 
     #[test]
-    fn test_expand_template_regex_capture() {
-        let result = expand_template("echo {/(.+)\\.(.+)/1}", "file.txt", " ", "{}");
-        assert_eq!(result, "echo file");
+    fn test_windows_path_transforms() {
+        let windows = |transform: Transform, path| transform.apply(path, PathStyle::Windows);
+        assert_eq!(windows(Transform::Basename, "C:\\data\\in.txt"), "in.txt");
+        assert_eq!(windows(Transform::Basename, "C:/data/in.txt"), "in.txt");
+        assert_eq!(windows(Transform::Basename, "C:in.txt"), "in.txt");
+        assert_eq!(windows(Transform::Dirname, "C:\\data\\in.txt"), "C:\\data");
+        assert_eq!(windows(Transform::Dirname, "C:\\in.txt"), "C:\\");
+        assert_eq!(windows(Transform::Dirname, "C:in.txt"), "C:");
+        assert_eq!(windows(Transform::Dirname, "\\in.txt"), "\\");
+        assert_eq!(
+            windows(Transform::Dirname, "\\\\server\\share\\dir\\in.txt"),
+            "\\\\server\\share\\dir"
+        );
+        assert_eq!(
+            windows(Transform::Dirname, "\\\\server\\share\\in.txt"),
+            "\\\\server\\share\\"
+        );
+        assert_eq!(windows(Transform::Basename, "\\\\server\\share"), "");
+        assert_eq!(
+            windows(Transform::Noext, "C:\\v1.2\\notes"),
+            "C:\\v1.2\\notes"
+        );
+        assert_eq!(
+            windows(Transform::Noext, "C:\\v1.2\\notes.txt"),
+            "C:\\v1.2\\notes"
+        );
     }
 
     #[test]
-    fn test_expand_template_complex() {
-        let result = expand_template("cp {} {s/.mp4/.mp3/g}", "video.mp4", " ", "{}");
-        assert_eq!(result, "cp video.mp4 video.mp3");
-    }
+    fn test_preprocess_command() {
+        let filter = Preprocess::Command("tr a-z A-Z".to_string());
+        assert_eq!(
+            preprocess("abc def", &filter, PathStyle::Auto).unwrap(),
+            "ABC DEF"
+        );
 
-    #[test]
-    fn test_expand_template_empty_field() {
-        let result = expand_template("echo {5}", "one two three", " ", "{}");
-        assert_eq!(result, "echo ");
+        let failing = Preprocess::Command("echo nope >&2; exit 3".to_string());
+        let error = preprocess("abc", &failing, PathStyle::Auto).unwrap_err();
+        assert!(error.contains("exit status: 3"));
+        assert!(error.contains("(nope)"));
     }
 
     #[test]
-    fn test_config_default_values() {
+    fn test_start_seq_offsets_sequence_number() {
         use clap::Parser;
-        let config = Config::parse_from(["kyanite", "echo {}"]);
-        assert_eq!(config.workers, num_cpus::get());
-        assert!(!config.keep_order);
-        assert!(!config.dry_run);
-        assert!(!config.verbose);
-        assert_eq!(config.max_jobs, 0);
-        assert_eq!(config.placeholder, "{}");
-        assert_eq!(config.field_separator, " ");
-        assert_eq!(config.command, "echo {}");
+        let config = Config::parse_from(["kyanite", "--start-seq", "1001", "echo {#}"]);
+        let job = Job::new(4, "a".to_string());
+        let result = expand_command(config.template(), &config, None, &job, None).unwrap();
+        assert_eq!(result, "echo 1005");
     }
 
     #[test]
-    fn test_config_parsing() {
+    fn test_needs_total() {
         use clap::Parser;
-        let config = Config::parse_from([
+        assert!(!needs_total(&Config::parse_from([
+            "kyanite",
+            "echo {} {#}"
+        ])));
+        assert!(needs_total(&Config::parse_from([
+            "kyanite",
+            "echo {#}/{total}"
+        ])));
+        assert!(needs_total(&Config::parse_from([
             "kyanite",
-            "-j",
-            "4",
-            "-k",
-            "-n",
-            "-v",
-            "--max-jobs",
-            "10",
             "-I",
             "@",
-            "--field-separator",
-            ",",
+            "--on-success",
+            "echo @#@ of @total@",
             "echo @",
-        ]);
-        assert_eq!(config.workers, 4);
-        assert!(config.keep_order);
-        assert!(config.dry_run);
-        assert!(config.verbose);
-        assert_eq!(config.max_jobs, 10);
-        assert_eq!(config.placeholder, "@");
-        assert_eq!(config.field_separator, ",");
-        assert_eq!(config.command, "echo @");
+        ])));
     }
 
     #[test]
-    fn test_expand_template_edge_cases() {
-        let result = expand_template("echo {s/([/broken/g}", "test", " ", "{}");
-        assert!(result.contains("s/([/broken/g"));
+    fn test_expand_command_total() {
+        use clap::Parser;
+        let config = Config::parse_from(["kyanite", "echo {#}/{total} {}"]);
+        let job = Job::new(1, "x".to_string());
+        let result = expand_command(config.template(), &config, None, &job, Some(3));
+        assert_eq!(result.unwrap(), "echo 2/3 x");
+    }
 
-        let result = expand_template("echo {abc}", "test", " ", "{}");
-        assert_eq!(result, "echo {abc}");
+    #[test]
+    fn test_until_reached() {
+        use clap::Parser;
+        let result = |output: &str, error: Option<&str>| JobResult {
+            output: output.to_string(),
+            error: error.map(str::to_string),
+            ..JobResult::new(2)
+        };
 
-        let result = expand_template("echo {5}", "one two three", " ", "{}");
-        assert_eq!(result, "echo ");
+        let config = Config::parse_from(["kyanite", "curl {}"]);
+        assert_eq!(until_reached(&result("ok", None), &config), None);
+
+        let config = Config::parse_from(["kyanite", "--until-success", "curl {}"]);
+        assert_eq!(
+            until_reached(&result("", None), &config),
+            Some("job 3 succeeded".to_string())
+        );
+        assert_eq!(until_reached(&result("", Some("failed")), &config), None);
+
+        let config = Config::parse_from(["kyanite", "--until", "found: \\d+", "grep {}"]);
+        assert_eq!(until_reached(&result("nothing", None), &config), None);
+        assert_eq!(
+            until_reached(&result("found: 42", Some("failed")), &config),
+            Some("output of job 3 matched found: \\d+".to_string())
+        );
+        assert!(Config::try_parse_from(["kyanite", "--until", "(", "echo"]).is_err());
     }
 
     #[test]
-    fn test_simple_replacement_with_bracket_placeholder() {
-        let result = "echo []".replace("[]", "hello");
-        assert_eq!(result, "echo hello");
+    fn test_parse_jitter() {
+        let ms = Duration::from_millis;
+        assert_eq!(
+            parse_jitter("0..500ms"),
+            Ok(Jitter {
+                min: ms(0),
+                max: ms(500)
+            })
+        );
+        assert_eq!(
+            parse_jitter("100..500ms"),
+            Ok(Jitter {
+                min: ms(100),
+                max: ms(500)
+            })
+        );
+        assert_eq!(
+            parse_jitter("1s..1m"),
+            Ok(Jitter {
+                min: ms(1000),
+                max: ms(60_000)
+            })
+        );
+        assert_eq!(
+            parse_jitter("2s"),
+            Ok(Jitter {
+                min: ms(0),
+                max: ms(2000)
+            })
+        );
+        assert!(parse_jitter("5s..1s").is_err());
+        assert!(parse_jitter("a..b").is_err());
     }
 
     #[test]
-    fn test_simple_replacement_with_at_placeholder() {
-        let result = "echo @@".replace("@@", "world");
-        assert_eq!(result, "echo world");
+    fn test_jitter_sample_in_range() {
+        let jitter = Jitter {
+            min: Duration::from_millis(10),
+            max: Duration::from_millis(20),
+        };
+        let samples: Vec<Duration> = (0..200).map(|id| jitter.sample(id)).collect();
+        assert!(samples.iter().all(|d| jitter.min <= *d && *d <= jitter.max));
+        assert!(samples.iter().any(|d| *d != samples[0]));
+        assert_eq!(jitter.sample(7), samples[7]);
     }
 
     #[test]
-    fn test_simple_replacement_with_angle_bracket_placeholder() {
-        let result = "echo <>".replace("<>", "test");
-        assert_eq!(result, "echo test");
+    fn test_backoff_delay() {
+        let pattern = Regex::new(r"Retry-After: (\S+)").unwrap();
+        assert_eq!(
+            backoff_delay(&pattern, "HTTP/1.1 429\nRetry-After: 30\n"),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            backoff_delay(&pattern, "Retry-After: 1m30s"),
+            Some(Duration::from_secs(90))
+        );
+        assert_eq!(backoff_delay(&pattern, "HTTP/1.1 200"), None);
+        assert_eq!(backoff_delay(&pattern, "Retry-After: soon"), None);
     }
 
     #[test]
-    fn test_simple_replacement_with_custom_string_placeholder() {
-        let result = "echo PLACEHOLDER".replace("PLACEHOLDER", "value");
-        assert_eq!(result, "echo value");
+    fn test_run_state_pause() {
+        let state = RunState::new(1);
+        state.wait_until_resumed();
+
+        let started = Instant::now();
+        state.pause_for(Duration::from_millis(150));
+        state.pause_for(Duration::from_millis(50));
+        state.wait_until_resumed();
+        assert!(started.elapsed() >= Duration::from_millis(150));
     }
 
     #[test]
-    fn test_simple_replacement_multiple_occurrences() {
-        let result = "echo @@ and @@".replace("@@", "test");
-        assert_eq!(result, "echo test and test");
+    fn test_parse_estimate() {
+        assert_eq!(
+            parse_estimate("2s"),
+            Ok(Estimate::PerJob(Duration::from_secs(2)))
+        );
+        assert_eq!(parse_estimate("sample=5"), Ok(Estimate::Sample(5)));
+        assert!(parse_estimate("sample=0").is_err());
+        assert!(parse_estimate("soon").is_err());
+
+        use clap::Parser;
+        assert!(Config::try_parse_from(["kyanite", "--estimate", "2s", "echo"]).is_err());
+        assert!(Config::try_parse_from(["kyanite", "-n", "--estimate", "2s", "echo"]).is_ok());
     }
 
     #[test]
-    fn test_simple_replacement_with_special_characters() {
-        let result = "echo %%".replace("%%", "special");
-        assert_eq!(result, "echo special");
+    fn test_project_run() {
+        let second = Duration::from_secs(1);
+        assert_eq!(project_run(10, 4, second), (second * 3, vec![3, 3, 2, 2]));
+        assert_eq!(project_run(8, 4, second), (second * 2, vec![2, 2, 2, 2]));
+        assert_eq!(project_run(2, 8, second), (second, vec![1, 1]));
+        assert_eq!(project_run(0, 4, second), (Duration::ZERO, vec![0]));
     }
 
     #[test]
-    fn test_simple_replacement_with_empty_string() {
-        let result = "echo TEST".replace("TEST", "");
-        assert_eq!(result, "echo ");
+    fn test_sample_line_keeps_input_order() {
+        let mut reservoir = Vec::new();
+        for seen in 1..=1000 {
+            sample_line(&mut reservoir, 5, seen, seen.to_string());
+        }
+        assert_eq!(reservoir.len(), 5);
+        assert!(reservoir.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(
+            reservoir
+                .iter()
+                .all(|(index, line)| index.to_string() == *line)
+        );
+        assert!(reservoir.iter().any(|(index, _)| *index > 5));
     }
 
     #[test]
-    fn test_simple_replacement_preserves_other_content() {
-        let result = "start [middle] end".replace("[middle]", "REPLACED");
-        assert_eq!(result, "start REPLACED end");
+    fn test_random_streams_are_independent() {
+        assert_eq!(
+            random_u64(RandomStream::Jitter, 3),
+            random_u64(RandomStream::Jitter, 3)
+        );
+        assert_ne!(
+            random_u64(RandomStream::Jitter, 3),
+            random_u64(RandomStream::Sample, 3)
+        );
     }
 
     #[test]
-    fn test_placeholder_behavior_documentation() {
-        let result = expand_template("echo {1}", "first second", " ", "{}");
-        assert_eq!(result, "echo first");
+    fn test_reorder_strategies() {
+        let result = |id, start| JobResult {
+            start,
+            ..JobResult::new(id)
+        };
+        let ids = |results: Vec<JobResult>| results.iter().map(|r| r.id).collect::<Vec<_>>();
 
-        let result = expand_template("echo [1]", "first second", " ", "[]");
-        assert_eq!(result, "echo first");
+        let mut reorder = Reorder::new(OrderBy::Finish);
+        assert_eq!(ids(reorder.push(result(2, 0))), vec![2]);
 
-        let result = expand_template("echo @1@", "first second", " ", "@@");
-        assert_eq!(result, "echo first");
+        let mut reorder = Reorder::new(OrderBy::Input);
+        assert_eq!(ids(reorder.push(result(1, 0))), Vec::<usize>::new());
+        assert_eq!(ids(reorder.push(result(0, 1))), vec![0, 1]);
+        assert_eq!(ids(reorder.push(result(3, 2))), Vec::<usize>::new());
+        assert_eq!(ids(reorder.finish().collect()), vec![3]);
+
+        let mut reorder = Reorder::new(OrderBy::Start);
+        assert_eq!(ids(reorder.push(result(0, 1))), Vec::<usize>::new());
+        assert_eq!(ids(reorder.push(result(1, 0))), vec![1, 0]);
     }
 
     #[test]
-    fn test_template_expansion_with_angle_brackets() {
-        let result = expand_template("echo <1> <2>", "alpha beta gamma", " ", "<>");
-        assert_eq!(result, "echo alpha beta");
+    fn test_reorder_spills_over_budget() {
+        let budget = Arc::new(memory::Budget::new(10));
+        let mut reorder = Reorder::new(OrderBy::Input).with_budget(Some(Arc::clone(&budget)));
+        let result = |id| JobResult {
+            output: format!("output {}\n", id),
+            start: id,
+            ..JobResult::new(id)
+        };
+        for id in [2, 1] {
+            assert!(reorder.push(result(id)).is_empty());
+        }
+        assert!(!budget.exceeded());
+        assert_eq!(reorder.pending[&2].output, "");
+        assert_eq!(reorder.pending[&1].output, "output 1\n");
+
+        let ready = reorder.push(result(0));
+        let outputs: Vec<&str> = ready.iter().map(|r| r.output.as_str()).collect();
+        assert_eq!(outputs, vec!["output 0\n", "output 1\n", "output 2\n"]);
+        assert!(reorder.spilled.is_empty());
+
+        // the raw streams of a `--pipe` job are moved out too
+        let piped = JobResult {
+            streams: Some((b"block 4\n".to_vec(), b"warning\n".to_vec())),
+            ..JobResult::new(4)
+        };
+        assert!(reorder.push(piped).is_empty());
+        assert_eq!(reorder.pending[&4].streams, Some((Vec::new(), Vec::new())));
+        let ready = reorder.push(result(3));
+        assert_eq!(ready[1].stdout_text(), "block 4");
+        assert_eq!(ready[1].stderr_text(), "warning");
+        assert!(!budget.exceeded());
     }
 
     #[test]
-    fn test_template_expansion_with_brackets_field_access() {
-        let result = expand_template("echo [1] [2]", "first second third", " ", "[]");
-        assert_eq!(result, "echo first second");
+    fn test_config_order_by() {
+        use clap::Parser;
+        assert_eq!(
+            Config::parse_from(["kyanite", "x"]).order_by(),
+            OrderBy::Finish
+        );
+        assert_eq!(
+            Config::parse_from(["kyanite", "-k", "x"]).order_by(),
+            OrderBy::Input
+        );
+        assert_eq!(
+            Config::parse_from(["kyanite", "--order-by", "start", "x"]).order_by(),
+            OrderBy::Start
+        );
+        assert!(Config::try_parse_from(["kyanite", "-k", "--order-by", "start", "x"]).is_err());
     }
 
     #[test]
-    fn test_template_expansion_with_brackets_field_range() {
-        let result = expand_template("echo [2+]", "first second third fourth", " ", "[]");
-        assert_eq!(result, "echo second third fourth");
+    fn test_failure_report() {
+        let mut result = JobResult {
+            exit_code: Some(0),
+            streams: Some((b"done\n".to_vec(), Vec::new())),
+            input: "a.png".to_string(),
+            ..JobResult::new(2)
+        };
+        assert_eq!(failure_report(&result, 1), None);
+
+        result.error = Some("command failed with exit code: exit status: 3".to_string());
+        result.exit_code = Some(3);
+        result.streams = Some((b"partial\n".to_vec(), b"bad header\nskipped\n".to_vec()));
+        assert_eq!(
+            failure_report(&result, 1).unwrap(),
+            "job 3 failed (exit code 3): a.png\n  bad header\n  skipped"
+        );
+
+        result.error = Some("failed to execute command: not found".to_string());
+        result.exit_code = None;
+        result.streams = None;
+        assert_eq!(
+            failure_report(&result, 1).unwrap(),
+            "job 3 failed (failed to execute command: not found): a.png"
+        );
     }
 
     #[test]
-    fn test_template_expansion_with_brackets_sed() {
-        let result = expand_template("echo [s/old/new/g]", "old old new", " ", "[]");
-        assert_eq!(result, "echo new new new");
+    fn test_parse_shell() {
+        assert_eq!(parse_shell("sh"), Ok(Shell::Sh));
+        assert_eq!(parse_shell("wsl"), Ok(Shell::Wsl(None)));
+        assert_eq!(
+            parse_shell("wsl:Ubuntu-22.04"),
+            Ok(Shell::Wsl(Some("Ubuntu-22.04".to_string())))
+        );
+        assert!(parse_shell("wsl:").is_err());
+        assert!(parse_shell("bash").is_err());
     }
 
     #[test]
-    fn test_template_expansion_with_brackets_regex_capture() {
-        let result = expand_template("echo [/(.+)\\.(.+)/1]", "file.txt", " ", "[]");
-        assert_eq!(result, "echo file");
+    fn test_wsl_path() {
+        assert_eq!(
+            wsl_path("C:\\Users\\me\\in.txt").as_deref(),
+            Some("/mnt/c/Users/me/in.txt")
+        );
+        assert_eq!(wsl_path("d:/data").as_deref(), Some("/mnt/d/data"));
+        assert_eq!(
+            wsl_path("\\\\wsl$\\Ubuntu\\home\\me").as_deref(),
+            Some("/home/me")
+        );
+        assert_eq!(wsl_path("\\\\fileserver\\share\\x"), None);
+        assert_eq!(wsl_path("C:relative"), None);
+        assert_eq!(wsl_path("plain input"), None);
+        assert_eq!(wsl_path("/already/unix"), None);
     }
 
     #[test]
-    fn test_template_expansion_with_brackets_complex() {
-        let result = expand_template("cp [] [s/.mp4/.mp3/g]", "video.mp4", " ", "[]");
-        assert_eq!(result, "cp video.mp4 video.mp3");
+    fn test_sandboxed_command() {
+        let mut command = shell_command("echo hi");
+        command
+            .env("KYANITE_INPUT", "a")
+            .env_remove("KYANITE_JOBSERVER");
+        let wrapped = sandboxed(Path::new("jobs.sb"), &command);
+
+        assert_eq!(wrapped.get_program(), "sandbox-exec");
+        let args: Vec<_> = wrapped.get_args().collect();
+        assert_eq!(args, ["-f", "jobs.sb", "sh", "-c", "echo hi"]);
+        let envs: Vec<_> = wrapped.get_envs().collect();
+        assert!(envs.contains(&("KYANITE_INPUT".as_ref(), Some("a".as_ref()))));
+        assert!(envs.contains(&("KYANITE_JOBSERVER".as_ref(), None)));
     }
 
     #[test]
-    fn test_template_expansion_with_at_placeholder_field_access() {
-        let result = expand_template("echo @1@ @2@", "first second third", " ", "@@");
-        assert_eq!(result, "echo first second");
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("512M"), Ok(512 << 20));
+        assert_eq!(parse_size("2g"), Ok(2 << 30));
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("99999999999T").is_err());
     }
 
     #[test]
-    fn test_template_expansion_with_at_placeholder_field_range() {
-        let result = expand_template("echo @2+@", "first second third fourth", " ", "@@");
-        assert_eq!(result, "echo second third fourth");
+    fn test_pacer_spaces_lines_across_threads() {
+        assert_eq!(parse_rate("0.5"), Ok(0.5));
+        assert!(parse_rate("0").is_err());
+        let pacer = Pacer::new(100.0);
+        let started = Instant::now();
+        thread::scope(|scope| {
+            for _ in 0..3 {
+                scope.spawn(|| {
+                    for _ in 0..4 {
+                        pacer.wait();
+                    }
+                });
+            }
+        });
+        // the first line goes at once, the other eleven 10ms apart
+        assert!(started.elapsed() >= Duration::from_millis(110));
     }
 
     #[test]
-    fn test_template_expansion_with_at_placeholder_sed() {
-        let result = expand_template("echo @s/old/new/g@", "old old new", " ", "@@");
-        assert_eq!(result, "echo new new new");
+    fn test_parse_max_temp() {
+        assert_eq!(parse_max_temp("85C"), Ok(85.0));
+        assert_eq!(parse_max_temp("72.5"), Ok(72.5));
+        assert!(parse_max_temp("hot").is_err());
+        assert!(parse_max_temp("-5C").is_err());
     }
 
     #[test]
-    fn test_template_expansion_with_at_placeholder_regex_capture() {
-        let result = expand_template("echo @/(.+)\\.(.+)/1@", "file.txt", " ", "@@");
-        assert_eq!(result, "echo file");
+    fn test_remote_command() {
+        let command = remote_command("user@build1", &shell_command("echo 'hi there' > out"));
+        assert_eq!(command.get_program(), "ssh");
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(
+            args,
+            [
+                "-o",
+                "BatchMode=yes",
+                "-o",
+                "ConnectTimeout=10",
+                "--",
+                "user@build1",
+                "sh -c 'echo '\\''hi there'\\'' > out'"
+            ]
+        );
     }
 
     #[test]
-    fn test_template_expansion_with_percent_placeholder() {
-        let result = expand_template("echo %s/foo/bar/g%", "foo foo baz", " ", "%%");
-        assert_eq!(result, "echo bar bar baz");
+    fn test_write_atomically() {
+        let dir = std::env::temp_dir().join(format!("kyanite-test-outfile-{}", std::process::id()));
+        let path = dir.join("out").join("a.txt");
+
+        let saved = write_atomically(&path, b"done\n", true, Some(".part")).unwrap();
+        assert_eq!(saved.as_deref(), Some(path.as_path()));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "done\n");
+
+        assert_eq!(write_atomically(&path, b"half", false, None).unwrap(), None);
+        let partial = write_atomically(&path, b"half", false, Some(".part")).unwrap();
+        assert_eq!(partial, Some(dir.join("out").join("a.txt.part")));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "done\n");
+
+        let mut names: Vec<_> = fs::read_dir(dir.join("out"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["a.txt", "a.txt.part"]);
+        fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn test_template_expansion_with_pipe_placeholder() {
-        let result = expand_template("echo |1| |2|", "one two three", " ", "||");
-        assert_eq!(result, "echo one two");
+    fn test_claim_outfile() {
+        let state = RunState::new(1);
+        let claim = |path: &str, line, seq, collision| {
+            state.claim_outfile(path.to_string(), line, seq, collision)
+        };
+        assert_eq!(
+            claim("out/a.txt", "a", 1, OutfileCollision::Fail).unwrap(),
+            "out/a.txt"
+        );
+        assert_eq!(
+            claim("out/a.txt", "a", 2, OutfileCollision::Fail).unwrap(),
+            "out/a.txt"
+        );
+        assert_eq!(
+            claim("out/a.txt", "A", 3, OutfileCollision::Fail).unwrap_err(),
+            "output path out/a.txt is already used by input \"a\""
+        );
+        assert_eq!(
+            claim("out/a.txt", "A", 3, OutfileCollision::Uniquify).unwrap(),
+            "out/a.3.txt"
+        );
+        assert_eq!(uniquify("v1.2/notes", 4), "v1.2/notes.4");
+        assert_eq!(uniquify(".hidden", 5), ".hidden.5");
     }
 
     #[test]
-    fn test_template_expansion_with_custom_delimiter_field_access() {
-        let result = expand_template("echo ::1::", "a b c d", " ", "::");
-        assert!(result.contains("a"), "Result should contain 'a'");
+    fn test_expand_meta() {
+        let meta = Meta::parse(r#"{"a.csv": {"owner": "ana"}, "2": {"owner": "li"}}"#).unwrap();
+        let job = |id, line: &str| Job::new(id, line.to_string());
+        let template = "notify {meta:owner} about {} ({meta:team})";
+        assert_eq!(
+            expand_meta(template, "{}", &meta, &job(0, "a.csv")),
+            "notify ana about {} ()"
+        );
+        assert_eq!(
+            expand_meta(template, "{}", &meta, &job(1, "b.csv")),
+            "notify li about {} ()"
+        );
+        assert_eq!(
+            expand_meta("echo [meta:owner]", "[]", &meta, &job(0, "a.csv")),
+            "echo ana"
+        );
     }
 
     #[test]
-    fn test_template_expansion_with_custom_delimiter_field_range() {
-        let result = expand_template("echo ::2+::", "a b c d", " ", "::");
-        assert!(result.contains("b"), "Result should contain 'b'");
+    fn test_looks_destructive() {
+        assert!(looks_destructive("rm -rf out/a"));
+        assert!(looks_destructive("cd x && rm a"));
+        assert!(looks_destructive("psql -c 'DROP TABLE users'"));
+        assert!(looks_destructive("sqlite3 db 'delete from jobs'"));
+        assert!(looks_destructive("rsync -a --delete src/ dst/"));
+        assert!(!looks_destructive("echo confirm a.txt"));
+        assert!(!looks_destructive("convert warm.png cold.png"));
+        assert!(!looks_destructive("grep -r delete src"));
     }
 
     #[test]
-    fn test_template_expansion_with_colons_sed() {
-        let result = expand_template("echo :s/a/x/g:", "a b c", " ", "::");
-        assert_eq!(result, "echo x b c");
+    fn test_confirm_batch() {
+        use clap::Parser;
+        let jobs: Vec<Job> = (0..3).map(|id| Job::new(id, format!("f{}", id))).collect();
+        let config = Config::parse_from(["kyanite", "--confirm-threshold", "5", "touch {}"]);
+        let mut out = Vec::new();
+        assert!(confirm_batch(&jobs, &config, &mut "".as_bytes(), &mut out).unwrap());
+        assert!(out.is_empty());
+
+        let config = Config::parse_from(["kyanite", "--confirm-threshold", "2", "touch {}"]);
+        assert!(!confirm_batch(&jobs, &config, &mut "y\n".as_bytes(), &mut out).unwrap());
+        let mut out = Vec::new();
+        assert!(confirm_batch(&jobs, &config, &mut "3\n".as_bytes(), &mut out).unwrap());
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("about to run 3 jobs, for example:\n  touch f0\n"));
+        assert!(out.ends_with("type 3 to run them: "));
+
+        let config = Config::parse_from(["kyanite", "--confirm-threshold", "10", "rm {}"]);
+        let mut out = Vec::new();
+        assert!(!confirm_batch(&jobs, &config, &mut "\n".as_bytes(), &mut out).unwrap());
+        assert!(
+            String::from_utf8(out)
+                .unwrap()
+                .contains("3 commands look destructive")
+        );
     }
 
     #[test]
-    fn test_template_expansion_mixed_placeholders() {
-        let result = expand_template("echo [] [1]", "hello world", " ", "[]");
-        assert_eq!(result, "echo hello world hello");
+    fn test_test_template() {
+        use clap::Parser;
+        let config = Config::parse_from([
+            "kyanite",
+            "test-template",
+            "convert {1} {s/png/jpg/} # {#}",
+            "--case",
+            "a.png b=convert a.png a.jpg b # 1",
+            "--case",
+            "c.png=convert c.png c.png # 2",
+        ]);
+        let Some(Action::TestTemplate(args)) = config.action else {
+            panic!("expected test-template subcommand");
+        };
+        let mut out = Vec::new();
+        assert_eq!(test_template(&args, &mut out), 1);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "ok: a.png b\n\
+             FAIL: c.png\n  expected: convert c.png c.png # 2\n  actual:   convert c.png c.jpg # 2\n\
+             1 passed, 1 failed\n"
+        );
+        assert!(parse_template_case("no separator").is_err());
     }
 
     #[test]
-    fn test_template_expansion_field_minus_with_custom_placeholder() {
-        let result = expand_template("echo [3-]", "first second third fourth", " ", "[]");
-        assert_eq!(result, "echo first second third");
+    fn test_map_expands_lines_in_order() {
+        use clap::Parser;
+        let config =
+            Config::parse_from(["kyanite", "map", "-j", "3", "{#}/{total} {s/.png/.jpg/}"]);
+        let Some(Action::Map(args)) = config.action else {
+            panic!("expected map subcommand");
+        };
+        let mut out = Vec::new();
+        map_lines(&args, &b"a.png\n\nb.png\nc d.png\nx\n"[..], &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "1/4 a.jpg\n2/4 b.jpg\n3/4 c d.jpg\n4/4 x\n"
+        );
     }
 
     #[test]
-    fn test_template_expansion_case_insensitive_with_custom_placeholder() {
-        let result = expand_template("echo [s/HELLO/world/i]", "HELLO hello", " ", "[]");
-        assert_eq!(result, "echo world hello");
+    fn test_explain_traces_each_placeholder() {
+        let config = Config::parse_from([
+            "kyanite",
+            "--field-separator",
+            ",",
+            "--",
+            "convert {1} {/(\\d+)/1} {s/csv/png/} {#} {}",
+        ]);
+        let mut out = Vec::new();
+        explain(&config, "a.csv,b", &mut out);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "template: convert {1} {/(\\d+)/1} {s/csv/png/} {#} {}\n\
+             input:    \"a.csv,b\"\n\
+             after job placeholders: convert {1} {/(\\d+)/1} {s/csv/png/} 1 {}\n\
+             {s/csv/png/}: substitute /csv/ with \"png\" in the input -> \"a.png,b\"\n\
+             \x20 after sed placeholders: convert {1} {/(\\d+)/1} a.png,b 1 {}\n\
+             {1}: split on \",\" into 2 fields, field 1 -> \"a.csv\"\n\
+             \x20 after field placeholders: convert a.csv {/(\\d+)/1} a.png,b 1 {}\n\
+             {/(\\d+)/1}: /(\\d+)/ does not match -> empty\n\
+             \x20 after capture placeholders: convert a.csv  a.png,b 1 {}\n\
+             {}: the whole input -> \"a.csv,b\"\n\
+             command: convert a.csv  a.png,b 1 a.csv,b\n"
+        );
+    }
+
+    #[test]
+    fn test_template_problems_cover_every_template() {
+        let config = Config::parse_from([
+            "kyanite",
+            "--columns",
+            "2",
+            "--outfile",
+            "out/{3}.txt",
+            "--on-failure",
+            "echo {exit} {s/x/y}",
+            "--",
+            "cp {1} {2}",
+        ]);
+        assert_eq!(
+            template_problems(&config),
+            [
+                "--outfile {3} never expands: there is no field 3 in input declared with --columns 2",
+                "--on-failure {s/x/y} never expands: a substitution needs the form \
+                 s/pattern/replacement/flags",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_verify_checksum() {
+        const ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let result = |stdout: &[u8]| JobResult {
+            exit_code: Some(0),
+            streams: Some((stdout.to_vec(), Vec::new())),
+            ..JobResult::new(0)
+        };
+        let job = |line: String| Job::new(0, line);
+        let config = Config::parse_from(["kyanite", "--verify-sha256-field", "2", "x"]);
+        let line = format!("a.txt {}", ABC.to_uppercase());
+        let verified = verify_checksum(result(b"abc"), &config, None, &job(line.clone()), None);
+        assert_eq!(verified.error, None);
+        let verified = verify_checksum(result(b"abd"), &config, None, &job(line), None);
+        assert!(
+            verified
+                .error
+                .unwrap()
+                .ends_with(&format!("expected {}", ABC.to_uppercase()))
+        );
+        let verified = verify_checksum(result(b"abc"), &config, None, &job("a".into()), None);
+        assert_eq!(
+            verified.error.as_deref(),
+            Some("input has no field 2 to verify against")
+        );
 
-        let result = expand_template("echo [s/HELLO/world/gi]", "HELLO hello", " ", "[]");
-        assert_eq!(result, "echo world world");
+        let dir = std::env::temp_dir().join(format!("kyanite-verify-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.bin"), "abc").unwrap();
+        let verify = format!("--verify={}/{{1}}={{2}}", dir.display());
+        let config = Config::parse_from(["kyanite", &verify, "x"]);
+        let line = format!("a.bin {}", ABC);
+        let verified = verify_checksum(result(b""), &config, None, &job(line), None);
+        assert_eq!(verified.error, None);
+        let line = format!("b.bin {}", ABC);
+        let verified = verify_checksum(result(b""), &config, None, &job(line), None);
+        assert!(verified.error.unwrap().starts_with("error reading"));
+        fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn test_template_expansion_regex_capture_group2() {
-        let result = expand_template("echo [/(\\d+)-(\\w+)/2]", "123-abc", " ", "[]");
-        assert_eq!(result, "echo abc");
+    fn test_diff_subcommand_parses_run_options() {
+        let config = Config::parse_from([
+            "kyanite", "diff", "--joblog", "old.log", "-j", "3", "gzip {}",
+        ]);
+        let Some(Action::Diff(args)) = config.action else {
+            panic!("expected diff subcommand");
+        };
+        assert_eq!(args.joblog, PathBuf::from("old.log"));
+        assert_eq!(args.run, ["-j", "3", "gzip {}"]);
     }
 
     #[test]
-    fn test_template_expansion_empty_field_with_custom_placeholder() {
-        let result = expand_template("echo [5]", "one two three", " ", "[]");
-        assert_eq!(result, "echo ");
+    fn test_resume_skips_recorded_inputs() {
+        let path = std::env::temp_dir().join(format!("kyanite-resume-{}.log", std::process::id()));
+        fs::write(
+            &path,
+            "Seq\tStarttime\tJobRuntime\tExitval\tFingerprint\tInput\tCommand\n\
+             1\t0.000\t0.000\t0\tf\ta\techo a\n\
+             2\t0.000\t0.000\t1\tf\tb\techo b\n",
+        )
+        .unwrap();
+        let resumed = |flag: &str| {
+            let log = path.to_str().unwrap();
+            let config = Config::parse_from(["kyanite", "--joblog", log, flag, "echo {}"]);
+            let input: Input =
+                Box::new(["a", "b", "c"].into_iter().map(|line| Ok(line.to_string())));
+            resume_input(&config, &path, input)
+                .map(Result::unwrap)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(resumed("--resume"), ["c"]);
+        assert_eq!(resumed("--resume-failed"), ["b", "c"]);
+        fs::remove_file(&path).unwrap();
     }
 
     #[test]
-    fn test_placeholder_at_start() {
-        let result = "@@ suffix".replace("@@", "prefix");
-        assert_eq!(result, "prefix suffix");
+    fn test_interrupted_pipe_part_run_resumes() {
+        let dir = std::env::temp_dir().join(format!("kyanite-part-resume-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let data = dir.join("data");
+        fs::write(&data, "aaa\nbbb\nccc\nddd\n").unwrap();
+        let log = dir.join("jobs.log");
+        let argv = |extra: &[&'static str]| {
+            let mut argv = vec!["kyanite", "--pipe", "--pipe-part", "--block", "4"];
+            argv.extend(extra);
+            argv.extend([
+                "-a",
+                data.to_str().unwrap(),
+                "--joblog",
+                log.to_str().unwrap(),
+            ]);
+            argv.push("wc -l");
+            argv
+        };
+        let parts = |config: &Config| -> Input {
+            let path = data.to_str().unwrap();
+            Box::new(part::Parts::open(path, config.block, config.recend.clone()).unwrap())
+        };
+
+        // the first run finished one range and failed another before it was interrupted
+        let config = Config::parse_from(argv(&[]));
+        let ranges: Vec<String> = parts(&config).map(Result::unwrap).collect();
+        assert_eq!(ranges.len(), 4);
+        let joblog = joblog::JobLog::open(&log, Duration::ZERO).unwrap();
+        for (seq, exit) in [(1, 0), (2, 1)] {
+            let input = &ranges[seq - 1];
+            let entry = joblog::Entry {
+                seq,
+                start: SystemTime::now(),
+                runtime: Duration::ZERO,
+                exit,
+                fingerprint: &joblog::fingerprint(config.template(), input),
+                input,
+                command: config.template(),
+                usage: None,
+                signal: None,
+            };
+            joblog.record(&entry, Vec::new()).unwrap();
+        }
+        drop(joblog);
+
+        let config = Config::parse_from(argv(&["--resume"]));
+        let resumed: Vec<String> = resume_input(&config, &log, parts(&config))
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(resumed, ranges[2..]);
+        assert_eq!(part::read(&resumed[0]).unwrap(), b"ccc\n");
+        let config = Config::parse_from(argv(&[]));
+        let rerun: Vec<String> = diff_input(&config, &log, parts(&config))
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rerun, ranges[1..]);
+        fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn test_placeholder_at_end() {
-        let result = "prefix @".replace("@", "suffix");
-        assert_eq!(result, "prefix suffix");
+    fn test_records_split_at_pattern() {
+        let lines = ["preamble", ">seq1", "ACGT", "", "TTGA", ">seq2", "GG"];
+        let records = Records {
+            lines: Box::new(lines.into_iter().map(|line| Ok(line.to_string()))),
+            start: Regex::new("^>").unwrap(),
+            pending: None,
+        };
+        let records: Vec<String> = records.map(Result::unwrap).collect();
+        assert_eq!(records, ["preamble", ">seq1\nACGT\n\nTTGA", ">seq2\nGG"]);
+
+        let result = expand_template("echo {line:1} {line:2} {line:9}", &records[1], " ", "{}");
+        assert_eq!(result, "echo >seq1 ACGT ");
+        let marked = expand_template_marked("x [line:2]", "a\nb", " ", "[]", true);
+        assert_eq!(marked, format!("x {}", mark_input("b".to_string(), true)));
     }
 
     #[test]
-    fn test_placeholder_with_spaces() {
-        let result = "echo { x }".replace("{ x }", "test");
-        assert_eq!(result, "echo test");
+    fn test_pipe_blocks_keep_records_whole() {
+        let blocks = |input: &'static str, args: &[&str]| {
+            let argv = ["kyanite", "--pipe"].iter().chain(args).chain(&["wc -l"]);
+            let config = Config::parse_from(argv);
+            Blocks::new(input.as_bytes(), &config)
+                .map(|block| String::from_utf8(block.unwrap()).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            blocks("aa\nbb\ncccccc\nd\n", &["--block", "5"]),
+            ["aa\n", "bb\n", "cccccc\n", "d\n"]
+        );
+        assert_eq!(blocks("aa\nbb\ncc", &["--block", "6"]), ["aa\nbb\n", "cc"]);
+        let fasta = ">a\nAC\nGT\n>b\nTT\n>c\nG\n";
+        assert_eq!(
+            blocks(fasta, &["--block", "8", "--recstart", ">"]),
+            [">a\nAC\nGT\n", ">b\nTT\n", ">c\nG\n"]
+        );
+        assert_eq!(
+            blocks("r1;r2;r3;", &["--block", "6", "--recend", ";"]),
+            ["r1;r2;", "r3;"]
+        );
     }
 
     #[test]
-    fn test_placeholder_with_underscores() {
-        let result = "echo _placeholder_".replace("_placeholder_", "value");
-        assert_eq!(result, "echo value");
+    fn test_pipe_blocks_pass_bytes_through() {
+        let config = Config::parse_from(["kyanite", "--pipe", "--block", "4", "cat"]);
+        let data = vec![0xff, 0xfe, b'\n', 0x00, 0x80, b'\n', b'a'];
+        let source: Box<dyn Read + Send> = Box::new(io::Cursor::new(data.clone()));
+        let pending = Arc::clone(&config.pipe_blocks);
+        let names: Vec<String> = block_input(Blocks::new(source, &config), pending)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(names, ["bytes 0-3", "bytes 3-6", "bytes 6-7"]);
+
+        // a block the input filters left out is dropped once a later one is taken
+        let first = new_job(0, names[0].clone(), &config);
+        let last = new_job(1, names[2].clone(), &config);
+        assert_eq!(first.block.as_deref(), Some(&data[..3]));
+        assert_eq!(last.block.as_deref(), Some(&b"a"[..]));
+        assert!(config.pipe_blocks.take(&names[1]).is_none());
+
+        let state = RunState::new(1);
+        let (output, _) = run_command(shell_command("cat"), Some(&data), None, 0, &state).unwrap();
+        assert_eq!(output.stdout, data);
     }
 
     #[test]
-    fn test_placeholder_with_dashes() {
-        let result = "echo --value--".replace("--value--", "test");
-        assert_eq!(result, "echo test");
+    fn test_run_command_feeds_stdin() {
+        let state = RunState::new(1);
+        let (output, _) =
+            run_command(shell_command("wc -c"), Some(b"abc"), None, 0, &state).unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "3");
+        let (output, _) = run_command(
+            shell_command("true"),
+            Some(&[b'x'; 1 << 20]),
+            None,
+            0,
+            &state,
+        )
+        .unwrap();
+        assert!(output.status.success());
     }
 
     #[test]
-    fn test_placeholder_substring_replacement() {
-        let result = "test_test_test".replace("test", "X");
-        assert_eq!(result, "X_X_X");
+    fn test_redact() {
+        let config = Config::parse_from([
+            "kyanite",
+            "--redact",
+            r"token=\w+",
+            "--redact",
+            r"(user)\:\w+:$1:***",
+            "x",
+        ]);
+        assert_eq!(
+            config.redact("curl -H token=abc123 -u user:hunter2 host"),
+            "curl -H [REDACTED] -u user:*** host"
+        );
+        assert!(matches!(config.redact("plain"), Cow::Borrowed("plain")));
+        assert!(parse_redact("(unclosed").is_err());
     }
 
     #[test]
-    fn test_placeholder_case_sensitive() {
-        let result = "ABC abc".replace("ABC", "123");
-        assert_eq!(result, "123 abc");
+    fn test_lanes_run_jobs_of_a_key_in_order() {
+        let lanes = Lanes::default();
+        let job = |id: usize, line: &str| Job::new(id, line.to_string());
+        let first = lanes.admit("a", job(0, "a1")).unwrap();
+        assert!(lanes.admit("a", job(1, "a2")).is_none());
+        assert!(lanes.admit("a", job(2, "a3")).is_none());
+        assert_eq!(lanes.admit("b", job(3, "b1")).unwrap().line, "b1");
+        assert!(lanes.take_ready().is_none());
+
+        drop(lanes.hold("a".to_string()));
+        let (key, next) = lanes.take_ready().unwrap();
+        assert_eq!((key.as_str(), next.line.as_str()), ("a", "a2"));
+        assert!(lanes.admit("a", job(4, "a4")).is_none());
+        let hold = lanes.hold(key);
+        assert!(lanes.take_ready().is_none());
+        drop(hold);
+        assert_eq!(lanes.take_ready().unwrap().1.line, "a3");
+
+        let mut left: Vec<usize> = lanes.drain().iter().map(|job| job.id).collect();
+        left.sort();
+        assert_eq!(left, [4]);
+        assert_eq!(first.id, 0);
     }
 
     #[test]
-    fn test_placeholder_with_numbers() {
-        let result = "echo #1#".replace("#1#", "first");
-        assert_eq!(result, "echo first");
+    fn test_tune_modes() {
+        let config = Config::parse_from(["kyanite", "--tune", "gzip {}"]);
+        assert_eq!(config.tune, Some(TuneMode::Report));
+        let config = Config::parse_from(["kyanite", "--tune=apply", "--pipe", "wc -l"]);
+        assert_eq!(config.tune, Some(TuneMode::Apply));
+        assert!(Config::try_parse_from(["kyanite", "--tune", "-j", "4", "gzip {}"]).is_err());
     }
 
     #[test]
-    fn test_placeholder_with_colon() {
-        let result = "echo ::val::".replace("::val::", "content");
-        assert_eq!(result, "echo content");
+    fn test_group_by_routes_each_key_to_one_job() {
+        let config = Config::parse_from([
+            "kyanite",
+            "--pipe",
+            "--group-by",
+            "{1}",
+            "-j",
+            "3",
+            r#"while read key value; do echo "$KYANITE_SLOT $key $value"; done"#,
+        ]);
+        let input: String = (0..60).map(|i| format!("k{} {}\n", i % 7, i)).collect();
+        let out = Mutex::new(Vec::new());
+        assert!(run_partitioned(&config, "{1}", input.as_bytes(), &out).unwrap());
+
+        let out = String::from_utf8(out.into_inner().unwrap()).unwrap();
+        let mut slots = HashMap::new();
+        let mut values = Vec::new();
+        for line in out.lines() {
+            let [slot, key, value] = line.split(' ').collect::<Vec<_>>()[..] else {
+                panic!("unexpected line {:?}", line);
+            };
+            assert_eq!(
+                *slots.entry(key.to_string()).or_insert(slot),
+                slot,
+                "{}",
+                key
+            );
+            values.push(value.parse::<usize>().unwrap());
+        }
+        values.sort();
+        assert_eq!(values, (0..60).collect::<Vec<_>>());
+        assert_eq!(slots.len(), 7);
+
+        let config =
+            Config::parse_from(["kyanite", "--pipe", "--group-by", "{}", "-j", "2", "exit 3"]);
+        let out = Mutex::new(Vec::new());
+        assert!(!run_partitioned(&config, "{}", &b""[..], &out).unwrap());
     }
 
     #[test]
-    fn test_placeholder_with_pipe() {
-        let result = "echo |val|".replace("|val|", "data");
-        assert_eq!(result, "echo data");
+    fn test_supervise_restarts_panicked_workers() {
+        let (_job_tx, job_rx) = mpsc::channel();
+        let job_rx = Mutex::new(job_rx);
+        let (result_tx, result_rx) = mpsc::channel();
+        let job = |id| Job::new(id, format!("line {}", id));
+
+        // the replacement runs the job the first worker panicked on, and the queue it held is
+        // usable again
+        let mut ran = Vec::new();
+        supervise(0, 1, &job_rx, &result_tx, |handover| {
+            if let Some(retry) = handover.retry.take() {
+                ran.push(retry.id);
+                return;
+            }
+            handover.running = Some(job(1));
+            let _rx = job_rx.lock().unwrap();
+            panic!("template bug");
+        });
+        assert_eq!(ran, [1]);
+        assert!(!job_rx.is_poisoned());
+        assert!(result_rx.try_recv().is_err());
+
+        // a job that panics its worker twice is reported failed
+        let mut calls = 0;
+        supervise(0, 1, &job_rx, &result_tx, |handover| {
+            calls += 1;
+            if calls < 3 {
+                handover.running = Some(handover.retry.take().unwrap_or_else(|| job(2)));
+                panic!("{} calls", calls);
+            }
+        });
+        let results: Vec<_> = result_rx
+            .try_iter()
+            .map(|result| (result.id, result.error, result.input))
+            .collect();
+        assert_eq!(
+            results,
+            [(
+                2,
+                Some("worker panicked: 2 calls".to_string()),
+                "line 2".to_string()
+            )]
+        );
     }
 
     #[test]
-    fn test_multiple_different_placeholders() {
-        let result = "start @ end %"
-            .replace("@", "middle1")
-            .replace("%", "middle2");
-        assert_eq!(result, "start middle1 end middle2");
+    fn test_panicking_job_leaves_other_jobs_running() {
+        let (job_tx, job_rx) = mpsc::channel();
+        for id in 0..6 {
+            job_tx.send(Job::new(id, id.to_string())).unwrap();
+        }
+        drop(job_tx);
+        let job_rx = Arc::new(Mutex::new(job_rx));
+        let (result_tx, result_rx) = mpsc::channel();
+        let state = Arc::new(RunState::new(2));
+        let panicked = Arc::new(AtomicBool::new(false));
+
+        let workers: Vec<_> = (0..2)
+            .map(|worker_id| {
+                let (job_rx, result_tx) = (Arc::clone(&job_rx), result_tx.clone());
+                let (state, panicked) = (Arc::clone(&state), Arc::clone(&panicked));
+                thread::spawn(move || {
+                    supervise(worker_id, 1, &job_rx, &result_tx, |handover| {
+                        loop {
+                            let job = match handover.retry.take() {
+                                Some(job) => job,
+                                None => match job_rx.lock().unwrap().recv() {
+                                    Ok(job) => job,
+                                    Err(_) => return,
+                                },
+                            };
+                            handover.running = Some(job.clone());
+                            state.pause_for(Duration::ZERO);
+                            // job 3 panics once while holding a lock the other jobs need
+                            if job.id == 3 && !panicked.swap(true, Ordering::SeqCst) {
+                                let _held = state.paused_until.lock();
+                                panic!("job bug");
+                            }
+                            handover.running = None;
+                            state.send_result(&result_tx, JobResult::new(job.id));
+                        }
+                    })
+                })
+            })
+            .collect();
+        drop(result_tx);
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        let mut results: Vec<_> = result_rx
+            .iter()
+            .map(|result| (result.id, result.error))
+            .collect();
+        results.sort();
+        assert_eq!(results, (0..6).map(|id| (id, None)).collect::<Vec<_>>());
+        assert!(state.paused_until.is_poisoned());
     }
 
+    #[cfg(target_os = "linux")]
     #[test]
-    fn test_placeholder_with_special_regex_chars() {
-        let result = "echo $1$".replace("$1$", "group1");
-        assert_eq!(result, "echo group1");
+    fn test_timeout_escalates_to_kill() {
+        let config = Config::parse_from([
+            "kyanite",
+            "--timeout",
+            "200ms",
+            "--timeout-grace",
+            "200ms",
+            "true",
+        ]);
+        let state = Arc::new(RunState::new(1));
+        enforce_timeout(
+            &state,
+            Duration::from_millis(200),
+            Duration::from_millis(200),
+        );
+
+        let started = Instant::now();
+        let command = shell_command("trap '' TERM; sleep 5; echo done");
+        let result = execute(0, command, None, None, 0, &config, &state);
+        assert_eq!(result.error.as_deref(), Some("timed out after 200ms"));
+        assert!(started.elapsed() < Duration::from_secs(3));
+
+        let result = execute(
+            0,
+            shell_command("echo quick"),
+            None,
+            None,
+            0,
+            &config,
+            &state,
+        );
+        assert_eq!((result.error, result.output.as_str()), (None, "quick"));
     }
 
     #[test]
-    fn test_placeholder_long_string() {
-        let result = "echo __PLACEHOLDER_HERE__".replace("__PLACEHOLDER_HERE__", "value");
-        assert_eq!(result, "echo value");
+    fn test_ramp_up_opens_slots_in_turn() {
+        let state = RunState::new(4).with_ramp_up(Some(Duration::from_millis(400)));
+        assert!(state.admits(0));
+        assert!(!state.admits(1));
+        thread::sleep(Duration::from_millis(150));
+        assert!(state.admits(1));
+        assert!(!state.admits(3));
+        assert!(RunState::new(4).with_ramp_up(None).admits(3));
     }
 
     #[test]
-    fn test_placeholder_empty_value_replacement() {
-        let result = "start [] end".replace("[]", "multi word value");
-        assert_eq!(result, "start multi word value end");
+    fn test_line_too_long() {
+        assert_eq!(line_too_long("abcd", Some(4)), None);
+        assert_eq!(line_too_long(&"x".repeat(100), None), None);
+        assert_eq!(
+            line_too_long("abcde", Some(4)).as_deref(),
+            Some("input line of 5 bytes is longer than --max-line-length 4")
+        );
     }
 
     #[test]
-    fn test_real_world_file_renaming_with_brackets() {
-        let result = expand_template("mv [] [s/\\.jpg/.png/]", "photo.jpg", " ", "[]");
-        assert_eq!(result, "mv photo.jpg photo.png");
+    fn test_retry_delay() {
+        let args = [
+            "kyanite",
+            "--retries",
+            "3",
+            "--retry-delay",
+            "100ms",
+            "true",
+        ];
+        let config = Config::parse_from(args);
+        assert_eq!(retry_delay(&config, 3), Duration::from_millis(100));
+        let config = Config::parse_from(args.iter().chain(&["--retry-backoff"]));
+        let delays: Vec<_> = (1..=3)
+            .map(|attempts| retry_delay(&config, attempts))
+            .collect();
+        assert_eq!(delays, [100, 200, 400].map(Duration::from_millis));
     }
 
     #[test]
-    fn test_real_world_csv_field_extraction_with_at() {
-        let result = expand_template(
-            "echo 'User: @1@, Email: @3@'",
-            "alice admin alice@example.com",
-            " ",
-            "@@",
+    fn test_check_command_length() {
+        assert_eq!(check_command_length(7, Some(7)), Ok(()));
+        assert_eq!(
+            check_command_length(8, Some(7)),
+            Err("command of 8 bytes is longer than --max-chars of 7 bytes".to_string())
         );
-        assert_eq!(result, "echo 'User: alice, Email: alice@example.com'");
+        assert!(system_command_limit() > 1024);
+        let error = check_command_length(system_command_limit() + 1, None).unwrap_err();
+        assert!(error.contains("longer than the system limit"), "{}", error);
     }
 
     #[test]
-    fn test_field_separator_with_comma() {
-        let result = expand_template(
-            "echo \"Name: {1}, Email: {2}\"",
-            "jacobi,j@cobi.dev",
-            ",",
-            "{}",
+    fn test_batches_fit_the_command_limit() {
+        use clap::Parser;
+        let batches = |args: &[&str], lines: &[&str]| {
+            let config = Config::parse_from(["kyanite"].iter().chain(args));
+            let lines: Vec<io::Result<String>> =
+                lines.iter().map(|line| Ok(line.to_string())).collect();
+            let input: Input = Box::new(lines.into_iter());
+            let batches: Vec<String> = Batches::new(input, &config).map(Result::unwrap).collect();
+            let lengths = batches.iter().filter_map(|batch| {
+                let job = Job::new(0, batch.clone());
+                let (cmd_str, command) = prepare_command(&config, None, &job, None, None).ok()?;
+                let args: usize = command.get_args().skip(2).map(|arg| arg.len() + 1).sum();
+                Some(cmd_str.len() + args)
+            });
+            (lengths.max().unwrap_or(0), batches)
+        };
+
+        let lines = ["alpha", "beta", "", "gamma", "delta", "epsilon", "zeta"];
+        // `rm -- alpha beta` with `kyanite alpha beta` for "$@" takes 35 bytes
+        let (longest, filled) = batches(&["--xargs", "--max-chars", "40", "rm -- {}"], &lines);
+        assert_eq!(filled, ["alpha\nbeta", "gamma\ndelta", "epsilon\nzeta"]);
+        assert!(longest <= 40, "{}", longest);
+        let (_, counted) = batches(&["--max-args", "4", "rm \"$@\""], &lines);
+        assert_eq!(counted, ["alpha\nbeta\ngamma\ndelta", "epsilon\nzeta"]);
+
+        // a line that cannot share a command runs alone, failing on its own if too long
+        let (_, alone) = batches(
+            &["--xargs", "--max-chars", "30", "ls {}"],
+            &["a", &"x".repeat(40), "b"],
         );
-        assert_eq!(result, "echo \"Name: jacobi, Email: j@cobi.dev\"");
+        assert_eq!(alone.len(), 3);
+        let config = Config::parse_from(["kyanite", "--xargs", "--max-chars", "30", "ls {}"]);
+        let job = Job::new(0, alone[1].clone());
+        assert!(prepare_command(&config, None, &job, None, None).is_err());
+
+        // the inputs of `-0` may hold newlines, so their batches are kept apart by NULs
+        let (_, nul) = batches(&["-0", "--max-args", "2", "ls"], &["a\nb", "c", "d"]);
+        assert_eq!(nul, ["a\nb\0c", "d"]);
+        let config = Config::parse_from(["kyanite", "-0", "--max-args", "2", "ls"]);
+        let job = Job::new(0, nul[0].clone());
+        let (_, command) = prepare_command(&config, None, &job, None, None).unwrap();
+        let args: Vec<_> = command.get_args().skip(3).collect();
+        assert_eq!(args, ["a\nb", "c"]);
     }
 
     #[test]
-    fn test_field_separator_with_comma_multiple_lines() {
-        let result1 = expand_template(
-            "echo \"Name: {1}, Email: {2}\"",
-            "jacobi,j@cobi.dev",
-            ",",
-            "{}",
-        );
-        assert_eq!(result1, "echo \"Name: jacobi, Email: j@cobi.dev\"");
-
-        let result2 = expand_template("echo \"Name: {1}, Email: {2}\"", "jade,j@de", ",", "{}");
-        assert_eq!(result2, "echo \"Name: jade, Email: j@de\"");
+    fn test_halt_on_error_and_exit_status() {
+        let config = Config::parse_from(["kyanite", "--halt-on-error", "echo {}"]);
+        let mut counts = FailureCounts::default();
+        counts.record(false);
+        assert_eq!(counts.exceeded(&config), None);
+        assert_eq!(exit_status(&counts, false), 0);
+        counts.record(true);
+        assert_eq!(counts.exceeded(&config), Some("a job failed".to_string()));
+        assert_eq!(exit_status(&counts, false), EXIT_SOME_FAILED);
+        assert_eq!(exit_status(&counts, true), 0);
+
+        let mut failed = FailureCounts::default();
+        failed.record(true);
+        assert_eq!(exit_status(&failed, false), EXIT_ALL_FAILED);
+        assert_eq!(exit_status(&failed, true), EXIT_ALL_FAILED);
+        assert_eq!(exit_status(&FailureCounts::default(), false), 0);
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_real_world_log_processing_with_angle_brackets() {
-        let result = expand_template(
-            "echo Timestamp: <1>, Level: <2>",
-            "2024-01-15 ERROR",
-            " ",
-            "<>",
-        );
-        assert_eq!(result, "echo Timestamp: 2024-01-15, Level: ERROR");
+    fn test_signal_running_reaches_jobs() {
+        let state = RunState::new(2);
+        let mut child = Command::new("sleep").arg("5").spawn().unwrap();
+        state.register(1, child.id());
+        state.signal_running(false);
+        let status = child.wait().unwrap();
+        use std::os::unix::process::ExitStatusExt;
+        assert_eq!(status.signal(), Some(libc::SIGTERM));
+        assert!(state.killing.load(Ordering::SeqCst));
     }
 
     #[test]
-    fn test_real_world_batch_video_conversion() {
-        let result = expand_template("ffmpeg -i [] [s/\\.avi/.mp4/]", "movie.avi", " ", "[]");
-        assert_eq!(result, "ffmpeg -i movie.avi movie.mp4");
+    fn test_sources_form_cross_product() {
+        let args: Vec<String> = [":::", "a", "b", ":::", "1", "2", ":::", "x"]
+            .map(String::from)
+            .into();
+        let sources = input_sources(&args).unwrap();
+        assert_eq!(sources.len(), 3);
+        let lines = product(&sources[0][0], &sources[1..]);
+        let shown: Vec<_> = lines.iter().map(|line| shown_input(line, " ")).collect();
+        assert_eq!(shown, ["a 1 x", "a 2 x"]);
+        assert_eq!(product("line", &[]), ["line"]);
+        assert!(input_sources(&["a".to_string()]).is_err());
+
+        // a value holding the separator stays one field
+        let [line] = &product("a b", &[vec!["1".to_string()]])[..] else {
+            panic!("expected one combination");
+        };
+        assert_eq!(
+            expand_template("[{1}] [{2}] [{}]", line, " ", "{}"),
+            "[a b] [1] [a b 1]"
+        );
+        assert_eq!(split_fields(line, " "), ["a b", "1"]);
     }
 
     #[test]
-    fn test_real_world_backup_with_timestamp() {
-        let result = expand_template("cp [] backups/[]-2024", "document.txt", " ", "[]");
-        assert_eq!(result, "cp document.txt backups/document.txt-2024");
+    fn test_tag_lines() {
+        assert_eq!(tag_lines("one\ntwo", "a.txt\t"), "a.txt\tone\na.txt\ttwo");
+        assert_eq!(tag_lines("only", "x: "), "x: only");
     }
 
     #[test]
-    fn test_real_world_url_rewriting_with_at() {
-        let result = expand_template("echo @s/http:/https:/@", "http://example.com", " ", "@@");
-        assert_eq!(result, "echo https://example.com");
+    fn test_escaping_placeholders() {
+        let line = "O'Brien \"Pat\"";
+        assert_eq!(
+            expand_template(
+                "psql -c \"SELECT {sqlq:1}\" {csvq} {jsonq:2}",
+                line,
+                " ",
+                "{}"
+            ),
+            r#"psql -c "SELECT 'O''Brien'" "O'Brien ""Pat""" "\"Pat\"""#
+        );
+        assert_eq!(expand_template("[sqlq:3]", line, " ", "[]"), "''");
     }
 
     #[test]
-    fn test_real_word_field_ranges_for_logs() {
-        let result = expand_template(
-            "echo 'Message: [3+]'",
-            "INFO 2024-01-15 This is a log message",
-            " ",
-            "[]",
+    fn test_missing_field_policies() {
+        assert_eq!(
+            expand_template("echo {2!} {1?}", "a b", " ", "{}"),
+            "echo b a"
         );
-        assert_eq!(result, "echo 'Message: This is a log message'");
+        assert_eq!(missing_field("echo {2!} {3?}", "a b c", " ", "{}"), None);
+        assert_eq!(
+            missing_field("echo {3?} {2+!}", "a", " ", "{}"),
+            Some(("field 2 is missing ({2+!})".to_string(), true))
+        );
+        assert_eq!(
+            missing_field("echo [2?]", "a,", ",", "[]"),
+            Some(("field 2 is missing ([2?])".to_string(), false))
+        );
+        assert_eq!(missing_field("echo {3} !?", "a", " ", "{}"), None);
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_real_world_extract_extension_with_regex() {
-        let result = expand_template("echo [/(.+)\\.(.+)/2]", "archive.tar.gz", " ", "[]");
-        assert_eq!(result, "echo gz");
+    fn test_streams_stay_apart_and_echo_lines() {
+        let config = Config::parse_from(["kyanite", "--line-buffer", "true"]);
+        let state = RunState::new(1);
+        let command = shell_command("echo out; echo err >&2; printf tail");
+        let result = execute(0, command, None, None, 0, &config, &state);
+        assert_eq!(
+            (result.output.as_str(), result.stderr.as_str()),
+            ("out\ntail", "err")
+        );
+        assert_eq!(result.combined_output(), "out\ntail\nerr");
+
+        let lines = Mutex::new(Vec::new());
+        let echo = |stderr: bool, line: &[u8]| lines.lock().unwrap().push((stderr, line.to_vec()));
+        let command = shell_command("echo one; sleep 0.1; echo two >&2");
+        let result = execute(0, command, None, Some(&echo), 0, &config, &state);
+        assert!(result.echoed);
+        assert_eq!(
+            lines.into_inner().unwrap(),
+            [(false, b"one\n".to_vec()), (true, b"two\n".to_vec())]
+        );
     }
 
     #[test]
-    fn test_real_world_filename_sanitization() {
-        let result = expand_template("echo [s/ /_/g]", "my document file.txt", " ", "[]");
-        assert_eq!(result, "echo my_document_file.txt");
+    fn test_results_directory() {
+        let dir = std::env::temp_dir().join(format!("kyanite-results-{}", std::process::id()));
+        let config = Config::parse_from(["kyanite", "--results", "unused", "true"]);
+        let result = JobResult {
+            output: "out".to_string(),
+            stderr: "err".to_string(),
+            exit_code: Some(0),
+            streams: Some((b"out\n".to_vec(), b"err\n".to_vec())),
+            ..JobResult::new(0)
+        };
+        let job = Job::new(0, "a \"b\"".to_string());
+        let state = RunState::new(1).with_manifest(Some(Manifest::default()));
+        let runtime = Duration::from_millis(1500);
+        let (result, staged) = save_results(result, &dir, &config, &job, "run", runtime, &state);
+        assert!(staged.is_empty());
+        let job_dir = dir.join("1");
+        assert_eq!(
+            result.output,
+            format!(
+                "exited 0 in 1.50s: 4B stdout, 4B stderr in {}",
+                job_dir.display()
+            )
+        );
+        assert!(result.stderr.is_empty());
+        let read = |name: &str| fs::read_to_string(job_dir.join(name)).unwrap();
+        assert_eq!(
+            (read("stdout"), read("stderr")),
+            ("out\n".to_string(), "err\n".to_string())
+        );
+        assert_eq!(
+            read("meta.json"),
+            "{\"seq\":1,\"command\":\"run\",\"input\":\"a \\\"b\\\"\",\"exit\":0,\"error\":null,\"runtime\":1.500}\n"
+        );
+        let manifest = state.manifest.as_ref().unwrap().to_json();
+        assert_eq!(manifest.matches("\"path\":").count(), 3);
+        let with_results = [
+            "kyanite",
+            "--results",
+            "out",
+            "--manifest",
+            "m.json",
+            "true",
+        ];
+        assert!(Config::try_parse_from(with_results).is_ok());
+        assert!(Config::try_parse_from(["kyanite", "--manifest", "m.json", "true"]).is_err());
+        fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn test_real_word_case_conversion() {
-        let result = expand_template("mv [] [s/[A-Z]/\\L$0/g]", "FILE.TXT", " ", "[]");
-        assert!(result.contains("FILE.TXT"));
+    fn test_pipe_output_passed_through() {
+        use clap::Parser;
+        let gzip = vec![0x1f, 0x8b, 0x08, 0xff, b'\n', b'\n'];
+        let result = JobResult {
+            output: String::from_utf8_lossy(&gzip).trim_end().to_string(),
+            exit_code: Some(0),
+            streams: Some((gzip.clone(), Vec::new())),
+            input: "block".to_string(),
+            ..JobResult::new(0)
+        };
+        let config = |args: &[&str]| Config::parse_from(["kyanite"].iter().chain(args));
+        assert_eq!(
+            passed_through(&result, &config(&["--pipe", "gzip"])),
+            Some((&gzip[..], &[][..]))
+        );
+        assert_eq!(
+            passed_through(&result, &config(&["--pipe", "--tag", "gzip"])),
+            None
+        );
+        assert_eq!(passed_through(&result, &config(&["gzip"])), None);
     }
 
     #[test]
-    fn test_real_word_process_list_of_paths() {
-        let result = expand_template("echo 'Processing: [1]'", "/home/user/file.txt", " ", "[]");
-        assert_eq!(result, "echo 'Processing: /home/user/file.txt'");
+    fn test_malformed_command_lines_exit_with_usage_status() {
+        let bad_value = Config::try_parse_from(["kyanite", "--preprocess-failure", "shout", "x"]);
+        assert_eq!(bad_value.err().unwrap().exit_code(), EXIT_USAGE);
+        let conflict = Config::try_parse_from(["kyanite", "--pipe", "--pty", "cat"]);
+        assert_eq!(conflict.err().unwrap().exit_code(), EXIT_USAGE);
+        assert_ne!(EXIT_USAGE, EXIT_INTERNAL_ERROR);
     }
 
     #[test]
-    fn test_real_word_create_symlinks() {
-        let result = expand_template("ln -s [1] [2]", "original.txt link-to-original", " ", "[]");
-        assert_eq!(result, "ln -s original.txt link-to-original");
+    fn test_usage_problems_found_after_parsing() {
+        let usage =
+            |args: &[&str]| usage_problem(&Config::parse_from(["kyanite"].iter().chain(args)));
+        let parts = ["--pipe", "--pipe-part", "-a", "a", "-a", "b", "wc -l"];
+        assert_eq!(
+            usage(&parts),
+            Some("--pipe-part reads its blocks from exactly one -a file")
+        );
+        assert_eq!(usage(&["--pipe", "--pipe-part", "-a", "a", "wc -l"]), None);
+        assert_eq!(
+            usage(&["--sandbox-profile", "jobs.sb", "echo {}"]).is_some(),
+            cfg!(not(target_os = "macos"))
+        );
+        assert!(compat::translate(compat::Tool::Xargs, &["-L".into(), "1".into()]).is_err());
     }
 
     #[test]
-    fn test_real_word_remove_file_extension() {
-        let result = expand_template("echo [s/.txt//]", "document.txt", " ", "[]");
-        assert_eq!(result, "echo document");
+    fn test_frozen_run_keeps_its_input_position() {
+        let config = Config::parse_from(["kyanite", "echo {}"]);
+        let state = RunState::new(1);
+        let (job_tx, job_rx) = mpsc::channel();
+        let job_rx = Mutex::new(job_rx);
+        // the input is still open when the run is frozen
+        let (line_tx, line_rx) = mpsc::channel::<String>();
+        let path = std::env::temp_dir().join(format!("kyanite-thaw-{}", std::process::id()));
+        thread::scope(|scope| {
+            let reader = scope.spawn(|| {
+                let input: Input = Box::new(line_rx.into_iter().map(Ok));
+                read_input(input, job_tx, &config, &state);
+            });
+            for line in ["a", "b"] {
+                line_tx.send(line.to_string()).unwrap();
+            }
+            while state.queued.load(Ordering::SeqCst) < 2 {
+                thread::sleep(Duration::from_millis(5));
+            }
+            let _ = state.frozen.set(path.clone());
+            state.stop("frozen");
+            assert_eq!(freeze_run(&path, &config, &job_rx, &state).unwrap(), 2);
+            line_tx.send("c".to_string()).unwrap();
+            reader.join().unwrap();
+        });
+        let frozen = Frozen::parse(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(frozen.read, Some((2, 2)));
+
+        // the thawed run picks up the same input where the frozen one stopped reading
+        let mut config = Config::parse_from(["kyanite", "echo {}"]);
+        config.thawed = Some((path, frozen));
+        let (job_tx, job_rx) = mpsc::channel();
+        let input: Input = Box::new(
+            ["a", "", "b", "c", "d"]
+                .map(|s| Ok(s.to_string()))
+                .into_iter(),
+        );
+        read_input(input, job_tx, &config, &RunState::new(1));
+        let jobs: Vec<_> = job_rx.iter().map(|job| (job.id, job.line)).collect();
+        let expected =
+            [(0, "a"), (1, "b"), (2, "c"), (3, "d")].map(|(id, line)| (id, line.to_string()));
+        assert_eq!(jobs, expected);
     }
 
     #[test]
-    fn test_real_word_extract_filename_from_path() {
-        let result = expand_template("echo [3]", "path to file.txt", " ", "[]");
-        assert_eq!(result, "echo file.txt");
+    fn test_remaining_input_waits_a_bounded_time() {
+        let path = std::env::temp_dir().join(format!("kyanite-rest-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let state = RunState::new(1);
+        let (job_tx, job_rx) = mpsc::channel();
+        let job_rx = Mutex::new(job_rx);
+        job_tx.send(Job::new(0, "a".to_string())).unwrap();
+        let wait = Duration::from_millis(50);
+        assert!(!write_remaining(path, &job_rx, &state, wait).unwrap());
+        assert_eq!(fs::read_to_string(path).unwrap(), "a\n");
+
+        job_tx.send(Job::new(1, "b".to_string())).unwrap();
+        drop(job_tx);
+        assert!(write_remaining(path, &job_rx, &state, wait).unwrap());
+        assert_eq!(fs::read_to_string(path).unwrap(), "b\n");
+        fs::remove_file(path).unwrap();
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_real_word_batch_resize_images() {
-        let result = expand_template(
-            "convert [] -resize 800x600 resized/[]",
-            "photo.jpg",
-            " ",
-            "[]",
-        );
-        assert_eq!(
-            result,
-            "convert photo.jpg -resize 800x600 resized/photo.jpg"
-        );
+    fn test_terminate_reaches_what_the_job_started() {
+        let state = RunState::new(1);
+        let started = Instant::now();
+        thread::scope(|scope| {
+            let job = scope
+                .spawn(|| run_command(shell_command("sleep 10 & wait"), None, None, 0, &state));
+            while state.running[0].lock().unwrap().is_none() {
+                thread::sleep(Duration::from_millis(5));
+            }
+            thread::sleep(Duration::from_millis(100));
+            state.kill_running();
+            // the background sleep holds the output open until it is terminated too
+            job.join().unwrap().unwrap();
+        });
+        assert!(started.elapsed() < Duration::from_secs(5));
     }
 
     #[test]
-    fn test_real_word_extract_fields_from_ps_output() {
-        let result = expand_template(
-            "echo 'PID: @1@, USER: @2@, CMD: @3+@'",
-            "1234 root /usr/bin/myapp --daemon",
-            " ",
-            "@@",
+    fn test_retry_notice_gives_the_delay_in_seconds() {
+        assert_eq!(
+            retry_notice(1, Duration::ZERO, 1, 3),
+            "job 1 failed, retrying (retry 1 of 3)"
         );
         assert_eq!(
-            result,
-            "echo 'PID: 1234, USER: root, CMD: /usr/bin/myapp --daemon'"
+            retry_notice(4, Duration::from_millis(1500), 2, 3),
+            "job 4 failed, retrying in 1.5s (retry 2 of 3)"
         );
     }
 
     #[test]
-    fn test_real_word_normalize_whitespace() {
-        let result = expand_template("echo [s/\\s+/ /g]", "hello    world   test", " ", "[]");
-        assert_eq!(result, "echo hello world test");
-    }
-
-    #[test]
-    fn test_real_word_replace_underscores_with_dashes() {
-        let result = expand_template("echo [s/_/-/g]", "my_file_name.txt", " ", "[]");
-        assert_eq!(result, "echo my-file-name.txt");
+    fn test_arg_files_after_the_first_are_sources() {
+        let dir = std::env::temp_dir().join(format!("kyanite-sources-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (first, second) = (dir.join("first"), dir.join("second"));
+        fs::write(&first, "x\n").unwrap();
+        fs::write(&second, "1\n\n2 3\n").unwrap();
+        let config = Config::parse_from([
+            "kyanite",
+            "-a",
+            first.to_str().unwrap(),
+            "-a",
+            second.to_str().unwrap(),
+            "echo {2}",
+        ]);
+        assert_eq!(arg_file_sources(&config), [["1", "2 3"]]);
+        fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn test_real_word_add_prefix_to_files() {
-        let result = expand_template("echo [s/^/backup_/]", "file.txt", " ", "[]");
-        assert_eq!(result, "echo backup_file.txt");
+    fn test_jobs_cancelled_by_until_success_are_not_failures() {
+        let config = Arc::new(Config::parse_from([
+            "kyanite",
+            "--until-success",
+            "curl {}",
+        ]));
+        let (result_tx, result_rx) = mpsc::channel();
+        result_tx
+            .send(JobResult::failed(0, "exit 1".into()))
+            .unwrap();
+        result_tx.send(JobResult::new(1)).unwrap();
+        result_tx
+            .send(JobResult::failed(2, "terminated".into()))
+            .unwrap();
+        drop(result_tx);
+        let state = Arc::new(RunState::new(1));
+        let counts = result_collector(result_rx, config, Arc::clone(&state), None);
+        assert_eq!((counts.total, counts.succeeded), (1, 1));
+        assert_eq!(state.failures.load(Ordering::SeqCst), 1);
+        assert_eq!(exit_status(&counts, false), 0);
     }
 
     #[test]
-    fn test_real_word_add_suffix_to_files() {
-        let result = expand_template("echo [].bak", "file.txt", " ", "[]");
-        assert_eq!(result, "echo file.txt.bak");
+    fn test_path_placeholders() {
+        assert_eq!(
+            expand_template("mv {} {//}/{/.}.bak {.}", "in/a.tar.gz", " ", "{}"),
+            "mv in/a.tar.gz in/a.tar.bak in/a.tar"
+        );
+        assert_eq!(expand_template("{/} {//}", "a.txt", " ", "{}"), "a.txt .");
+        assert_eq!(expand_template("@/.@", "x/y.txt", " ", "@@"), "y");
+
+        let windows = |name, line| path_placeholder(name, line, PathStyle::Windows).unwrap();
+        assert_eq!(windows("/", r"C:\data\in.txt"), "in.txt");
+        assert_eq!(windows("//", r"C:\data\in.txt"), r"C:\data");
+        assert_eq!(windows("//", r"\\server\share\in.txt"), r"\\server\share\");
+        assert_eq!(windows("/.", r"C:\data\in.txt"), "in");
+        assert_eq!(windows(".", r"C:\data.d\in"), r"C:\data.d\in");
+        let unix = |name, line| path_placeholder(name, line, PathStyle::Unix).unwrap();
+        assert_eq!(unix("/", r"C:\data\in.txt"), r"C:\data\in.txt");
+        assert_eq!(path_placeholder("s", "a", PathStyle::Unix), None);
+        assert!(lint::check("cp {} {//}/{/.}.bak", "{}", None, false).is_empty());
     }
 }
@@ -0,0 +1,103 @@
+use crate::mux::json_string;
+use crate::sha256::{self, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+/// The files each job produced, written as JSON at the end of a run with `--manifest`
+///
+/// Sizes and checksums are taken from the data as it was saved, so the manifest does not read
+/// the outputs back.
+#[derive(Default)]
+pub struct Manifest {
+    outputs: Mutex<Vec<Output>>,
+}
+
+struct Output {
+    seq: usize,
+    input: String,
+    path: PathBuf,
+    size: usize,
+    sha256: String,
+    partial: bool,
+}
+
+impl Manifest {
+    /// Records a file saved for a job; `partial` marks the `--partial-suffix` output of a
+    /// failed job
+    pub fn record(&self, seq: usize, input: &str, path: &Path, data: &[u8], partial: bool) {
+        let mut hasher = Sha256::default();
+        hasher.update(data);
+        self.outputs
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Output {
+                seq,
+                input: input.to_string(),
+                path: path.to_path_buf(),
+                size: data.len(),
+                sha256: sha256::hex(&hasher.finalize()),
+                partial,
+            });
+    }
+
+    /// Renders `{"jobs":[{"seq":N,"input":...,"files":[{"path":...,"size":N,"sha256":...}]}]}`
+    /// in sequence order
+    pub fn to_json(&self) -> String {
+        let mut outputs = self.outputs.lock().unwrap_or_else(PoisonError::into_inner);
+        outputs.sort_by_key(|output| output.seq);
+        let mut jobs: Vec<String> = Vec::new();
+        let mut files = Vec::new();
+        for (i, output) in outputs.iter().enumerate() {
+            let partial = if output.partial {
+                ",\"partial\":true"
+            } else {
+                ""
+            };
+            files.push(format!(
+                "{{\"path\":{},\"size\":{},\"sha256\":\"{}\"{}}}",
+                json_string(&output.path.to_string_lossy()),
+                output.size,
+                output.sha256,
+                partial
+            ));
+            if outputs
+                .get(i + 1)
+                .is_some_and(|next| next.seq == output.seq)
+            {
+                continue;
+            }
+            jobs.push(format!(
+                "{{\"seq\":{},\"input\":{},\"files\":[{}]}}",
+                output.seq,
+                json_string(&output.input),
+                files.join(",")
+            ));
+            files.clear();
+        }
+        format!("{{\"jobs\":[{}]}}\n", jobs.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_json() {
+        let manifest = Manifest::default();
+        manifest.record(2, "b \"x\"", Path::new("out/b.part"), b"", true);
+        manifest.record(1, "a", Path::new("out/a"), b"abc", false);
+        assert_eq!(
+            manifest.to_json(),
+            concat!(
+                r#"{"jobs":[{"seq":1,"input":"a","files":[{"path":"out/a","size":3,"#,
+                r#""sha256":"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"}]},"#,
+                r#"{"seq":2,"input":"b \"x\"","files":[{"path":"out/b.part","size":0,"#,
+                r#""sha256":"e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855","#,
+                r#""partial":true}]}]}"#,
+                "\n"
+            )
+        );
+        assert_eq!(Manifest::default().to_json(), "{\"jobs\":[]}\n");
+    }
+}
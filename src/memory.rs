@@ -0,0 +1,167 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::time::Duration;
+
+/// Bounds what kyanite itself holds for `--self-mem-limit`: the input waiting for a worker and
+/// the output of finished jobs not printed yet
+///
+/// The queue may take half of the limit before reading input waits for the workers. A worker
+/// whose finished job would take the total past the limit waits for the printing side to take
+/// the results before it. Output the printing side holds back for ordering never makes a
+/// worker wait, as the job it waits for may be the one held up; it is moved to disk instead
+/// while the total is past the limit. A single input or output larger than its share still
+/// passes once nothing else is held, rather than never.
+pub struct Budget {
+    limit: u64,
+    held: Mutex<Held>,
+    freed: Condvar,
+}
+
+#[derive(Default)]
+struct Held {
+    queued: u64,
+    results: u64,
+    kept: u64,
+}
+
+impl Budget {
+    pub fn new(limit: u64) -> Self {
+        Budget {
+            limit,
+            held: Mutex::new(Held::default()),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Takes room for a queued input, first waiting while the queue holds its share
+    pub fn queue(&self, bytes: u64, stopped: impl Fn() -> bool) {
+        let mut held = self.held.lock().unwrap_or_else(PoisonError::into_inner);
+        while held.queued > 0 && held.queued + bytes > self.limit / 2 && !stopped() {
+            held = self.wait(held);
+        }
+        held.queued += bytes;
+    }
+
+    /// Gives back the room of an input a worker took off the queue
+    pub fn dequeue(&self, bytes: u64) {
+        let mut held = self.held.lock().unwrap_or_else(PoisonError::into_inner);
+        held.queued = held.queued.saturating_sub(bytes);
+        self.freed.notify_all();
+    }
+
+    /// Takes room for a finished job's output, first waiting while there is none to spare
+    pub fn hold(&self, bytes: u64, stopped: impl Fn() -> bool) {
+        let mut held = self.held.lock().unwrap_or_else(PoisonError::into_inner);
+        while held.results > 0 && held.queued + held.results + bytes > self.limit && !stopped() {
+            held = self.wait(held);
+        }
+        held.results += bytes;
+    }
+
+    /// Gives back the room of a result the printing side took
+    pub fn release(&self, bytes: u64) {
+        let mut held = self.held.lock().unwrap_or_else(PoisonError::into_inner);
+        held.results = held.results.saturating_sub(bytes);
+        self.freed.notify_all();
+    }
+
+    /// Counts output the printing side holds back for ordering
+    pub fn keep(&self, bytes: u64) {
+        self.held
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .kept += bytes;
+    }
+
+    /// Stops counting held back output that was printed or moved to disk
+    pub fn let_go(&self, bytes: u64) {
+        let mut held = self.held.lock().unwrap_or_else(PoisonError::into_inner);
+        held.kept = held.kept.saturating_sub(bytes);
+    }
+
+    /// Whether what is held has gone past the limit, so held back output should be moved out
+    pub fn exceeded(&self) -> bool {
+        let held = self.held.lock().unwrap_or_else(PoisonError::into_inner);
+        held.queued + held.results + held.kept > self.limit
+    }
+
+    // waits are bounded so a stop is noticed even if nothing is freed
+    fn wait<'a>(&self, held: std::sync::MutexGuard<'a, Held>) -> std::sync::MutexGuard<'a, Held> {
+        self.freed
+            .wait_timeout(held, Duration::from_millis(100))
+            .unwrap_or_else(PoisonError::into_inner)
+            .0
+    }
+}
+
+/// Writes the parts of held back output to a file in `dir`, to be read back with [`unspill`]
+pub fn spill(dir: &Path, id: usize, parts: &[&[u8]]) -> io::Result<PathBuf> {
+    let path = dir.join(format!("kyanite-spill-{}-{}", std::process::id(), id));
+    let size = parts.iter().map(|part| 8 + part.len()).sum();
+    let mut data = Vec::with_capacity(size);
+    for part in parts {
+        data.extend_from_slice(&(part.len() as u64).to_le_bytes());
+        data.extend_from_slice(part);
+    }
+    fs::write(&path, data)?;
+    Ok(path)
+}
+
+/// Reads back and removes output written by [`spill`], as the parts it was given
+pub fn unspill(path: &Path) -> io::Result<Vec<Vec<u8>>> {
+    let data = fs::read(path)?;
+    let _ = fs::remove_file(path);
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "corrupt spill file");
+    let mut parts = Vec::new();
+    let mut rest = data.as_slice();
+    while !rest.is_empty() {
+        let (len, after) = rest.split_first_chunk::<8>().ok_or_else(invalid)?;
+        let len = usize::try_from(u64::from_le_bytes(*len)).map_err(|_| invalid())?;
+        if len > after.len() {
+            return Err(invalid());
+        }
+        let (part, after) = after.split_at(len);
+        parts.push(part.to_vec());
+        rest = after;
+    }
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    #[test]
+    fn test_budget_waits_for_room() {
+        let budget = Arc::new(Budget::new(100));
+        budget.queue(60, || false);
+        budget.hold(30, || false);
+        assert!(!budget.exceeded());
+
+        let done = Arc::new(AtomicBool::new(false));
+        let waiting = {
+            let (budget, done) = (Arc::clone(&budget), Arc::clone(&done));
+            thread::spawn(move || {
+                budget.hold(30, || false);
+                done.store(true, Ordering::SeqCst);
+            })
+        };
+        thread::sleep(Duration::from_millis(150));
+        assert!(!done.load(Ordering::SeqCst));
+        budget.dequeue(60);
+        waiting.join().unwrap();
+        assert!(done.load(Ordering::SeqCst));
+
+        let path = spill(&std::env::temp_dir(), 7, &[b"out\n", b"", b"err"]).unwrap();
+        assert_eq!(
+            unspill(&path).unwrap(),
+            [b"out\n".to_vec(), Vec::new(), b"err".to_vec()]
+        );
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Per-job metadata loaded with `--meta`, looked up by input line or by line number
+///
+/// The file is a JSON object whose keys are input lines (or 1-based line numbers) and whose
+/// values are objects of fields, e.g. `{"a.csv": {"owner": "ana"}, "2": {"owner": "li"}}`.
+#[derive(Debug, Default)]
+pub struct Meta {
+    records: HashMap<String, HashMap<String, String>>,
+}
+
+impl Meta {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut parser = Parser { text, position: 0 };
+        let Value::Object(entries) = parser.document()? else {
+            return Err("expected an object keyed by input line".to_string());
+        };
+        let mut records = HashMap::new();
+        for (key, value) in entries {
+            let Value::Object(fields) = value else {
+                return Err(format!("metadata for {:?} is not an object", key));
+            };
+            let fields = fields
+                .into_iter()
+                .map(|(name, value)| (name, value.to_text()))
+                .collect();
+            records.insert(key, fields);
+        }
+        Ok(Meta { records })
+    }
+
+    /// Returns a field of the record for `line`, falling back to the record for `number`
+    pub fn get(&self, line: &str, number: usize, field: &str) -> Option<&str> {
+        self.records
+            .get(line)
+            .or_else(|| self.records.get(&number.to_string()))?
+            .get(field)
+            .map(String::as_str)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Value {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Renders a field value for substitution: strings as-is, null as empty, the rest as JSON
+    fn to_text(&self) -> String {
+        match self {
+            Value::Null => String::new(),
+            Value::Bool(value) => value.to_string(),
+            Value::Number(number) => number.clone(),
+            Value::String(text) => text.clone(),
+            Value::Array(items) => {
+                let items: Vec<String> = items.iter().map(Value::to_json).collect();
+                format!("[{}]", items.join(","))
+            }
+            Value::Object(_) => self.to_json(),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        match self {
+            Value::String(text) => crate::mux::json_string(text),
+            Value::Null => "null".to_string(),
+            Value::Object(entries) => {
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|(key, value)| {
+                        format!("{}:{}", crate::mux::json_string(key), value.to_json())
+                    })
+                    .collect();
+                format!("{{{}}}", entries.join(","))
+            }
+            value => value.to_text(),
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn document(&mut self) -> Result<Value, String> {
+        let value = self.value()?;
+        self.skip_whitespace();
+        if self.position < self.text.len() {
+            return Err(self.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+        let rest = &self.text[self.position..];
+        match rest.chars().next() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => self.string().map(Value::String),
+            Some('t') if rest.starts_with("true") => self.literal(4, Value::Bool(true)),
+            Some('f') if rest.starts_with("false") => self.literal(5, Value::Bool(false)),
+            Some('n') if rest.starts_with("null") => self.literal(4, Value::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let len = rest
+                    .find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c)))
+                    .unwrap_or(rest.len());
+                self.position += len;
+                Ok(Value::Number(rest[..len].to_string()))
+            }
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn literal(&mut self, len: usize, value: Value) -> Result<Value, String> {
+        self.position += len;
+        Ok(value)
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        self.position += 1;
+        let mut entries = Vec::new();
+        if self.eat('}') {
+            return Ok(Value::Object(entries));
+        }
+        loop {
+            self.skip_whitespace();
+            if !self.text[self.position..].starts_with('"') {
+                return Err(self.error("expected a string key"));
+            }
+            let key = self.string()?;
+            if !self.eat(':') {
+                return Err(self.error("expected ':'"));
+            }
+            entries.push((key, self.value()?));
+            if self.eat('}') {
+                return Ok(Value::Object(entries));
+            }
+            if !self.eat(',') {
+                return Err(self.error("expected ',' or '}'"));
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.position += 1;
+        let mut items = Vec::new();
+        if self.eat(']') {
+            return Ok(Value::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            if self.eat(']') {
+                return Ok(Value::Array(items));
+            }
+            if !self.eat(',') {
+                return Err(self.error("expected ',' or ']'"));
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.position += 1;
+        let mut out = String::new();
+        let text = self.text;
+        let mut chars = text[self.position..].char_indices();
+        while let Some((offset, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.position += offset + 1;
+                    return Ok(out);
+                }
+                '\\' => {
+                    let escaped = match chars.next().map(|(_, c)| c) {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('u') => {
+                            let invalid = || self.error("invalid \\u escape");
+                            let high = hex4(&mut chars).ok_or_else(invalid)?;
+                            let code = if (0xd800..0xdc00).contains(&high) {
+                                // a high surrogate is followed by `\uXXXX` holding the low one
+                                chars.nth(1);
+                                let low = hex4(&mut chars).ok_or_else(invalid)?;
+                                0x10000
+                                    + ((high - 0xd800) << 10)
+                                    + (low.wrapping_sub(0xdc00) & 0x3ff)
+                            } else {
+                                high
+                            };
+                            char::from_u32(code).unwrap_or('\u{fffd}')
+                        }
+                        Some(c) => c,
+                        None => break,
+                    };
+                    out.push(escaped);
+                }
+                c => out.push(c),
+            }
+        }
+        Err(self.error("unterminated string"))
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.text[self.position..].starts_with(c) {
+            self.position += c.len_utf8();
+            return true;
+        }
+        false
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("{} at byte {}", message, self.position)
+    }
+}
+
+fn hex4(chars: &mut std::str::CharIndices) -> Option<u32> {
+    let hex: String = chars.take(4).map(|(_, c)| c).collect();
+    u32::from_str_radix(&hex, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_by_line_then_number() {
+        let meta = Meta::parse(
+            r#"{
+                "a.csv": {"owner": "ana", "rows": 120, "tags": ["x", "y"], "note": null},
+                "2": {"owner": "li \"lee\" \u00e9\ud83d\ude00"}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(meta.get("a.csv", 1, "owner"), Some("ana"));
+        assert_eq!(meta.get("a.csv", 1, "rows"), Some("120"));
+        assert_eq!(meta.get("a.csv", 1, "tags"), Some("[\"x\",\"y\"]"));
+        assert_eq!(meta.get("a.csv", 1, "note"), Some(""));
+        assert_eq!(meta.get("b.csv", 2, "owner"), Some("li \"lee\" é😀"));
+        assert_eq!(meta.get("b.csv", 3, "owner"), None);
+        assert_eq!(meta.get("a.csv", 1, "missing"), None);
+    }
+
+    #[test]
+    fn test_rejects_malformed_files() {
+        assert!(Meta::parse("[]").is_err());
+        assert!(Meta::parse(r#"{"a": 1}"#).is_err());
+        assert!(Meta::parse(r#"{"a": {"b": 1}"#).is_err());
+        assert!(Meta::parse(r#"{"a": {"b": 1}} x"#).is_err());
+        assert!(Meta::parse(r#"{"a": {"b": "open}}"#).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use std::fmt::Write;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Frames a finished job's output as NDJSON records so consumers can demultiplex it
+///
+/// Each non-empty stream becomes one `{"seq":N,"stream":"stdout"|"stderr",...}` record, followed
+/// by an `exit` record. Output that is not valid UTF-8 is carried in `data_base64` instead of `data`.
+pub fn frames(
+    seq: usize,
+    stdout: &[u8],
+    stderr: &[u8],
+    exit_code: Option<i32>,
+    error: Option<&str>,
+) -> String {
+    let mut out = String::new();
+    for (stream, data) in [("stdout", stdout), ("stderr", stderr)] {
+        if data.is_empty() {
+            continue;
+        }
+        let payload = match std::str::from_utf8(data) {
+            Ok(text) => format!("\"data\":{}", json_string(text)),
+            Err(_) => format!("\"data_base64\":\"{}\"", base64(data)),
+        };
+        let _ = writeln!(
+            out,
+            "{{\"seq\":{},\"stream\":\"{}\",{}}}",
+            seq, stream, payload
+        );
+    }
+
+    let code = exit_code.map_or("null".to_string(), |code| code.to_string());
+    let error = error.map_or("null".to_string(), json_string);
+    let _ = write!(
+        out,
+        "{{\"seq\":{},\"stream\":\"exit\",\"code\":{},\"error\":{}}}",
+        seq, code, error
+    );
+    out
+}
+
+/// Encodes a string as a JSON string literal
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+pub fn base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_string_escapes() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(
+            json_string("a \"b\"\\\n\t\u{1}"),
+            "\"a \\\"b\\\"\\\\\\n\\t\\u0001\""
+        );
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64(&[0xff, 0xfe]), "//4=");
+    }
+
+    #[test]
+    fn test_frames() {
+        assert_eq!(
+            frames(3, b"out\n", b"", Some(0), None),
+            "{\"seq\":3,\"stream\":\"stdout\",\"data\":\"out\\n\"}\n\
+             {\"seq\":3,\"stream\":\"exit\",\"code\":0,\"error\":null}"
+        );
+        assert_eq!(
+            frames(1, b"", &[0xff], None, Some("failed")),
+            "{\"seq\":1,\"stream\":\"stderr\",\"data_base64\":\"/w==\"}\n\
+             {\"seq\":1,\"stream\":\"exit\",\"code\":null,\"error\":\"failed\"}"
+        );
+    }
+}
@@ -0,0 +1,348 @@
+use crate::mux::json_string;
+use crate::sha256::{self, Sha256};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::{Mutex, PoisonError, mpsc};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Spans sent to the collector in one request
+const BATCH: usize = 512;
+
+/// Span index of the run, which job spans, indexed by sequence number, are children of
+const ROOT: usize = usize::MAX;
+
+/// An OTLP/HTTP collector address, `http://host[:port][/path]`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Endpoint {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+/// Parses an `--otel-endpoint`, adding the `/v1/traces` path OTLP collectors serve traces on
+/// unless it is already there
+pub fn parse_endpoint(s: &str) -> Result<Endpoint, String> {
+    let Some(rest) = s.strip_prefix("http://") else {
+        return Err(format!(
+            "unsupported endpoint {}: only http:// collectors are supported",
+            s
+        ));
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, ""),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .map_err(|_| format!("invalid port in endpoint {}", s))?,
+        ),
+        None => (authority, 80),
+    };
+    if host.is_empty() {
+        return Err(format!("endpoint {} has no host", s));
+    }
+    let path = path.trim_end_matches('/');
+    let path = if path.ends_with("/v1/traces") {
+        path.to_string()
+    } else {
+        format!("{}/v1/traces", path)
+    };
+    Ok(Endpoint {
+        host: host.to_string(),
+        port,
+        path,
+    })
+}
+
+/// Exports one span per job, and a root span for the run, to an OpenTelemetry collector
+///
+/// Spans are encoded in the OTLP JSON format and posted in batches from an exporter thread, so
+/// a slow collector never holds up a worker; export errors are reported once and further
+/// spans dropped.
+pub struct Tracer {
+    trace_id: String,
+    start: SystemTime,
+    spans: Mutex<Option<mpsc::Sender<String>>>,
+    exporter: Mutex<Option<JoinHandle<()>>>,
+}
+
+/// A finished job as exported to the collector
+pub struct JobSpan<'a> {
+    pub seq: usize,
+    pub start: SystemTime,
+    pub runtime: Duration,
+    pub exit: Option<i32>,
+    pub error: Option<&'a str>,
+    pub worker: usize,
+    pub host: Option<&'a str>,
+    pub input: &'a str,
+}
+
+impl Tracer {
+    pub fn new(endpoint: Endpoint) -> Self {
+        let start = SystemTime::now();
+        let mut hasher = Sha256::default();
+        hasher.update(&nanos(start).to_le_bytes());
+        hasher.update(&std::process::id().to_le_bytes());
+        let trace_id = sha256::hex(&hasher.finalize()[..16]);
+
+        let (spans, queued) = mpsc::channel::<String>();
+        let exporter = thread::spawn(move || {
+            let mut failed = false;
+            while let Ok(span) = queued.recv() {
+                let mut batch = vec![span];
+                while batch.len() < BATCH
+                    && let Ok(span) = queued.try_recv()
+                {
+                    batch.push(span);
+                }
+                if failed {
+                    continue;
+                }
+                if let Err(e) = post(&endpoint, &request(&batch)) {
+                    eprintln!(
+                        "error exporting spans to http://{}:{}{}: {}",
+                        endpoint.host, endpoint.port, endpoint.path, e
+                    );
+                    failed = true;
+                }
+            }
+        });
+        Tracer {
+            trace_id,
+            start,
+            spans: Mutex::new(Some(spans)),
+            exporter: Mutex::new(Some(exporter)),
+        }
+    }
+
+    fn span_id(&self, index: usize) -> String {
+        let mut hasher = Sha256::default();
+        hasher.update(self.trace_id.as_bytes());
+        hasher.update(&index.to_le_bytes());
+        sha256::hex(&hasher.finalize()[..8])
+    }
+
+    fn send(&self, span: String) {
+        if let Some(spans) = &*self.spans.lock().unwrap_or_else(PoisonError::into_inner) {
+            let _ = spans.send(span);
+        }
+    }
+
+    pub fn job(&self, job: &JobSpan) {
+        let mut attributes = vec![
+            string("kyanite.input", job.input),
+            int("kyanite.seq", job.seq as i64),
+            int("kyanite.worker", job.worker as i64),
+        ];
+        if let Some(exit) = job.exit {
+            attributes.push(int("process.exit_code", i64::from(exit)));
+        }
+        if let Some(host) = job.host {
+            attributes.push(string("host.name", host));
+        }
+        let name = format!("job {}", job.seq);
+        let times = (job.start, job.start + job.runtime);
+        self.send(self.span(job.seq, &name, times, &attributes, job.error));
+    }
+
+    /// Exports the root span of the run and waits for every span to be sent
+    pub fn finish(&self, template: &str, succeeded: usize, failed: usize) {
+        let attributes = [
+            string("kyanite.template", template),
+            int("kyanite.succeeded", succeeded as i64),
+            int("kyanite.failed", failed as i64),
+        ];
+        let error = (failed > 0).then(|| format!("{} jobs failed", failed));
+        let times = (self.start, SystemTime::now());
+        self.send(self.span(ROOT, "kyanite run", times, &attributes, error.as_deref()));
+        self.spans
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(exporter) = self
+            .exporter
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+        {
+            let _ = exporter.join();
+        }
+    }
+
+    /// Encodes span `id` of the trace, as a child of the root span unless it is the root
+    fn span(
+        &self,
+        id: usize,
+        name: &str,
+        (start, end): (SystemTime, SystemTime),
+        attributes: &[String],
+        error: Option<&str>,
+    ) -> String {
+        let parent = match id {
+            ROOT => String::new(),
+            _ => format!("\"parentSpanId\":\"{}\",", self.span_id(ROOT)),
+        };
+        // status codes: 1 is ok, 2 is error
+        let status = match error {
+            None => "{\"code\":1}".to_string(),
+            Some(error) => format!("{{\"code\":2,\"message\":{}}}", json_string(error)),
+        };
+        format!(
+            "{{\"traceId\":\"{}\",\"spanId\":\"{}\",{}\"name\":{},\"kind\":1,\"startTimeUnixNano\":\"{}\",\"endTimeUnixNano\":\"{}\",\"attributes\":[{}],\"status\":{}}}",
+            self.trace_id,
+            self.span_id(id),
+            parent,
+            json_string(name),
+            nanos(start),
+            nanos(end),
+            attributes.join(","),
+            status
+        )
+    }
+}
+
+fn nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+fn string(key: &str, value: &str) -> String {
+    format!(
+        "{{\"key\":\"{}\",\"value\":{{\"stringValue\":{}}}}}",
+        key,
+        json_string(value)
+    )
+}
+
+fn int(key: &str, value: i64) -> String {
+    // OTLP JSON carries 64-bit integers as strings
+    format!(
+        "{{\"key\":\"{}\",\"value\":{{\"intValue\":\"{}\"}}}}",
+        key, value
+    )
+}
+
+/// Wraps spans in an OTLP export request from the `kyanite` service
+fn request(spans: &[String]) -> String {
+    format!(
+        "{{\"resourceSpans\":[{{\"resource\":{{\"attributes\":[{}]}},\"scopeSpans\":[{{\"scope\":{{\"name\":\"kyanite\"}},\"spans\":[{}]}}]}}]}}",
+        string("service.name", "kyanite"),
+        spans.join(",")
+    )
+}
+
+fn post(endpoint: &Endpoint, body: &str) -> io::Result<()> {
+    let mut stream = TcpStream::connect((endpoint.host.as_str(), endpoint.port))?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        endpoint.path,
+        endpoint.host,
+        endpoint.port,
+        body.len(),
+        body
+    )?;
+    let mut status = String::new();
+    BufReader::new(stream).read_line(&mut status)?;
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(io::Error::other(format!(
+            "collector answered {:?}",
+            status.trim_end()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_parse_endpoint() {
+        let endpoint = |host: &str, port, path: &str| Endpoint {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        };
+        assert_eq!(
+            parse_endpoint("http://localhost:4318"),
+            Ok(endpoint("localhost", 4318, "/v1/traces"))
+        );
+        assert_eq!(
+            parse_endpoint("http://otel/v1/traces"),
+            Ok(endpoint("otel", 80, "/v1/traces"))
+        );
+        assert_eq!(
+            parse_endpoint("http://otel:4318/prefix/"),
+            Ok(endpoint("otel", 4318, "/prefix/v1/traces"))
+        );
+        assert!(parse_endpoint("https://otel:4318").is_err());
+        assert!(parse_endpoint("http://otel:x").is_err());
+        assert!(parse_endpoint("http://:4318").is_err());
+    }
+
+    #[test]
+    fn test_exports_job_and_root_spans() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // the spans may arrive in one request or two
+        let collector = thread::spawn(move || {
+            let mut requests = String::new();
+            while !requests.contains("kyanite run") {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                loop {
+                    let read = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some((head, body)) = text.split_once("\r\n\r\n")
+                        && let Some(length) = head
+                            .lines()
+                            .find_map(|line| line.strip_prefix("Content-Length: "))
+                        && body.len() >= length.parse().unwrap()
+                    {
+                        break;
+                    }
+                }
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                    .unwrap();
+                requests.push_str(&String::from_utf8(request).unwrap());
+            }
+            requests
+        });
+
+        let tracer = Tracer::new(parse_endpoint(&format!("http://127.0.0.1:{}", port)).unwrap());
+        tracer.job(&JobSpan {
+            seq: 1,
+            start: SystemTime::now(),
+            runtime: Duration::from_millis(5),
+            exit: Some(2),
+            error: Some("exit status 2"),
+            worker: 0,
+            host: Some("build-1"),
+            input: "a.txt",
+        });
+        tracer.finish("gzip {}", 0, 1);
+
+        let request = collector.join().unwrap();
+        assert!(request.starts_with("POST /v1/traces HTTP/1.1\r\n"));
+        let root = tracer.span_id(ROOT);
+        assert!(request.contains(&format!("\"parentSpanId\":\"{}\",\"name\":\"job 1\"", root)));
+        assert!(request.contains(&format!("\"spanId\":\"{}\",\"name\":\"kyanite run\"", root)));
+        assert!(request.contains(
+            r#"{"key":"process.exit_code","value":{"intValue":"2"}},{"key":"host.name","value":{"stringValue":"build-1"}}"#
+        ));
+        assert!(request.contains(r#""status":{"code":2,"message":"exit status 2"}"#));
+        assert_eq!(request.matches(&tracer.trace_id).count(), 2);
+    }
+}
@@ -0,0 +1,169 @@
+use crate::compress;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Splits a file into byte ranges of about `size` bytes for `--pipe-part`, each ending after a
+/// `--recend` match, as `PATH:START-END` descriptors
+///
+/// The ranges depend only on the file's contents, the block size and the record end, so a
+/// rerun over the same file yields the same descriptors and `kyanite diff` can tell from the
+/// job log which ranges are done.
+pub struct Parts {
+    path: String,
+    file: File,
+    len: u64,
+    pos: u64,
+    size: u64,
+    recend: regex::bytes::Regex,
+}
+
+impl Parts {
+    pub fn open(path: &str, size: u64, recend: regex::bytes::Regex) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        // the ranges are offsets into the file, which are not record ends of what a compressed
+        // file holds
+        let mut header = Vec::new();
+        (&file).take(8).read_to_end(&mut header)?;
+        if let Some(format) = compress::Format::detect(&header) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "--pipe-part cannot split {}-compressed input; decompress it first or use --pipe",
+                    format.program()
+                ),
+            ));
+        }
+        Ok(Parts {
+            path: path.to_string(),
+            file,
+            len,
+            pos: 0,
+            size: size.max(1),
+            recend,
+        })
+    }
+
+    /// The end of the first record end reaching `from` or past it, or the end of the file
+    fn boundary(&mut self, from: u64) -> io::Result<u64> {
+        // a match may end right at `from`, so the search starts a byte before it
+        let base = from - 1;
+        self.file.seek(SeekFrom::Start(base))?;
+        let mut buffer = Vec::new();
+        loop {
+            let filled = buffer.len();
+            buffer.resize(filled + 64 * 1024, 0);
+            let read = self.file.read(&mut buffer[filled..])?;
+            buffer.truncate(filled + read);
+            let eof = read == 0;
+            // a match touching the end of the buffer may continue in data not read yet
+            let found = self
+                .recend
+                .find_iter(&buffer)
+                .find(|found| found.end() >= 1 && (found.end() < buffer.len() || eof));
+            if let Some(found) = found {
+                return Ok(base + found.end() as u64);
+            }
+            if eof {
+                return Ok(self.len);
+            }
+        }
+    }
+}
+
+impl Iterator for Parts {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.len {
+            return None;
+        }
+        let start = self.pos;
+        let end = if self.len - start <= self.size {
+            self.len
+        } else {
+            match self.boundary(start + self.size) {
+                Ok(end) => end.min(self.len),
+                Err(e) => {
+                    self.pos = self.len;
+                    return Some(Err(e));
+                }
+            }
+        };
+        self.pos = end;
+        Some(Ok(format!("{}:{}-{}", self.path, start, end)))
+    }
+}
+
+/// The path and byte range of a `PATH:START-END` descriptor
+pub fn parse(descriptor: &str) -> Option<(&str, u64, u64)> {
+    let (path, range) = descriptor.rsplit_once(':')?;
+    let (start, end) = range.split_once('-')?;
+    let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+    (start <= end).then_some((path, start, end))
+}
+
+/// Reads the bytes a descriptor names, to feed a job's stdin
+pub fn read(descriptor: &str) -> io::Result<Vec<u8>> {
+    let Some((path, start, end)) = parse(descriptor) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "not a PATH:START-END range",
+        ));
+    };
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut data = Vec::new();
+    file.take(end - start).read_to_end(&mut data)?;
+    if data.len() as u64 != end - start {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "range runs past the end of the file",
+        ));
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_parts_cover_file_at_record_ends() {
+        let path = std::env::temp_dir().join(format!("kyanite-part-{}", std::process::id()));
+        let path = path.display().to_string();
+        let parts = |contents: &str, size, recend| {
+            fs::write(&path, contents).unwrap();
+            let recend = regex::bytes::Regex::new(recend).unwrap();
+            let parts: Vec<String> = Parts::open(&path, size, recend)
+                .unwrap()
+                .map(Result::unwrap)
+                .collect();
+            let data: Vec<Vec<u8>> = parts.iter().map(|part| read(part).unwrap()).collect();
+            assert_eq!(data.concat(), contents.as_bytes());
+            parts
+                .iter()
+                .map(|part| part.strip_prefix(&path).unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            parts("aa\nbb\ncccccc\nd\n", 5, r"\n"),
+            [":0-6", ":6-13", ":13-15"]
+        );
+        assert_eq!(parts("abcd\nefgh\n", 5, r"\n"), [":0-5", ":5-10"]);
+        assert_eq!(parts("r1;r2;r3;", 4, ";"), [":0-6", ":6-9"]);
+        assert_eq!(parts("no record end", 4, r"\n"), [":0-13"]);
+        assert_eq!(parts("", 4, r"\n"), Vec::<String>::new());
+
+        assert_eq!(parse("a:b.txt:3-7"), Some(("a:b.txt", 3, 7)));
+        assert_eq!(parse("a.txt:7-3"), None);
+        assert!(read(&format!("{}:0-99", path)).is_err());
+
+        fs::write(&path, [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 3]).unwrap();
+        let recend = regex::bytes::Regex::new(r"\n").unwrap();
+        let error = Parts::open(&path, 4, recend).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,75 @@
+use std::fs;
+use std::path::Path;
+
+/// Degrees below `--max-temp` the hottest sensor must cool to before jobs start again
+pub const COOLDOWN: f64 = 5.0;
+
+/// The hottest reading of any hwmon sensor, in degrees Celsius
+pub fn max_temperature() -> Option<f64> {
+    let mut hottest = None;
+    for hwmon in fs::read_dir("/sys/class/hwmon").ok()?.flatten() {
+        let Ok(entries) = fs::read_dir(hwmon.path()) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with("temp")
+                && name.ends_with("_input")
+                && let Some(degrees) = read_millidegrees(&entry.path())
+            {
+                hottest = Some(degrees.max(hottest.unwrap_or(f64::MIN)));
+            }
+        }
+    }
+    hottest
+}
+
+fn read_millidegrees(path: &Path) -> Option<f64> {
+    let millidegrees: i64 = fs::read_to_string(path).ok()?.trim().parse().ok()?;
+    Some(millidegrees as f64 / 1000.0)
+}
+
+/// Whether the machine runs on battery: no mains supply is online and a battery is discharging
+pub fn on_battery() -> bool {
+    let mut supplies = Vec::new();
+    if let Ok(entries) = fs::read_dir("/sys/class/power_supply") {
+        for entry in entries.flatten() {
+            let read = |name: &str| {
+                fs::read_to_string(entry.path().join(name))
+                    .map(|value| value.trim().to_string())
+                    .unwrap_or_default()
+            };
+            supplies.push((read("type"), read("online"), read("status")));
+        }
+    }
+    discharging(&supplies)
+}
+
+/// Decides [`on_battery`] from each supply's `type`, `online` and `status`
+fn discharging(supplies: &[(String, String, String)]) -> bool {
+    let mains = supplies
+        .iter()
+        .any(|(kind, online, _)| kind == "Mains" && online == "1");
+    let battery = supplies
+        .iter()
+        .any(|(kind, _, status)| kind == "Battery" && status == "Discharging");
+    !mains && battery
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_on_battery() {
+        let supply = |kind: &str, online: &str, status: &str| {
+            (kind.to_string(), online.to_string(), status.to_string())
+        };
+        let battery = supply("Battery", "", "Discharging");
+        assert!(discharging(&[supply("Mains", "0", ""), battery.clone()]));
+        assert!(!discharging(&[supply("Mains", "1", ""), battery]));
+        assert!(!discharging(&[supply("Battery", "", "Charging")]));
+        assert!(!discharging(&[]));
+    }
+}
@@ -0,0 +1,123 @@
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Mutex, PoisonError};
+
+/// Guards `ptsname`, which returns a pointer to static storage
+static PTSNAME: Mutex<()> = Mutex::new(());
+
+/// Spawns a command with a new pseudo-terminal as its controlling terminal, stdout and stderr,
+/// returning the child along with the master side to read its output from
+pub fn spawn(mut command: Command) -> io::Result<(Child, File)> {
+    let (master, slave) = open()?;
+    command
+        .stdin(Stdio::null())
+        .stdout(slave.try_clone()?)
+        .stderr(slave);
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() == -1 || libc::ioctl(1, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let child = command.spawn()?;
+    Ok((child, master))
+}
+
+/// Reads everything the job writes to its terminal until every slave descriptor is closed
+pub fn read_output(master: &mut File) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut buffer = [0; 8192];
+    loop {
+        match master.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => output.extend_from_slice(&buffer[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            // Linux reports EIO on the master once the last slave descriptor is closed
+            Err(e) if e.raw_os_error() == Some(libc::EIO) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(output)
+}
+
+/// Opens a new pseudo-terminal pair sized like kyanite's own terminal, or 80x24
+fn open() -> io::Result<(File, File)> {
+    let fd = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let master = unsafe { File::from_raw_fd(fd) };
+    unsafe {
+        if libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) == -1
+            || libc::grantpt(fd) != 0
+            || libc::unlockpt(fd) != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    let path = {
+        let _guard = PTSNAME.lock().unwrap_or_else(PoisonError::into_inner);
+        let name = unsafe { libc::ptsname(fd) };
+        if name.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        unsafe { CStr::from_ptr(name) }
+            .to_string_lossy()
+            .into_owned()
+    };
+    let slave = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(path)?;
+    configure(&slave);
+    Ok((master, slave))
+}
+
+/// Keeps `\n` line endings in captured output and sets the terminal size
+fn configure(slave: &File) {
+    let fd = slave.as_raw_fd();
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut termios) == 0 {
+            termios.c_oflag &= !libc::ONLCR;
+            libc::tcsetattr(fd, libc::TCSANOW, &termios);
+        }
+
+        let mut size: libc::winsize = std::mem::zeroed();
+        if libc::ioctl(libc::STDERR_FILENO, libc::TIOCGWINSZ, &mut size) != 0 || size.ws_col == 0 {
+            size.ws_row = 24;
+            size.ws_col = 80;
+        }
+        libc::ioctl(fd, libc::TIOCSWINSZ, &size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_sees_a_terminal() {
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg("[ -t 1 ] && [ -t 2 ] && echo tty; echo err >&2; stty size </dev/tty");
+        let (mut child, mut master) = spawn(command).unwrap();
+        let output = read_output(&mut master).unwrap();
+        assert!(child.wait().unwrap().success());
+
+        let output = String::from_utf8_lossy(&output);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[..2], ["tty", "err"]);
+        assert!(!output.contains('\r'));
+    }
+}
@@ -0,0 +1,158 @@
+use std::fs;
+
+/// Headroom `--reserve-cpus` and `--reserve-mem` keep free for interactive use
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reservation {
+    pub cpus: usize,
+    pub memory: u64,
+}
+
+/// Idle CPUs and available memory, as last measured
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Headroom {
+    pub idle_cpus: f64,
+    pub available: u64,
+}
+
+impl Reservation {
+    pub fn violated(&self, headroom: Headroom) -> bool {
+        headroom.idle_cpus < self.cpus as f64 || headroom.available < self.memory
+    }
+
+    /// Whether there is room to resume a paused job: a CPU and an eighth of the reserved memory
+    /// beyond the reservation, so a job is not paused again as soon as it resumes
+    pub fn restored(&self, headroom: Headroom) -> bool {
+        let cpus = if self.cpus > 0 { self.cpus + 1 } else { 0 };
+        headroom.idle_cpus >= cpus as f64 && headroom.available >= self.memory + self.memory / 8
+    }
+}
+
+/// Measures headroom from `/proc`, CPU time as the share idle since the previous measurement
+#[derive(Default)]
+pub struct Meter {
+    last: Option<(u64, u64)>,
+}
+
+impl Meter {
+    /// Returns the headroom, or None on the first call and when `/proc` cannot be read
+    pub fn measure(&mut self, cpus: usize) -> Option<Headroom> {
+        let times = parse_cpu_times(&fs::read_to_string("/proc/stat").ok()?)?;
+        let available = parse_available(&fs::read_to_string("/proc/meminfo").ok()?)?;
+        let (idle, total) = times;
+        let (last_idle, last_total) = self.last.replace(times)?;
+        let elapsed = total.saturating_sub(last_total).max(1);
+        let idle_share = idle.saturating_sub(last_idle) as f64 / elapsed as f64;
+        Some(Headroom {
+            idle_cpus: idle_share * cpus as f64,
+            available,
+        })
+    }
+}
+
+/// Idle and total jiffies over all CPUs from the `cpu` line of `/proc/stat`; waiting for I/O
+/// counts as idle
+fn parse_cpu_times(stat: &str) -> Option<(u64, u64)> {
+    let line = stat.lines().find(|line| line.starts_with("cpu "))?;
+    let times: Vec<u64> = line
+        .split_whitespace()
+        .skip(1)
+        .take(8)
+        .map(|field| field.parse().ok())
+        .collect::<Option<_>>()?;
+    let idle = times.get(3)? + times.get(4).copied().unwrap_or(0);
+    Some((idle, times.iter().sum()))
+}
+
+/// `MemAvailable` from `/proc/meminfo`, in bytes
+fn parse_available(meminfo: &str) -> Option<u64> {
+    let line = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))?;
+    let kib: u64 = line.trim().strip_suffix("kB")?.trim().parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// A process and all its descendants, parents first, so a job's whole command can be paused
+pub fn process_tree(pid: u32) -> Vec<u32> {
+    let mut parents = Vec::new();
+    if let Ok(entries) = fs::read_dir("/proc") {
+        for entry in entries.flatten() {
+            let Some(child) = entry
+                .file_name()
+                .to_str()
+                .and_then(|s| s.parse::<u32>().ok())
+            else {
+                continue;
+            };
+            if let Ok(stat) = fs::read_to_string(entry.path().join("stat"))
+                && let Some(parent) = parse_parent(&stat)
+            {
+                parents.push((child, parent));
+            }
+        }
+    }
+    let mut tree = vec![pid];
+    let mut i = 0;
+    while i < tree.len() {
+        let parent = tree[i];
+        tree.extend(
+            parents
+                .iter()
+                .filter(|&&(_, p)| p == parent)
+                .map(|&(child, _)| child),
+        );
+        i += 1;
+    }
+    tree
+}
+
+/// Sends `signal` to a process and all its descendants
+pub fn signal_tree(pid: u32, signal: libc::c_int) {
+    for pid in process_tree(pid) {
+        unsafe {
+            libc::kill(pid as libc::pid_t, signal);
+        }
+    }
+}
+
+/// The parent pid in `/proc/<pid>/stat`, after the command name, which may contain spaces
+fn parse_parent(stat: &str) -> Option<u32> {
+    let (_, rest) = stat.rsplit_once(')')?;
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proc() {
+        let stat = "cpu  100 5 50 800 20 0 5 0 0 0\ncpu0 50 2 25 400 10 0 2 0 0 0\n";
+        assert_eq!(parse_cpu_times(stat), Some((820, 980)));
+        let meminfo = "MemTotal:       16000000 kB\nMemAvailable:    4000000 kB\n";
+        assert_eq!(parse_available(meminfo), Some(4_096_000_000));
+        assert_eq!(parse_available("MemTotal: 1 kB\n"), None);
+        assert_eq!(
+            parse_parent("4242 (my (odd) cmd) S 17 4242 4242 0 -1"),
+            Some(17)
+        );
+    }
+
+    #[test]
+    fn test_reservation_hysteresis() {
+        let reservation = Reservation {
+            cpus: 2,
+            memory: 800,
+        };
+        let headroom = |idle_cpus, available| Headroom {
+            idle_cpus,
+            available,
+        };
+        assert!(reservation.violated(headroom(1.5, 4000)));
+        assert!(reservation.violated(headroom(4.0, 700)));
+        assert!(!reservation.violated(headroom(2.5, 850)));
+        assert!(!reservation.restored(headroom(2.5, 4000)));
+        assert!(!reservation.restored(headroom(3.0, 850)));
+        assert!(reservation.restored(headroom(3.0, 900)));
+    }
+}
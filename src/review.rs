@@ -0,0 +1,171 @@
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, Write};
+use std::process::Output;
+
+/// A failed job kept for `--review`
+#[derive(Debug)]
+pub struct FailedJob {
+    /// The job's sequence number, as `{#}` has it
+    pub seq: usize,
+    pub command: String,
+    pub output: String,
+    pub error: String,
+}
+
+/// Walks through failed jobs interactively, returning how many were fixed by a retry
+pub fn review(
+    failed: Vec<FailedJob>,
+    input: &mut impl BufRead,
+    out: &mut impl Write,
+    run: impl Fn(&str) -> io::Result<Output>,
+) -> io::Result<usize> {
+    let mut fixed = 0;
+    writeln!(out, "\n{} failed jobs to review", failed.len())?;
+
+    'jobs: for mut job in failed {
+        loop {
+            writeln!(out, "\n[job {}] {}", job.seq, job.error)?;
+            writeln!(out, "  command: {}", job.command)?;
+            if !job.output.is_empty() {
+                writeln!(out, "  output: {}", job.output)?;
+            }
+            let Some(action) = prompt(
+                input,
+                out,
+                "(r)etry, (e)dit and retry, (s)kip, (d)ump to file, (q)uit? ",
+            )?
+            else {
+                break 'jobs;
+            };
+
+            match action.as_str() {
+                "r" | "retry" => {}
+                "e" | "edit" => {
+                    let Some(command) = prompt(input, out, "command: ")? else {
+                        break 'jobs;
+                    };
+                    if !command.is_empty() {
+                        job.command = command;
+                    }
+                }
+                "" | "s" | "skip" => continue 'jobs,
+                "d" | "dump" => {
+                    let Some(path) = prompt(input, out, "file: ")? else {
+                        break 'jobs;
+                    };
+                    match dump(&job, &path) {
+                        Ok(()) => writeln!(out, "job {} written to {}", job.seq, path)?,
+                        Err(e) => writeln!(out, "error writing {}: {}", path, e)?,
+                    }
+                    continue 'jobs;
+                }
+                "q" | "quit" => break 'jobs,
+                other => {
+                    writeln!(out, "unknown action: {}", other)?;
+                    continue;
+                }
+            }
+
+            match run(&job.command) {
+                Ok(output) => {
+                    let combined = format!(
+                        "{}{}",
+                        String::from_utf8_lossy(&output.stdout),
+                        String::from_utf8_lossy(&output.stderr)
+                    );
+                    if !combined.trim_end().is_empty() {
+                        writeln!(out, "{}", combined.trim_end())?;
+                    }
+                    if output.status.success() {
+                        writeln!(out, "job {} succeeded", job.seq)?;
+                        fixed += 1;
+                        continue 'jobs;
+                    }
+                    job.error = format!("command failed with exit code: {}", output.status);
+                    job.output = combined.trim_end().to_string();
+                }
+                Err(e) => job.error = format!("failed to execute command: {}", e),
+            }
+        }
+    }
+
+    Ok(fixed)
+}
+
+fn prompt(
+    input: &mut impl BufRead,
+    out: &mut impl Write,
+    text: &str,
+) -> io::Result<Option<String>> {
+    write!(out, "{}", text)?;
+    out.flush()?;
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim().to_string()))
+}
+
+/// Appends a failed job as a shell snippet, with its error and output as comments
+fn dump(job: &FailedJob, path: &str) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "# job {}: {}", job.seq, job.error)?;
+    for line in job.output.lines() {
+        writeln!(file, "# {}", line)?;
+    }
+    writeln!(file, "{}", job.command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn failed(seq: usize, command: &str) -> FailedJob {
+        FailedJob {
+            seq,
+            command: command.to_string(),
+            output: "boom".to_string(),
+            error: "command failed with exit code: exit status: 1".to_string(),
+        }
+    }
+
+    fn sh(command: &str) -> io::Result<Output> {
+        Command::new("sh").arg("-c").arg(command).output()
+    }
+
+    #[test]
+    fn test_review_retry_edit_and_skip() {
+        let jobs = vec![failed(0, "false"), failed(1, "false"), failed(2, "false")];
+        let mut input = "r\ne\necho fixed\n\n".as_bytes();
+        let mut out = Vec::new();
+
+        let fixed = review(jobs, &mut input, &mut out, sh).unwrap();
+        assert_eq!(fixed, 1);
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("3 failed jobs to review"));
+        assert_eq!(out.matches("[job 0]").count(), 2);
+        assert!(out.contains("fixed\njob 0 succeeded"));
+        assert!(out.contains("[job 1]"));
+        let last = out.rsplit("\n[job ").next().unwrap();
+        assert!(last.starts_with("2] "));
+        assert!(last.ends_with("(q)uit? "));
+    }
+
+    #[test]
+    fn test_review_dump_and_quit() {
+        let path = std::env::temp_dir().join(format!("kyanite-test-review-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let jobs = vec![failed(4, "convert a.png"), failed(5, "false")];
+        let input = format!("d\n{}\nq\n", path.display());
+        let mut out = Vec::new();
+
+        let fixed = review(jobs, &mut input.as_bytes(), &mut out, sh).unwrap();
+        assert_eq!(fixed, 0);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "# job 4: command failed with exit code: exit status: 1\n# boom\nconvert a.png\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,83 @@
+use std::fs::File;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+/// Which of `slots` a key goes to, the same for the same key throughout a run
+pub fn slot(key: &str, slots: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    (hasher.finish() % slots as u64) as usize
+}
+
+/// Spreads job output across the `--split-output` files `PREFIX.0` to `PREFIX.N-1`
+///
+/// Each job's output goes whole to one file, flushed as it is written so downstream consumers
+/// see results as jobs complete.
+pub struct Splitter {
+    files: Vec<BufWriter<File>>,
+    next: usize,
+}
+
+impl Splitter {
+    pub fn create(prefix: &str, count: usize) -> io::Result<Self> {
+        let files = paths(prefix, count)
+            .into_iter()
+            .map(|path| {
+                File::create(&path)
+                    .map(BufWriter::new)
+                    .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(Splitter { files, next: 0 })
+    }
+
+    /// Writes one job's output to the file its key hashes to, or without a key to the next
+    /// file in turn
+    pub fn write(&mut self, key: Option<&str>, output: &str) -> io::Result<()> {
+        let index = match key {
+            Some(key) => slot(key, self.files.len()),
+            None => {
+                let index = self.next;
+                self.next = (self.next + 1) % self.files.len();
+                index
+            }
+        };
+        let file = &mut self.files[index];
+        writeln!(file, "{}", output)?;
+        file.flush()
+    }
+}
+
+pub fn paths(prefix: &str, count: usize) -> Vec<PathBuf> {
+    (0..count)
+        .map(|i| PathBuf::from(format!("{}.{}", prefix, i)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_round_robin_and_keyed() {
+        let dir = std::env::temp_dir().join(format!("kyanite-split-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let prefix = dir.join("part").display().to_string();
+        let mut splitter = Splitter::create(&prefix, 2).unwrap();
+        for output in ["a", "b", "c\nd"] {
+            splitter.write(None, output).unwrap();
+        }
+        for _ in 0..2 {
+            splitter.write(Some("key"), "k").unwrap();
+        }
+        let read = |path: &PathBuf| fs::read_to_string(path).unwrap();
+        let contents: Vec<String> = paths(&prefix, 2).iter().map(read).collect();
+        let keyed = slot("key", 2);
+        let mut expected = vec!["a\nc\nd\n".to_string(), "b\n".to_string()];
+        expected[keyed].push_str("k\nk\n");
+        assert_eq!(contents, expected);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,251 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// Named pipe that `--status-fifo` writes progress lines to
+pub struct StatusFifo {
+    pipe: Mutex<Pipe>,
+}
+
+enum Pipe {
+    Waiting,
+    Open(File),
+    Closed,
+}
+
+impl StatusFifo {
+    pub fn new() -> Self {
+        StatusFifo {
+            pipe: Mutex::new(Pipe::Waiting),
+        }
+    }
+
+    /// Opens the pipe for writing, creating it first if needed; blocks until a reader opens it
+    pub fn open(&self, path: &Path) -> io::Result<()> {
+        #[cfg(unix)]
+        if !path.exists() {
+            make_fifo(path)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut pipe = self.pipe.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Pipe::Waiting = *pipe {
+            *pipe = Pipe::Open(file);
+        }
+        Ok(())
+    }
+
+    /// Writes a status line, returning false once the pipe is closed or its reader went away
+    pub fn write(&self, line: &str) -> bool {
+        let mut pipe = self.pipe.lock().unwrap_or_else(PoisonError::into_inner);
+        match &mut *pipe {
+            Pipe::Waiting => true,
+            Pipe::Open(file) => {
+                if writeln!(file, "{}", line)
+                    .and_then(|_| file.flush())
+                    .is_err()
+                {
+                    *pipe = Pipe::Closed;
+                    return false;
+                }
+                true
+            }
+            Pipe::Closed => false,
+        }
+    }
+
+    /// Writes the final status line and closes the pipe, so the reader sees end of file
+    pub fn finish(&self, line: &str) {
+        self.write(line);
+        *self.pipe.lock().unwrap_or_else(PoisonError::into_inner) = Pipe::Closed;
+    }
+}
+
+#[cfg(unix)]
+fn make_fifo(path: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    if unsafe { libc::mkfifo(path.as_ptr(), 0o600) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Formats one status line, e.g. `done=12 total=40 failed=1 rate=2.40/s`
+pub fn line(done: usize, total: Option<usize>, failed: usize, elapsed: Duration) -> String {
+    let total = total.map_or("?".to_string(), |total| total.to_string());
+    let secs = elapsed.as_secs_f64();
+    let rate = if secs > 0.0 { done as f64 / secs } else { 0.0 };
+    format!(
+        "done={} total={} failed={} rate={:.2}/s",
+        done, total, failed, rate
+    )
+}
+
+/// Formats the `--bar` and `--eta` progress line, e.g.
+/// `[=====>         ] 12/40 30% | 3 running, 1 failed | 2.40/s | ETA 0:11`, or while the total
+/// is not known yet, `12 done | 3 running, 1 failed | 2.40/s`
+pub fn progress(
+    done: usize,
+    total: Option<usize>,
+    running: usize,
+    failed: usize,
+    elapsed: Duration,
+    bar: bool,
+) -> String {
+    let secs = elapsed.as_secs_f64();
+    let rate = if secs > 0.0 { done as f64 / secs } else { 0.0 };
+    let counts = format!("{} running, {} failed | {:.2}/s", running, failed, rate);
+    let Some(total) = total else {
+        return format!("{} done | {}", done, counts);
+    };
+    let fraction = if total == 0 {
+        1.0
+    } else {
+        done.min(total) as f64 / total as f64
+    };
+    let eta = match done {
+        0 if total > 0 => "?".to_string(),
+        0 => clock(Duration::ZERO),
+        _ => clock(elapsed.mul_f64(total.saturating_sub(done) as f64 / done as f64)),
+    };
+    let mut line = format!(
+        "{}/{} {:.0}% | {} | ETA {}",
+        done,
+        total,
+        fraction * 100.0,
+        counts,
+        eta
+    );
+    if bar {
+        const WIDTH: usize = 30;
+        let filled = (fraction * WIDTH as f64) as usize;
+        let head = if filled < WIDTH { ">" } else { "" };
+        line = format!(
+            "[{}{}{}] {}",
+            "=".repeat(filled),
+            head,
+            " ".repeat(WIDTH.saturating_sub(filled + head.len())),
+            line
+        );
+    }
+    line
+}
+
+/// A duration as `M:SS`, or `H:MM:SS` from an hour on
+fn clock(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs / 3600 {
+        0 => format!("{}:{:02}", secs / 60, secs % 60),
+        hours => format!("{}:{:02}:{:02}", hours, secs / 60 % 60, secs % 60),
+    }
+}
+
+/// Formats the status a `SIGUSR1` or `SIGINFO` prints: the totals, then what each worker runs
+/// and for how long
+pub fn snapshot(
+    done: usize,
+    failed: usize,
+    queued: usize,
+    elapsed: Duration,
+    workers: &[Option<(String, Duration)>],
+) -> String {
+    let running = workers.iter().flatten().count();
+    let mut text = format!(
+        "status after {:.1}s: {} done ({} failed), {} running, {} queued",
+        elapsed.as_secs_f64(),
+        done,
+        failed,
+        running,
+        queued
+    );
+    for (id, current) in workers.iter().enumerate() {
+        match current {
+            Some((command, since)) => text.push_str(&format!(
+                "\n  worker {}: {:.1}s {}",
+                id,
+                since.as_secs_f64(),
+                command
+            )),
+            None => text.push_str(&format!("\n  worker {}: idle", id)),
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line() {
+        assert_eq!(
+            line(12, Some(40), 1, Duration::from_secs(5)),
+            "done=12 total=40 failed=1 rate=2.40/s"
+        );
+        assert_eq!(
+            line(0, None, 0, Duration::ZERO),
+            "done=0 total=? failed=0 rate=0.00/s"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fifo_is_created_and_closed() {
+        let path = std::env::temp_dir().join(format!("kyanite-test-status-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let status = StatusFifo::new();
+
+        let reader = {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                while !path.exists() {
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+                std::fs::read_to_string(&path).unwrap()
+            })
+        };
+        status.open(&path).unwrap();
+        assert!(status.write("done=1"));
+        status.finish("done=2");
+        assert!(!status.write("done=3"));
+
+        assert_eq!(reader.join().unwrap(), "done=1\ndone=2\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let workers = [
+            Some(("sleep 5".to_string(), Duration::from_millis(3250))),
+            None,
+        ];
+        assert_eq!(
+            snapshot(5, 1, 7, Duration::from_secs(12), &workers),
+            "status after 12.0s: 5 done (1 failed), 1 running, 7 queued\n  worker 0: 3.2s sleep 5\n  worker 1: idle"
+        );
+    }
+
+    #[test]
+    fn test_progress_line() {
+        let elapsed = Duration::from_secs(5);
+        assert_eq!(
+            progress(10, Some(40), 3, 1, elapsed, false),
+            "10/40 25% | 3 running, 1 failed | 2.00/s | ETA 0:15"
+        );
+        assert_eq!(
+            progress(10, Some(40), 3, 1, elapsed, true),
+            "[=======>                      ] 10/40 25% | 3 running, 1 failed | 2.00/s | ETA 0:15"
+        );
+        assert_eq!(
+            progress(10, None, 3, 1, elapsed, true),
+            "10 done | 3 running, 1 failed | 2.00/s"
+        );
+        assert!(progress(0, Some(4), 1, 0, elapsed, true).ends_with("ETA ?"));
+        assert!(progress(4, Some(4), 0, 0, elapsed, true).starts_with("[====="));
+        assert_eq!(clock(Duration::from_secs(3725)), "1:02:05");
+    }
+}
@@ -0,0 +1,104 @@
+/// The syslog facility `--syslog` logs job failures under
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Facility {
+    User,
+    Daemon,
+    Cron,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+/// How bad a job failure is, from its exit code
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// The command ran and reported failure
+    Warning,
+    /// The command was killed by a signal, timed out or could not be started
+    Error,
+    /// The command is not executable or was not found, so every job will fail alike
+    Critical,
+}
+
+pub fn severity(exit: Option<i32>) -> Severity {
+    match exit {
+        Some(126 | 127) => Severity::Critical,
+        Some(code) if (1..128).contains(&code) => Severity::Warning,
+        _ => Severity::Error,
+    }
+}
+
+/// Writes job failures to the system log as `kyanite[PID]`
+pub struct Syslog {
+    #[cfg_attr(not(unix), allow(dead_code))]
+    facility: Facility,
+}
+
+impl Syslog {
+    pub fn open(facility: Facility) -> Self {
+        #[cfg(unix)]
+        unsafe {
+            libc::openlog(c"kyanite".as_ptr(), libc::LOG_PID, facility.code());
+        }
+        Syslog { facility }
+    }
+
+    #[cfg(unix)]
+    pub fn log(&self, severity: Severity, message: &str) {
+        let priority = match severity {
+            Severity::Warning => libc::LOG_WARNING,
+            Severity::Error => libc::LOG_ERR,
+            Severity::Critical => libc::LOG_CRIT,
+        };
+        // syslog takes a C string, so NULs in job output must not cut the message short
+        let message = std::ffi::CString::new(message.replace('\0', " ")).unwrap_or_default();
+        unsafe {
+            libc::syslog(
+                self.facility.code() | priority,
+                c"%s".as_ptr(),
+                message.as_ptr(),
+            );
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn log(&self, _severity: Severity, _message: &str) {}
+}
+
+#[cfg(unix)]
+impl Facility {
+    fn code(self) -> libc::c_int {
+        match self {
+            Facility::User => libc::LOG_USER,
+            Facility::Daemon => libc::LOG_DAEMON,
+            Facility::Cron => libc::LOG_CRON,
+            Facility::Local0 => libc::LOG_LOCAL0,
+            Facility::Local1 => libc::LOG_LOCAL1,
+            Facility::Local2 => libc::LOG_LOCAL2,
+            Facility::Local3 => libc::LOG_LOCAL3,
+            Facility::Local4 => libc::LOG_LOCAL4,
+            Facility::Local5 => libc::LOG_LOCAL5,
+            Facility::Local6 => libc::LOG_LOCAL6,
+            Facility::Local7 => libc::LOG_LOCAL7,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_severity_from_exit_code() {
+        assert_eq!(severity(Some(1)), Severity::Warning);
+        assert_eq!(severity(Some(125)), Severity::Warning);
+        assert_eq!(severity(Some(127)), Severity::Critical);
+        assert_eq!(severity(Some(137)), Severity::Error);
+        assert_eq!(severity(None), Severity::Error);
+    }
+}
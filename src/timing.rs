@@ -0,0 +1,204 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Where one job's time went, for `--time-report`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Timings {
+    /// The program the job ran, which its stages are reported under
+    pub class: String,
+    /// Queued until a worker took it
+    pub queue: Duration,
+    /// Starting the command
+    pub spawn: Duration,
+    /// The command running, retries included
+    pub run: Duration,
+    /// Saving and printing its output
+    pub output: Duration,
+    /// Done but held back until the jobs before it were printed
+    pub reorder: Duration,
+}
+
+impl Timings {
+    fn stages(&self) -> [Duration; 5] {
+        [self.queue, self.spawn, self.run, self.output, self.reorder]
+    }
+}
+
+const STAGES: [&str; 5] = ["queue", "spawn", "run", "output", "reorder"];
+
+/// The class of a command: the name of the program it starts with
+pub fn class(command: &str) -> String {
+    let program = command.split_whitespace().next().unwrap_or_default();
+    program.rsplit('/').next().unwrap_or(program).to_string()
+}
+
+/// Collects the timings of a run's jobs as they pass through the pipeline
+pub struct Clock {
+    queued: Mutex<HashMap<usize, Instant>>,
+    spawning: Vec<Mutex<Duration>>,
+    classes: Mutex<BTreeMap<String, (usize, Timings)>>,
+}
+
+impl Clock {
+    pub fn new(workers: usize) -> Self {
+        Clock {
+            queued: Mutex::new(HashMap::new()),
+            spawning: (0..workers).map(|_| Mutex::new(Duration::ZERO)).collect(),
+            classes: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Notes that a job was queued for the workers
+    pub fn queued(&self, id: usize) {
+        self.queued
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id, Instant::now());
+    }
+
+    /// How long a job a worker just took had been queued
+    pub fn taken(&self, id: usize) -> Duration {
+        self.queued
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&id)
+            .map_or(Duration::ZERO, |queued| queued.elapsed())
+    }
+
+    /// Adds the time a worker took to start a command of its current job
+    pub fn spawned(&self, worker: usize, took: Duration) {
+        if let Some(spawning) = self.spawning.get(worker) {
+            *spawning.lock().unwrap_or_else(PoisonError::into_inner) += took;
+        }
+    }
+
+    /// The time a worker spent starting commands since this was last asked
+    pub fn take_spawn(&self, worker: usize) -> Duration {
+        self.spawning
+            .get(worker)
+            .map_or(Duration::ZERO, |spawning| {
+                std::mem::take(&mut *spawning.lock().unwrap_or_else(PoisonError::into_inner))
+            })
+    }
+
+    pub fn record(&self, timings: Timings) {
+        let mut classes = self.classes.lock().unwrap_or_else(PoisonError::into_inner);
+        let (jobs, total) = classes.entry(timings.class.clone()).or_default();
+        *jobs += 1;
+        total.queue += timings.queue;
+        total.spawn += timings.spawn;
+        total.run += timings.run;
+        total.output += timings.output;
+        total.reorder += timings.reorder;
+    }
+
+    pub fn report(&self, wall: Duration, workers: usize) -> String {
+        report(
+            &self.classes.lock().unwrap_or_else(PoisonError::into_inner),
+            wall,
+            workers,
+        )
+    }
+}
+
+/// Formats the stage totals of each class, what share of the jobs' time each stage took and
+/// which setting the stages point to
+fn report(classes: &BTreeMap<String, (usize, Timings)>, wall: Duration, workers: usize) -> String {
+    let mut text = format!(
+        "time report: {:.2}s wall time with {} workers\n  {:<12} {:>6}",
+        wall.as_secs_f64(),
+        workers,
+        "class",
+        "jobs"
+    );
+    for stage in STAGES {
+        text.push_str(&format!(" {:>9}", stage));
+    }
+    let mut totals = [Duration::ZERO; 5];
+    for (class, (jobs, timings)) in classes {
+        text.push_str(&format!("\n  {:<12} {:>6}", class, jobs));
+        for (total, stage) in totals.iter_mut().zip(timings.stages()) {
+            text.push_str(&format!(" {:>8.2}s", stage.as_secs_f64()));
+            *total += stage;
+        }
+    }
+    let sum: Duration = totals.iter().sum();
+    if sum.is_zero() {
+        text.push_str("\n  no jobs ran");
+        return text;
+    }
+    text.push_str("\n  share:");
+    for (stage, total) in STAGES.iter().zip(totals) {
+        let share = total.as_secs_f64() / sum.as_secs_f64() * 100.0;
+        text.push_str(&format!(" {} {:.0}%", stage, share));
+    }
+    // jobs queue behind the workers whenever there are more of them, so the queue only says
+    // something about -j once the jobs themselves spend their time well
+    let [queue, spawn, run, output, reorder] = totals;
+    let busy = (spawn + run + output + reorder).as_secs_f64();
+    let overhead = [(spawn, "spawn"), (output, "output"), (reorder, "reorder")]
+        .into_iter()
+        .max_by_key(|(total, _)| *total)
+        .filter(|(total, _)| total.as_secs_f64() >= busy * 0.05);
+    let hint = match overhead {
+        Some((_, "spawn")) => {
+            "starting commands is costly next to their work: give each job more, e.g. --pipe"
+        }
+        Some((_, "output")) => {
+            "output handling slows the jobs: write less, or to files with --outfile or --results"
+        }
+        Some(_) => "finished jobs waited to print in order: drop -k, or use --order-by start",
+        None if queue > run => {
+            "jobs mostly waited for a free worker: raise -j if the machine has capacity to spare"
+        }
+        None => "the commands took the time: only faster commands or more machines help",
+    };
+    text.push_str(&format!("\n  {}", hint));
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_points_at_largest_stage() {
+        let clock = Clock::new(2);
+        let secs = Duration::from_secs;
+        for class_of in ["/usr/bin/gzip -9 a", "gzip b", "sha256sum c"] {
+            clock.record(Timings {
+                class: class(class_of),
+                queue: secs(3),
+                spawn: Duration::ZERO,
+                run: secs(6),
+                output: Duration::ZERO,
+                reorder: Duration::ZERO,
+            });
+        }
+        clock.spawned(1, Duration::from_millis(5));
+        assert_eq!(clock.take_spawn(1), Duration::from_millis(5));
+        assert_eq!(clock.take_spawn(1), Duration::ZERO);
+
+        let report = clock.report(secs(20), 2);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], "time report: 20.00s wall time with 2 workers");
+        assert_eq!(
+            lines[2],
+            "  gzip              2     6.00s     0.00s    12.00s     0.00s     0.00s"
+        );
+        assert!(lines[3].starts_with("  sha256sum         1"));
+        assert_eq!(
+            lines[4],
+            "  share: queue 33% spawn 0% run 67% output 0% reorder 0%"
+        );
+        assert!(lines[5].contains("faster commands"));
+
+        clock.record(Timings {
+            class: "gzip".to_string(),
+            spawn: secs(1),
+            ..Timings::default()
+        });
+        assert!(clock.report(secs(20), 2).ends_with("e.g. --pipe"));
+    }
+}
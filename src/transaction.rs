@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+
+/// Groups of `--transaction-size` consecutive jobs, any failure in which rolls the whole group
+/// back with `--rollback`
+pub struct Transactions {
+    size: usize,
+    groups: Mutex<HashMap<usize, Group>>,
+}
+
+#[derive(Default)]
+struct Group {
+    inputs: Vec<(usize, String)>,
+    failed: bool,
+}
+
+impl Transactions {
+    pub fn new(size: usize) -> Self {
+        Transactions {
+            size,
+            groups: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records a finished job, returning its group's number and inputs when it was the group's
+    /// last job and any of them failed; the last group is short when `total` is known
+    pub fn finish(
+        &self,
+        id: usize,
+        input: &str,
+        failed: bool,
+        total: Option<usize>,
+    ) -> Option<(usize, Vec<String>)> {
+        let number = id / self.size;
+        let mut groups = self.groups.lock().unwrap_or_else(PoisonError::into_inner);
+        let group = groups.entry(number).or_default();
+        group.inputs.push((id, input.to_string()));
+        group.failed |= failed;
+        let first = number * self.size;
+        let size = total.map_or(self.size, |total| {
+            self.size.min(total.saturating_sub(first))
+        });
+        if group.inputs.len() < size {
+            return None;
+        }
+        let group = groups.remove(&number)?;
+        group.failed.then(|| (number, inputs(group)))
+    }
+
+    /// Takes the groups with a failure that never finished, as when the run was stopped
+    pub fn unfinished(&self) -> Vec<(usize, Vec<String>)> {
+        let mut groups: Vec<_> = self
+            .groups
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .drain()
+            .filter(|(_, group)| group.failed)
+            .map(|(number, group)| (number, inputs(group)))
+            .collect();
+        groups.sort_by_key(|(number, _)| *number);
+        groups
+    }
+}
+
+/// A group's inputs in input order
+fn inputs(mut group: Group) -> Vec<String> {
+    group.inputs.sort_by_key(|(id, _)| *id);
+    group.inputs.into_iter().map(|(_, input)| input).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failed_groups_roll_back() {
+        let transactions = Transactions::new(2);
+        assert_eq!(transactions.finish(1, "b", true, None), None);
+        assert_eq!(
+            transactions.finish(0, "a", false, None),
+            Some((0, vec!["a".to_string(), "b".to_string()]))
+        );
+        assert_eq!(transactions.finish(2, "c", false, None), None);
+        assert_eq!(transactions.finish(3, "d", false, None), None);
+        // the last group of five jobs holds one
+        assert_eq!(
+            transactions.finish(4, "e", true, Some(5)),
+            Some((2, vec!["e".to_string()]))
+        );
+
+        assert_eq!(transactions.finish(6, "g", true, None), None);
+        assert_eq!(transactions.finish(8, "i", false, None), None);
+        assert_eq!(transactions.unfinished(), vec![(3, vec!["g".to_string()])]);
+    }
+}
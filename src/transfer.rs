@@ -0,0 +1,116 @@
+use std::io;
+use std::process::{Command, Stdio};
+use std::sync::{Condvar, Mutex, PoisonError};
+
+/// Copies each job's input file to the remote host that runs it, for `--transfer`
+///
+/// Transfers run inside the workers, so staging one job's file overlaps with other jobs
+/// executing; at most `limit` run at once, each capped at `bwlimit` KiB/s.
+pub struct Transfers {
+    limit: usize,
+    bwlimit: Option<u64>,
+    active: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl Transfers {
+    pub fn new(limit: usize, bwlimit: Option<u64>) -> Self {
+        Transfers {
+            limit: limit.max(1),
+            bwlimit,
+            active: Mutex::new(0),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Copies `path` to the same relative path on `host`, waiting for a free transfer slot
+    pub fn send(&self, host: &str, path: &str) -> io::Result<()> {
+        self.acquire();
+        let status = self
+            .command(host, path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .status();
+        self.release();
+
+        let status = status?;
+        if !status.success() {
+            return Err(io::Error::other(format!("rsync failed with {}", status)));
+        }
+        Ok(())
+    }
+
+    fn acquire(&self) {
+        let mut active = self.active.lock().unwrap_or_else(PoisonError::into_inner);
+        while *active >= self.limit {
+            active = self
+                .freed
+                .wait(active)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        *active += 1;
+    }
+
+    fn release(&self) {
+        *self.active.lock().unwrap_or_else(PoisonError::into_inner) -= 1;
+        self.freed.notify_one();
+    }
+
+    fn command(&self, host: &str, path: &str) -> Command {
+        let mut command = Command::new("rsync");
+        command.args(["--archive", "--relative"]);
+        if let Some(bwlimit) = self.bwlimit {
+            command.arg(format!("--bwlimit={}", bwlimit));
+        }
+        command
+            .args(["--rsh", "ssh -o BatchMode=yes -o ConnectTimeout=10", "--"])
+            .arg(path)
+            .arg(format!("{}:", host));
+        command
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_rsync_command() {
+        let transfers = Transfers::new(2, Some(512));
+        let command = transfers.command("user@build1", "data/in 1.txt");
+        assert_eq!(command.get_program(), "rsync");
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(
+            args,
+            [
+                "--archive",
+                "--relative",
+                "--bwlimit=512",
+                "--rsh",
+                "ssh -o BatchMode=yes -o ConnectTimeout=10",
+                "--",
+                "data/in 1.txt",
+                "user@build1:"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_slots_limit_concurrent_transfers() {
+        let transfers = Arc::new(Transfers::new(1, None));
+        transfers.acquire();
+        let waiting = {
+            let transfers = Arc::clone(&transfers);
+            thread::spawn(move || transfers.acquire())
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!waiting.is_finished());
+
+        transfers.release();
+        waiting.join().unwrap();
+        assert_eq!(*transfers.active.lock().unwrap(), 1);
+    }
+}
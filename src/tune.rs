@@ -0,0 +1,172 @@
+use std::io::{self, Write};
+use std::process::Stdio;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Shells started to time a single spawn, and per worker count tried
+const SPAWNS: usize = 32;
+
+/// Bytes written through a job's stdin to time the pipe
+const PIPED: usize = 16 << 20;
+
+/// What `--tune` measured on this machine
+#[derive(Debug)]
+pub struct Calibration {
+    pub spawn: Duration,
+    pub rates: Vec<(usize, f64)>,
+    pub throughput: f64,
+}
+
+/// Times starting shells one at a time and from up to twice `cpus` workers at once, and
+/// piping data through `cat`, the way jobs are started and fed
+pub fn calibrate(cpus: usize) -> io::Result<Calibration> {
+    let started = Instant::now();
+    for _ in 0..SPAWNS {
+        spawn_true()?;
+    }
+    let spawn = started.elapsed() / SPAWNS as u32;
+
+    let mut rates = Vec::new();
+    let mut workers = 1;
+    while workers <= cpus.max(1) * 2 {
+        let started = Instant::now();
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers)
+                .map(|_| scope.spawn(|| (0..SPAWNS).try_for_each(|_| spawn_true())))
+                .collect();
+            handles
+                .into_iter()
+                .try_for_each(|handle| handle.join().unwrap())
+        })?;
+        let rate = (workers * SPAWNS) as f64 / started.elapsed().as_secs_f64();
+        rates.push((workers, rate));
+        workers *= 2;
+    }
+
+    let mut child = crate::shell_command("cat > /dev/null")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    let chunk = vec![b'x'; 64 * 1024];
+    let started = Instant::now();
+    for _ in 0..PIPED / chunk.len() {
+        stdin.write_all(&chunk)?;
+    }
+    drop(stdin);
+    child.wait()?;
+    let throughput = PIPED as f64 / started.elapsed().as_secs_f64();
+
+    Ok(Calibration {
+        spawn,
+        rates,
+        throughput,
+    })
+}
+
+fn spawn_true() -> io::Result<()> {
+    crate::shell_command("true")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(drop)
+}
+
+impl Calibration {
+    /// The fewest workers that start jobs within 10% of the fastest rate measured, which is
+    /// as many as short jobs can use
+    pub fn workers(&self) -> usize {
+        let peak = self.rates.iter().map(|&(_, rate)| rate).fold(0.0, f64::max);
+        self.rates
+            .iter()
+            .find(|&&(_, rate)| rate >= peak * 0.9)
+            .map_or(1, |&(workers, _)| workers)
+    }
+
+    /// A `--pipe` block that takes ten spawns' time to feed, so starting its job is cheap
+    /// next to moving its data; a power of two between 64K and 64M
+    pub fn block(&self) -> u64 {
+        let bytes = self.throughput * self.spawn.as_secs_f64() * 10.0;
+        (bytes as u64).clamp(64 << 10, 64 << 20).next_power_of_two()
+    }
+
+    /// Prints the measurements and the recommended settings
+    pub fn report(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "spawn latency: {:?}", self.spawn)?;
+        for (workers, rate) in &self.rates {
+            writeln!(out, "spawns with -j {}: {:.0}/s", workers, rate)?;
+        }
+        writeln!(out, "pipe throughput: {}/s", size(self.throughput as u64))?;
+        writeln!(
+            out,
+            "recommended: -j {} --block {}",
+            self.workers(),
+            size(self.block())
+        )
+    }
+}
+
+/// Formats a byte count the way `--block` takes it, rounded down to the largest unit
+pub fn size(bytes: u64) -> String {
+    for (unit, shift) in [("G", 30), ("M", 20), ("K", 10)] {
+        if bytes >= 1 << shift {
+            return format!("{}{}", bytes >> shift, unit);
+        }
+    }
+    bytes.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recommendations() {
+        let calibration = Calibration {
+            spawn: Duration::from_millis(2),
+            rates: vec![
+                (1, 400.0),
+                (2, 780.0),
+                (4, 1500.0),
+                (8, 1600.0),
+                (16, 1550.0),
+            ],
+            throughput: 500.0 * (1 << 20) as f64,
+        };
+        assert_eq!(calibration.workers(), 4);
+        assert_eq!(calibration.block(), 16 << 20);
+        let mut out = Vec::new();
+        calibration.report(&mut out).unwrap();
+        let report = String::from_utf8(out).unwrap();
+        assert!(report.contains("pipe throughput: 500M/s\n"), "{}", report);
+        assert!(
+            report.ends_with("recommended: -j 4 --block 16M\n"),
+            "{}",
+            report
+        );
+
+        let slow = Calibration {
+            spawn: Duration::from_micros(10),
+            rates: vec![(1, 100.0)],
+            throughput: 1000.0,
+        };
+        assert_eq!((slow.workers(), slow.block()), (1, 64 << 10));
+        assert_eq!(size(1536), "1K");
+        assert_eq!(size(512), "512");
+    }
+
+    #[test]
+    fn test_calibrate() {
+        let calibration = calibrate(1).unwrap();
+        assert_eq!(
+            calibration
+                .rates
+                .iter()
+                .map(|&(w, _)| w)
+                .collect::<Vec<_>>(),
+            [1, 2]
+        );
+        assert!(calibration.throughput > 0.0);
+    }
+}
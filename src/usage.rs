@@ -0,0 +1,173 @@
+use std::io::{self, BufRead, BufReader, Read};
+use std::process::{Child, ExitStatus, Output};
+use std::thread;
+use std::time::Duration;
+
+/// The CPU time and peak memory a job's command used, with everything it waited for
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub cpu: Duration,
+    pub max_rss: u64,
+}
+
+/// Receives each line of a job's output as soon as it is written, with whether it came from
+/// stderr, for `--line-buffer`
+pub type Echo<'a> = &'a (dyn Fn(bool, &[u8]) + Sync);
+
+/// Like [`Child::wait_with_output`], also reporting what the child used and passing each line
+/// of output to `echo` as it arrives
+///
+/// On unix the child is reaped with `wait4`, whose usage covers the shell and every descendant
+/// it waited for; the peak memory is that of the largest of them.
+pub fn wait_with_output(
+    mut child: Child,
+    echo: Option<Echo>,
+) -> io::Result<(Output, Option<Usage>)> {
+    let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+    thread::scope(|scope| {
+        let stdout = scope.spawn(move || read_all(stdout, echo.map(|echo| (echo, false))));
+        let stderr = scope.spawn(move || read_all(stderr, echo.map(|echo| (echo, true))));
+        let (status, usage) = wait(&mut child)?;
+        Ok((
+            Output {
+                status,
+                stdout: stdout.join().unwrap()?,
+                stderr: stderr.join().unwrap()?,
+            },
+            usage,
+        ))
+    })
+}
+
+#[cfg(unix)]
+fn wait(child: &mut Child) -> io::Result<(ExitStatus, Option<Usage>)> {
+    use std::os::unix::process::ExitStatusExt;
+    let mut status = 0;
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    let pid = child.id() as libc::pid_t;
+    loop {
+        if unsafe { libc::wait4(pid, &mut status, 0, &mut rusage) } == pid {
+            return Ok((ExitStatusExt::from_raw(status), Some(from_rusage(&rusage))));
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Ok((child.wait()?, None));
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn wait(child: &mut Child) -> io::Result<(ExitStatus, Option<Usage>)> {
+    Ok((child.wait()?, None))
+}
+
+fn read_all(pipe: Option<impl Read>, echo: Option<(Echo, bool)>) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let Some(mut pipe) = pipe else {
+        return Ok(data);
+    };
+    let Some((echo, stderr)) = echo else {
+        pipe.read_to_end(&mut data)?;
+        return Ok(data);
+    };
+    let mut reader = BufReader::new(pipe);
+    loop {
+        let start = data.len();
+        if reader.read_until(b'\n', &mut data)? == 0 {
+            return Ok(data);
+        }
+        echo(stderr, &data[start..]);
+    }
+}
+
+#[cfg(unix)]
+fn from_rusage(rusage: &libc::rusage) -> Usage {
+    let time = |time: libc::timeval| {
+        Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+    };
+    // macOS counts the peak in bytes, the others in KiB
+    let unit = if cfg!(target_os = "macos") { 1 } else { 1024 };
+    Usage {
+        cpu: time(rusage.ru_utime) + time(rusage.ru_stime),
+        max_rss: rusage.ru_maxrss as u64 * unit,
+    }
+}
+
+/// What all the jobs of a run used together, for the summary at the end
+#[derive(Debug, Default)]
+pub struct Totals {
+    jobs: usize,
+    cpu: Duration,
+    peak: Option<(u64, usize)>,
+}
+
+impl Totals {
+    pub fn add(&mut self, seq: usize, usage: Usage) {
+        self.jobs += 1;
+        self.cpu += usage.cpu;
+        if self.peak.is_none_or(|(peak, _)| usage.max_rss > peak) {
+            self.peak = Some((usage.max_rss, seq));
+        }
+    }
+
+    pub fn cpu(&self) -> Duration {
+        self.cpu
+    }
+
+    pub fn max_rss(&self) -> Option<u64> {
+        self.peak.map(|(peak, _)| peak)
+    }
+
+    /// e.g. `resource usage: 12.40s CPU over 8 jobs (1.55s per job), peak memory 96M in job 3`
+    pub fn summary(&self) -> Option<String> {
+        let (peak, seq) = self.peak?;
+        Some(format!(
+            "resource usage: {:.2}s CPU over {} jobs ({:.2}s per job), peak memory {} in job {}",
+            self.cpu.as_secs_f64(),
+            self.jobs,
+            self.cpu.as_secs_f64() / self.jobs as f64,
+            crate::tune::size(peak),
+            seq
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_totals_summary() {
+        let mut totals = Totals::default();
+        assert_eq!(totals.summary(), None);
+        let usage = |millis, max_rss| Usage {
+            cpu: Duration::from_millis(millis),
+            max_rss,
+        };
+        totals.add(1, usage(1500, 4 << 20));
+        totals.add(2, usage(500, 96 << 20));
+        totals.add(3, usage(2000, 8 << 20));
+        assert_eq!(
+            totals.summary().unwrap(),
+            "resource usage: 4.00s CPU over 3 jobs (1.33s per job), peak memory 96M in job 2"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_wait_reports_usage() {
+        let child = std::process::Command::new("sh")
+            .args(["-c", "echo out; echo err >&2; exit 3"])
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let (output, usage) = wait_with_output(child, None).unwrap();
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(
+            (&output.stdout[..], &output.stderr[..]),
+            (&b"out\n"[..], &b"err\n"[..])
+        );
+        assert!(usage.unwrap().max_rss > 0);
+    }
+}
@@ -0,0 +1,364 @@
+use crate::mux::{base64, json_string};
+use crate::usage::{Totals, Usage};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, PoisonError, mpsc};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+/// Appended to a client's key to prove the server speaks WebSocket (RFC 6455)
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Job lifecycle events streamed as JSON text frames to the WebSocket clients of `--ws-listen`
+///
+/// Events are queued to a sender thread so a slow dashboard never holds up a worker. A client
+/// that connects mid-run is first sent the latest progress event.
+pub struct EventStream {
+    events: Mutex<Option<mpsc::Sender<String>>>,
+    sender: Mutex<Option<JoinHandle<()>>>,
+    clients: Arc<Mutex<Clients>>,
+    started: Mutex<HashMap<usize, Instant>>,
+}
+
+#[derive(Default)]
+struct Clients {
+    streams: Vec<TcpStream>,
+    latest: Option<String>,
+}
+
+impl EventStream {
+    pub fn listen(addr: &str) -> io::Result<Self> {
+        Ok(Self::serve(TcpListener::bind(addr)?))
+    }
+
+    fn serve(listener: TcpListener) -> Self {
+        let clients = Arc::new(Mutex::new(Clients::default()));
+        let accepting = Arc::clone(&clients);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let clients = Arc::clone(&accepting);
+                thread::spawn(move || {
+                    let _ = handshake(stream, &clients);
+                });
+            }
+        });
+
+        let (events, queued) = mpsc::channel::<String>();
+        let sending = Arc::clone(&clients);
+        let sender = thread::spawn(move || {
+            for event in queued {
+                let mut clients = sending.lock().unwrap_or_else(PoisonError::into_inner);
+                if event.starts_with("{\"event\":\"progress\"") {
+                    clients.latest = Some(event.clone());
+                }
+                let frame = frame(0x1, event.as_bytes());
+                clients
+                    .streams
+                    .retain_mut(|stream| stream.write_all(&frame).is_ok());
+            }
+        });
+        EventStream {
+            events: Mutex::new(Some(events)),
+            sender: Mutex::new(Some(sender)),
+            clients,
+            started: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn send(&self, event: String) {
+        if let Some(events) = &*self.events.lock().unwrap_or_else(PoisonError::into_inner) {
+            let _ = events.send(event);
+        }
+    }
+
+    pub fn run(&self, workers: usize) {
+        self.send(format!("{{\"event\":\"run\",\"workers\":{}}}", workers));
+    }
+
+    pub fn start(&self, job: usize, worker: usize, host: Option<&str>, input: &str) {
+        self.started
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(job, Instant::now());
+        self.send(format!(
+            "{{\"event\":\"start\",\"job\":{},\"worker\":{},\"host\":{},\"input\":{}}}",
+            job,
+            worker,
+            host.map_or("null".to_string(), json_string),
+            json_string(input)
+        ));
+    }
+
+    pub fn finish(
+        &self,
+        job: usize,
+        exit_code: Option<i32>,
+        error: Option<&str>,
+        usage: Option<Usage>,
+    ) {
+        let duration = self
+            .started
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&job);
+        self.send(format!(
+            "{{\"event\":\"finish\",\"job\":{},\"status\":\"{}\",\"exit\":{},\"duration\":{},\"error\":{},\"cpu\":{},\"max_rss\":{}}}",
+            job,
+            if error.is_none() { "ok" } else { "failed" },
+            exit_code.map_or("null".to_string(), |code| code.to_string()),
+            duration.map_or("null".to_string(), |started| format!(
+                "{:.3}",
+                started.elapsed().as_secs_f64()
+            )),
+            error.map_or("null".to_string(), json_string),
+            usage.map_or("null".to_string(), |usage| format!(
+                "{:.3}",
+                usage.cpu.as_secs_f64()
+            )),
+            usage.map_or("null".to_string(), |usage| usage.max_rss.to_string())
+        ));
+    }
+
+    pub fn progress(&self, done: usize, total: Option<usize>, failed: usize) {
+        self.send(format!(
+            "{{\"event\":\"progress\",\"done\":{},\"total\":{},\"failed\":{}}}",
+            done,
+            total.map_or("null".to_string(), |total| total.to_string()),
+            failed
+        ));
+    }
+
+    /// Sends the summary of the run, waits for every event to go out and closes the clients
+    pub fn close(&self, succeeded: usize, failed: usize, stopped: Option<&str>, usage: &Totals) {
+        self.send(format!(
+            "{{\"event\":\"end\",\"succeeded\":{},\"failed\":{},\"stopped\":{},\"cpu\":{:.3},\"max_rss\":{}}}",
+            succeeded,
+            failed,
+            stopped.map_or("null".to_string(), json_string),
+            usage.cpu().as_secs_f64(),
+            usage.max_rss().map_or("null".to_string(), |peak| peak.to_string())
+        ));
+        self.events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(sender) = self
+            .sender
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+        {
+            let _ = sender.join();
+        }
+        for mut stream in self
+            .clients
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .streams
+            .drain(..)
+        {
+            let _ = stream.write_all(&frame(0x8, &[]));
+            let _ = stream.shutdown(Shutdown::Write);
+        }
+    }
+}
+
+/// Answers a client's upgrade request and adds it to the clients, holding their lock so no
+/// event is sent between the handshake and the latest progress
+fn handshake(mut stream: TcpStream, clients: &Mutex<Clients>) -> io::Result<()> {
+    let mut key = None;
+    let mut reader = BufReader::new(&stream);
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.trim().eq_ignore_ascii_case("sec-websocket-key")
+        {
+            key = Some(value.trim().to_string());
+        }
+    }
+    let Some(key) = key else {
+        stream.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")?;
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a WebSocket upgrade",
+        ));
+    };
+    let mut clients = clients.lock().unwrap_or_else(PoisonError::into_inner);
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(&key)
+    )?;
+    if let Some(latest) = &clients.latest {
+        stream.write_all(&frame(0x1, latest.as_bytes()))?;
+    }
+    clients.streams.push(stream);
+    Ok(())
+}
+
+fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, GUID).as_bytes()))
+}
+
+/// Frames an unmasked, unfragmented server message
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= 0xffff => {
+            frame.push(126);
+            frame.extend((len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend((len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// SHA-1, which the WebSocket handshake requires; nothing else relies on it
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend(((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (bytes, word) in digest.chunks_mut(4).zip(h) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_accept_key() {
+        assert_eq!(
+            crate::sha256::hex(&sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        // the example handshake of RFC 6455
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert_eq!(frame(0x1, b"hi"), [0x81, 2, b'h', b'i']);
+        assert_eq!(&frame(0x1, &[0; 300])[..4], [0x81, 126, 1, 44]);
+    }
+
+    /// Reads one unmasked text frame of under 64K
+    fn read_frame(stream: &mut TcpStream) -> Option<String> {
+        let mut header = [0u8; 2];
+        stream.read_exact(&mut header).ok()?;
+        let len = match header[1] {
+            126 => {
+                let mut len = [0u8; 2];
+                stream.read_exact(&mut len).ok()?;
+                u16::from_be_bytes(len) as usize
+            }
+            len => len as usize,
+        };
+        let mut payload = vec![0; len];
+        stream.read_exact(&mut payload).ok()?;
+        (header[0] == 0x81).then(|| String::from_utf8(payload).unwrap())
+    }
+
+    #[test]
+    fn test_streams_events_to_clients() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let events = EventStream::serve(listener);
+        events.progress(0, Some(2), 0);
+
+        // a late client first gets the latest progress
+        thread::sleep(std::time::Duration::from_millis(50));
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .write_all(
+                b"GET / HTTP/1.1\r\nHost: x\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                  Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            )
+            .unwrap();
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            let mut byte = [0u8];
+            client.read_exact(&mut byte).unwrap();
+            response.push(byte[0]);
+        }
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101 "), "{}", response);
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert_eq!(
+            read_frame(&mut client).unwrap(),
+            r#"{"event":"progress","done":0,"total":2,"failed":0}"#
+        );
+
+        events.start(0, 1, Some("build-1"), "a \"b\"");
+        let usage = Usage {
+            cpu: std::time::Duration::from_millis(250),
+            max_rss: 2048,
+        };
+        events.finish(0, Some(3), Some("exit status 3"), Some(usage));
+        let mut totals = Totals::default();
+        totals.add(1, usage);
+        events.close(1, 1, None, &totals);
+        assert_eq!(
+            read_frame(&mut client).unwrap(),
+            r#"{"event":"start","job":0,"worker":1,"host":"build-1","input":"a \"b\""}"#
+        );
+        let finish = read_frame(&mut client).unwrap();
+        assert!(finish.starts_with(r#"{"event":"finish","job":0,"status":"failed","exit":3,"#));
+        assert!(finish.ends_with(r#""error":"exit status 3","cpu":0.250,"max_rss":2048}"#));
+        assert_eq!(
+            read_frame(&mut client).unwrap(),
+            r#"{"event":"end","succeeded":1,"failed":1,"stopped":null,"cpu":0.250,"max_rss":2048}"#
+        );
+        assert_eq!(read_frame(&mut client), None);
+    }
+}
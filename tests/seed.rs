@@ -0,0 +1,45 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Runs kyanite with `args` on the lines 1 to 100, returning its stdout
+fn run(args: &[&str]) -> Vec<u8> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_kyanite"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let input: String = (1..=100).map(|i| format!("{}\n", i)).collect();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    output.stdout
+}
+
+#[test]
+fn test_seeded_runs_are_byte_identical() {
+    let args = |seed| {
+        [
+            "--seed",
+            seed,
+            "--sample",
+            "10",
+            "--sample-random",
+            "--jitter",
+            "0..20ms",
+            "-j",
+            "4",
+            "-k",
+            "echo {}",
+        ]
+    };
+    let first = run(&args("7"));
+    assert_eq!(first, run(&args("7")));
+    assert_eq!(String::from_utf8_lossy(&first).lines().count(), 10);
+    assert_ne!(first, run(&args("8")));
+}